@@ -33,6 +33,10 @@ fn main() {
 				println!("Gateway closed on us with code {:?}: {}", code, body);
 				break;
 			}
+			Err(discord::Error::GatewayClosed(code, body)) => {
+				println!("Gateway refused to continue with {:?}: {}", code, body);
+				break;
+			}
 			Err(err) => println!("Receive error: {:?}", err),
 		}
 	}
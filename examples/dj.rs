@@ -38,10 +38,10 @@ pub fn main() {
 					state = State::new(ready);
 					println!("[Ready] Reconnected successfully.");
 				}
-				if let discord::Error::Closed(..) = err {
-					break;
+				match err {
+					discord::Error::Closed(..) | discord::Error::GatewayClosed(..) => break,
+					_ => continue,
 				}
-				continue;
 			}
 		};
 		state.update(&event);
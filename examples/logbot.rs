@@ -37,6 +37,10 @@ fn main() {
 				println!("[Error] Connection closed with status {:?}: {}", code, body);
 				break;
 			}
+			Err(discord::Error::GatewayClosed(code, body)) => {
+				println!("[Error] Connection closed for good with {:?}: {}", code, body);
+				break;
+			}
 			Err(err) => {
 				println!("[Warning] Receive error: {:?}", err);
 				continue;
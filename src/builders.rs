@@ -3,6 +3,8 @@
 //! These types do not usually need to be imported, but the methods available
 //! on them are very relevant to where they are used.
 
+use std::collections::BTreeMap;
+
 use serde_json::Value;
 
 use chrono::offset::FixedOffset;
@@ -53,12 +55,21 @@ builder! {
 	/// Patch content for the `edit_server` call.
 	EditServer(Object);
 
+	/// Content for the `create_channel_ex` call.
+	CreateChannel(Object);
+
 	/// Patch content for the `edit_channel` call.
 	EditChannel(Object);
 
+	/// Patch content for the `edit_group` call.
+	EditGroup(Object);
+
 	/// Patch content for the `edit_member` call.
 	EditMember(Object);
 
+	/// Patch content for the `edit_voice_state` and `edit_user_voice_state` calls.
+	EditVoiceState(Object);
+
 	/// Patch content for the `edit_profile` call.
 	EditProfile(Object);
 
@@ -68,6 +79,15 @@ builder! {
 	/// Patch content for the `edit_role` call.
 	EditRole(Object);
 
+	/// Patch content for the `edit_guild_sticker` call.
+	EditSticker(Object);
+
+	/// Patch content for the `create_scheduled_event` and `edit_scheduled_event` calls.
+	EditScheduledEvent(Object);
+
+	/// Content for the `create_invite_ex` call.
+	CreateInvite(Object);
+
 	/// Content for the `send_message` call.
 	SendMessage(Object);
 
@@ -85,6 +105,27 @@ builder! {
 
 	/// Inner patch content for the `send_embed` call.
 	EmbedFieldsBuilder(Vec<Value>);
+
+	/// List of action rows for use within `send_message`.
+	ComponentsBuilder(Vec<Value>);
+
+	/// List of components within an action row.
+	ActionRowBuilder(Vec<Value>);
+
+	/// Button content for use within an action row.
+	ButtonBuilder(Object);
+
+	/// Select menu content for use within an action row.
+	SelectMenuBuilder(Object);
+
+	/// Option content for use within a select menu.
+	SelectOptionBuilder(Object);
+
+	/// Content for the `create_global_command` and `create_server_command` calls.
+	CreateCommand(Object);
+
+	/// Option content for use within an application command.
+	CommandOptionBuilder(Object);
 }
 
 macro_rules! set {
@@ -100,8 +141,8 @@ impl EditServer {
 	}
 
 	/// Edit the server's voice region.
-	pub fn region(self, region: &str) -> Self {
-		set!(self, "region", region)
+	pub fn region(self, region: Region) -> Self {
+		set!(self, "region", region.name())
 	}
 
 	/// Edit the server's icon. Use `None` to remove the icon.
@@ -129,19 +170,101 @@ impl EditServer {
 		set!(self, "verification_level", verification_level)
 	}
 
+	/// Edit the default notification level for members of the server.
+	///
+	/// Only `All` and `Mentions` are meaningful at the server level.
+	pub fn default_message_notifications(self, level: NotificationLevel) -> Self {
+		set!(self, "default_message_notifications", level)
+	}
+
+	/// Edit which members' messages are scanned for explicit content.
+	pub fn explicit_content_filter(self, filter: ExplicitContentFilter) -> Self {
+		set!(self, "explicit_content_filter", filter)
+	}
+
 	/// Edit the server's splash. Use `None` to remove the splash.
 	pub fn splash(self, splash: Option<&str>) -> Self {
 		set!(self, "splash", splash)
 	}
+
+	/// Edit whether the boost progress bar is shown.
+	pub fn premium_progress_bar(self, enabled: bool) -> Self {
+		set!(self, "premium_progress_bar_enabled", enabled)
+	}
+}
+
+impl CreateChannel {
+	/// Set the text channel's topic. May be up to 1024 characters long, or
+	/// 4096 for forum channels.
+	pub fn topic(self, topic: &str) -> Self {
+		set!(self, "topic", topic)
+	}
+
+	/// Set the channel's position in the list.
+	pub fn position(self, position: u64) -> Self {
+		set!(self, "position", position)
+	}
+
+	/// Set the category the channel is placed in.
+	pub fn parent(self, category: ChannelId) -> Self {
+		set!(self, "parent_id", category)
+	}
+
+	/// Set the voice channel's bitrate.
+	pub fn bitrate(self, bitrate: u64) -> Self {
+		set!(self, "bitrate", bitrate)
+	}
+
+	/// Set the voice channel's user limit. Zero (`0`) means unlimited.
+	pub fn user_limit(self, user_limit: u64) -> Self {
+		set!(self, "user_limit", user_limit)
+	}
+
+	/// Set whether the channel is marked NSFW.
+	pub fn nsfw(self, nsfw: bool) -> Self {
+		set!(self, "nsfw", nsfw)
+	}
+
+	/// Set the slowmode delay, in seconds, between each user's messages. May
+	/// be up to 21600 (six hours); zero disables slowmode.
+	pub fn rate_limit_per_user(self, seconds: u64) -> Self {
+		set!(self, "rate_limit_per_user", seconds)
+	}
+
+	/// Set the channel's permission overwrites.
+	pub fn permission_overwrites(self, overwrites: &[PermissionOverwrite]) -> Self {
+		set!(self, "permission_overwrites", overwrites_json(overwrites))
+	}
+}
+
+/// Convert permission overwrites to the form the channel endpoints accept.
+fn overwrites_json(overwrites: &[PermissionOverwrite]) -> Value {
+	overwrites
+		.iter()
+		.map(|overwrite| {
+			let (id, kind) = match overwrite.kind {
+				PermissionOverwriteType::Member(id) => (id.0, "member"),
+				PermissionOverwriteType::Role(id) => (id.0, "role"),
+			};
+			json!({
+				"id": id,
+				"type": kind,
+				"allow": overwrite.allow.bits(),
+				"deny": overwrite.deny.bits(),
+			})
+		})
+		.collect()
 }
 
 impl EditChannel {
-	/// Edit the channel's name.
+	/// Edit the channel's name. Must be between 1 and 100 characters long,
+	/// without control characters.
 	pub fn name(self, name: &str) -> Self {
 		set!(self, "name", name)
 	}
 
-	/// Edit the text channel's topic.
+	/// Edit the text channel's topic. May be up to 1024 characters long, or
+	/// 4096 for forum channels.
 	pub fn topic(self, topic: &str) -> Self {
 		set!(self, "topic", topic)
 	}
@@ -160,6 +283,69 @@ impl EditChannel {
 	pub fn user_limit(self, user_limit: u64) -> Self {
 		set!(self, "user_limit", user_limit)
 	}
+
+	/// Edit whether the channel is marked NSFW.
+	pub fn nsfw(self, nsfw: bool) -> Self {
+		set!(self, "nsfw", nsfw)
+	}
+
+	/// Move the channel into a category, or out of any category with `None`.
+	pub fn category(self, category: Option<ChannelId>) -> Self {
+		set!(self, "parent_id", category)
+	}
+
+	/// Edit the slowmode delay, in seconds, between each user's messages. May
+	/// be up to 21600 (six hours); zero disables slowmode.
+	pub fn rate_limit_per_user(self, seconds: u64) -> Self {
+		set!(self, "rate_limit_per_user", seconds)
+	}
+
+	/// Replace the channel's permission overwrites.
+	pub fn permission_overwrites(self, overwrites: &[PermissionOverwrite]) -> Self {
+		set!(self, "permission_overwrites", overwrites_json(overwrites))
+	}
+
+	/// Edit the forum channel's available tags. Existing tags which are
+	/// omitted are deleted.
+	pub fn available_tags(self, tags: &[ForumTag]) -> Self {
+		set!(self, "available_tags", tags)
+	}
+
+	/// Edit the emoji shown in the add reaction button on forum posts. Use
+	/// `None` to remove it.
+	pub fn default_reaction_emoji(self, emoji: Option<DefaultReaction>) -> Self {
+		set!(self, "default_reaction_emoji", emoji)
+	}
+
+	/// Edit the default order of posts in the forum channel.
+	pub fn default_sort_order(self, order: SortOrder) -> Self {
+		set!(self, "default_sort_order", order)
+	}
+
+	/// Edit the default layout of posts in the forum channel.
+	pub fn default_forum_layout(self, layout: ForumLayout) -> Self {
+		set!(self, "default_forum_layout", layout)
+	}
+
+	/// Edit the tags applied to a forum post.
+	pub fn applied_tags(self, tags: &[ForumTagId]) -> Self {
+		set!(self, "applied_tags", tags)
+	}
+}
+
+impl EditGroup {
+	/// Edit the group's name. Use `None` to remove the name, so that the group
+	/// is shown by its recipients' names.
+	pub fn name(self, name: Option<&str>) -> Self {
+		set!(self, "name", name)
+	}
+
+	/// Edit the group's icon. Use `None` to remove the icon.
+	///
+	/// The icon should be a data URL, such as one returned by `read_image`.
+	pub fn icon(self, icon: Option<&str>) -> Self {
+		set!(self, "icon", icon)
+	}
 }
 
 impl EditMember {
@@ -187,6 +373,33 @@ impl EditMember {
 	pub fn channel(self, channel: ChannelId) -> Self {
 		set!(self, "channel_id", channel.0)
 	}
+
+	/// Edit the member's flags. Only `BYPASSES_VERIFICATION` may be changed;
+	/// other flags are ignored.
+	pub fn flags(self, flags: MemberFlags) -> Self {
+		set!(self, "flags", flags.bits())
+	}
+}
+
+impl EditVoiceState {
+	/// Set the stage channel the user is in. Required for all edits.
+	pub fn channel(self, channel: ChannelId) -> Self {
+		set!(self, "channel_id", channel)
+	}
+
+	/// Edit whether the user is suppressed, listening to the stage rather
+	/// than speaking.
+	pub fn suppress(self, suppress: bool) -> Self {
+		set!(self, "suppress", suppress)
+	}
+
+	/// Request to speak, or withdraw the request with `None`.
+	///
+	/// Only the current user's request can be edited. Any present or future
+	/// time is accepted.
+	pub fn request_to_speak(self, timestamp: Option<DateTime<FixedOffset>>) -> Self {
+		set!(self, "request_to_speak_timestamp", timestamp.map(|t| t.to_rfc3339()))
+	}
 }
 
 impl EditProfile {
@@ -240,9 +453,9 @@ impl EditRole {
 		set!(self, "permissions", permissions)
 	}
 
-	/// Edit the role's color. Set to zero for default.
-	pub fn color(self, color: u64) -> Self {
-		set!(self, "color", color)
+	/// Edit the role's color. Set to `Color::DEFAULT` for no color.
+	pub fn color<C: Into<Color>>(self, color: C) -> Self {
+		set!(self, "color", color.into())
 	}
 
 	/// Edit the role's hoist status (whether the role should be displayed separately in the sidebar).
@@ -256,6 +469,112 @@ impl EditRole {
 	}
 }
 
+impl EditSticker {
+	/// Edit the sticker's name. Must be between 2 and 30 characters long.
+	pub fn name(self, name: &str) -> Self {
+		set!(self, "name", name)
+	}
+
+	/// Edit the sticker's description. Use `None` to remove the description.
+	pub fn description(self, description: Option<&str>) -> Self {
+		set!(self, "description", description)
+	}
+
+	/// Edit the sticker's autocomplete and suggestion tags.
+	pub fn tags(self, tags: &str) -> Self {
+		set!(self, "tags", tags)
+	}
+}
+
+impl EditScheduledEvent {
+	/// Edit the event's name.
+	pub fn name(self, name: &str) -> Self {
+		set!(self, "name", name)
+	}
+
+	/// Edit the event's description.
+	pub fn description(self, description: &str) -> Self {
+		set!(self, "description", description)
+	}
+
+	/// Edit the stage or voice channel the event takes place in. Use `None`
+	/// when changing the event to `External`.
+	pub fn channel(self, channel: Option<ChannelId>) -> Self {
+		set!(self, "channel_id", channel)
+	}
+
+	/// Edit where an external event takes place.
+	pub fn location(self, location: &str) -> Self {
+		set!(self, "entity_metadata", { "location": location })
+	}
+
+	/// Edit when the event starts.
+	pub fn start_time(self, time: DateTime<FixedOffset>) -> Self {
+		set!(self, "scheduled_start_time", time.to_rfc3339())
+	}
+
+	/// Edit when the event ends. Required for external events.
+	pub fn end_time(self, time: DateTime<FixedOffset>) -> Self {
+		set!(self, "scheduled_end_time", time.to_rfc3339())
+	}
+
+	/// Edit where the event takes place.
+	pub fn entity_type(self, kind: ScheduledEventType) -> Self {
+		set!(self, "entity_type", kind)
+	}
+
+	/// Start, end, or cancel the event.
+	///
+	/// A scheduled event may become active or canceled, and an active event
+	/// may become completed.
+	pub fn status(self, status: ScheduledEventStatus) -> Self {
+		set!(self, "status", status)
+	}
+
+	/// Edit the event's cover image. `read_image` may be used to build an
+	/// `image` string.
+	pub fn image(self, image: &str) -> Self {
+		set!(self, "image", image)
+	}
+}
+
+impl CreateInvite {
+	/// Set how long the invite lasts, in seconds. Zero means forever.
+	///
+	/// Defaults to one day (86400 seconds).
+	pub fn max_age(self, seconds: u64) -> Self {
+		set!(self, "max_age", seconds)
+	}
+
+	/// Set how many times the invite may be used. Zero means no limit.
+	pub fn max_uses(self, uses: u64) -> Self {
+		set!(self, "max_uses", uses)
+	}
+
+	/// Set whether the invite only grants temporary membership.
+	pub fn temporary(self, temporary: bool) -> Self {
+		set!(self, "temporary", temporary)
+	}
+
+	/// Set whether to always create a new invite rather than reusing a
+	/// similar existing one.
+	pub fn unique(self, unique: bool) -> Self {
+		set!(self, "unique", unique)
+	}
+
+	/// Point the invite at a user's stream in the voice channel.
+	pub fn target_user(self, user: UserId) -> Self {
+		let this = set!(self, "target_type", InviteTargetType::Stream);
+		set!(this, "target_user_id", user)
+	}
+
+	/// Point the invite at an embedded application in the voice channel.
+	pub fn target_application(self, application: ApplicationId) -> Self {
+		let this = set!(self, "target_type", InviteTargetType::EmbeddedApplication);
+		set!(this, "target_application_id", application)
+	}
+}
+
 impl SendMessage {
 	/// Set the text content of the message.
 	pub fn content(self, content: &str) -> Self {
@@ -277,9 +596,39 @@ impl SendMessage {
 		set!(self, "embed", EmbedBuilder::__build(f))
 	}
 
+	/// Add one of several embeds. Up to 10 embeds may be sent at once.
+	pub fn add_embed<F: FnOnce(EmbedBuilder) -> EmbedBuilder>(mut self, f: F) -> Self {
+		let map = EmbedBuilder::__build(f);
+		self.0
+			.entry("embeds")
+			.or_insert_with(|| Value::Array(Vec::new()))
+			.as_array_mut()
+			.expect("embeds is not an array")
+			.push(Value::Object(map));
+		self
+	}
+
+	/// Attach interactive components, such as buttons and select menus.
+	///
+	/// See the `ComponentsBuilder` struct for details.
+	pub fn components<F: FnOnce(ComponentsBuilder) -> ComponentsBuilder>(self, f: F) -> Self {
+		set!(self, "components", ComponentsBuilder::__build(f))
+	}
+
 	/// Restrict allowed mentions for this message.
-	pub fn allowed_mentions<F: FnOnce(AllowedMentions) -> AllowedMentions>(self, f: F) -> Self {
-		set!(self, "allowed_mentions", AllowedMentions::__build(f))
+	///
+	/// Once this is set, only the mentions explicitly allowed by the builder
+	/// will notify anyone; `|b| b` suppresses all mentions. Calling this again,
+	/// or calling `reply`, adds to the existing restrictions.
+	pub fn allowed_mentions<F: FnOnce(AllowedMentions) -> AllowedMentions>(
+		mut self,
+		f: F,
+	) -> Self {
+		let existing = match self.0.remove("allowed_mentions") {
+			Some(Value::Object(map)) => map,
+			_ => Object::new(),
+		};
+		set!(self, "allowed_mentions", AllowedMentions::__apply(f, existing))
 	}
 
 	/// Reply to the given message, optionally mentioning the sender.
@@ -292,19 +641,74 @@ impl SendMessage {
 		}}).allowed_mentions(|b| b.replied_user(mention))
 	}
 
+	/// Reference the given message without changing allowed mentions.
+	///
+	/// The message may be in another channel of the same server. If
+	/// `fail_if_not_exists` is false, the message is sent without a reference
+	/// if the referenced message has been deleted.
+	pub fn message_reference(
+		self,
+		channel: ChannelId,
+		message_id: MessageId,
+		fail_if_not_exists: bool,
+	) -> Self {
+		set!(self, "message_reference", json! {{
+			"channel_id": channel,
+			"message_id": message_id,
+			"fail_if_not_exists": fail_if_not_exists,
+		}})
+	}
+
 	/// Change the message's flags.
 	///
-	/// Can only be set while editing. Only `SUPPRESS_EMBEDS` can be edited on
-	/// request.
+	/// Only `SUPPRESS_EMBEDS` and `SUPPRESS_NOTIFICATIONS` may be set when
+	/// sending, and only `SUPPRESS_EMBEDS` may be changed when editing.
 	pub fn flags(self, flags: MessageFlags) -> Self {
 		set!(self, "flags", flags)
 	}
 
-	// TODO: file, payload_json, message_reference
+	/// Set whether links in the message are shown without embeds.
+	pub fn suppress_embeds(self, suppress: bool) -> Self {
+		self.set_flag(MessageFlags::SUPPRESS_EMBEDS, suppress)
+	}
+
+	/// Set whether the message is sent without push and desktop
+	/// notifications. Mentions are still highlighted.
+	pub fn suppress_notifications(self, suppress: bool) -> Self {
+		self.set_flag(MessageFlags::SUPPRESS_NOTIFICATIONS, suppress)
+	}
+
+	fn set_flag(self, flag: MessageFlags, on: bool) -> Self {
+		let mut flags = self
+			.0
+			.get("flags")
+			.and_then(Value::as_u64)
+			.map_or(MessageFlags::empty(), MessageFlags::from_bits_truncate);
+		flags.set(flag, on);
+		self.flags(flags)
+	}
+
+	// Files are attached by `Discord::send_message_with_files`.
 }
 
 impl AllowedMentions {
-	// TODO: parse, roles, users
+	/// Allow all mentions of the given types to notify.
+	///
+	/// Do not combine `MentionType::Users` with `users`, or
+	/// `MentionType::Roles` with `roles`; Discord rejects such messages.
+	pub fn parse(self, types: &[MentionType]) -> Self {
+		set!(self, "parse", types)
+	}
+
+	/// Allow mentions of the given users to notify.
+	pub fn users(self, users: &[UserId]) -> Self {
+		set!(self, "users", users)
+	}
+
+	/// Allow mentions of the given roles to notify.
+	pub fn roles(self, roles: &[RoleId]) -> Self {
+		set!(self, "roles", roles)
+	}
 
 	/// Set to `false` to disable mentioning a replied-to user.
 	pub fn replied_user(self, replied_user: bool) -> Self {
@@ -334,8 +738,8 @@ impl EmbedBuilder {
 	}
 
 	/// Add the "color code of the embed".
-	pub fn color(self, color: u64) -> Self {
-		set!(self, "color", color)
+	pub fn color<C: Into<Color>>(self, color: C) -> Self {
+		set!(self, "color", color.into())
 	}
 
 	/// Add "footer information". See the `EmbedFooterBuilder` struct for the editable fields.
@@ -406,3 +810,226 @@ impl EmbedFieldsBuilder {
 		self
 	}
 }
+
+impl ComponentsBuilder {
+	/// Add an action row containing the given components.
+	///
+	/// A message may have up to 5 action rows. See the `ActionRowBuilder`
+	/// struct for the components which may be added.
+	pub fn action_row<F: FnOnce(ActionRowBuilder) -> ActionRowBuilder>(mut self, f: F) -> Self {
+		self.0.push(json! {{
+			"type": ComponentType::ActionRow,
+			"components": ActionRowBuilder::__build(f),
+		}});
+		self
+	}
+}
+
+impl ActionRowBuilder {
+	/// Add a button. An action row may contain up to 5 buttons.
+	///
+	/// Buttons with the `Link` style must have a `url` and no `custom_id`;
+	/// all other buttons must have a `custom_id` and no `url`.
+	pub fn button<F: FnOnce(ButtonBuilder) -> ButtonBuilder>(
+		mut self,
+		style: ButtonStyle,
+		f: F,
+	) -> Self {
+		let mut map = ButtonBuilder::__build(f);
+		map.insert("type".into(), json!(ComponentType::Button));
+		map.insert("style".into(), json!(style));
+		self.0.push(Value::Object(map));
+		self
+	}
+
+	/// Add a select menu. A select menu must be alone in its action row.
+	pub fn select_menu<F: FnOnce(SelectMenuBuilder) -> SelectMenuBuilder>(
+		mut self,
+		custom_id: &str,
+		f: F,
+	) -> Self {
+		let mut map = SelectMenuBuilder::__build(f);
+		map.insert("type".into(), json!(ComponentType::SelectMenu));
+		map.insert("custom_id".into(), json!(custom_id));
+		self.0.push(Value::Object(map));
+		self
+	}
+}
+
+impl ButtonBuilder {
+	/// Set the text that appears on the button.
+	pub fn label(self, label: &str) -> Self {
+		set!(self, "label", label)
+	}
+
+	/// Set the identifier sent along with interactions on this button.
+	pub fn custom_id(self, custom_id: &str) -> Self {
+		set!(self, "custom_id", custom_id)
+	}
+
+	/// Set the URL which a `Link` style button navigates to.
+	pub fn url(self, url: &str) -> Self {
+		set!(self, "url", url)
+	}
+
+	/// Set the emoji that appears on the button.
+	pub fn emoji(self, emoji: ReactionEmoji) -> Self {
+		set!(self, "emoji", emoji)
+	}
+
+	/// Set whether the button is disabled.
+	pub fn disabled(self, disabled: bool) -> Self {
+		set!(self, "disabled", disabled)
+	}
+}
+
+impl SelectMenuBuilder {
+	/// Set the placeholder text shown when nothing is selected.
+	pub fn placeholder(self, placeholder: &str) -> Self {
+		set!(self, "placeholder", placeholder)
+	}
+
+	/// Set the minimum number of options which must be chosen.
+	pub fn min_values(self, min_values: u64) -> Self {
+		set!(self, "min_values", min_values)
+	}
+
+	/// Set the maximum number of options which may be chosen.
+	pub fn max_values(self, max_values: u64) -> Self {
+		set!(self, "max_values", max_values)
+	}
+
+	/// Set whether the select menu is disabled.
+	pub fn disabled(self, disabled: bool) -> Self {
+		set!(self, "disabled", disabled)
+	}
+
+	/// Add an option with the given user-facing `label` and developer-defined
+	/// `value`. A select menu may have up to 25 options.
+	///
+	/// See the `SelectOptionBuilder` struct for the editable fields.
+	pub fn option<F: FnOnce(SelectOptionBuilder) -> SelectOptionBuilder>(
+		mut self,
+		label: &str,
+		value: &str,
+		f: F,
+	) -> Self {
+		let mut map = SelectOptionBuilder::__build(f);
+		map.insert("label".into(), json!(label));
+		map.insert("value".into(), json!(value));
+		self.0
+			.entry("options")
+			.or_insert_with(|| Value::Array(Vec::new()))
+			.as_array_mut()
+			.expect("options is not an array")
+			.push(Value::Object(map));
+		self
+	}
+}
+
+impl SelectOptionBuilder {
+	/// Set an additional description of the option.
+	pub fn description(self, description: &str) -> Self {
+		set!(self, "description", description)
+	}
+
+	/// Set the emoji shown alongside the option.
+	pub fn emoji(self, emoji: ReactionEmoji) -> Self {
+		set!(self, "emoji", emoji)
+	}
+
+	/// Set whether the option is selected by default.
+	pub fn default(self, default: bool) -> Self {
+		set!(self, "default", default)
+	}
+}
+
+impl CreateCommand {
+	/// Set how the command is invoked. Defaults to `ChatInput`.
+	pub fn kind(self, kind: CommandType) -> Self {
+		set!(self, "type", kind)
+	}
+
+	/// Set the command's name in other locales, such as `"fr"` or `"pt-BR"`.
+	pub fn name_localizations(self, names: &BTreeMap<String, String>) -> Self {
+		set!(self, "name_localizations", names)
+	}
+
+	/// Set the command's description in other locales.
+	pub fn description_localizations(self, descriptions: &BTreeMap<String, String>) -> Self {
+		set!(self, "description_localizations", descriptions)
+	}
+
+	/// Set whether the command may be used in private channels. Only
+	/// meaningful for global commands.
+	pub fn dm_permission(self, allowed: bool) -> Self {
+		set!(self, "dm_permission", allowed)
+	}
+
+	/// Set the permissions a member needs to use the command by default.
+	pub fn default_member_permissions(self, permissions: Permissions) -> Self {
+		set!(self, "default_member_permissions", permissions.bits().to_string())
+	}
+
+	/// Add an option with the given name and description. A command may have
+	/// up to 25 options, and required options must be listed first.
+	///
+	/// See the `CommandOptionBuilder` struct for the editable fields.
+	pub fn option<F: FnOnce(CommandOptionBuilder) -> CommandOptionBuilder>(
+		mut self,
+		kind: CommandOptionType,
+		name: &str,
+		description: &str,
+		f: F,
+	) -> Self {
+		push_command_option(&mut self.0, kind, name, description, f);
+		self
+	}
+}
+
+impl CommandOptionBuilder {
+	/// Set the option's name in other locales.
+	pub fn name_localizations(self, names: &BTreeMap<String, String>) -> Self {
+		set!(self, "name_localizations", names)
+	}
+
+	/// Set the option's description in other locales.
+	pub fn description_localizations(self, descriptions: &BTreeMap<String, String>) -> Self {
+		set!(self, "description_localizations", descriptions)
+	}
+
+	/// Set whether the option must be given.
+	pub fn required(self, required: bool) -> Self {
+		set!(self, "required", required)
+	}
+
+	/// Add a nested option, for subcommands and subcommand groups.
+	pub fn option<F: FnOnce(CommandOptionBuilder) -> CommandOptionBuilder>(
+		mut self,
+		kind: CommandOptionType,
+		name: &str,
+		description: &str,
+		f: F,
+	) -> Self {
+		push_command_option(&mut self.0, kind, name, description, f);
+		self
+	}
+}
+
+fn push_command_option<F: FnOnce(CommandOptionBuilder) -> CommandOptionBuilder>(
+	map: &mut Object,
+	kind: CommandOptionType,
+	name: &str,
+	description: &str,
+	f: F,
+) {
+	let mut option = CommandOptionBuilder::__build(f);
+	option.insert("type".into(), json!(kind));
+	option.insert("name".into(), json!(name));
+	option.insert("description".into(), json!(description));
+	map.entry("options")
+		.or_insert_with(|| Value::Array(Vec::new()))
+		.as_array_mut()
+		.expect("options is not an array")
+		.push(Value::Object(option));
+}
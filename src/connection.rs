@@ -1,6 +1,9 @@
+use std::collections::BTreeMap;
 #[cfg(feature = "voice")]
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use websocket::client::{Client, Receiver, Sender};
 use websocket::stream::WebSocketStream;
@@ -10,10 +13,12 @@ use serde_json;
 use internal::Status;
 use model::*;
 #[cfg(feature = "voice")]
-use voice::VoiceConnection;
+use voice::{VoiceConnection, VoiceError};
 use {Error, ReceiverExt, Result, SenderExt};
 
 const GATEWAY_VERSION: u64 = 6;
+/// How long `try_recv_event` waits for data which has not yet arrived.
+const POLL_TIMEOUT: Duration = Duration::from_millis(1);
 
 #[cfg(feature = "voice")]
 macro_rules! finish_connection {
@@ -34,10 +39,133 @@ pub struct ConnectionBuilder<'a> {
 	base_url: String,
 	token: &'a str,
 
-	//large_threshold: Option<u32>,
-	shard: Option<[u8; 2]>,
+	large_threshold: Option<u64>,
+	guild_subscriptions: Option<bool>,
+	shard: Option<[u32; 2]>,
 	intents: Option<Intents>,
-	// TODO: presence
+	unknown_events: UnknownEvents,
+	reconnect_policy: ReconnectPolicy,
+	presence: Option<serde_json::Value>,
+	browser: Option<String>,
+	device: Option<String>,
+}
+
+/// What to do with the payloads of events this library does not recognize.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnknownEvents {
+	/// Return the full payload in `Event::Unknown`
+	Retain,
+	/// Return `Event::Unknown` with an empty payload, freeing it immediately
+	Discard,
+}
+
+/// Counts of how a `Connection` has recovered from lost connections, from
+/// `Connection::reconnect_stats`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReconnectStats {
+	/// Sessions resumed without missing any events
+	pub resumes: u64,
+	/// Attempts to resume which failed, leading to a full reconnect
+	pub failed_resumes: u64,
+	/// New sessions started after the first, each of which began with a
+	/// `Ready` event and may have missed events
+	pub reconnects: u64,
+}
+
+/// How a `Connection` waits between attempts to resume or reconnect after
+/// losing its connection to the gateway.
+///
+/// Before each attempt, the connection sleeps for `initial_delay`, multiplied
+/// by `multiplier` for every previous attempt and capped at `max_delay`, then
+/// shortened by up to the `jitter` fraction at random so that many clients do
+/// not reconnect in lockstep. A resume is tried first, once; if it fails, up
+/// to `max_attempts` full reconnects (but at least one) are made, the last of
+/// them to a freshly fetched gateway URL.
+///
+/// The default makes three attempts one second apart, with no backoff or
+/// jitter.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+	/// The delay before the first attempt
+	pub initial_delay: Duration,
+	/// The longest delay between attempts
+	pub max_delay: Duration,
+	/// The factor by which the delay grows after each attempt
+	pub multiplier: f64,
+	/// The fraction of each delay, from 0 to 1, which may be randomly skipped
+	pub jitter: f64,
+	/// The number of full reconnects to attempt before giving up
+	pub max_attempts: u32,
+}
+
+impl Default for ReconnectPolicy {
+	fn default() -> ReconnectPolicy {
+		ReconnectPolicy {
+			initial_delay: Duration::from_secs(1),
+			max_delay: Duration::from_secs(1),
+			multiplier: 1.0,
+			jitter: 0.0,
+			max_attempts: 3,
+		}
+	}
+}
+
+impl ReconnectPolicy {
+	/// Back off exponentially, doubling the delay from one second up to a
+	/// minute, with up to half of each delay skipped at random.
+	pub fn exponential(max_attempts: u32) -> ReconnectPolicy {
+		ReconnectPolicy {
+			initial_delay: Duration::from_secs(1),
+			max_delay: Duration::from_secs(60),
+			multiplier: 2.0,
+			jitter: 0.5,
+			max_attempts: max_attempts,
+		}
+	}
+
+	/// The delay before the given attempt, counting from zero.
+	fn delay(&self, attempt: u32) -> Duration {
+		let initial = duration_millis(self.initial_delay) as f64;
+		let max = duration_millis(self.max_delay) as f64;
+		let mut millis = (initial * self.multiplier.powi(attempt as i32)).min(max);
+		if self.jitter > 0.0 {
+			millis -= millis * self.jitter.min(1.0) * random_fraction();
+		}
+		Duration::from_millis(millis.max(0.0) as u64)
+	}
+
+	/// Make up to `max_attempts` attempts (but at least one) with `connect`,
+	/// which is told each attempt's number and whether it is the last, waiting
+	/// with `sleep` before each.
+	fn retry<T, S, C>(&self, mut sleep: S, mut connect: C) -> Result<T>
+	where
+		S: FnMut(Duration),
+		C: FnMut(u32, bool) -> Result<T>,
+	{
+		let attempts = ::std::cmp::max(self.max_attempts, 1);
+		let mut attempt = 0;
+		loop {
+			sleep(self.delay(attempt));
+			let last = attempt + 1 == attempts;
+			match connect(attempt, last) {
+				Err(e) if !last => debug!("Reconnect attempt {} failed: {:?}", attempt + 1, e),
+				result => return result,
+			}
+			attempt += 1;
+		}
+	}
+}
+
+fn duration_millis(duration: Duration) -> u64 {
+	duration.as_secs() * 1000 + u64::from(duration.subsec_nanos()) / 1_000_000
+}
+
+/// A random number between 0 and 1, without pulling in an RNG.
+fn random_fraction() -> f64 {
+	use std::collections::hash_map::RandomState;
+	use std::hash::{BuildHasher, Hasher};
+	let bits = RandomState::new().build_hasher().finish();
+	(bits >> 11) as f64 / (1u64 << 53) as f64
 }
 
 impl<'a> ConnectionBuilder<'a> {
@@ -45,16 +173,22 @@ impl<'a> ConnectionBuilder<'a> {
 		ConnectionBuilder {
 			base_url,
 			token,
-			//large_threshold: None,
+			large_threshold: None,
+			guild_subscriptions: None,
 			shard: None,
 			intents: None,
+			unknown_events: UnknownEvents::Retain,
+			reconnect_policy: ReconnectPolicy::default(),
+			presence: None,
+			browser: None,
+			device: None,
 		}
 	}
 
 	/// Connect to only a specific shard.
 	///
 	/// The `shard_id` is indexed at 0 while `total_shards` is indexed at 1.
-	pub fn with_shard(&mut self, shard_id: u8, total_shards: u8) -> &mut Self {
+	pub fn with_shard(&mut self, shard_id: u32, total_shards: u32) -> &mut Self {
 		self.shard = Some([shard_id, total_shards]);
 		self
 	}
@@ -64,21 +198,83 @@ impl<'a> ConnectionBuilder<'a> {
 		self
 	}
 
+	/// Set the member count above which a server is considered large.
+	///
+	/// Offline members of large servers are not sent with the server, and
+	/// must be requested separately. Must be between 50 and 250, the default.
+	pub fn with_large_threshold(&mut self, large_threshold: u64) -> &mut Self {
+		self.large_threshold = Some(large_threshold);
+		self
+	}
+
+	/// Set whether presence and typing events are sent for servers.
+	///
+	/// Disabling these subscriptions greatly reduces the event volume from
+	/// very large servers, at the cost of presences in the `State` becoming
+	/// stale. Defaults to true.
+	pub fn with_guild_subscriptions(&mut self, guild_subscriptions: bool) -> &mut Self {
+		self.guild_subscriptions = Some(guild_subscriptions);
+		self
+	}
+
+	/// Set whether the payloads of unrecognized events are kept.
+	///
+	/// Bots which ignore `Event::Unknown` may discard the payloads to reduce
+	/// memory use. The kinds of unknown events are counted either way; see
+	/// `Connection::unknown_event_counts`. Defaults to `Retain`.
+	pub fn with_unknown_events(&mut self, policy: UnknownEvents) -> &mut Self {
+		self.unknown_events = policy;
+		self
+	}
+
+	/// Set how to wait between attempts to resume or reconnect after the
+	/// connection is lost. See `ReconnectPolicy` for the default.
+	pub fn with_reconnect_policy(&mut self, policy: ReconnectPolicy) -> &mut Self {
+		self.reconnect_policy = policy;
+		self
+	}
+
+	/// Set the presence to show as soon as the connection is established,
+	/// rather than calling `Connection::set_presence` afterwards.
+	pub fn with_presence(
+		&mut self,
+		game: Option<Game>,
+		status: OnlineStatus,
+		afk: bool,
+	) -> &mut Self {
+		let activities: Vec<Game> = game.into_iter().collect();
+		self.presence = Some(presence_json(&activities, status, afk, None));
+		self
+	}
+
+	/// Set the browser and device names reported to Discord when identifying.
+	///
+	/// Default to "Discord library for Rust" and "discord-rs".
+	pub fn with_properties(&mut self, browser: &str, device: &str) -> &mut Self {
+		self.browser = Some(browser.to_owned());
+		self.device = Some(device.to_owned());
+		self
+	}
+
 	/// Establish a websocket connection over which events can be received.
 	///
 	/// Also returns the `ReadyEvent` sent by Discord upon establishing the
 	/// connection, which contains the initial state as seen by the client.
 	pub fn connect(&self) -> Result<(Connection, ReadyEvent)> {
+		let large_threshold = self.large_threshold.unwrap_or(250);
+		if !(50..=250).contains(&large_threshold) {
+			return Err(Error::OutOfRange("large_threshold", 50, 250));
+		}
 		let mut d = json! {{
 			"token": self.token,
 			"properties": {
 				"$os": ::std::env::consts::OS,
-				"$browser": "Discord library for Rust",
-				"$device": "discord-rs",
+				"$browser": self.browser.as_ref().map_or("Discord library for Rust", |s| &s[..]),
+				"$device": self.device.as_ref().map_or("discord-rs", |s| &s[..]),
 				"$referring_domain": "",
 				"$referrer": "",
 			},
-			"large_threshold": 250,
+			"large_threshold": large_threshold,
 			"compress": true,
 			"v": GATEWAY_VERSION,
 		}};
@@ -88,11 +284,23 @@ impl<'a> ConnectionBuilder<'a> {
 		if let Some(intents) = self.intents {
 			d["intents"] = intents.bits().into();
 		}
+		if let Some(guild_subscriptions) = self.guild_subscriptions {
+			d["guild_subscriptions"] = guild_subscriptions.into();
+		}
+		if let Some(ref presence) = self.presence {
+			d["presence"] = presence.clone();
+		}
 		let identify = json! {{
 			"op": 2,
 			"d": d
 		}};
-		Connection::__connect(&self.base_url, self.token.clone(), identify)
+		let unknown_events = self.unknown_events;
+		let reconnect_policy = self.reconnect_policy;
+		Connection::__connect(&self.base_url, self.token, identify).map(|(mut conn, ready)| {
+			conn.unknown_events = unknown_events;
+			conn.reconnect_policy = reconnect_policy;
+			(conn, ready)
+		})
 	}
 }
 
@@ -104,13 +312,31 @@ pub struct Connection {
 	voice_handles: HashMap<Option<ServerId>, VoiceConnection>,
 	#[cfg(feature = "voice")]
 	user_id: UserId,
+	#[cfg(feature = "voice")]
+	pending_events: VecDeque<Event>,
 	ws_url: String,
 	token: String,
 	session_id: Option<String>,
+	resume_url: Option<String>,
 	last_sequence: u64,
 	identify: serde_json::Value,
+	unknown_events: UnknownEvents,
+	unknown_counts: BTreeMap<String, u64>,
+	reconnect_policy: ReconnectPolicy,
+	shard: Option<[u32; 2]>,
+	heartbeat_sent: Arc<Mutex<Option<Instant>>>,
+	latency: Option<Duration>,
+	raw_hook: Option<RawHook>,
+	stats: ReconnectStats,
+	// whether `download_all_members` has been called, so members should be
+	// requested again after a reconnect
+	members_downloaded: bool,
+	// servers whose members are requested when they next become available
+	resync_servers: Vec<ServerId>,
 }
 
+type RawHook = Box<dyn FnMut(&str, &serde_json::Value) + Send>;
+
 impl Connection {
 	/// Establish a connection to the Discord websocket servers.
 	///
@@ -123,7 +349,7 @@ impl Connection {
 	pub fn new(
 		base_url: &str,
 		token: &str,
-		shard: Option<[u8; 2]>,
+		shard: Option<[u32; 2]>,
 	) -> Result<(Connection, ReadyEvent)> {
 		ConnectionBuilder { shard, .. ConnectionBuilder::new(base_url.to_owned(), token) }.connect()
 	}
@@ -137,7 +363,7 @@ impl Connection {
 		let (mut sender, mut receiver) = response.begin().split();
 
 		// send the handshake
-		sender.send_json(&identify)?;
+		sender.send_gateway(&identify)?;
 
 		// read the Hello and spawn the keepalive thread
 		let heartbeat_interval;
@@ -150,9 +376,13 @@ impl Connection {
 		}
 
 		let (tx, rx) = mpsc::channel();
-		::std::thread::Builder::new()
-			.name("Discord Keepalive".into())
-			.spawn(move || keepalive(heartbeat_interval, sender, rx))?;
+		let heartbeat_sent = Arc::new(Mutex::new(None));
+		{
+			let heartbeat_sent = heartbeat_sent.clone();
+			::std::thread::Builder::new()
+				.name("Discord Keepalive".into())
+				.spawn(move || keepalive(heartbeat_interval, sender, rx, heartbeat_sent))?;
+		}
 
 		// read the Ready event
 		let sequence;
@@ -196,6 +426,7 @@ impl Connection {
 			);
 		}
 		let session_id = ready.session_id.clone();
+		let resume_url = ready.resume_gateway_url.clone();
 
 		// return the connection
 		Ok((
@@ -205,11 +436,23 @@ impl Connection {
 				ws_url: base_url.to_owned(),
 				token: token.to_owned(),
 				session_id: Some(session_id),
+				resume_url: resume_url,
 				last_sequence: sequence,
-				identify: identify;
+				identify: identify,
+				unknown_events: UnknownEvents::Retain,
+				unknown_counts: BTreeMap::new(),
+				reconnect_policy: ReconnectPolicy::default(),
+				shard: ready.shard,
+				heartbeat_sent: heartbeat_sent,
+				latency: None,
+				raw_hook: None,
+				stats: ReconnectStats::default(),
+				members_downloaded: false,
+				resync_servers: Vec::new();
 				// voice only
 				user_id: ready.user.id,
 				voice_handles: HashMap::new(),
+				pending_events: VecDeque::new(),
 			),
 			ready,
 		))
@@ -231,27 +474,24 @@ impl Connection {
 	///
 	/// `afk` will help Discord determine where to send notifications.
 	pub fn set_presence(&self, game: Option<Game>, status: OnlineStatus, afk: bool) {
-		let status = match status {
-			OnlineStatus::Offline => OnlineStatus::Invisible,
-			other => other,
-		};
-		let game = match game {
-			Some(Game {
-				kind: GameType::Streaming,
-				url: Some(url),
-				name,
-			}) => json! {{ "type": GameType::Streaming, "url": url, "name": name }},
-			Some(game) => json! {{ "name": game.name, "type": GameType::Playing }},
-			None => json!(null),
-		};
+		let activities: Vec<Game> = game.into_iter().collect();
+		self.set_presence_ex(&activities, status, afk, None)
+	}
+
+	/// Sets the active presence of the client, with any number of activities.
+	///
+	/// `since` is the Unix time in milliseconds at which the client went idle,
+	/// if it has.
+	pub fn set_presence_ex(
+		&self,
+		activities: &[Game],
+		status: OnlineStatus,
+		afk: bool,
+		since: Option<u64>,
+	) {
 		let msg = json! {{
 			"op": 3,
-			"d": {
-				"afk": afk,
-				"since": 0,
-				"status": status,
-				"game": game,
-			}
+			"d": presence_json(activities, status, afk, since),
 		}};
 		let _ = self.keepalive_channel.send(Status::SendMessage(msg));
 	}
@@ -272,6 +512,44 @@ impl Connection {
 		})
 	}
 
+	/// Connect to a voice channel, blocking until the voice connection is
+	/// established or fails.
+	///
+	/// Gateway events received while waiting are queued and returned by
+	/// subsequent calls to `recv_event`. The `timeout` covers the whole wait,
+	/// including for Discord to assign a voice server; if it elapses,
+	/// `VoiceError::HandshakeTimeout` is returned.
+	///
+	/// Pass `None` to connect to group and one-on-one calls.
+	#[cfg(feature = "voice")]
+	pub fn connect_voice_and_wait(
+		&mut self,
+		server_id: Option<ServerId>,
+		channel_id: ChannelId,
+		timeout: Duration,
+	) -> Result<()> {
+		{
+			let voice = self.voice(server_id);
+			voice.__begin_wait();
+			voice.connect(channel_id);
+		}
+		let started = Instant::now();
+		while !self.voice(server_id).__is_connecting() {
+			let remaining = match timeout.checked_sub(started.elapsed()) {
+				Some(remaining) => remaining,
+				None => return Err(VoiceError::HandshakeTimeout.into()),
+			};
+			match self.next_event_timeout(remaining)? {
+				Some(event) => self.pending_events.push_back(event),
+				None => return Err(VoiceError::HandshakeTimeout.into()),
+			}
+		}
+		let remaining = timeout
+			.checked_sub(started.elapsed())
+			.unwrap_or(Duration::from_secs(0));
+		self.voice(server_id).__wait_result(remaining)
+	}
+
 	/// Drop the voice connection for a server, forgetting all settings.
 	///
 	/// Calling `.voice(server_id).disconnect()` will disconnect from voice but retain the mute
@@ -284,117 +562,390 @@ impl Connection {
 	}
 
 	/// Receive an event over the websocket, blocking until one is available.
+	///
+	/// If the connection is lost and cannot be resumed, a new session is
+	/// started and its `Event::Ready` is returned; any `State` should be
+	/// updated with it, as events may have been missed. `recv_event_into`
+	/// takes care of this.
+	///
+	/// If Discord closes the connection for a reason which reconnecting cannot
+	/// fix, such as an invalid token or disallowed intents,
+	/// `Error::GatewayClosed` is returned and the connection should be
+	/// abandoned.
 	pub fn recv_event(&mut self) -> Result<Event> {
+		#[cfg(feature = "voice")]
+		{
+			if let Some(event) = self.pending_events.pop_front() {
+				return Ok(event);
+			}
+		}
+		self.next_event()
+	}
+
+	/// Receive an event and apply it to a `State`, returning the event.
+	///
+	/// When a new session replaces a lost one, the state is rebuilt from its
+	/// `Ready` event. If `download_all_members` was called earlier, members of
+	/// large servers are requested again as those servers become available.
+	pub fn recv_event_into(&mut self, state: &mut ::State) -> Result<Event> {
+		let event = self.recv_event()?;
+		state.update(&event);
+		match event {
+			Event::Ready(ref ready) if self.members_downloaded => {
+				self.resync_servers = ready
+					.servers
+					.iter()
+					.map(|server| match *server {
+						PossibleServer::Online(ref server) => server.id,
+						PossibleServer::Offline(id) => id,
+					})
+					.collect();
+				self.request_resync_members(state);
+			}
+			Event::ServerCreate(PossibleServer::Online(ref server))
+				if self.resync_servers.contains(&server.id) =>
+			{
+				self.request_resync_members(state);
+			}
+			_ => {}
+		}
+		Ok(event)
+	}
+
+	/// Request the members of any large servers awaiting resynchronization
+	/// which are now available.
+	fn request_resync_members(&mut self, state: &mut ::State) {
+		let mut servers = Vec::new();
+		self.resync_servers.retain(|&id| match state.find_server(id) {
+			Some(server) => {
+				if server.large {
+					servers.push(id);
+				}
+				false
+			}
+			None => true,
+		});
+		if !servers.is_empty() {
+			let msg = json! {{
+				"op": 8,
+				"d": {
+					"guild_id": servers,
+					"query": "",
+					"limit": 0,
+				}
+			}};
+			let _ = self.keepalive_channel.send(Status::SendMessage(msg));
+		}
+	}
+
+	/// Get counts of how the connection has recovered from being lost.
+	pub fn reconnect_stats(&self) -> ReconnectStats {
+		self.stats
+	}
+
+	/// Receive an event if one is available, without blocking.
+	///
+	/// Returns `Ok(None)` if no event was waiting. Once part of an event has
+	/// arrived, this may block briefly until the rest of it is received.
+	pub fn try_recv_event(&mut self) -> Result<Option<Event>> {
+		#[cfg(feature = "voice")]
+		{
+			if let Some(event) = self.pending_events.pop_front() {
+				return Ok(Some(event));
+			}
+		}
+		loop {
+			if !self.wait_readable(None)? {
+				return Ok(None);
+			}
+			if let Some(event) = self.recv_one()? {
+				return Ok(Some(event));
+			}
+		}
+	}
+
+	/// Receive an event, blocking for at most the given duration.
+	///
+	/// Returns `Ok(None)` if no event arrived in time. Once part of an event
+	/// has arrived, this may block past the timeout until the rest of it is
+	/// received. Reconnecting after the connection is lost may also take
+	/// longer than the timeout.
+	pub fn recv_event_timeout(&mut self, timeout: Duration) -> Result<Option<Event>> {
+		#[cfg(feature = "voice")]
+		{
+			if let Some(event) = self.pending_events.pop_front() {
+				return Ok(Some(event));
+			}
+		}
+		self.next_event_timeout(timeout)
+	}
+
+	fn next_event_timeout(&mut self, timeout: Duration) -> Result<Option<Event>> {
+		let deadline = Instant::now() + timeout;
 		loop {
-			match self.receiver.recv_json(GatewayEvent::decode) {
-				Err(Error::WebSocket(err)) => {
-					warn!("Websocket error, reconnecting: {:?}", err);
-					// Try resuming if we haven't received an InvalidateSession
-					if let Some(session_id) = self.session_id.clone() {
-						match self.resume(session_id) {
-							Ok(event) => return Ok(event),
-							Err(e) => debug!("Failed to resume: {:?}", e),
+			let now = Instant::now();
+			if now >= deadline || !self.wait_readable(Some(deadline - now))? {
+				return Ok(None);
+			}
+			if let Some(event) = self.recv_one()? {
+				return Ok(Some(event));
+			}
+		}
+	}
+
+	/// Wait until data can be read from the websocket, or until the timeout
+	/// elapses. With no timeout, only check whether data is already waiting.
+	fn wait_readable(&mut self, timeout: Option<Duration>) -> Result<bool> {
+		use std::io::BufRead;
+
+		if !self.receiver.get_mut().buffer().is_empty() {
+			return Ok(true);
+		}
+		// a read timeout rather than non-blocking mode, which would also make
+		// the keepalive thread's writes to the same socket fail
+		let timeout = timeout.unwrap_or(POLL_TIMEOUT);
+		::set_read_timeout(self.receiver.get_mut().get_ref(), Some(timeout))?;
+		// filling the buffer consumes nothing, so no partial frame is lost
+		let result = self.receiver.get_mut().fill_buf().map(|_| ());
+		::set_read_timeout(self.receiver.get_mut().get_ref(), None)?;
+		match result {
+			Err(ref err) if ::is_timeout(err) => Ok(false),
+			// other errors, including closure, are handled by the next read
+			_ => Ok(true),
+		}
+	}
+
+	fn next_event(&mut self) -> Result<Event> {
+		loop {
+			if let Some(event) = self.recv_one()? {
+				return Ok(event);
+			}
+		}
+	}
+
+	/// Read one message from the gateway, returning the event it carried if
+	/// any.
+	fn recv_one(&mut self) -> Result<Option<Event>> {
+		let event = self.recv_gateway()?;
+		match event {
+			Some(Event::Resumed { .. }) => self.stats.resumes += 1,
+			// the first Ready is returned by `connect` instead
+			Some(Event::Ready(_)) => self.stats.reconnects += 1,
+			_ => {}
+		}
+		Ok(event)
+	}
+
+	fn recv_gateway(&mut self) -> Result<Option<Event>> {
+		let hook = &mut self.raw_hook;
+		match self.receiver.recv_json(|value| {
+			observe(hook, &value);
+			GatewayEvent::decode(value)
+		}) {
+			Err(Error::WebSocket(err)) => {
+				warn!("Websocket error, reconnecting: {:?}", err);
+				// Try resuming if we haven't received an InvalidateSession
+				if let Some(session_id) = self.session_id.clone() {
+					match self.resume(session_id) {
+						Ok(event) => return Ok(Some(event)),
+						Err(e) => {
+							debug!("Failed to resume: {:?}", e);
+							self.stats.failed_resumes += 1;
 						}
 					}
-					// If resuming didn't work, reconnect
-					return self.reconnect().map(Event::Ready);
 				}
-				Err(Error::Closed(num, message)) => {
-					debug!("Closure, reconnecting: {:?}: {}", num, message);
-					// Try resuming if we haven't received a 4006 or an InvalidateSession
-					if num != Some(4006) {
-						if let Some(session_id) = self.session_id.clone() {
-							match self.resume(session_id) {
-								Ok(event) => return Ok(event),
-								Err(e) => debug!("Failed to resume: {:?}", e),
-							}
+				// If resuming didn't work, reconnect
+				return self.reconnect().map(|ready| Some(Event::Ready(ready)));
+			}
+			Err(Error::Closed(num, message)) => {
+				if let Recovery::Resume(session_id) =
+					recovery_after_close(num, message, &self.session_id)?
+				{
+					match self.resume(session_id) {
+						Ok(event) => return Ok(Some(event)),
+						Err(e) => {
+							debug!("Failed to resume: {:?}", e);
+							self.stats.failed_resumes += 1;
 						}
 					}
-					// If resuming didn't work, reconnect
-					return self.reconnect().map(Event::Ready);
 				}
-				Err(error) => return Err(error),
-				Ok(GatewayEvent::Hello(interval)) => {
-					debug!("Mysterious late-game hello: {}", interval);
+				// If resuming didn't work, reconnect
+				return self.reconnect().map(|ready| Some(Event::Ready(ready)));
+			}
+			Err(error) => return Err(error),
+			Ok(GatewayEvent::Hello(interval)) => {
+				debug!("Mysterious late-game hello: {}", interval);
+			}
+			Ok(GatewayEvent::Dispatch(sequence, mut event)) => {
+				self.last_sequence = sequence;
+				if let Event::Unknown(ref kind, ref mut payload) = event {
+					*self.unknown_counts.entry(kind.clone()).or_insert(0) += 1;
+					if self.unknown_events == UnknownEvents::Discard {
+						*payload = Default::default();
+					}
 				}
-				Ok(GatewayEvent::Dispatch(sequence, event)) => {
-					self.last_sequence = sequence;
-					let _ = self.keepalive_channel.send(Status::Sequence(sequence));
-					#[cfg(feature = "voice")]
+				let _ = self.keepalive_channel.send(Status::Sequence(sequence));
+				#[cfg(feature = "voice")]
+				{
+					if let Event::VoiceStateUpdate(server_id, ref voice_state) = event {
+						self.voice(server_id).__update_state(voice_state);
+					}
+					if let Event::VoiceServerUpdate {
+						server_id,
+						ref endpoint,
+						ref token,
+						..
+					} = event
 					{
-						if let Event::VoiceStateUpdate(server_id, ref voice_state) = event {
-							self.voice(server_id).__update_state(voice_state);
-						}
-						if let Event::VoiceServerUpdate {
-							server_id,
-							ref endpoint,
-							ref token,
-							..
-						} = event
-						{
-							self.voice(server_id).__update_server(endpoint, token);
-						}
+						self.voice(server_id).__update_server(endpoint, token);
 					}
-					return Ok(event);
 				}
-				Ok(GatewayEvent::Heartbeat(sequence)) => {
-					debug!("Heartbeat received with seq {}", sequence);
-					let map = json! {{
-						"op": 1,
-						"d": sequence,
-					}};
-					let _ = self.keepalive_channel.send(Status::SendMessage(map));
-				}
-				Ok(GatewayEvent::HeartbeatAck) => {}
-				Ok(GatewayEvent::Reconnect) => {
-					return self.reconnect().map(Event::Ready);
-				}
-				Ok(GatewayEvent::InvalidateSession) => {
-					debug!("Session invalidated, reidentifying");
-					self.session_id = None;
-					let _ = self
-						.keepalive_channel
-						.send(Status::SendMessage(self.identify.clone()));
+				return Ok(Some(event));
+			}
+			Ok(GatewayEvent::Heartbeat(sequence)) => {
+				debug!("Heartbeat received with seq {}", sequence);
+				let map = json! {{
+					"op": 1,
+					"d": sequence,
+				}};
+				let _ = self.keepalive_channel.send(Status::SendMessage(map));
+			}
+			Ok(GatewayEvent::HeartbeatAck) => {
+				let sent = self.heartbeat_sent.lock().ok().and_then(|mut sent| sent.take());
+				if let Some(sent) = sent {
+					self.latency = Some(sent.elapsed());
 				}
 			}
+			Ok(GatewayEvent::Reconnect) => {
+				return self.reconnect().map(|ready| Some(Event::Ready(ready)));
+			}
+			Ok(GatewayEvent::InvalidateSession) => {
+				debug!("Session invalidated, reidentifying");
+				self.session_id = None;
+				let _ = self
+					.keepalive_channel
+					.send(Status::SendMessage(self.identify.clone()));
+			}
+		}
+		Ok(None)
+	}
+
+	/// Move the connection's read loop to a background thread, from which
+	/// events are handed out to any number of subscribers.
+	///
+	/// Use `Dispatcher::stop` to get the connection back.
+	pub fn into_dispatcher(self) -> Result<::Dispatcher> {
+		::dispatch::Dispatcher::start(self)
+	}
+
+	/// Move the connection to a background thread and receive its events as
+	/// an asynchronous `Stream`, for use from async code without blocking.
+	///
+	/// Requires the `futures-bridge` feature.
+	#[cfg(feature = "futures-bridge")]
+	pub fn into_stream(self) -> Result<::EventStream> {
+		::stream::EventStream::start(self)
+	}
+
+	/// Get the shard this connection serves, as the shard id and the total
+	/// number of shards, or `None` if the connection is not sharded.
+	///
+	/// Useful for attributing events to shards when running several
+	/// connections at once.
+	#[inline]
+	pub fn shard_id(&self) -> Option<[u32; 2]> {
+		self.shard
+	}
+
+	/// Get the time between sending the most recent heartbeat and receiving
+	/// its acknowledgement, or `None` if no heartbeat has been acknowledged
+	/// yet on the current connection.
+	///
+	/// The acknowledgement is only seen once `recv_event` reads it, so a
+	/// program which is slow to receive events will see a higher latency.
+	pub fn latency(&self) -> Option<Duration> {
+		self.latency
+	}
+
+	/// Pass the kind and data of every event payload to `hook` before it is
+	/// decoded, replacing any previous hook.
+	///
+	/// The hook sees events which fail to decode or are not recognized, and
+	/// runs before `recv_event` returns, so it should be quick. Events
+	/// received while connecting, including the first `ReadyEvent`, are not
+	/// seen.
+	pub fn set_raw_event_hook<F>(&mut self, hook: F)
+	where
+		F: FnMut(&str, &serde_json::Value) + Send + 'static,
+	{
+		self.raw_hook = Some(Box::new(hook));
+	}
+
+	/// Remove the hook set by `set_raw_event_hook`.
+	pub fn clear_raw_event_hook(&mut self) {
+		self.raw_hook = None;
+	}
+
+	/// Get the number of events of each unrecognized kind received so far.
+	pub fn unknown_event_counts(&self) -> &BTreeMap<String, u64> {
+		&self.unknown_counts
+	}
+
+	/// Switch to a freshly established connection, keeping our settings
+	fn switch_to(&mut self, conn: Connection) {
+		let unknown_events = self.unknown_events;
+		let unknown_counts = ::std::mem::replace(&mut self.unknown_counts, BTreeMap::new());
+		let reconnect_policy = self.reconnect_policy;
+		let raw_hook = self.raw_hook.take();
+		let resync_servers = ::std::mem::replace(&mut self.resync_servers, Vec::new());
+		#[cfg(feature = "voice")]
+		let pending_events = ::std::mem::replace(&mut self.pending_events, VecDeque::new());
+		let old = ::std::mem::replace(self, conn);
+		self.stats = old.stats;
+		self.members_downloaded = old.members_downloaded;
+		old.raw_shutdown();
+		self.raw_hook = raw_hook;
+		self.resync_servers = resync_servers;
+		self.unknown_events = unknown_events;
+		self.unknown_counts = unknown_counts;
+		self.reconnect_policy = reconnect_policy;
+		#[cfg(feature = "voice")]
+		{
+			self.pending_events = pending_events;
 		}
 	}
 
 	/// Reconnect after receiving an OP7 RECONNECT
 	fn reconnect(&mut self) -> Result<ReadyEvent> {
-		::sleep_ms(1000);
 		self.keepalive_channel
 			.send(Status::Aborted)
 			.expect("Could not stop the keepalive thread, there will be a thread leak.");
 		trace!("Reconnecting...");
-		// Make two attempts on the current known gateway URL
-		for _ in 0..2 {
-			if let Ok((conn, ready)) = Connection::__connect(&self.ws_url, &self.token, self.identify.clone()) {
-				::std::mem::replace(self, conn).raw_shutdown();
-				self.session_id = Some(ready.session_id.clone());
-				return Ok(ready);
+		let (conn, ready) = self.reconnect_policy.retry(::std::thread::sleep, |_, last| {
+			// Make all but the last attempt on the current known gateway URL;
+			// if those fail, hit REST for a new endpoint
+			if last {
+				let url = ::Discord::from_token_raw(self.token.to_owned()).get_gateway_url()?;
+				Connection::__connect(&url, &self.token, self.identify.clone())
+			} else {
+				Connection::__connect(&self.ws_url, &self.token, self.identify.clone())
 			}
-			::sleep_ms(1000);
-		}
-
-		// If those fail, hit REST for a new endpoint
-		let url = ::Discord::from_token_raw(self.token.to_owned()).get_gateway_url()?;
-		let (conn, ready) = Connection::__connect(&url, &self.token, self.identify.clone())?;
-		::std::mem::replace(self, conn).raw_shutdown();
+		})?;
+		self.switch_to(conn);
 		self.session_id = Some(ready.session_id.clone());
 		Ok(ready)
 	}
 
 	/// Resume using our existing session
 	fn resume(&mut self, session_id: String) -> Result<Event> {
-		::sleep_ms(1000);
+		::std::thread::sleep(self.reconnect_policy.delay(0));
 		trace!("Resuming...");
 		// close connection and re-establish
 		self.receiver
 			.get_mut()
 			.get_mut()
 			.shutdown(::std::net::Shutdown::Both)?;
-		let url = build_gateway_url(&self.ws_url)?;
+		let url = build_gateway_url(self.resume_url.as_ref().unwrap_or(&self.ws_url))?;
 		let response = Client::connect(url)?.send()?;
 		response.validate()?;
 		let (mut sender, mut receiver) = response.begin().split();
@@ -408,12 +959,16 @@ impl Connection {
 				"session_id": session_id,
 			}
 		}};
-		sender.send_json(&resume)?;
+		sender.send_gateway(&resume)?;
 
 		// TODO: when Discord has implemented it, observe the RESUMING event here
 		let first_event;
 		loop {
-			match receiver.recv_json(GatewayEvent::decode)? {
+			let hook = &mut self.raw_hook;
+			match receiver.recv_json(|value| {
+				observe(hook, &value);
+				GatewayEvent::decode(value)
+			})? {
 				GatewayEvent::Hello(interval) => {
 					let _ = self
 						.keepalive_channel
@@ -423,8 +978,9 @@ impl Connection {
 					if let Event::Resumed { .. } = event {
 						trace!("Resumed successfully");
 					}
-					if let Event::Ready(ReadyEvent { ref session_id, .. }) = event {
-						self.session_id = Some(session_id.clone());
+					if let Event::Ready(ref ready) = event {
+						self.session_id = Some(ready.session_id.clone());
+						self.resume_url = ready.resume_gateway_url.clone();
 					}
 					self.last_sequence = seq;
 					first_event = event;
@@ -432,7 +988,7 @@ impl Connection {
 				}
 				GatewayEvent::InvalidateSession => {
 					debug!("Session invalidated in resume, reidentifying");
-					sender.send_json(&self.identify)?;
+					sender.send_gateway(&self.identify)?;
 				}
 				other => {
 					debug!("Unexpected event: {:?}", other);
@@ -510,11 +1066,26 @@ impl Connection {
 		}
 	}
 
+	/// Send a payload with the given opcode and data to the gateway as-is.
+	///
+	/// Useful for opcodes which this library does not yet support. Any
+	/// replies arrive through `recv_event`, as `Event::Unknown` if they are
+	/// not recognized. Heartbeats, identifies, and resumes are handled by the
+	/// connection and should not be sent this way.
+	pub fn send_raw(&self, op: u64, d: serde_json::Value) {
+		let msg = json! {{
+			"op": op,
+			"d": d,
+		}};
+		let _ = self.keepalive_channel.send(Status::SendMessage(msg));
+	}
+
 	/// Requests a download of all member information for large servers.
 	///
 	/// The members lists are cleared on call, and then refilled as chunks are received. When
 	/// `unknown_members()` returns 0, the download has completed.
 	pub fn download_all_members(&mut self, state: &mut ::State) {
+		self.members_downloaded = true;
 		if state.unknown_members() == 0 {
 			return;
 		}
@@ -529,6 +1100,43 @@ impl Connection {
 		}};
 		let _ = self.keepalive_channel.send(Status::SendMessage(msg));
 	}
+
+	/// Requests members of a server, which arrive as `ServerMembersChunk`
+	/// events.
+	///
+	/// If `user_ids` is empty, requests up to `limit` members whose usernames
+	/// start with `query`, where an empty query and a limit of zero request
+	/// every member. Otherwise, requests the members with the given IDs.
+	/// `presences` asks for the members' presences to be included. The `nonce`
+	/// is echoed in the resulting chunks to tell requests apart.
+	pub fn request_members(
+		&self,
+		server: ServerId,
+		query: &str,
+		limit: u32,
+		user_ids: &[UserId],
+		presences: bool,
+		nonce: Option<&str>,
+	) {
+		let mut d = json! {{
+			"guild_id": server,
+			"limit": limit,
+			"presences": presences,
+		}};
+		if user_ids.is_empty() {
+			d["query"] = query.into();
+		} else {
+			d["user_ids"] = json!(user_ids);
+		}
+		if let Some(nonce) = nonce {
+			d["nonce"] = nonce.into();
+		}
+		let msg = json! {{
+			"op": 8,
+			"d": d,
+		}};
+		let _ = self.keepalive_channel.send(Status::SendMessage(msg));
+	}
 }
 
 impl Drop for Connection {
@@ -538,13 +1146,120 @@ impl Drop for Connection {
 	}
 }
 
+/// How to carry on after the gateway closes the connection.
+pub(crate) enum Recovery {
+	/// Resume the given session.
+	Resume(String),
+	/// Open a fresh connection and identify.
+	Reconnect,
+}
+
+/// Decide how to carry on after the gateway closes the connection with the
+/// given status code and message, while holding the given session, or fail
+/// with `Error::GatewayClosed` if the close code forbids reconnecting.
+pub(crate) fn recovery_after_close(
+	num: Option<u16>,
+	message: String,
+	session_id: &Option<String>,
+) -> Result<Recovery> {
+	let code = num.and_then(|num| CloseCode::from_num(num as u64));
+	if let Some(code) = code {
+		if !code.can_reconnect() {
+			return Err(Error::GatewayClosed(code, message));
+		}
+	}
+	debug!("Closure, reconnecting: {:?}: {}", num, message);
+	// Try resuming if the close code and lack of InvalidateSession allow
+	match *session_id {
+		Some(ref session_id) if code.map_or(true, CloseCode::can_resume) => {
+			Ok(Recovery::Resume(session_id.clone()))
+		}
+		_ => Ok(Recovery::Reconnect),
+	}
+}
+
+/// Build the presence payload sent by `set_presence` and when identifying.
+fn presence_json(
+	activities: &[Game],
+	status: OnlineStatus,
+	afk: bool,
+	since: Option<u64>,
+) -> serde_json::Value {
+	let status = match status {
+		OnlineStatus::Offline => OnlineStatus::Invisible,
+		other => other,
+	};
+	let activities: Vec<_> = activities.iter().map(activity_json).collect();
+	json! {{
+		"afk": afk,
+		"since": since,
+		"status": status,
+		// older clients only show the first activity
+		"game": activities.first(),
+		"activities": activities,
+	}}
+}
+
+fn activity_json(game: &Game) -> serde_json::Value {
+	// a stream without a URL is shown as an ordinary game
+	let kind = match game.kind {
+		GameType::Streaming if game.url.is_none() => GameType::Playing,
+		kind => kind,
+	};
+	let mut activity = json! {{
+		"name": game.name,
+		"type": kind,
+	}};
+	if kind == GameType::Streaming {
+		activity["url"] = json!(game.url);
+	}
+	if let Some(ref state) = game.state {
+		activity["state"] = json!(state);
+	}
+	if let Some(ref emoji) = game.emoji {
+		activity["emoji"] = json!(emoji);
+	}
+	activity
+}
+
+/// Pass a dispatch payload to the raw event hook, if there is one.
+fn observe(hook: &mut Option<RawHook>, value: &serde_json::Value) {
+	if let Some(hook) = hook.as_mut() {
+		if let Some(kind) = value.get("t").and_then(|t| t.as_str()) {
+			hook(kind, value.get("d").unwrap_or(&serde_json::Value::Null));
+		}
+	}
+}
+
+#[cfg(not(feature = "etf"))]
+const GATEWAY_ENCODING: &'static str = "json";
+#[cfg(feature = "etf")]
+const GATEWAY_ENCODING: &'static str = "etf";
+
 #[inline]
 fn build_gateway_url(base: &str) -> Result<::websocket::client::request::Url> {
-	::websocket::client::request::Url::parse(&format!("{}?v={}", base, GATEWAY_VERSION))
+	::websocket::client::request::Url::parse(&format!(
+		"{}?v={}&encoding={}",
+		base, GATEWAY_VERSION, GATEWAY_ENCODING
+	))
 		.map_err(|_| Error::Other("Invalid gateway URL"))
 }
 
-fn keepalive(interval: u64, mut sender: Sender<WebSocketStream>, channel: mpsc::Receiver<Status>) {
+/// Whether an error from `recv_event` means the connection cannot continue,
+/// rather than a single event failing to decode.
+pub(crate) fn is_fatal(err: &Error) -> bool {
+	match *err {
+		Error::Json(_) | Error::Decode(..) | Error::Chrono(_) => false,
+		_ => true,
+	}
+}
+
+fn keepalive(
+	interval: u64,
+	mut sender: Sender<WebSocketStream>,
+	channel: mpsc::Receiver<Status>,
+	heartbeat_sent: Arc<Mutex<Option<Instant>>>,
+) {
 	let mut timer = ::Timer::new(interval);
 	let mut last_sequence = 0;
 
@@ -553,7 +1268,7 @@ fn keepalive(interval: u64, mut sender: Sender<WebSocketStream>, channel: mpsc::
 
 		loop {
 			match channel.try_recv() {
-				Ok(Status::SendMessage(val)) => match sender.send_json(&val) {
+				Ok(Status::SendMessage(val)) => match sender.send_gateway(&val) {
 					Ok(()) => {}
 					Err(e) => warn!("Error sending gateway message: {:?}", e),
 				},
@@ -577,11 +1292,57 @@ fn keepalive(interval: u64, mut sender: Sender<WebSocketStream>, channel: mpsc::
 				"op": 1,
 				"d": last_sequence
 			}};
-			match sender.send_json(&map) {
-				Ok(()) => {}
+			match sender.send_gateway(&map) {
+				Ok(()) => {
+					if let Ok(mut sent) = heartbeat_sent.lock() {
+						*sent = Some(Instant::now());
+					}
+				}
 				Err(e) => warn!("Error sending gateway keeaplive: {:?}", e),
 			}
 		}
 	}
 	let _ = sender.get_mut().shutdown(::std::net::Shutdown::Both);
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_reconnect_policy() {
+		let mut policy = ReconnectPolicy::exponential(8);
+		policy.jitter = 0.0;
+		let mut sleeps = Vec::new();
+		let mut attempts = Vec::new();
+		let result: Result<()> = policy.retry(
+			|delay| sleeps.push(duration_millis(delay)),
+			|attempt, last| {
+				attempts.push((attempt, last));
+				Err(Error::Protocol("refused"))
+			},
+		);
+		assert!(result.is_err());
+		assert_eq!(sleeps, [1000, 2000, 4000, 8000, 16000, 32000, 60000, 60000]);
+		assert_eq!(attempts.len(), 8);
+		assert!(attempts[..7].iter().all(|&(_, last)| !last));
+		assert_eq!(attempts[7], (7, true));
+
+		// at least one attempt is made, which is the last
+		policy.max_attempts = 0;
+		let mut attempts = Vec::new();
+		let result = policy.retry(|_| (), |attempt, last| {
+			attempts.push((attempt, last));
+			Ok(())
+		});
+		assert!(result.is_ok());
+		assert_eq!(attempts, [(0, true)]);
+
+		// jitter only ever shortens a delay
+		let policy = ReconnectPolicy::exponential(3);
+		for _ in 0..20 {
+			let millis = duration_millis(policy.delay(1));
+			assert!((1000..=2000).contains(&millis), "{}", millis);
+		}
+	}
+}
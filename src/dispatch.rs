@@ -0,0 +1,167 @@
+//! Delivery of a connection's events to several consumers.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use connection::{is_fatal, Connection};
+use model::Event;
+use Result;
+
+/// How often the read loop checks whether it has been asked to stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+type Filter = Box<dyn Fn(&Event) -> bool + Send>;
+type Subscriber = (Filter, mpsc::Sender<Event>);
+
+/// Runs a `Connection`'s read loop on a background thread, handing a copy of
+/// each event to every subscriber interested in it.
+///
+/// Obtained from `Connection::into_dispatcher`. Subscribers whose receivers
+/// have been dropped are forgotten. If the connection fails in a way it
+/// cannot recover from, the thread stops and all receivers are disconnected.
+///
+/// ```ignore
+/// let dispatcher = connection.into_dispatcher()?;
+/// let messages = dispatcher.subscribe_filtered(|event| match *event {
+///     Event::MessageCreate(_) => true,
+///     _ => false,
+/// });
+/// std::thread::spawn(move || for event in messages { /* ... */ });
+/// ```
+pub struct Dispatcher {
+	subscribers: Arc<Mutex<Vec<Subscriber>>>,
+	stop: Arc<AtomicBool>,
+	thread: Option<JoinHandle<Connection>>,
+}
+
+impl Dispatcher {
+	pub(crate) fn start(mut connection: Connection) -> Result<Dispatcher> {
+		let subscribers = Arc::new(Mutex::new(Vec::<Subscriber>::new()));
+		let stop = Arc::new(AtomicBool::new(false));
+		let thread = {
+			let subscribers = subscribers.clone();
+			let stop = stop.clone();
+			::std::thread::Builder::new()
+				.name("Discord Dispatcher".into())
+				.spawn(move || {
+					while !stop.load(Ordering::SeqCst) {
+						let event = match connection.recv_event_timeout(POLL_INTERVAL) {
+							Ok(Some(event)) => event,
+							Ok(None) => continue,
+							Err(err) => {
+								warn!("Error in dispatcher: {:?}", err);
+								if is_fatal(&err) {
+									subscribers.lock().expect("Subscribers poisoned").clear();
+									break;
+								}
+								continue;
+							}
+						};
+						deliver(&mut subscribers.lock().expect("Subscribers poisoned"), &event);
+					}
+					connection
+				})?
+		};
+		Ok(Dispatcher {
+			subscribers: subscribers,
+			stop: stop,
+			thread: Some(thread),
+		})
+	}
+
+	/// Receive every event from now on.
+	pub fn subscribe(&self) -> mpsc::Receiver<Event> {
+		self.subscribe_filtered(|_| true)
+	}
+
+	/// Receive the events from now on for which `filter` returns true.
+	///
+	/// The filter runs on the dispatcher's thread, so it should be quick.
+	pub fn subscribe_filtered<F>(&self, filter: F) -> mpsc::Receiver<Event>
+	where
+		F: Fn(&Event) -> bool + Send + 'static,
+	{
+		let (tx, rx) = mpsc::channel();
+		self.subscribers
+			.lock()
+			.expect("Subscribers poisoned")
+			.push((Box::new(filter), tx));
+		rx
+	}
+
+	/// Stop the read loop and take back the connection.
+	///
+	/// All receivers are disconnected once any events already sent to them
+	/// have been received.
+	pub fn stop(mut self) -> Connection {
+		self.stop_thread().expect("Dispatcher thread panicked")
+	}
+
+	fn stop_thread(&mut self) -> Option<Connection> {
+		self.stop.store(true, Ordering::SeqCst);
+		self.subscribers.lock().map(|mut s| s.clear()).ok();
+		self.thread.take().and_then(|thread| thread.join().ok())
+	}
+}
+
+impl Drop for Dispatcher {
+	fn drop(&mut self) {
+		self.stop_thread();
+	}
+}
+
+/// Send an event to each subscriber interested in it, forgetting those whose
+/// receivers have been dropped.
+fn deliver(subscribers: &mut Vec<Subscriber>, event: &Event) {
+	subscribers.retain(|&(ref filter, ref sender)| {
+		!filter(event) || sender.send(event.clone()).is_ok()
+	});
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use model::{ChannelId, UserId};
+
+	fn subscriber<F>(filter: F) -> (Subscriber, mpsc::Receiver<Event>)
+	where
+		F: Fn(&Event) -> bool + Send + 'static,
+	{
+		let (tx, rx) = mpsc::channel();
+		((Box::new(filter), tx), rx)
+	}
+
+	#[test]
+	fn test_deliver() {
+		let resumed = Event::Resumed { trace: vec![] };
+		let typing = Event::TypingStart {
+			channel_id: ChannelId(1),
+			user_id: UserId(2),
+			timestamp: ::chrono::Utc::now(),
+		};
+		let (all, all_rx) = subscriber(|_| true);
+		let (typists, typists_rx) = subscriber(|event| match *event {
+			Event::TypingStart { .. } => true,
+			_ => false,
+		});
+		let (gone, gone_rx) = subscriber(|_| true);
+		drop(gone_rx);
+		let mut subscribers = vec![all, typists, gone];
+
+		deliver(&mut subscribers, &resumed);
+		assert_eq!(subscribers.len(), 2);
+		deliver(&mut subscribers, &typing);
+		assert_eq!(all_rx.try_iter().count(), 2);
+		assert_eq!(typists_rx.try_iter().count(), 1);
+
+		// subscribers are only forgotten once an event for them goes undelivered
+		drop(typists_rx);
+		deliver(&mut subscribers, &resumed);
+		assert_eq!(subscribers.len(), 2);
+		deliver(&mut subscribers, &typing);
+		assert_eq!(subscribers.len(), 1);
+	}
+}
@@ -0,0 +1,310 @@
+//! Conversion between JSON values and Erlang's External Term Format, which
+//! the gateway can send and receive in place of JSON.
+//!
+//! Terms are mapped the way Discord's erlpack does: maps to objects, lists
+//! and tuples to arrays, binaries to strings, and the atoms `nil`, `true`, and
+//! `false` to null and booleans. Other atoms become strings.
+
+use serde_json::{Map, Number, Value};
+
+use {Error, Result};
+
+/// The version byte which begins every External Term Format payload.
+pub const VERSION: u8 = 131;
+
+const NEW_FLOAT_EXT: u8 = 70;
+const SMALL_INTEGER_EXT: u8 = 97;
+const INTEGER_EXT: u8 = 98;
+const FLOAT_EXT: u8 = 99;
+const ATOM_EXT: u8 = 100;
+const SMALL_TUPLE_EXT: u8 = 104;
+const LARGE_TUPLE_EXT: u8 = 105;
+const NIL_EXT: u8 = 106;
+const STRING_EXT: u8 = 107;
+const LIST_EXT: u8 = 108;
+const BINARY_EXT: u8 = 109;
+const SMALL_BIG_EXT: u8 = 110;
+const LARGE_BIG_EXT: u8 = 111;
+const SMALL_ATOM_EXT: u8 = 115;
+const MAP_EXT: u8 = 116;
+const ATOM_UTF8_EXT: u8 = 118;
+const SMALL_ATOM_UTF8_EXT: u8 = 119;
+
+/// Decode a complete payload, including its version byte.
+pub fn decode(payload: &[u8]) -> Result<Value> {
+	if payload.first() != Some(&VERSION) {
+		return Err(Error::Protocol("ETF payload has the wrong version"));
+	}
+	let mut decoder = Decoder { data: &payload[1..] };
+	let value = decoder.term()?;
+	if !decoder.data.is_empty() {
+		return Err(Error::Protocol("ETF payload has trailing data"));
+	}
+	Ok(value)
+}
+
+/// Encode a payload, including its version byte.
+pub fn encode(value: &Value) -> Vec<u8> {
+	let mut buf = vec![VERSION];
+	encode_term(&mut buf, value);
+	buf
+}
+
+struct Decoder<'a> {
+	data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+	fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+		if self.data.len() < len {
+			return Err(Error::Protocol("ETF payload ended unexpectedly"));
+		}
+		let (head, tail) = self.data.split_at(len);
+		self.data = tail;
+		Ok(head)
+	}
+
+	fn u8(&mut self) -> Result<u8> {
+		Ok(self.take(1)?[0])
+	}
+
+	fn u16(&mut self) -> Result<usize> {
+		let bytes = self.take(2)?;
+		Ok((bytes[0] as usize) << 8 | bytes[1] as usize)
+	}
+
+	fn u32(&mut self) -> Result<usize> {
+		let bytes = self.take(4)?;
+		Ok(bytes.iter().fold(0, |acc, &b| acc << 8 | b as usize))
+	}
+
+	fn string(&mut self, len: usize) -> Result<String> {
+		Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+	}
+
+	fn term(&mut self) -> Result<Value> {
+		match self.u8()? {
+			SMALL_INTEGER_EXT => Ok(Value::from(self.u8()?)),
+			INTEGER_EXT => {
+				let bits = self.u32()? as u32;
+				Ok(Value::from(bits as i32))
+			}
+			NEW_FLOAT_EXT => {
+				let bits = self.take(8)?.iter().fold(0u64, |acc, &b| acc << 8 | b as u64);
+				float(f64::from_bits(bits))
+			}
+			FLOAT_EXT => {
+				let text = self.string(31)?;
+				match text.trim_end_matches('\0').trim().parse() {
+					Ok(f) => float(f),
+					Err(_) => Err(Error::Protocol("ETF payload has an invalid float")),
+				}
+			}
+			ATOM_EXT | ATOM_UTF8_EXT => {
+				let len = self.u16()?;
+				self.string(len).map(atom)
+			}
+			SMALL_ATOM_EXT | SMALL_ATOM_UTF8_EXT => {
+				let len = self.u8()? as usize;
+				self.string(len).map(atom)
+			}
+			SMALL_TUPLE_EXT => {
+				let len = self.u8()? as usize;
+				self.array(len)
+			}
+			LARGE_TUPLE_EXT => {
+				let len = self.u32()?;
+				self.array(len)
+			}
+			NIL_EXT => Ok(Value::Array(Vec::new())),
+			STRING_EXT => {
+				// a list of small integers, packed one per byte
+				let len = self.u16()?;
+				Ok(Value::Array(self.take(len)?.iter().map(|&b| Value::from(b)).collect()))
+			}
+			LIST_EXT => {
+				let len = self.u32()?;
+				let list = self.array(len)?;
+				// proper lists end with an empty list, which is dropped
+				match self.term()? {
+					Value::Array(ref tail) if tail.is_empty() => Ok(list),
+					_ => Err(Error::Protocol("ETF payload has an improper list")),
+				}
+			}
+			BINARY_EXT => {
+				let len = self.u32()?;
+				self.string(len).map(Value::String)
+			}
+			SMALL_BIG_EXT => {
+				let len = self.u8()? as usize;
+				self.big(len)
+			}
+			LARGE_BIG_EXT => {
+				let len = self.u32()?;
+				self.big(len)
+			}
+			MAP_EXT => {
+				let len = self.u32()?;
+				let mut map = Map::new();
+				for _ in 0..len {
+					let key = match self.term()? {
+						Value::String(key) => key,
+						Value::Number(key) => key.to_string(),
+						_ => return Err(Error::Protocol("ETF payload has an unsupported map key")),
+					};
+					let value = self.term()?;
+					map.insert(key, value);
+				}
+				Ok(Value::Object(map))
+			}
+			_ => Err(Error::Protocol("ETF payload has an unsupported term")),
+		}
+	}
+
+	fn array(&mut self, len: usize) -> Result<Value> {
+		let mut array = Vec::with_capacity(::std::cmp::min(len, self.data.len()));
+		for _ in 0..len {
+			array.push(self.term()?);
+		}
+		Ok(Value::Array(array))
+	}
+
+	fn big(&mut self, len: usize) -> Result<Value> {
+		let negative = self.u8()? != 0;
+		let digits = self.take(len)?;
+		if digits.iter().skip(8).any(|&b| b != 0) {
+			return Err(Error::Protocol("ETF payload has an integer over 64 bits"));
+		}
+		let magnitude = digits
+			.iter()
+			.take(8)
+			.rev()
+			.fold(0u64, |acc, &b| acc << 8 | b as u64);
+		if !negative {
+			Ok(Value::from(magnitude))
+		} else if magnitude <= 1 << 63 {
+			Ok(Value::from((magnitude as i64).wrapping_neg()))
+		} else {
+			Err(Error::Protocol("ETF payload has an integer over 64 bits"))
+		}
+	}
+}
+
+fn atom(name: String) -> Value {
+	match &name[..] {
+		"nil" | "null" => Value::Null,
+		"true" => Value::Bool(true),
+		"false" => Value::Bool(false),
+		_ => Value::String(name),
+	}
+}
+
+fn float(f: f64) -> Result<Value> {
+	Number::from_f64(f)
+		.map(Value::Number)
+		.ok_or(Error::Protocol("ETF payload has a non-finite float"))
+}
+
+fn encode_term(buf: &mut Vec<u8>, value: &Value) {
+	match *value {
+		Value::Null => encode_atom(buf, "nil"),
+		Value::Bool(true) => encode_atom(buf, "true"),
+		Value::Bool(false) => encode_atom(buf, "false"),
+		Value::Number(ref number) => {
+			if let Some(n) = number.as_u64() {
+				if n < 256 {
+					buf.push(SMALL_INTEGER_EXT);
+					buf.push(n as u8);
+				} else if n <= i32::MAX as u64 {
+					buf.push(INTEGER_EXT);
+					buf.extend_from_slice(&(n as u32).to_be_bytes());
+				} else {
+					encode_big(buf, false, n);
+				}
+			} else if let Some(n) = number.as_i64() {
+				if n >= i32::MIN as i64 {
+					buf.push(INTEGER_EXT);
+					buf.extend_from_slice(&(n as i32).to_be_bytes());
+				} else {
+					encode_big(buf, true, n.wrapping_neg() as u64);
+				}
+			} else if let Some(f) = number.as_f64() {
+				buf.push(NEW_FLOAT_EXT);
+				buf.extend_from_slice(&f.to_bits().to_be_bytes());
+			}
+		}
+		Value::String(ref text) => {
+			buf.push(BINARY_EXT);
+			buf.extend_from_slice(&(text.len() as u32).to_be_bytes());
+			buf.extend_from_slice(text.as_bytes());
+		}
+		Value::Array(ref array) => {
+			if !array.is_empty() {
+				buf.push(LIST_EXT);
+				buf.extend_from_slice(&(array.len() as u32).to_be_bytes());
+				for item in array {
+					encode_term(buf, item);
+				}
+			}
+			buf.push(NIL_EXT);
+		}
+		Value::Object(ref map) => {
+			buf.push(MAP_EXT);
+			buf.extend_from_slice(&(map.len() as u32).to_be_bytes());
+			for (key, value) in map {
+				encode_term(buf, &Value::String(key.clone()));
+				encode_term(buf, value);
+			}
+		}
+	}
+}
+
+fn encode_atom(buf: &mut Vec<u8>, name: &str) {
+	buf.push(SMALL_ATOM_UTF8_EXT);
+	buf.push(name.len() as u8);
+	buf.extend_from_slice(name.as_bytes());
+}
+
+fn encode_big(buf: &mut Vec<u8>, negative: bool, magnitude: u64) {
+	let digits = magnitude.to_le_bytes();
+	let len = 8 - magnitude.leading_zeros() as usize / 8;
+	buf.push(SMALL_BIG_EXT);
+	buf.push(len as u8);
+	buf.push(negative as u8);
+	buf.extend_from_slice(&digits[..len]);
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_etf_round_trip() {
+		let value = json! {{
+			"op": 0,
+			"s": 42,
+			"t": "MESSAGE_CREATE",
+			"d": {
+				"id": 80351110224678912u64,
+				"negative": -5,
+				"large_negative": -9007199254740993i64,
+				"ratio": 0.5,
+				"nonce": null,
+				"tts": false,
+				"mentions": [],
+				"roles": [1, 300, 70000],
+			}
+		}};
+		let encoded = encode(&value);
+		assert_eq!(encoded[0], VERSION);
+		assert_eq!(decode(&encoded).unwrap(), value);
+
+		// {a => 'nil', b => [1, 2]} as Erlang would encode it
+		let erlang = [
+			131, 116, 0, 0, 0, 2, 100, 0, 1, b'a', 100, 0, 3, b'n', b'i', b'l', 109, 0, 0, 0, 1,
+			b'b', 107, 0, 2, 1, 2,
+		];
+		assert_eq!(decode(&erlang).unwrap(), json! {{ "a": null, "b": [1, 2] }});
+		assert!(decode(&erlang[..10]).is_err());
+	}
+}
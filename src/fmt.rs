@@ -0,0 +1,216 @@
+//! Helpers for building message text with Discord's markdown.
+//!
+//! Discord offers no way to escape markup inside code blocks, and splitting
+//! long text across messages easily breaks the markup it contains, so these
+//! take care of both.
+
+use chrono::{DateTime, TimeZone};
+
+/// The most characters a message's content may hold.
+pub const MESSAGE_LIMIT: usize = 2000;
+
+/// The most characters a message's content may hold when sent by a user with
+/// Nitro.
+pub const PREMIUM_MESSAGE_LIMIT: usize = 4000;
+
+const ZERO_WIDTH_SPACE: char = '\u{200B}';
+
+/// Escape markdown in text so that it displays exactly as written.
+///
+/// Characters which can begin a quote, heading, or list are escaped only at
+/// the start of a line.
+pub fn escape(text: &str) -> String {
+	let mut result = String::with_capacity(text.len());
+	let mut line_start = true;
+	for ch in text.chars() {
+		match ch {
+			'\\' | '*' | '_' | '~' | '`' | '|' => result.push('\\'),
+			'>' | '#' | '-' if line_start => result.push('\\'),
+			_ => {}
+		}
+		result.push(ch);
+		line_start = ch == '\n';
+	}
+	result
+}
+
+/// Make text bold.
+///
+/// Asterisks in the text are escaped so that it cannot end the bold early,
+/// while other markup is left as-is.
+pub fn bold(text: &str) -> String {
+	wrap(text, "**", '*')
+}
+
+/// Hide text behind a spoiler, to be revealed when clicked.
+///
+/// Pipes in the text are escaped so that it cannot end the spoiler early,
+/// while other markup is left as-is.
+pub fn spoiler(text: &str) -> String {
+	wrap(text, "||", '|')
+}
+
+fn wrap(text: &str, marker: &str, special: char) -> String {
+	let mut result = String::with_capacity(text.len() + 2 * marker.len());
+	result.push_str(marker);
+	for ch in text.chars() {
+		if ch == special {
+			result.push('\\');
+		}
+		result.push(ch);
+	}
+	// a trailing backslash would escape the closing marker
+	if text.chars().rev().take_while(|&ch| ch == '\\').count() % 2 == 1 {
+		result.push('\\');
+	}
+	result.push_str(marker);
+	result
+}
+
+/// Put text in a code block, highlighted as the given language if it is not
+/// empty.
+///
+/// Runs of backticks in the text are broken up with zero-width spaces so
+/// that they cannot end the block early. The result may be longer than a
+/// message allows; see `code_blocks` to split it.
+pub fn code_block(lang: &str, text: &str) -> String {
+	let lang = code_block_lang(lang);
+	fence(&lang, &escape_backticks(text))
+}
+
+/// Put text in as many code blocks as needed for each to fit in a message
+/// of at most `limit` characters, such as `MESSAGE_LIMIT`.
+///
+/// Text is split at line breaks where possible. Each block is highlighted as
+/// the given language and escaped as by `code_block`.
+///
+/// Panics if `limit` is too small to fit a code block's fences.
+pub fn code_blocks(lang: &str, text: &str, limit: usize) -> Vec<String> {
+	let lang = code_block_lang(lang);
+	let overhead = fence(&lang, "").chars().count();
+	assert!(limit > overhead, "limit of {} cannot fit a code block", limit);
+	let chunk_len = limit - overhead;
+
+	let escaped: Vec<char> = escape_backticks(text).chars().collect();
+	let mut blocks = Vec::new();
+	let mut rest = &escaped[..];
+	while rest.len() > chunk_len {
+		// prefer to end the block at the last line break which fits
+		let (chunk, skip) = match rest[..chunk_len + 1].iter().rposition(|&ch| ch == '\n') {
+			Some(pos) if pos > 0 => (&rest[..pos], pos + 1),
+			_ => (&rest[..chunk_len], chunk_len),
+		};
+		blocks.push(fence(&lang, &chunk.iter().collect::<String>()));
+		rest = &rest[skip..];
+	}
+	blocks.push(fence(&lang, &rest.iter().collect::<String>()));
+	blocks
+}
+
+fn fence(lang: &str, text: &str) -> String {
+	format!("```{}\n{}\n```", lang, text)
+}
+
+fn code_block_lang(lang: &str) -> String {
+	// anything else would be treated as part of the block's text
+	lang.chars()
+		.filter(|&ch| ch.is_alphanumeric() || ch == '+' || ch == '-' || ch == '_' || ch == '#')
+		.collect()
+}
+
+fn escape_backticks(text: &str) -> String {
+	let mut result = String::with_capacity(text.len());
+	let mut chars = text.chars().peekable();
+	while let Some(ch) = chars.next() {
+		result.push(ch);
+		if ch == '`' && chars.peek() == Some(&'`') {
+			result.push(ZERO_WIDTH_SPACE);
+		}
+	}
+	result
+}
+
+/// How a timestamp is displayed.
+///
+/// Timestamps are shown in each reader's own time zone and locale, so the
+/// examples here are only illustrative.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TimestampStyle {
+	/// "16:20"
+	ShortTime,
+	/// "16:20:30"
+	LongTime,
+	/// "20/04/2021"
+	ShortDate,
+	/// "20 April 2021"
+	LongDate,
+	/// "20 April 2021 16:20", the default
+	ShortDateTime,
+	/// "Tuesday, 20 April 2021 16:20"
+	LongDateTime,
+	/// "2 months ago", kept up to date as time passes
+	Relative,
+}
+
+impl TimestampStyle {
+	fn letter(self) -> char {
+		match self {
+			TimestampStyle::ShortTime => 't',
+			TimestampStyle::LongTime => 'T',
+			TimestampStyle::ShortDate => 'd',
+			TimestampStyle::LongDate => 'D',
+			TimestampStyle::ShortDateTime => 'f',
+			TimestampStyle::LongDateTime => 'F',
+			TimestampStyle::Relative => 'R',
+		}
+	}
+}
+
+impl Default for TimestampStyle {
+	fn default() -> TimestampStyle {
+		TimestampStyle::ShortDateTime
+	}
+}
+
+/// Display a moment in time in each reader's own time zone.
+///
+/// ```ignore
+/// let deadline = Utc::now() + Duration::hours(1);
+/// let text = format!("Voting closes {}", timestamp(&deadline, TimestampStyle::Relative));
+/// ```
+pub fn timestamp<Tz: TimeZone>(time: &DateTime<Tz>, style: TimestampStyle) -> String {
+	format!("<t:{}:{}>", time.timestamp(), style.letter())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_markup() {
+		assert_eq!(escape("*hi*\n> `x`"), "\\*hi\\*\n\\> \\`x\\`");
+		assert_eq!(bold("a**b\\"), "**a\\*\\*b\\\\**");
+		assert_eq!(spoiler("a||b"), "||a\\|\\|b||");
+		let time = ::chrono::Utc.timestamp(1618932630, 0);
+		assert_eq!(timestamp(&time, TimestampStyle::Relative), "<t:1618932630:R>");
+	}
+
+	#[test]
+	fn test_code_blocks() {
+		assert_eq!(code_block("rust ", "a```b"), "```rust\na`\u{200B}`\u{200B}`b\n```");
+
+		let text = "first line\nsecond line\n".repeat(200);
+		let blocks = code_blocks("", &text, MESSAGE_LIMIT);
+		assert!(blocks.len() > 1);
+		for block in &blocks {
+			assert!(block.chars().count() <= MESSAGE_LIMIT);
+		}
+		let joined: Vec<&str> = blocks.iter().map(|b| &b[4..b.len() - 4]).collect();
+		assert_eq!(joined.join("\n"), text);
+
+		let long = "x".repeat(3000);
+		let blocks = code_blocks("", &long, MESSAGE_LIMIT);
+		assert_eq!(blocks.len(), 2);
+		assert_eq!(blocks[0].chars().count(), MESSAGE_LIMIT);
+	}
+}
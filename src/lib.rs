@@ -13,7 +13,9 @@
 //!
 //! For more in-depth tracking of Discord state, a `State` can be seeded with
 //! the `ReadyEvent` obtained when opening a `Connection` and kept updated with
-//! the events received over it.
+//! the events received over it. Recorded gateway sessions can be played back
+//! through the same machinery with the `replay` module, for testing event
+//! handlers without a live connection.
 //!
 #![cfg_attr(
 	not(feature = "voice"),
@@ -49,6 +51,10 @@ extern crate byteorder;
 extern crate opus;
 #[cfg(feature = "voice")]
 extern crate sodiumoxide;
+#[cfg(feature = "futures-bridge")]
+extern crate futures_channel;
+#[cfg(feature = "futures-bridge")]
+extern crate futures_core;
 
 use std::collections::BTreeMap;
 use std::time;
@@ -56,9 +62,21 @@ use std::time;
 type Object = serde_json::Map<String, serde_json::Value>;
 
 mod connection;
+mod dispatch;
 mod error;
-mod ratelimit;
+#[cfg(feature = "etf")]
+mod etf;
+pub mod fmt;
+#[cfg(feature = "voice")]
+pub mod loopback;
+mod middleware;
+mod preflight;
+pub mod ratelimit;
+pub mod replay;
+mod sharding;
 mod state;
+#[cfg(feature = "futures-bridge")]
+mod stream;
 #[cfg(feature = "voice")]
 pub mod voice;
 
@@ -75,11 +93,19 @@ pub mod builders;
 pub mod model;
 
 use builders::*;
-pub use connection::Connection;
-pub use error::{Error, Result};
+pub use connection::{Connection, ReconnectPolicy, ReconnectStats, UnknownEvents};
+pub use dispatch::Dispatcher;
+pub use error::{redact, Error, Result};
+pub use middleware::Middleware;
+pub use preflight::Preflight;
+pub use sharding::ShardManager;
 use model::*;
 use ratelimit::RateLimits;
-pub use state::{ChannelRef, State};
+pub use state::{
+	CacheOptions, ChannelRef, SharedState, Snapshot, State, StateChange, StateWarning,
+};
+#[cfg(feature = "futures-bridge")]
+pub use stream::EventStream;
 
 const USER_AGENT: &'static str = concat!(
 	"DiscordBot (https://github.com/SpaceManiac/discord-rs, ",
@@ -98,6 +124,14 @@ macro_rules! status_concat {
 }
 
 macro_rules! request {
+	($self_:ident, reason($reason:expr), $method:ident($body:expr), $url:expr, $($rest:tt)*) => {{
+		let path = format!(api_concat!($url), $($rest)*);
+		$self_.request_with_reason(&path, $reason, || $self_.client.$method(&path).body(&$body))?
+	}};
+	($self_:ident, reason($reason:expr), $method:ident, $url:expr, $($rest:tt)*) => {{
+		let path = format!(api_concat!($url), $($rest)*);
+		$self_.request_with_reason(&path, $reason, || $self_.client.$method(&path))?
+	}};
 	($self_:ident, $method:ident($body:expr), $url:expr, $($rest:tt)*) => {{
 		let path = format!(api_concat!($url), $($rest)*);
 		$self_.request(&path, || $self_.client.$method(&path).body(&$body))?
@@ -116,6 +150,8 @@ macro_rules! request {
 	}};
 }
 
+pub mod oauth2;
+
 /// Client for the Discord REST API.
 ///
 /// Log in to the API with a user's email and password using `new()`. Call
@@ -126,6 +162,70 @@ pub struct Discord {
 	rate_limits: RateLimits,
 	client: hyper::Client,
 	token: String,
+	middleware: Vec<Box<dyn Middleware>>,
+}
+
+/// Builder for a `Discord` client with non-default settings.
+///
+/// ```ignore
+/// let discord = DiscordBuilder::new()
+///     .with_token_redaction(false)
+///     .bot_token(&token)?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct DiscordBuilder {
+	token_redaction: bool,
+}
+
+impl Default for DiscordBuilder {
+	fn default() -> DiscordBuilder {
+		DiscordBuilder {
+			token_redaction: true,
+		}
+	}
+}
+
+impl DiscordBuilder {
+	/// Start with the default settings.
+	pub fn new() -> DiscordBuilder {
+		DiscordBuilder::default()
+	}
+
+	/// Set whether tokens are masked in errors and in this library's logging,
+	/// as by `redact`. Defaults to true.
+	///
+	/// Errors are formatted without knowing which client they came from, so
+	/// this setting applies to the whole process once a client is built.
+	pub fn with_token_redaction(&mut self, token_redaction: bool) -> &mut Self {
+		self.token_redaction = token_redaction;
+		self
+	}
+
+	/// Log in as a bot account using the given authentication token.
+	///
+	/// See `Discord::from_bot_token`.
+	pub fn bot_token(&self, token: &str) -> Result<Discord> {
+		self.build(format!("Bot {}", token.trim()))
+	}
+
+	/// Log in as a user account using the given authentication token.
+	///
+	/// See `Discord::from_user_token`.
+	pub fn user_token(&self, token: &str) -> Result<Discord> {
+		self.build(token.trim().to_owned())
+	}
+
+	/// Act on behalf of a user using an OAuth2 access token.
+	///
+	/// See `Discord::from_bearer_token`.
+	pub fn bearer_token(&self, token: &str) -> Result<Discord> {
+		self.build(format!("Bearer {}", token.trim()))
+	}
+
+	fn build(&self, token: String) -> Result<Discord> {
+		error::set_redaction(self.token_redaction);
+		Ok(Discord::from_token_raw(token))
+	}
 }
 
 fn tls_client() -> hyper::Client {
@@ -164,6 +264,7 @@ impl Discord {
 			rate_limits: RateLimits::default(),
 			client: client,
 			token: token,
+			middleware: Vec::new(),
 		})
 	}
 
@@ -227,6 +328,7 @@ impl Discord {
 				rate_limits: RateLimits::default(),
 				client: client,
 				token: token,
+				middleware: Vec::new(),
 			}
 		} else if let Some(password) = password {
 			Discord::new(email, password)?
@@ -263,19 +365,67 @@ impl Discord {
 			rate_limits: RateLimits::default(),
 			client: tls_client(),
 			token: token,
+			middleware: Vec::new(),
 		}
 	}
 
 	/// Log in as a bot account using the given authentication token.
 	///
-	/// The token will automatically be prefixed with "Bot ".
+	/// The token will automatically be prefixed with "Bot ". Use a
+	/// `DiscordBuilder` to change the client's settings.
 	pub fn from_bot_token(token: &str) -> Result<Discord> {
-		Ok(Discord::from_token_raw(format!("Bot {}", token.trim())))
+		DiscordBuilder::new().bot_token(token)
 	}
 
 	/// Log in as a user account using the given authentication token.
 	pub fn from_user_token(token: &str) -> Result<Discord> {
-		Ok(Discord::from_token_raw(token.trim().to_owned()))
+		DiscordBuilder::new().user_token(token)
+	}
+
+	/// Act on behalf of a user using an OAuth2 access token.
+	///
+	/// The token will automatically be prefixed with "Bearer ". Only the
+	/// endpoints which act on the authorizing user, such as `get_current_user`
+	/// and `get_servers`, are available; other calls fail without making a
+	/// request, and a websocket connection cannot be established.
+	pub fn from_bearer_token(token: &str) -> Result<Discord> {
+		DiscordBuilder::new().bearer_token(token)
+	}
+
+	/// Fail early if the endpoint cannot be used with this client's token.
+	fn check_token_scope(&self, url: &str) -> Result<()> {
+		const BEARER_ENDPOINTS: &'static [&'static str] = &["/users/@me", "/oauth2/@me"];
+
+		if !self.token.starts_with("Bearer ") {
+			return Ok(());
+		}
+		let path = url.strip_prefix(api_concat!("")).unwrap_or(url);
+		let allowed = BEARER_ENDPOINTS.iter().any(|endpoint| {
+			path.strip_prefix(endpoint).map_or(false, |rest| match rest.chars().next() {
+				None | Some('/') | Some('?') => true,
+				_ => false,
+			})
+		});
+		if allowed {
+			Ok(())
+		} else {
+			Err(Error::Other("Endpoint is not available with a bearer token"))
+		}
+	}
+
+	/// Check the bot's permissions against the given `State` before making
+	/// requests which need them.
+	///
+	/// See `Preflight` for the checked methods.
+	pub fn preflight<'a>(&'a self, state: &'a State) -> Preflight<'a> {
+		Preflight::new(self, state)
+	}
+
+	/// Register middleware to observe every request made by this client.
+	///
+	/// See `Middleware` for details.
+	pub fn add_middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+		self.middleware.push(Box::new(middleware));
 	}
 
 	/// Log out from the Discord API, invalidating this clients's token.
@@ -294,18 +444,49 @@ impl Discord {
 		url: &str,
 		f: F,
 	) -> Result<hyper::client::Response> {
+		self.request_with_reason(url, "", f)
+	}
+
+	/// Issue a request, recording the reason in the server's audit log if it is
+	/// not empty.
+	fn request_with_reason<'a, F: Fn() -> hyper::client::RequestBuilder<'a>>(
+		&self,
+		url: &str,
+		reason: &str,
+		f: F,
+	) -> Result<hyper::client::Response> {
+		self.check_token_scope(url)?;
 		self.rate_limits.pre_check(url);
+		let reason = AuditLogReason::new(reason);
 		let f2 = || {
-			f().header(hyper::header::ContentType::json())
-				.header(hyper::header::Authorization(self.token.clone()))
+			// `headers` replaces any set so far, so everything goes in one set,
+			// with ours last so that middleware cannot override them
+			let mut headers = hyper::header::Headers::new();
+			for middleware in &self.middleware {
+				middleware.before_request(url, &mut headers);
+			}
+			headers.set(hyper::header::ContentType::json());
+			headers.set(hyper::header::Authorization(self.token.clone()));
+			if let Some(ref reason) = reason {
+				headers.set(reason.clone());
+			}
+			f().headers(headers)
 		};
-		let result = retry(&f2);
+		let send = || {
+			let start = time::Instant::now();
+			let result = retry(&f2);
+			if let Ok(response) = result.as_ref() {
+				self.observe_response(url, response, start.elapsed());
+			}
+			result
+		};
+		let result = send();
 		if let Ok(response) = result.as_ref() {
 			if self.rate_limits.post_update(url, response) {
 				// we were rate limited, we have slept, it is time to retry
 				// the request once. if it fails the second time, give up
 				debug!("Retrying after having been ratelimited");
-				let result = retry(f2);
+				let result = send();
 				if let Ok(response) = result.as_ref() {
 					self.rate_limits.post_update(url, response);
 				}
@@ -315,6 +496,17 @@ impl Discord {
 		check_status(result)
 	}
 
+	fn observe_response(
+		&self,
+		url: &str,
+		response: &hyper::client::Response,
+		elapsed: time::Duration,
+	) {
+		for middleware in &self.middleware {
+			middleware.after_response(url, response, elapsed);
+		}
+	}
+
 	/// Create a channel.
 	pub fn create_channel(
 		&self,
@@ -322,13 +514,81 @@ impl Discord {
 		name: &str,
 		kind: ChannelType,
 	) -> Result<Channel> {
+		self.create_channel_ex(server, name, kind, |c| c)
+	}
+
+	/// Create a channel, setting any of its details at creation time. See
+	/// `CreateChannel` for the available fields.
+	///
+	/// ```ignore
+	/// // Create a slowmode channel in a category, hidden from @everyone
+	/// discord.create_channel_ex(server_id, "staff", ChannelType::Text, |c| c
+	///     .parent(category_id)
+	///     .topic("Staff discussion")
+	///     .rate_limit_per_user(10)
+	///     .permission_overwrites(&[PermissionOverwrite {
+	///         kind: PermissionOverwriteType::Role(server_id.everyone()),
+	///         allow: Permissions::empty(),
+	///         deny: permissions::READ_MESSAGES,
+	///     }])
+	/// );
+	/// ```
+	pub fn create_channel_ex<F: FnOnce(CreateChannel) -> CreateChannel>(
+		&self,
+		server: ServerId,
+		name: &str,
+		kind: ChannelType,
+		f: F,
+	) -> Result<Channel> {
+		let mut map = CreateChannel::__build(f);
+		map.insert("name".into(), json!(name));
+		map.insert("type".into(), json!(kind.num()));
+		let topic_limit = if kind == ChannelType::Forum {
+			FORUM_TOPIC_LIMIT
+		} else {
+			TOPIC_LIMIT
+		};
+		validate_channel_edit(&map, topic_limit)?;
+		let body = serde_json::to_string(&map)?;
+		let response = request!(self, post(body), "/guilds/{}/channels", server);
+		Channel::decode(serde_json::from_reader(response)?)
+	}
+
+	/// Create a category and move the given channels into it, in the order
+	/// listed.
+	///
+	/// The channels are moved with a single bulk update after the category is
+	/// created. If moving them fails, the category is left in place.
+	pub fn create_category(
+		&self,
+		server: ServerId,
+		name: &str,
+		channels: &[ChannelId],
+	) -> Result<ChannelCategory> {
 		let map = json! {{
 			"name": name,
-			"type": kind.num(),
+			"type": ChannelType::Category.num(),
 		}};
 		let body = serde_json::to_string(&map)?;
 		let response = request!(self, post(body), "/guilds/{}/channels", server);
-		Channel::decode(serde_json::from_reader(response)?)
+		let category = ChannelCategory::decode(serde_json::from_reader(response)?)?;
+		if channels.is_empty() {
+			return Ok(category);
+		}
+		let map: serde_json::Value = channels
+			.iter()
+			.enumerate()
+			.map(|(pos, &id)| {
+				json! {{
+					"id": id,
+					"parent_id": category.id,
+					"position": pos,
+				}}
+			})
+			.collect();
+		let body = serde_json::to_string(&map)?;
+		check_empty(request!(self, patch(body), "/guilds/{}/channels", server))?;
+		Ok(category)
 	}
 
 	/// Get the list of channels in a server.
@@ -345,10 +605,16 @@ impl Discord {
 
 	/// Edit a channel's details. See `EditChannel` for the editable fields.
 	///
+	/// Only the fields set in the builder are changed. Works for categories
+	/// and threads as well as ordinary server channels. A topic longer than a
+	/// text channel allows costs an extra request, to check whether the
+	/// channel is a forum.
+	///
 	/// ```ignore
-	/// // Edit a channel's name and topic
-	/// discord.edit_channel(channel_id, "general", |ch| ch
-	///     .topic("Welcome to the general chat!")
+	/// // Rename a channel and move it into a category
+	/// discord.edit_channel(channel_id, |ch| ch
+	///     .name("general")
+	///     .category(Some(category_id))
 	/// );
 	/// ```
 	pub fn edit_channel<F: FnOnce(EditChannel) -> EditChannel>(
@@ -356,38 +622,21 @@ impl Discord {
 		channel: ChannelId,
 		f: F,
 	) -> Result<PublicChannel> {
-		// Work around the fact that this supposed PATCH call actually requires all fields
-		let mut map = Object::new();
-		match self.get_channel(channel)? {
-			Channel::Private(_) => return Err(Error::Other("Can not edit private channels")),
-			Channel::Public(channel) => {
-				map.insert("name".into(), channel.name.into());
-				map.insert("position".into(), channel.position.into());
-
-				match channel.kind {
-					ChannelType::Text => {
-						map.insert("topic".into(), json!(channel.topic));
-					}
-					ChannelType::Voice => {
-						map.insert("bitrate".into(), json!(channel.bitrate));
-						map.insert("user_limit".into(), json!(channel.user_limit));
-					}
-					_ => {
-						return Err(Error::Other(stringify!(format!(
-							"Unreachable channel type: {:?}",
-							channel.kind
-						))))
+		let map = EditChannel::__build(f);
+		let topic_len = map.get("topic").and_then(|v| v.as_str()).map(|t| t.chars().count());
+		// only forum channels allow longer topics, so check what the channel is
+		let topic_limit = match topic_len {
+			Some(len) if len > TOPIC_LIMIT => {
+				match self.get_channel(channel)? {
+					Channel::Public(ref public) if public.kind == ChannelType::Forum => {
+						FORUM_TOPIC_LIMIT
 					}
+					_ => TOPIC_LIMIT,
 				}
 			}
-			Channel::Group(group) => {
-				map.insert("name".into(), json!(group.name));
-			}
-			Channel::Category(_) => {}
-			Channel::News => {}
-			Channel::Store => {}
+			_ => TOPIC_LIMIT,
 		};
-		let map = EditChannel::__apply(f, map);
+		validate_channel_edit(&map, topic_limit)?;
 		let body = serde_json::to_string(&map)?;
 		let response = request!(self, patch(body), "/channels/{}", channel);
 		PublicChannel::decode(serde_json::from_reader(response)?)
@@ -395,12 +644,18 @@ impl Discord {
 
 	/// Delete a channel.
 	pub fn delete_channel(&self, channel: ChannelId) -> Result<Channel> {
-		let response = request!(self, delete, "/channels/{}", channel);
+		self.delete_channel_with_reason(channel, "")
+	}
+
+	/// Delete a channel, recording the reason in the server's audit log.
+	pub fn delete_channel_with_reason(&self, channel: ChannelId, reason: &str) -> Result<Channel> {
+		let response = request!(self, reason(reason), delete, "/channels/{}", channel);
 		Channel::decode(serde_json::from_reader(response)?)
 	}
 
 	/// Indicate typing on a channel for the next 5 seconds.
-	pub fn broadcast_typing(&self, channel: ChannelId) -> Result<()> {
+	pub fn broadcast_typing<C: IntoTextChannel>(&self, channel: C) -> Result<()> {
+		let channel: ChannelId = channel.into();
 		check_empty(request!(self, post, "/channels/{}/typing", channel))
 	}
 
@@ -444,12 +699,66 @@ impl Discord {
 		from_reader(response)
 	}
 
+	/// Iterate backwards through a channel's message history, newest first.
+	///
+	/// Messages are fetched in pages of 100 as the iterator is advanced, and
+	/// each request waits out any rate limit as usual. An error ends the
+	/// iteration after it is yielded.
+	///
+	/// ```ignore
+	/// for message in discord.messages_iter(channel).take(500) {
+	///     let message = message?;
+	///     println!("{}: {}", message.author.name, message.content);
+	/// }
+	/// ```
+	pub fn messages_iter<'a>(&'a self, channel: ChannelId) -> MessagesIter<'a> {
+		MessagesIter {
+			discord: self,
+			channel: channel,
+			buffer: Vec::new().into_iter(),
+			before: None,
+			done: false,
+		}
+	}
+
 	/// Gets the pinned messages for a given channel.
 	pub fn get_pinned_messages(&self, channel: ChannelId) -> Result<Vec<Message>> {
 		let response = request!(self, get, "/channels/{}/pins", channel);
 		from_reader(response)
 	}
 
+	/// Publish a message in a news channel to all channels following it.
+	///
+	/// Requires the "SEND_MESSAGES" permission for the current user's own
+	/// messages, or "MANAGE_MESSAGES" for others' messages.
+	pub fn crosspost_message(&self, channel: ChannelId, message: MessageId) -> Result<Message> {
+		let response = request!(
+			self,
+			post,
+			"/channels/{}/messages/{}/crosspost",
+			channel,
+			message
+		);
+		from_reader(response)
+	}
+
+	/// Follow a news channel, so that messages published in it are sent to
+	/// the `target` channel.
+	///
+	/// Requires the "MANAGE_WEBHOOKS" permission in the target channel.
+	pub fn follow_news_channel(
+		&self,
+		channel: ChannelId,
+		target: ChannelId,
+	) -> Result<FollowedChannel> {
+		let map = json! {{
+			"webhook_channel_id": target,
+		}};
+		let body = serde_json::to_string(&map)?;
+		let response = request!(self, post(body), "/channels/{}/followers", channel);
+		from_reader(response)
+	}
+
 	/// Pin the given message to the given channel.
 	///
 	/// Requires that the logged in account have the "MANAGE_MESSAGES" permission.
@@ -479,15 +788,49 @@ impl Discord {
 	/// Send a message to a given channel.
 	///
 	/// The `nonce` will be returned in the result and also transmitted to other
-	/// clients. The empty string is a good default if you don't care.
+	/// clients. If no nonce is set, one is generated.
+	///
+	/// Sends which fail with a gateway error (502, 503, or 504) or a transport
+	/// error are retried a few times with the same nonce. Unless a nonce is
+	/// given with `SendMessage::nonce`, one is generated and Discord is asked
+	/// to enforce it, so a retry of a send which actually went through returns
+	/// the original message instead of posting a duplicate.
 	pub fn send_message_ex<F: FnOnce(SendMessage) -> SendMessage>(
 		&self,
 		channel: ChannelId,
 		f: F,
 	) -> Result<Message> {
-		let map = SendMessage::__build(f);
+		self.send_message_object(channel, SendMessage::__build(f))
+	}
+
+	/// Send a message built by `SendMessage`, retrying as described for
+	/// `send_message_ex`.
+	fn send_message_object(&self, channel: ChannelId, mut map: Object) -> Result<Message> {
+		// a nonce of the caller's own is only enforced if they ask for it
+		if map.get("nonce").and_then(|n| n.as_str()).map_or(true, str::is_empty) {
+			map.insert("nonce".into(), generate_nonce().into());
+			map.insert("enforce_nonce".into(), true.into());
+		}
 		let body = serde_json::to_string(&map)?;
-		let response = request!(self, post(body), "/channels/{}/messages", channel);
+		let mut delay = 1000;
+		for _ in 0..MESSAGE_RETRIES {
+			match self.send_message_once(channel, &body) {
+				Err(Error::Status(status, _)) if is_transient(status) => {
+					warn!("Message send failed with {}, retrying", status);
+				}
+				Err(Error::Hyper(err)) => {
+					warn!("Message send failed with {}, retrying", err);
+				}
+				other => return other,
+			}
+			sleep_ms(delay);
+			delay *= 2;
+		}
+		self.send_message_once(channel, &body)
+	}
+
+	fn send_message_once(&self, channel: ChannelId, body: &str) -> Result<Message> {
+		let response = request!(self, post(*body), "/channels/{}/messages", channel);
 		from_reader(response)
 	}
 
@@ -530,6 +873,19 @@ impl Discord {
 		self.send_message_ex(channel, |b| b.content(text).nonce(nonce).tts(tts))
 	}
 
+	/// Send a message which notifies no one, for notices which should not
+	/// disturb the server.
+	///
+	/// The message is sent with `SUPPRESS_NOTIFICATIONS` and with all mentions
+	/// disabled, including `@everyone`.
+	pub fn send_silent_message(&self, channel: ChannelId, text: &str) -> Result<Message> {
+		self.send_message_ex(channel, |b| {
+			b.content(text)
+				.suppress_notifications(true)
+				.allowed_mentions(|m| m)
+		})
+	}
+
 	/// Edit a previously posted message.
 	///
 	/// Requires that either the message was posted by this user, or this user
@@ -548,8 +904,22 @@ impl Discord {
 	/// Requires that either the message was posted by this user, or this user
 	/// has permission to manage other members' messages.
 	pub fn delete_message(&self, channel: ChannelId, message: MessageId) -> Result<()> {
+		self.delete_message_with_reason(channel, message, "")
+	}
+
+	/// Delete a previously posted message, recording the reason in the
+	/// server's audit log.
+	///
+	/// Only deletions of other users' messages appear in the audit log.
+	pub fn delete_message_with_reason(
+		&self,
+		channel: ChannelId,
+		message: MessageId,
+		reason: &str,
+	) -> Result<()> {
 		check_empty(request!(
 			self,
+			reason(reason),
 			delete,
 			"/channels/{}/messages/{}",
 			channel,
@@ -625,21 +995,186 @@ impl Discord {
 		&self,
 		channel: ChannelId,
 		text: &str,
+		file: R,
+		filename: &str,
+	) -> Result<Message> {
+		self.send_message_with_files(channel, vec![(filename, file)], |b| b.content(text))
+	}
+
+	/// Send a message with files attached to a given channel.
+	///
+	/// Each file is given with its filename. The message itself is built as for
+	/// `send_message_ex` and sent alongside the files as `payload_json`. To
+	/// attach files of several types, use `Box<Read>` as the reader type.
+	///
+	/// Unlike `send_message_ex`, failed sends are not retried, since the files
+	/// cannot be read a second time.
+	pub fn send_message_with_files<F, R>(
+		&self,
+		channel: ChannelId,
+		files: Vec<(&str, R)>,
+		f: F,
+	) -> Result<Message>
+	where
+		F: FnOnce(SendMessage) -> SendMessage,
+		R: ::std::io::Read,
+	{
+		let map = SendMessage::__build(f);
+		let cr = multipart::mock::ClientRequest::default();
+		let mut multi = multipart::client::Multipart::from_request(cr)?;
+		multi.write_text("payload_json", serde_json::to_string(&map)?)?;
+		for (i, (filename, mut file)) in files.into_iter().enumerate() {
+			multi.write_stream(format!("files[{}]", i), &mut file, Some(filename), None)?;
+		}
+		let url = format!(api_concat!("/channels/{}/messages"), channel);
+		let response = self.send_multipart(&url, multi.send()?)?;
+		Message::decode(serde_json::from_reader(response)?)
+	}
+
+	/// Send an audio file as a voice message to a given channel.
+	///
+	/// Discord requires voice messages to be Ogg Opus audio and to have no
+	/// text or embeds. The `metadata` can be computed from the audio's PCM
+	/// samples with `VoiceMessageMetadata::from_pcm`.
+	pub fn send_voice_message<R: ::std::io::Read>(
+		&self,
+		channel: ChannelId,
 		mut file: R,
 		filename: &str,
+		metadata: &VoiceMessageMetadata,
 	) -> Result<Message> {
+		let map = json! {{
+			"flags": MessageFlags::IS_VOICE_MESSAGE.bits(),
+			"attachments": [{
+				"id": 0,
+				"filename": filename,
+				"duration_secs": metadata.duration_secs,
+				"waveform": base64::encode(&metadata.waveform),
+			}],
+		}};
+		let cr = multipart::mock::ClientRequest::default();
+		let mut multi = multipart::client::Multipart::from_request(cr)?;
+		multi.write_text("payload_json", serde_json::to_string(&map)?)?;
+		multi.write_stream("files[0]", &mut file, Some(filename), None)?;
+		let url = format!(api_concat!("/channels/{}/messages"), channel);
+		let response = self.send_multipart(&url, multi.send()?)?;
+		Message::decode(serde_json::from_reader(response)?)
+	}
+
+	/// Reserve places to upload files to before attaching them to a message in
+	/// the given channel, given each file's name and size in bytes.
+	///
+	/// Upload the files with `upload_attachment`. Uploading this way allows
+	/// larger files than a multipart message send; `send_message_with_uploads`
+	/// does every step.
+	pub fn create_attachment_slots(
+		&self,
+		channel: ChannelId,
+		files: &[(&str, u64)],
+	) -> Result<Vec<AttachmentSlot>> {
+		let files: Vec<_> = files
+			.iter()
+			.enumerate()
+			.map(|(i, &(filename, size))| {
+				json! {{
+					"id": i.to_string(),
+					"filename": filename,
+					"file_size": size,
+				}}
+			})
+			.collect();
+		let body = serde_json::to_string(&json! {{ "files": files }})?;
+		let response = request!(self, post(body), "/channels/{}/attachments", channel);
+		let mut value: serde_json::Value = from_reader(response)?;
+		serde_json::from_value(value["attachments"].take()).map_err(From::from)
+	}
+
+	/// Upload a file's contents to a slot from `create_attachment_slots`.
+	///
+	/// The `size` must match the size the slot was created with.
+	pub fn upload_attachment<R: ::std::io::Read>(
+		&self,
+		slot: &AttachmentSlot,
+		mut file: R,
+		size: u64,
+	) -> Result<()> {
+		let client = tls_client();
+		let response = client
+			.put(&slot.upload_url)
+			.body(hyper::client::Body::SizedBody(&mut file, size))
+			.send();
+		check_status(response).map(|_| ())
+	}
+
+	/// Send a message with files attached to a given channel, uploading the
+	/// files ahead of the message.
+	///
+	/// Works as `send_message_with_files`, but allows larger files. If the
+	/// channel does not support uploading ahead, the files are sent with the
+	/// message instead.
+	pub fn send_message_with_uploads<F, R>(
+		&self,
+		channel: ChannelId,
+		mut files: Vec<(&str, R)>,
+		f: F,
+	) -> Result<Message>
+	where
+		F: FnOnce(SendMessage) -> SendMessage,
+		R: ::std::io::Read + ::std::io::Seek,
+	{
+		use std::io::SeekFrom;
+
+		let mut sizes = Vec::with_capacity(files.len());
+		for &mut (filename, ref mut file) in &mut files {
+			let size = file.seek(SeekFrom::End(0))?;
+			file.seek(SeekFrom::Start(0))?;
+			sizes.push((filename, size));
+		}
+		let slots = match self.create_attachment_slots(channel, &sizes) {
+			Ok(slots) => slots,
+			Err(Error::Status(status, _))
+				if status == hyper::status::StatusCode::NotFound
+					|| status == hyper::status::StatusCode::MethodNotAllowed =>
+			{
+				debug!("Uploading ahead unavailable, sending files with the message");
+				return self.send_message_with_files(channel, files, f);
+			}
+			Err(err) => return Err(err),
+		};
+		if slots.len() != files.len() {
+			return Err(Error::Protocol("Wrong number of attachment slots returned"));
+		}
+
+		let mut attachments = Vec::with_capacity(files.len());
+		let uploads = slots.iter().zip(files.into_iter().zip(&sizes));
+		for (slot, ((filename, file), &(_, size))) in uploads {
+			self.upload_attachment(slot, file, size)?;
+			attachments.push(json! {{
+				"id": slot.id,
+				"filename": filename,
+				"uploaded_filename": slot.upload_filename,
+			}});
+		}
+		let mut map = SendMessage::__build(f);
+		map.insert("attachments".into(), attachments.into());
+		self.send_message_object(channel, map)
+	}
+
+	/// POST a multipart form body which has already been written out.
+	fn send_multipart(
+		&self,
+		url: &str,
+		http_buffer: multipart::mock::HttpBuffer,
+	) -> Result<hyper::client::Response> {
 		use std::io::Write;
 
-		let url = match hyper::Url::parse(&format!(api_concat!("/channels/{}/messages"), channel)) {
+		self.check_token_scope(url)?;
+
+		let parsed_url = match hyper::Url::parse(url) {
 			Ok(url) => url,
-			Err(_) => return Err(Error::Other("Invalid URL in send_file")),
+			Err(_) => return Err(Error::Other("Invalid URL in send_multipart")),
 		};
 		// NB: We're NOT using the Hyper itegration of multipart in order not to wrestle with the openssl-sys dependency hell.
-		let cr = multipart::mock::ClientRequest::default();
-		let mut multi = multipart::client::Multipart::from_request(cr)?;
-		multi.write_text("content", text)?;
-		multi.write_stream("file", &mut file, Some(filename), None)?;
-		let http_buffer: multipart::mock::HttpBuffer = multi.send()?;
 		fn multipart_mime(bound: &str) -> hyper::mime::Mime {
 			use hyper::mime::{Attr, Mime, SubLevel, TopLevel, Value};
 			Mime(
@@ -651,7 +1186,10 @@ impl Discord {
 
 		let tls = hyper_native_tls::NativeTlsClient::new().expect("Error initializing NativeTlsClient");
 		let connector = hyper::net::HttpsConnector::new(tls);
-		let mut request = hyper::client::Request::with_connector(hyper::method::Method::Post, url, &connector)?;
+		let mut request = hyper::client::Request::with_connector(hyper::method::Method::Post, parsed_url, &connector)?;
+		for middleware in &self.middleware {
+			middleware.before_request(url, request.headers_mut());
+		}
 		request
 			.headers_mut()
 			.set(hyper::header::Authorization(self.token.clone()));
@@ -663,9 +1201,14 @@ impl Discord {
 			.set(hyper::header::ContentType(multipart_mime(
 				&http_buffer.boundary,
 			)));
+		let start = time::Instant::now();
 		let mut request = request.start()?;
 		request.write(&http_buffer.buf[..])?;
-		Message::decode(serde_json::from_reader(check_status(request.send())?)?)
+		let result = request.send();
+		if let Ok(response) = result.as_ref() {
+			self.observe_response(url, response, start.elapsed());
+		}
+		check_status(result)
 	}
 
 	/// Acknowledge this message as "read" by this client.
@@ -898,6 +1441,20 @@ impl Discord {
 		emoji: ReactionEmoji,
 		limit: Option<i32>,
 		after: Option<UserId>,
+	) -> Result<Vec<User>> {
+		self.get_reactions_of_type(channel, message, emoji, ReactionType::Normal, limit, after)
+	}
+
+	/// Get reactors for the `Emoji` in a `Message` who added either normal
+	/// or super reactions. See `get_reactions` for the other parameters.
+	pub fn get_reactions_of_type(
+		&self,
+		channel: ChannelId,
+		message: MessageId,
+		emoji: ReactionEmoji,
+		kind: ReactionType,
+		limit: Option<i32>,
+		after: Option<UserId>,
 	) -> Result<Vec<User>> {
 		let emoji = match emoji {
 			ReactionEmoji::Custom { name, id } => format!("{}:{}", name, id.0),
@@ -911,6 +1468,10 @@ impl Discord {
 			limit.unwrap_or(50)
 		);
 
+		if kind != ReactionType::Normal {
+			use std::fmt::Write;
+			let _ = write!(endpoint, "&type={}", kind.num());
+		}
 		if let Some(amount) = after {
 			use std::fmt::Write;
 			let _ = write!(endpoint, "&after={}", amount);
@@ -920,31 +1481,248 @@ impl Discord {
 		from_reader(response)
 	}
 
-	/// Get the list of servers this user knows about.
-	pub fn get_servers(&self) -> Result<Vec<ServerInfo>> {
-		let response = request!(self, get, "/users/@me/guilds");
-		from_reader(response)
-	}
-
-	/// Gets a specific server.
-	pub fn get_server(&self, server_id: ServerId) -> Result<Server> {
-		let response = request!(self, get, "/guilds/{}", server_id);
-		from_reader(response)
+	/// Iterate through the users who reacted to a message with the given
+	/// emoji.
+	///
+	/// Users are fetched in pages of 100 as the iterator is advanced. An error
+	/// ends the iteration after it is yielded; the iterator's `cursor` can be
+	/// passed to `after` on a new iterator to pick up where it left off.
+	///
+	/// ```ignore
+	/// let mut reactors = discord.reactions_iter(channel, message, emoji.clone());
+	/// for user in &mut reactors {
+	///     println!("{}", user?.name);
+	/// }
+	/// ```
+	pub fn reactions_iter<'a>(
+		&'a self,
+		channel: ChannelId,
+		message: MessageId,
+		emoji: ReactionEmoji,
+	) -> ReactionsIter<'a> {
+		ReactionsIter {
+			discord: self,
+			channel: channel,
+			message: message,
+			emoji: emoji,
+			kind: ReactionType::Normal,
+			buffer: Vec::new().into_iter(),
+			after: None,
+			cursor: None,
+			done: false,
+		}
 	}
 
-	/// Gets the list of a specific server's members.
-	pub fn get_server_members(&self, server_id: ServerId, limit: Option<u32>, after: Option<u32>) -> Result<Vec<Member>> {
-		let limit = limit.unwrap_or(1);
-		let after = after.unwrap_or(0);
-		let response = request!(self, get, "/guilds/{}/members?limit={}&after={}", server_id, limit, after);
+	/// Create a new thread from an existing message.
+	///
+	/// `auto_archive_duration` is the number of minutes of inactivity after
+	/// which the thread is archived, and should be one of 60, 1440, 4320, or
+	/// 10080.
+	pub fn start_thread_with_message(
+		&self,
+		channel: ChannelId,
+		message: MessageId,
+		name: &str,
+		auto_archive_duration: u64,
+	) -> Result<ThreadChannel> {
+		let map = json! {{
+			"name": name,
+			"auto_archive_duration": auto_archive_duration,
+		}};
+		let body = serde_json::to_string(&map)?;
+		let response = request!(
+			self,
+			post(body),
+			"/channels/{}/messages/{}/threads",
+			channel,
+			message
+		);
+		from_reader(response)
+	}
+
+	/// Create a new thread which is not connected to an existing message.
+	///
+	/// The `kind` should be `PublicThread`, `PrivateThread`, or `NewsThread`.
+	/// See `start_thread_with_message` for `auto_archive_duration`.
+	pub fn start_thread_without_message(
+		&self,
+		channel: ChannelId,
+		name: &str,
+		kind: ChannelType,
+		auto_archive_duration: u64,
+	) -> Result<ThreadChannel> {
+		let map = json! {{
+			"name": name,
+			"type": kind.num(),
+			"auto_archive_duration": auto_archive_duration,
+		}};
+		let body = serde_json::to_string(&map)?;
+		let response = request!(self, post(body), "/channels/{}/threads", channel);
+		from_reader(response)
+	}
+
+	/// Create a new post in a forum channel.
+	///
+	/// A post is a thread whose initial message is built by `f`, as in
+	/// `send_message_ex`. The `applied_tags` must be taken from the forum
+	/// channel's `available_tags`.
+	pub fn create_forum_post<F: FnOnce(SendMessage) -> SendMessage>(
+		&self,
+		channel: ChannelId,
+		name: &str,
+		applied_tags: &[ForumTagId],
+		f: F,
+	) -> Result<ThreadChannel> {
+		let map = json! {{
+			"name": name,
+			"applied_tags": applied_tags,
+			"message": SendMessage::__build(f),
+		}};
+		let body = serde_json::to_string(&map)?;
+		let response = request!(self, post(body), "/channels/{}/threads", channel);
+		from_reader(response)
+	}
+
+	/// Add the logged-in user to a thread. The thread must not be archived.
+	pub fn join_thread(&self, thread: ChannelId) -> Result<()> {
+		check_empty(request!(
+			self,
+			put,
+			"/channels/{}/thread-members/@me",
+			thread
+		))
+	}
+
+	/// Add another member to a thread.
+	///
+	/// Requires the ability to send messages in the thread, and that the
+	/// thread is not archived.
+	pub fn add_thread_member(&self, thread: ChannelId, user: UserId) -> Result<()> {
+		check_empty(request!(
+			self,
+			put,
+			"/channels/{}/thread-members/{}",
+			thread,
+			user
+		))
+	}
+
+	/// Get all active threads in a server, including public and private
+	/// threads.
+	pub fn list_active_threads(&self, server: ServerId) -> Result<ThreadList> {
+		let response = request!(self, get, "/guilds/{}/threads/active", server);
+		from_reader(response)
+	}
+
+	/// Get archived threads in a channel, most recently archived first.
+	///
+	/// If `private` is true, private threads are listed instead of public
+	/// ones, which requires the `MANAGE_THREADS` permission. Only threads
+	/// archived before `before` are returned, if it is specified.
+	pub fn list_archived_threads(
+		&self,
+		channel: ChannelId,
+		private: bool,
+		before: Option<chrono::DateTime<chrono::FixedOffset>>,
+		limit: Option<u64>,
+	) -> Result<ThreadList> {
+		use std::fmt::Write;
+		let mut url = format!(
+			api_concat!("/channels/{}/threads/archived/{}?limit={}"),
+			channel,
+			if private { "private" } else { "public" },
+			limit.unwrap_or(50)
+		);
+		if let Some(before) = before {
+			let before = before.with_timezone(&chrono::Utc);
+			let _ = write!(
+				url,
+				"&before={}",
+				before.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+			);
+		}
+		let response = self.request(&url, || self.client.get(&url))?;
+		from_reader(response)
+	}
+
+	/// Get the list of servers this user knows about.
+	pub fn get_servers(&self) -> Result<Vec<ServerInfo>> {
+		let response = request!(self, get, "/users/@me/guilds");
+		from_reader(response)
+	}
+
+	/// Gets a specific server.
+	pub fn get_server(&self, server_id: ServerId) -> Result<Server> {
+		let response = request!(self, get, "/guilds/{}", server_id);
+		from_reader(response)
+	}
+
+	/// Gets a specific server's information, including its approximate
+	/// member and online member counts.
+	pub fn get_server_with_counts(&self, server_id: ServerId) -> Result<Server> {
+		let response = request!(self, get, "/guilds/{}?with_counts=true", server_id);
+		from_reader(response)
+	}
+
+	/// Gets the list of a specific server's members.
+	#[deprecated(note = "`after` is a user ID, not a count. Use `get_members` instead.")]
+	pub fn get_server_members(&self, server_id: ServerId, limit: Option<u32>, after: Option<u32>) -> Result<Vec<Member>> {
+		let limit = limit.unwrap_or(1);
+		let after = after.unwrap_or(0);
+		let response = request!(self, get, "/guilds/{}/members?limit={}&after={}", server_id, limit, after);
+		from_reader(response)
+	}
+
+	/// Gets a page of a server's members, ordered by user ID.
+	///
+	/// Up to `limit` members (default 1, maximum 1000) whose IDs follow
+	/// `after` are returned. To enumerate every member, pass the ID of the last
+	/// member of each page as `after` for the next, until a page comes back
+	/// short. Requires the server members intent.
+	pub fn get_members(
+		&self,
+		server: ServerId,
+		limit: Option<u64>,
+		after: Option<UserId>,
+	) -> Result<Vec<Member>> {
+		use std::fmt::Write;
+		let limit = limit.unwrap_or(1);
+		check_member_limit(limit)?;
+		let mut url = format!(api_concat!("/guilds/{}/members?limit={}"), server, limit);
+		if let Some(after) = after {
+			let _ = write!(url, "&after={}", after);
+		}
+		let response = self.request(&url, || self.client.get(&url))?;
+		from_reader(response)
+	}
+
+	/// Search a server's members for those whose username or nickname begins
+	/// with `query`.
+	///
+	/// Up to `limit` members (default 1, maximum 1000) are returned.
+	pub fn search_members(
+		&self,
+		server: ServerId,
+		query: &str,
+		limit: Option<u64>,
+	) -> Result<Vec<Member>> {
+		let limit = limit.unwrap_or(1);
+		check_member_limit(limit)?;
+		let url = format!(
+			api_concat!("/guilds/{}/members/search?query={}&limit={}"),
+			server,
+			percent_encode(query),
+			limit
+		);
+		let response = self.request(&url, || self.client.get(&url))?;
 		from_reader(response)
 	}
 
 	/// Create a new server with the given name.
-	pub fn create_server(&self, name: &str, region: &str, icon: Option<&str>) -> Result<Server> {
+	pub fn create_server(&self, name: &str, region: Region, icon: Option<&str>) -> Result<Server> {
 		let map = json! {{
 			"name": name,
-			"region": region,
+			"region": region.name(),
 			"icon": icon,
 		}};
 		let body = serde_json::to_string(&map)?;
@@ -962,7 +1740,7 @@ impl Discord {
 	///     .name("My Cool Server")
 	///     .icon(Some("data:image/jpg;base64,..."))
 	///     .afk_timeout(300)
-	///     .region("us-south")
+	///     .region(Region::UsSouth)
 	/// );
 	/// ```
 	pub fn edit_server<F: FnOnce(EditServer) -> EditServer>(
@@ -1030,9 +1808,250 @@ impl Discord {
 		))
 	}
 
+	/// Get the stickers uploaded to a server.
+	pub fn get_guild_stickers(&self, server: ServerId) -> Result<Vec<Sticker>> {
+		let response = request!(self, get, "/guilds/{}/stickers", server);
+		from_reader(response)
+	}
+
+	/// Upload a sticker to a server.
+	///
+	/// The file must be a PNG, APNG, GIF, or Lottie JSON file of at most 512
+	/// KB. `tags` is used for autocomplete and suggestions. Requires the
+	/// `MANAGE_EMOJIS` permission.
+	pub fn create_guild_sticker<R: ::std::io::Read>(
+		&self,
+		server: ServerId,
+		name: &str,
+		description: &str,
+		tags: &str,
+		filename: &str,
+		mut file: R,
+	) -> Result<Sticker> {
+		let cr = multipart::mock::ClientRequest::default();
+		let mut multi = multipart::client::Multipart::from_request(cr)?;
+		multi.write_text("name", name)?;
+		multi.write_text("description", description)?;
+		multi.write_text("tags", tags)?;
+		multi.write_stream("file", &mut file, Some(filename), None)?;
+		let url = format!(api_concat!("/guilds/{}/stickers"), server);
+		let response = self.send_multipart(&url, multi.send()?)?;
+		from_reader(response)
+	}
+
+	/// Edit a server's sticker. See `EditSticker` for the editable fields.
+	///
+	/// Requires the `MANAGE_EMOJIS` permission.
+	pub fn edit_guild_sticker<F: FnOnce(EditSticker) -> EditSticker>(
+		&self,
+		server: ServerId,
+		sticker: StickerId,
+		f: F,
+	) -> Result<Sticker> {
+		let map = EditSticker::__build(f);
+		let body = serde_json::to_string(&map)?;
+		let response = request!(self, patch(body), "/guilds/{}/stickers/{}", server, sticker);
+		from_reader(response)
+	}
+
+	/// Delete a server's sticker.
+	///
+	/// Requires the `MANAGE_EMOJIS` permission.
+	pub fn delete_guild_sticker(&self, server: ServerId, sticker: StickerId) -> Result<()> {
+		check_empty(request!(
+			self,
+			delete,
+			"/guilds/{}/stickers/{}",
+			server,
+			sticker
+		))
+	}
+
+	/// Get the scheduled events of a server, optionally including the number
+	/// of interested users in each.
+	pub fn get_scheduled_events(
+		&self,
+		server: ServerId,
+		with_user_count: bool,
+	) -> Result<Vec<ScheduledEvent>> {
+		let response = request!(
+			self,
+			get,
+			"/guilds/{}/scheduled-events?with_user_count={}",
+			server,
+			with_user_count
+		);
+		from_reader(response)
+	}
+
+	/// Get a scheduled event, optionally including the number of interested
+	/// users.
+	pub fn get_scheduled_event(
+		&self,
+		server: ServerId,
+		event: ScheduledEventId,
+		with_user_count: bool,
+	) -> Result<ScheduledEvent> {
+		let response = request!(
+			self,
+			get,
+			"/guilds/{}/scheduled-events/{}?with_user_count={}",
+			server,
+			event,
+			with_user_count
+		);
+		from_reader(response)
+	}
+
+	/// Create a scheduled event in a server. See `EditScheduledEvent` for the
+	/// other fields, of which `channel` is required for stage and voice events
+	/// and `location` and `end_time` are required for external events.
+	///
+	/// ```ignore
+	/// discord.create_scheduled_event(server, "Game night", ScheduledEventType::Voice, start,
+	///     |e| e.channel(Some(voice_channel)).description("Bring snacks"));
+	/// ```
+	pub fn create_scheduled_event<F: FnOnce(EditScheduledEvent) -> EditScheduledEvent>(
+		&self,
+		server: ServerId,
+		name: &str,
+		kind: ScheduledEventType,
+		start_time: chrono::DateTime<chrono::FixedOffset>,
+		f: F,
+	) -> Result<ScheduledEvent> {
+		let mut map = EditScheduledEvent::__build(|b| {
+			f(b.name(name).entity_type(kind).start_time(start_time))
+		});
+		map.insert("privacy_level".into(), json!(ScheduledEventPrivacy::ServerOnly));
+		let body = serde_json::to_string(&map)?;
+		let response = request!(self, post(body), "/guilds/{}/scheduled-events", server);
+		from_reader(response)
+	}
+
+	/// Edit a scheduled event. See `EditScheduledEvent` for the editable fields.
+	pub fn edit_scheduled_event<F: FnOnce(EditScheduledEvent) -> EditScheduledEvent>(
+		&self,
+		server: ServerId,
+		event: ScheduledEventId,
+		f: F,
+	) -> Result<ScheduledEvent> {
+		let map = EditScheduledEvent::__build(f);
+		let body = serde_json::to_string(&map)?;
+		let response = request!(
+			self,
+			patch(body),
+			"/guilds/{}/scheduled-events/{}",
+			server,
+			event
+		);
+		from_reader(response)
+	}
+
+	/// Delete a scheduled event.
+	pub fn delete_scheduled_event(&self, server: ServerId, event: ScheduledEventId) -> Result<()> {
+		check_empty(request!(
+			self,
+			delete,
+			"/guilds/{}/scheduled-events/{}",
+			server,
+			event
+		))
+	}
+
+	/// Get a page of the users interested in a scheduled event, ordered by
+	/// user ID.
+	///
+	/// Up to `limit` users (default 100, maximum 100) whose IDs follow `after`
+	/// are returned.
+	pub fn get_scheduled_event_users(
+		&self,
+		server: ServerId,
+		event: ScheduledEventId,
+		limit: Option<u64>,
+		after: Option<UserId>,
+	) -> Result<Vec<ScheduledEventUser>> {
+		use std::fmt::Write;
+		let limit = limit.unwrap_or(100);
+		if !(1..=100).contains(&limit) {
+			return Err(Error::OutOfRange("limit", 1, 100));
+		}
+		let mut url = format!(
+			api_concat!("/guilds/{}/scheduled-events/{}/users?limit={}"),
+			server,
+			event,
+			limit
+		);
+		if let Some(after) = after {
+			let _ = write!(url, "&after={}", after);
+		}
+		let response = self.request(&url, || self.client.get(&url))?;
+		from_reader(response)
+	}
+
 	/// Get the ban list for the given server.
-	pub fn get_bans(&self, server: ServerId) -> Result<Vec<Ban>> {
-		let response = request!(self, get, "/guilds/{}/bans", server);
+	///
+	/// Up to `limit` bans (default 1000, maximum 1000) are returned, ordered
+	/// by user ID. Pass the user ID of the last ban as `after` to fetch the
+	/// next page, or of the first ban as `before` to fetch the previous page.
+	pub fn get_bans(
+		&self,
+		server: ServerId,
+		limit: Option<u64>,
+		before: Option<UserId>,
+		after: Option<UserId>,
+	) -> Result<Vec<Ban>> {
+		use std::fmt::Write;
+		let limit = limit.unwrap_or(1000);
+		if !(1..=1000).contains(&limit) {
+			return Err(Error::OutOfRange("limit", 1, 1000));
+		}
+		let mut url = format!(api_concat!("/guilds/{}/bans?limit={}"), server, limit);
+		if let Some(before) = before {
+			let _ = write!(url, "&before={}", before);
+		}
+		if let Some(after) = after {
+			let _ = write!(url, "&after={}", after);
+		}
+		let response = self.request(&url, || self.client.get(&url))?;
+		from_reader(response)
+	}
+
+	/// Ban up to 200 users from the server at once, deleting their messages
+	/// from the last `delete_message_seconds` seconds (at most 7 days).
+	///
+	/// Requires the "BAN_MEMBERS" and "MANAGE_SERVER" permissions. Users who
+	/// could not be banned, such as those already banned, are listed in the
+	/// result's `failed_users`.
+	pub fn bulk_ban(
+		&self,
+		server: ServerId,
+		users: &[UserId],
+		delete_message_seconds: u64,
+	) -> Result<BulkBan> {
+		self.bulk_ban_with_reason(server, users, delete_message_seconds, "")
+	}
+
+	/// Ban up to 200 users from the server at once, recording the reason in
+	/// the server's audit log.
+	pub fn bulk_ban_with_reason(
+		&self,
+		server: ServerId,
+		users: &[UserId],
+		delete_message_seconds: u64,
+		reason: &str,
+	) -> Result<BulkBan> {
+		if users.is_empty() || users.len() > 200 {
+			return Err(Error::OutOfRange("users", 1, 200));
+		}
+		if delete_message_seconds > 604800 {
+			return Err(Error::OutOfRange("delete_message_seconds", 0, 604800));
+		}
+		let map = json! {{
+			"user_ids": users,
+			"delete_message_seconds": delete_message_seconds,
+		}};
+		let body = serde_json::to_string(&map)?;
+		let response = request!(self, reason(reason), post(body), "/guilds/{}/bulk-ban", server);
 		from_reader(response)
 	}
 
@@ -1040,19 +2059,101 @@ impl Discord {
 	///
 	/// Zero may be passed for `delete_message_days` if no deletion is desired.
 	pub fn add_ban(&self, server: ServerId, user: UserId, delete_message_days: u32) -> Result<()> {
+		self.add_ban_with_reason(server, user, delete_message_days, "")
+	}
+
+	/// Ban a user from the server, recording the reason in the server's audit
+	/// log.
+	pub fn add_ban_with_reason(
+		&self,
+		server: ServerId,
+		user: UserId,
+		delete_message_days: u32,
+		reason: &str,
+	) -> Result<()> {
+		if delete_message_days > 7 {
+			return Err(Error::OutOfRange("delete_message_days", 0, 7));
+		}
+		self.add_ban_ex(server, user, delete_message_days as u64 * 86400, reason)
+	}
+
+	/// Ban a user from the server, deleting up to `delete_message_seconds`
+	/// (at most 604800, or seven days) of their recent messages.
+	///
+	/// The reason is recorded in the server's audit log if it is not empty.
+	pub fn add_ban_ex(
+		&self,
+		server: ServerId,
+		user: UserId,
+		delete_message_seconds: u64,
+		reason: &str,
+	) -> Result<()> {
+		if delete_message_seconds > 604800 {
+			return Err(Error::OutOfRange("delete_message_seconds", 0, 604800));
+		}
+		let map = json! {{
+			"delete_message_seconds": delete_message_seconds,
+		}};
+		let body = serde_json::to_string(&map)?;
 		check_empty(request!(
 			self,
-			put,
-			"/guilds/{}/bans/{}?delete_message_days={}",
+			reason(reason),
+			put(body),
+			"/guilds/{}/bans/{}",
 			server,
-			user,
-			delete_message_days
+			user
 		))
 	}
 
 	/// Unban a user from the server.
 	pub fn remove_ban(&self, server: ServerId, user: UserId) -> Result<()> {
-		check_empty(request!(self, delete, "/guilds/{}/bans/{}", server, user))
+		self.remove_ban_with_reason(server, user, "")
+	}
+
+	/// Unban a user from the server, recording the reason in the server's
+	/// audit log.
+	pub fn remove_ban_with_reason(&self, server: ServerId, user: UserId, reason: &str) -> Result<()> {
+		check_empty(request!(
+			self,
+			reason(reason),
+			delete,
+			"/guilds/{}/bans/{}",
+			server,
+			user
+		))
+	}
+
+	/// Pause invites and direct messages in a server, as during a raid.
+	///
+	/// Each time may be at most 24 hours in the future. Passing `None`
+	/// re-enables invites or direct messages respectively. Requires the
+	/// `MANAGE_SERVER` permission.
+	pub fn set_incident_actions(
+		&self,
+		server: ServerId,
+		invites_disabled_until: Option<chrono::DateTime<chrono::FixedOffset>>,
+		dms_disabled_until: Option<chrono::DateTime<chrono::FixedOffset>>,
+	) -> Result<IncidentActions> {
+		let limit = chrono::Utc::now() + chrono::Duration::hours(24);
+		if invites_disabled_until.map_or(false, |time| time > limit) {
+			return Err(Error::Validation(
+				"invites_disabled_until",
+				"may be at most 24 hours in the future",
+			));
+		}
+		if dms_disabled_until.map_or(false, |time| time > limit) {
+			return Err(Error::Validation(
+				"dms_disabled_until",
+				"may be at most 24 hours in the future",
+			));
+		}
+		let map = json! {{
+			"invites_disabled_until": invites_disabled_until.map(|time| time.to_rfc3339()),
+			"dms_disabled_until": dms_disabled_until.map(|time| time.to_rfc3339()),
+		}};
+		let body = serde_json::to_string(&map)?;
+		let response = request!(self, put(body), "/guilds/{}/incident-actions", server);
+		from_reader(response)
 	}
 
 	/// Extract information from an invite.
@@ -1060,8 +2161,26 @@ impl Discord {
 	/// The invite should either be a URL of the form `http://discord.gg/CODE`,
 	/// or a string containing just the `CODE`.
 	pub fn get_invite(&self, invite: &str) -> Result<Invite> {
+		self.get_invite_with(invite, false, false)
+	}
+
+	/// Extract information from an invite, optionally including approximate
+	/// member counts and the invite's expiration time.
+	pub fn get_invite_with(
+		&self,
+		invite: &str,
+		with_counts: bool,
+		with_expiration: bool,
+	) -> Result<Invite> {
 		let invite = resolve_invite(invite);
-		let response = request!(self, get, "/invite/{}", invite);
+		let response = request!(
+			self,
+			get,
+			"/invite/{}?with_counts={}&with_expiration={}",
+			invite,
+			with_counts,
+			with_expiration
+		);
 		Invite::decode(serde_json::from_reader(response)?)
 	}
 
@@ -1095,12 +2214,24 @@ impl Discord {
 		max_uses: u64,
 		temporary: bool,
 	) -> Result<RichInvite> {
-		let map = json! {{
-			"validate": null,
-			"max_age": max_age,
-			"max_uses": max_uses,
-			"temporary": temporary,
-		}};
+		self.create_invite_ex(channel, |i| i.max_age(max_age).max_uses(max_uses).temporary(temporary))
+	}
+
+	/// Create an invite to a channel, with full control over its options.
+	///
+	/// ```ignore
+	/// // invite people to watch a user's stream
+	/// let invite = discord.create_invite_ex(voice_channel, |i| i
+	///     .max_age(3600)
+	///     .unique(true)
+	///     .target_user(streamer));
+	/// ```
+	pub fn create_invite_ex<F: FnOnce(CreateInvite) -> CreateInvite>(
+		&self,
+		channel: ChannelId,
+		f: F,
+	) -> Result<RichInvite> {
+		let map = CreateInvite::__build(f);
 		let body = serde_json::to_string(&map)?;
 		let response = request!(self, post(body), "/channels/{}/invites", channel);
 		RichInvite::decode(serde_json::from_reader(response)?)
@@ -1141,6 +2272,35 @@ impl Discord {
 		))
 	}
 
+	/// Add a role to many members of a server, one request per member.
+	///
+	/// Requests are paced by the client's rate limiting, so this may take some
+	/// time for many members. `progress` is called with the number of members
+	/// done and the total after each one; if an error stops the assignment,
+	/// pass `&members[done..]` to resume where it left off. Members who have
+	/// left the server are skipped.
+	pub fn assign_role_to_members<F: FnMut(usize, usize)>(
+		&self,
+		server: ServerId,
+		role: RoleId,
+		members: &[UserId],
+		mut progress: F,
+	) -> Result<()> {
+		for (i, &user) in members.iter().enumerate() {
+			match self.add_member_role(server, user, role) {
+				Ok(()) => {}
+				Err(Error::Status(hyper::status::StatusCode::NotFound, Some(ref body)))
+					if body.get("code").and_then(|c| c.as_u64()) == Some(UNKNOWN_MEMBER) =>
+				{
+					debug!("Not assigning {:?} to {:?}, who is not a member", role, user);
+				}
+				Err(err) => return Err(err),
+			}
+			progress(i + 1, members.len());
+		}
+		Ok(())
+	}
+
 	/// Remove a role for a member of a server.
 	pub fn remove_member_role(&self, server: ServerId, user: UserId, role: RoleId) -> Result<()> {
 		check_empty(request!(
@@ -1161,11 +2321,24 @@ impl Discord {
 		server: ServerId,
 		user: UserId,
 		f: F,
+	) -> Result<()> {
+		self.edit_member_with_reason(server, user, "", f)
+	}
+
+	/// Edit member information, recording the reason in the server's audit
+	/// log.
+	pub fn edit_member_with_reason<F: FnOnce(EditMember) -> EditMember>(
+		&self,
+		server: ServerId,
+		user: UserId,
+		reason: &str,
+		f: F,
 	) -> Result<()> {
 		let map = EditMember::__build(f);
 		let body = serde_json::to_string(&map)?;
 		check_empty(request!(
 			self,
+			reason(reason),
 			patch(body),
 			"/guilds/{}/members/{}",
 			server,
@@ -1173,6 +2346,19 @@ impl Discord {
 		))
 	}
 
+	/// Replace a member's flags, such as to let a manually approved member
+	/// bypass the server's verification requirements.
+	///
+	/// Similar to `edit_member`.
+	pub fn edit_member_flags(
+		&self,
+		server: ServerId,
+		user: UserId,
+		flags: MemberFlags,
+	) -> Result<()> {
+		self.edit_member(server, user, |m| m.flags(flags))
+	}
+
 	/// Nickname current user.
 	///
 	/// Similar to `edit_member`
@@ -1189,8 +2375,15 @@ impl Discord {
 
 	/// Kick a member from a server.
 	pub fn kick_member(&self, server: ServerId, user: UserId) -> Result<()> {
+		self.kick_member_with_reason(server, user, "")
+	}
+
+	/// Kick a member from a server, recording the reason in the server's audit
+	/// log.
+	pub fn kick_member_with_reason(&self, server: ServerId, user: UserId, reason: &str) -> Result<()> {
 		check_empty(request!(
 			self,
+			reason(reason),
 			delete,
 			"/guilds/{}/members/{}",
 			server,
@@ -1205,25 +2398,35 @@ impl Discord {
 	}
 
 	/// Create a new role on a server.
+	///
+	/// Fields given as `None` are left at Discord's defaults.
 	pub fn create_role(
 		&self,
 		server: ServerId,
 		name: Option<&str>,
 		permissions: Option<Permissions>,
-		color: Option<u64>,
+		color: Option<Color>,
 		hoist: Option<bool>,
 		mentionable: Option<bool>,
 	) -> Result<Role> {
-		let map = json! {{
-			"name": name,
-			"permissions": permissions,
-			"color": color,
-			"hoist": hoist,
-			"mentionable": mentionable,
-		}};
-		let body = serde_json::to_string(&map)?;
-		let response = request!(self, post(body), "/guilds/{}/roles", server);
-		Role::decode(serde_json::from_reader(response)?)
+		self.create_role_from_builder(server, |mut b| {
+			if let Some(name) = name {
+				b = b.name(name);
+			}
+			if let Some(permissions) = permissions {
+				b = b.permissions(permissions);
+			}
+			if let Some(color) = color {
+				b = b.color(color);
+			}
+			if let Some(hoist) = hoist {
+				b = b.hoist(hoist);
+			}
+			if let Some(mentionable) = mentionable {
+				b = b.mentionable(mentionable);
+			}
+			b
+		})
 	}
 
 	/// Create a new role on a server.
@@ -1281,6 +2484,61 @@ impl Discord {
 		PrivateChannel::decode(serde_json::from_reader(response)?)
 	}
 
+	/// Close a private channel with another user.
+	///
+	/// The message history is kept, and the channel can be reopened with
+	/// `create_private_channel`.
+	pub fn close_private_channel(&self, channel: ChannelId) -> Result<PrivateChannel> {
+		let response = request!(self, delete, "/channels/{}", channel);
+		PrivateChannel::decode(serde_json::from_reader(response)?)
+	}
+
+	/// Leave a group channel.
+	///
+	/// If this user owns the group, ownership passes to another recipient.
+	pub fn leave_group(&self, channel: ChannelId) -> Result<Group> {
+		let response = request!(self, delete, "/channels/{}", channel);
+		Group::decode(serde_json::from_reader(response)?)
+	}
+
+	/// Edit a group channel's name or icon. See `EditGroup` for the editable
+	/// fields.
+	pub fn edit_group<F: FnOnce(EditGroup) -> EditGroup>(
+		&self,
+		channel: ChannelId,
+		f: F,
+	) -> Result<Group> {
+		let map = EditGroup::__build(f);
+		let body = serde_json::to_string(&map)?;
+		let response = request!(self, patch(body), "/channels/{}", channel);
+		Group::decode(serde_json::from_reader(response)?)
+	}
+
+	/// Add a user to a group channel.
+	///
+	/// Groups are limited to 10 recipients, including the current user.
+	pub fn add_group_recipient(&self, channel: ChannelId, user: UserId) -> Result<()> {
+		check_empty(request!(
+			self,
+			put,
+			"/channels/{}/recipients/{}",
+			channel,
+			user
+		))
+	}
+
+	/// Remove a user from a group channel. Only the group's owner may remove
+	/// other recipients.
+	pub fn remove_group_recipient(&self, channel: ChannelId, user: UserId) -> Result<()> {
+		check_empty(request!(
+			self,
+			delete,
+			"/channels/{}/recipients/{}",
+			channel,
+			user
+		))
+	}
+
 	/// Get the URL at which a user's avatar is located.
 	pub fn get_user_avatar_url(&self, user: UserId, avatar: &str) -> String {
 		format!(api_concat!("/users/{}/avatars/{}.jpg"), user, avatar)
@@ -1382,53 +2640,322 @@ impl Discord {
 		from_reader(response)
 	}
 
-	/// Move a server member to another voice channel.
-	pub fn move_member_voice(
+	/// Get a user's voice state within a server.
+	pub fn get_voice_state(&self, server: ServerId, user: UserId) -> Result<VoiceState> {
+		let response = request!(self, get, "/guilds/{}/voice-states/{}", server, user);
+		from_reader(response)
+	}
+
+	/// Get the logged-in user's voice state within a server.
+	pub fn get_current_user_voice_state(&self, server: ServerId) -> Result<VoiceState> {
+		let response = request!(self, get, "/guilds/{}/voice-states/@me", server);
+		from_reader(response)
+	}
+
+	/// Edit the logged-in user's voice state in a stage channel. See
+	/// `EditVoiceState` for the editable fields.
+	///
+	/// Requires the "VOICE_MUTE_MEMBERS" permission to unsuppress oneself.
+	pub fn edit_voice_state<F: FnOnce(EditVoiceState) -> EditVoiceState>(
+		&self,
+		server: ServerId,
+		f: F,
+	) -> Result<()> {
+		let map = EditVoiceState::__build(f);
+		let body = serde_json::to_string(&map)?;
+		check_empty(request!(
+			self,
+			patch(body),
+			"/guilds/{}/voice-states/@me",
+			server
+		))
+	}
+
+	/// Edit another user's voice state in a stage channel, such as to invite
+	/// them to speak. See `EditVoiceState` for the editable fields.
+	///
+	/// Requires the "VOICE_MUTE_MEMBERS" permission.
+	pub fn edit_user_voice_state<F: FnOnce(EditVoiceState) -> EditVoiceState>(
+		&self,
+		server: ServerId,
+		user: UserId,
+		f: F,
+	) -> Result<()> {
+		let map = EditVoiceState::__build(f);
+		let body = serde_json::to_string(&map)?;
+		check_empty(request!(
+			self,
+			patch(body),
+			"/guilds/{}/voice-states/{}",
+			server,
+			user
+		))
+	}
+
+	/// Move a server member to another voice channel.
+	pub fn move_member_voice<C: IntoVoiceChannel>(
+		&self,
+		server: ServerId,
+		user: UserId,
+		channel: C,
+	) -> Result<()> {
+		let map = json! {{ "channel_id": channel.into() }};
+		let body = serde_json::to_string(&map)?;
+		check_empty(request!(
+			self,
+			patch(body),
+			"/guilds/{}/members/{}",
+			server,
+			user
+		))
+	}
+
+	/// Start a prune operation, kicking members who have been inactive for the
+	/// specified number of days. Members with a role assigned will never be
+	/// pruned.
+	///
+	/// `days` must be between 1 and 30.
+	pub fn begin_server_prune(&self, server: ServerId, days: u16) -> Result<ServerPrune> {
+		self.begin_server_prune_with_roles(server, days, &[])
+	}
+
+	/// Start a prune operation, also considering members who have any of the
+	/// given roles.
+	///
+	/// `days` must be between 1 and 30.
+	pub fn begin_server_prune_with_roles(
+		&self,
+		server: ServerId,
+		days: u16,
+		include_roles: &[RoleId],
+	) -> Result<ServerPrune> {
+		check_prune_days(days)?;
+		let map = json! {{
+			"days": days,
+			"include_roles": include_roles,
+		}};
+		let body = serde_json::to_string(&map)?;
+		let response = request!(self, post(body), "/guilds/{}/prune", server);
+		from_reader(response)
+	}
+
+	/// Get the number of members who have been inactive for the specified
+	/// number of days and would be pruned by a prune operation. Members with a
+	/// role assigned will never be pruned.
+	///
+	/// `days` must be between 1 and 30.
+	pub fn get_server_prune_count(&self, server: ServerId, days: u16) -> Result<ServerPrune> {
+		self.preview_server_prune(server, days, &[])
+			.map(|preview| ServerPrune {
+				pruned: preview.pruned,
+			})
+	}
+
+	/// Perform a dry run of a prune operation which also considers members who
+	/// have any of the given roles.
+	///
+	/// `days` must be between 1 and 30.
+	pub fn preview_server_prune(
+		&self,
+		server: ServerId,
+		days: u16,
+		include_roles: &[RoleId],
+	) -> Result<ServerPrunePreview> {
+		use std::fmt::Write;
+		check_prune_days(days)?;
+		let mut url = format!(api_concat!("/guilds/{}/prune?days={}"), server, days);
+		if !include_roles.is_empty() {
+			url.push_str("&include_roles=");
+			for (i, role) in include_roles.iter().enumerate() {
+				if i > 0 {
+					url.push(',');
+				}
+				let _ = write!(url, "{}", role.0);
+			}
+		}
+		let response = self.request(&url, || self.client.get(&url))?;
+		let prune: ServerPrune = from_reader(response)?;
+		Ok(ServerPrunePreview {
+			pruned: prune.pruned,
+			days: days,
+			include_roles: include_roles.to_vec(),
+		})
+	}
+
+	/// Sets a note for the user that is readable only to the currently logged
+	/// in user.
+	///
+	/// This endpoint is only available for users, and so does not work for
+	/// bots.
+	pub fn edit_note(&self, user: UserId, note: &str) -> Result<()> {
+		let map = json! {{ "note": note }};
+		let body = serde_json::to_string(&map)?;
+		check_empty(request!(self, put(body), "/users/@me/notes/{}", user))
+	}
+
+	/// Get the SKUs offered by an application.
+	pub fn get_skus(&self, application: ApplicationId) -> Result<Vec<Sku>> {
+		let response = request!(self, get, "/applications/{}/skus", application);
+		from_reader(response)
+	}
+
+	/// Get up to 100 of an application's entitlements which have not ended,
+	/// optionally only those granted to a given user or server.
+	///
+	/// To enumerate every entitlement, pass the ID of the last entitlement of
+	/// each page as `after` for the next, until a page comes back short.
+	pub fn get_entitlements(
+		&self,
+		application: ApplicationId,
+		owner: Option<EntitlementOwner>,
+		after: Option<EntitlementId>,
+	) -> Result<Vec<Entitlement>> {
+		use std::fmt::Write;
+		let mut url = format!(
+			api_concat!("/applications/{}/entitlements?exclude_ended=true"),
+			application
+		);
+		match owner {
+			Some(EntitlementOwner::User(user)) => {
+				let _ = write!(url, "&user_id={}", user);
+			}
+			Some(EntitlementOwner::Server(server)) => {
+				let _ = write!(url, "&guild_id={}", server);
+			}
+			None => {}
+		}
+		if let Some(after) = after {
+			let _ = write!(url, "&after={}", after);
+		}
+		let response = self.request(&url, || self.client.get(&url))?;
+		from_reader(response)
+	}
+
+	/// Mark a consumable entitlement as used up.
+	pub fn consume_entitlement(
+		&self,
+		application: ApplicationId,
+		entitlement: EntitlementId,
+	) -> Result<()> {
+		check_empty(request!(
+			self,
+			post,
+			"/applications/{}/entitlements/{}/consume",
+			application,
+			entitlement
+		))
+	}
+
+	/// Get an application's global commands, including their names and
+	/// descriptions in every locale.
+	pub fn get_global_commands(
+		&self,
+		application: ApplicationId,
+	) -> Result<Vec<ApplicationCommand>> {
+		let response = request!(
+			self,
+			get,
+			"/applications/{}/commands?with_localizations=true",
+			application
+		);
+		from_reader(response)
+	}
+
+	/// Register a command usable everywhere the application is installed,
+	/// replacing any global command with the same name and kind.
+	///
+	/// ```ignore
+	/// let mut names = BTreeMap::new();
+	/// names.insert("fr".to_owned(), "bonjour".to_owned());
+	/// discord.create_global_command(app, "hello", "Say hello", |c| c
+	///     .name_localizations(&names)
+	///     .option(CommandOptionType::User, "target", "Who to greet", |o| o.required(true)))?;
+	/// ```
+	pub fn create_global_command<F: FnOnce(CreateCommand) -> CreateCommand>(
+		&self,
+		application: ApplicationId,
+		name: &str,
+		description: &str,
+		f: F,
+	) -> Result<ApplicationCommand> {
+		let map = command_json(name, description, f);
+		let body = serde_json::to_string(&map)?;
+		let response = request!(self, post(body), "/applications/{}/commands", application);
+		from_reader(response)
+	}
+
+	/// Delete a global command.
+	pub fn delete_global_command(
 		&self,
-		server: ServerId,
-		user: UserId,
-		channel: ChannelId,
+		application: ApplicationId,
+		command: CommandId,
 	) -> Result<()> {
-		let map = json! {{ "channel_id": channel }};
-		let body = serde_json::to_string(&map)?;
 		check_empty(request!(
 			self,
-			patch(body),
-			"/guilds/{}/members/{}",
-			server,
-			user
+			delete,
+			"/applications/{}/commands/{}",
+			application,
+			command
 		))
 	}
 
-	/// Start a prune operation, kicking members who have been inactive for the
-	/// specified number of days. Members with a role assigned will never be
-	/// pruned.
-	pub fn begin_server_prune(&self, server: ServerId, days: u16) -> Result<ServerPrune> {
-		let map = json! {{ "days": days }};
-		let body = serde_json::to_string(&map)?;
-		let response = request!(self, post(body), "/guilds/{}/prune", server);
+	/// Get the commands an application has registered in a server, including
+	/// their names and descriptions in every locale.
+	pub fn get_server_commands(
+		&self,
+		application: ApplicationId,
+		server: ServerId,
+	) -> Result<Vec<ApplicationCommand>> {
+		let response = request!(
+			self,
+			get,
+			"/applications/{}/guilds/{}/commands?with_localizations=true",
+			application,
+			server
+		);
 		from_reader(response)
 	}
 
-	/// Get the number of members who have been inactive for the specified
-	/// number of days and would be pruned by a prune operation. Members with a
-	/// role assigned will never be pruned.
-	pub fn get_server_prune_count(&self, server: ServerId, days: u16) -> Result<ServerPrune> {
-		let map = json! {{ "days": days }};
+	/// Register a command usable only in the given server, replacing any
+	/// command there with the same name and kind.
+	///
+	/// Unlike global commands, server commands are available immediately,
+	/// which makes them convenient for testing.
+	pub fn create_server_command<F: FnOnce(CreateCommand) -> CreateCommand>(
+		&self,
+		application: ApplicationId,
+		server: ServerId,
+		name: &str,
+		description: &str,
+		f: F,
+	) -> Result<ApplicationCommand> {
+		let map = command_json(name, description, f);
 		let body = serde_json::to_string(&map)?;
-		let response = request!(self, get(body), "/guilds/{}/prune", server);
+		let response = request!(
+			self,
+			post(body),
+			"/applications/{}/guilds/{}/commands",
+			application,
+			server
+		);
 		from_reader(response)
 	}
 
-	/// Sets a note for the user that is readable only to the currently logged
-	/// in user.
-	///
-	/// This endpoint is only available for users, and so does not work for
-	/// bots.
-	pub fn edit_note(&self, user: UserId, note: &str) -> Result<()> {
-		let map = json! {{ "note": note }};
-		let body = serde_json::to_string(&map)?;
-		check_empty(request!(self, put(body), "/users/@me/notes/{}", user))
+	/// Delete a command registered in a server.
+	pub fn delete_server_command(
+		&self,
+		application: ApplicationId,
+		server: ServerId,
+		command: CommandId,
+	) -> Result<()> {
+		check_empty(request!(
+			self,
+			delete,
+			"/applications/{}/guilds/{}/commands/{}",
+			application,
+			server,
+			command
+		))
 	}
 
 	/// Retrieves information about the application and the owner.
@@ -1442,18 +2969,16 @@ impl Discord {
 	///
 	/// This endpoint is only available for bots.
 	pub fn suggested_shard_count(&self) -> Result<u64> {
+		self.get_bot_gateway().map(|gateway| gateway.shards)
+	}
+
+	/// Retrieves the gateway URL, suggested shard count, and the limit on
+	/// starting new sessions.
+	///
+	/// This endpoint is only available for bots.
+	pub fn get_bot_gateway(&self) -> Result<BotGateway> {
 		let response = request!(self, get, "/gateway/bot");
-		let mut value: Object = serde_json::from_reader(response)?;
-		match value.remove("shards") {
-			Some(value) => match value.as_u64() {
-				Some(shards) => Ok(shards),
-				None => Err(Error::Decode("Invalid \"shards\"", value)),
-			},
-			None => Err(Error::Decode(
-				"suggested_shard_count missing \"shards\"",
-				serde_json::Value::Object(value),
-			)),
-		}
+		from_reader(response)
 	}
 
 	/// Establish a websocket connection over which events can be received.
@@ -1477,8 +3002,8 @@ impl Discord {
 	/// See `connect` if you do not want to use guild sharding.
 	pub fn connect_sharded(
 		&self,
-		shard_id: u8,
-		total_shards: u8,
+		shard_id: u32,
+		total_shards: u32,
 	) -> Result<(Connection, ReadyEvent)> {
 		self.connection_builder()?.with_shard(shard_id, total_shards).connect()
 	}
@@ -1490,6 +3015,30 @@ impl Discord {
 		Ok(connection::ConnectionBuilder::new(url, &self.token))
 	}
 
+	/// Check the health of Discord and of this client's connection to it.
+	///
+	/// Combines the status page's unresolved incidents and active
+	/// maintenances with the round-trip time of a REST request. The
+	/// `gateway_latency` from a live `Connection`, if any, is included in the
+	/// report as given. Failures are recorded in the report rather than
+	/// returned, so that a report is always available.
+	pub fn get_status(&self, gateway_latency: Option<time::Duration>) -> HealthReport {
+		let started = time::Instant::now();
+		let rest_latency = match self.get_gateway_url() {
+			Ok(_) => Some(started.elapsed()),
+			Err(err) => {
+				warn!("REST health check failed: {:?}", err);
+				None
+			}
+		};
+		HealthReport {
+			incidents: get_unresolved_incidents().ok(),
+			maintenances: get_active_maintenances().ok(),
+			rest_latency: rest_latency,
+			gateway_latency: gateway_latency,
+		}
+	}
+
 	fn get_gateway_url(&self) -> Result<String> {
 		let response = request!(self, get, "/gateway");
 		let mut value: BTreeMap<String, String> = serde_json::from_reader(response)?;
@@ -1506,22 +3055,86 @@ fn from_reader<T: serde::de::DeserializeOwned, R: std::io::Read>(r: R) -> Result
 
 /// Read an image from a file into a string suitable for upload.
 ///
-/// If the file's extension is `.png`, the claimed media type will be `image/png`, or `image/jpg`
-/// otherwise. Note that Discord may convert the image to JPEG or another format after upload.
+/// The claimed media type is detected from the file's contents, falling back
+/// to its extension, and may be PNG, GIF, WebP, or JPEG. Note that Discord may
+/// convert the image to JPEG or another format after upload.
 pub fn read_image<P: AsRef<::std::path::Path>>(path: P) -> Result<String> {
 	use std::io::Read;
 	let path = path.as_ref();
 	let mut vec = Vec::new();
 	std::fs::File::open(path)?.read_to_end(&mut vec)?;
-	Ok(format!(
-		"data:image/{};base64,{}",
-		if path.extension() == Some("png".as_ref()) {
-			"png"
-		} else {
-			"jpg"
-		},
-		base64::encode(&vec),
-	))
+	let mime = image_mime(&vec).unwrap_or_else(|| {
+		match path
+			.extension()
+			.and_then(|e| e.to_str())
+			.map(|e| e.to_ascii_lowercase())
+		{
+			Some(ref ext) if ext == "png" => "image/png",
+			Some(ref ext) if ext == "gif" => "image/gif",
+			Some(ref ext) if ext == "webp" => "image/webp",
+			_ => "image/jpeg",
+		}
+	});
+	Ok(encode_image(&vec, mime))
+}
+
+/// Encode image data which is already in memory into a string suitable for
+/// upload, with the given media type such as `image/gif`.
+pub fn encode_image(data: &[u8], mime: &str) -> String {
+	format!("data:{};base64,{}", mime, base64::encode(data))
+}
+
+/// Detect the media type of image data from its magic bytes.
+fn image_mime(data: &[u8]) -> Option<&'static str> {
+	if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+		Some("image/png")
+	} else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+		Some("image/gif")
+	} else if data.starts_with(b"\xff\xd8\xff") {
+		Some("image/jpeg")
+	} else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+		Some("image/webp")
+	} else {
+		None
+	}
+}
+
+/// A cache of images read by `read_image`, for icons and avatars which are
+/// uploaded repeatedly.
+///
+/// Files are read again only if their modification time has changed.
+#[derive(Debug, Default)]
+pub struct ImageCache {
+	entries: std::collections::HashMap<std::path::PathBuf, (std::time::SystemTime, String)>,
+}
+
+impl ImageCache {
+	/// Create an empty cache.
+	pub fn new() -> ImageCache {
+		ImageCache::default()
+	}
+
+	/// Read an image as `read_image` would, reusing the previous result if
+	/// the file has not been modified since.
+	pub fn read_image<P: AsRef<::std::path::Path>>(&mut self, path: P) -> Result<&str> {
+		use std::collections::hash_map::Entry;
+		let path = path.as_ref();
+		let modified = std::fs::metadata(path)?.modified()?;
+		match self.entries.entry(path.to_owned()) {
+			Entry::Occupied(mut e) => {
+				if e.get().0 != modified {
+					*e.get_mut() = (modified, read_image(path)?);
+				}
+				Ok(&e.into_mut().1)
+			}
+			Entry::Vacant(e) => Ok(&e.insert((modified, read_image(path)?)).1),
+		}
+	}
+
+	/// Forget all cached images.
+	pub fn clear(&mut self) {
+		self.entries.clear();
+	}
 }
 
 /// Retrieves the current unresolved incidents from the status page.
@@ -1564,6 +3177,30 @@ pub fn get_upcoming_maintenances() -> Result<Vec<Maintenance>> {
 	}
 }
 
+/// The combined health of Discord and of this client, from `Discord::get_status`.
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+	/// Unresolved incidents, or `None` if the status page could not be reached
+	pub incidents: Option<Vec<Incident>>,
+	/// Active maintenances, or `None` if the status page could not be reached
+	pub maintenances: Option<Vec<Maintenance>>,
+	/// Round-trip time of a REST request, or `None` if it failed
+	pub rest_latency: Option<time::Duration>,
+	/// The heartbeat latency of the gateway connection, if one was given
+	pub gateway_latency: Option<time::Duration>,
+}
+
+impl HealthReport {
+	/// Whether the REST API is reachable and the status page reports no
+	/// unresolved incidents.
+	///
+	/// An unreachable status page is not considered unhealthy by itself.
+	pub fn is_healthy(&self) -> bool {
+		self.rest_latency.is_some()
+			&& self.incidents.as_ref().map_or(true, |incidents| incidents.is_empty())
+	}
+}
+
 /// Argument to `get_messages` to specify the desired message retrieval.
 pub enum GetMessages {
 	/// Get the N most recent messages.
@@ -1576,6 +3213,130 @@ pub enum GetMessages {
 	Around(MessageId),
 }
 
+/// Iterator over a channel's message history, created by `messages_iter`.
+pub struct MessagesIter<'a> {
+	discord: &'a Discord,
+	channel: ChannelId,
+	buffer: std::vec::IntoIter<Message>,
+	before: Option<MessageId>,
+	done: bool,
+}
+
+impl<'a> MessagesIter<'a> {
+	/// Begin iterating from before the given message rather than the most
+	/// recent message.
+	pub fn before(mut self, message: MessageId) -> Self {
+		self.before = Some(message);
+		self
+	}
+}
+
+impl<'a> Iterator for MessagesIter<'a> {
+	type Item = Result<Message>;
+
+	fn next(&mut self) -> Option<Result<Message>> {
+		if let Some(message) = self.buffer.next() {
+			return Some(Ok(message));
+		}
+		if self.done {
+			return None;
+		}
+		let what = match self.before {
+			Some(id) => GetMessages::Before(id),
+			None => GetMessages::MostRecent,
+		};
+		let page = match self.discord.get_messages(self.channel, what, Some(100)) {
+			Ok(page) => page,
+			Err(err) => {
+				self.done = true;
+				return Some(Err(err));
+			}
+		};
+		if page.len() < 100 {
+			self.done = true;
+		}
+		match page.last() {
+			Some(last) => self.before = Some(last.id),
+			None => return None,
+		}
+		self.buffer = page.into_iter();
+		self.buffer.next().map(Ok)
+	}
+}
+
+/// Iterator over the users who reacted to a message, created by
+/// `reactions_iter`.
+pub struct ReactionsIter<'a> {
+	discord: &'a Discord,
+	channel: ChannelId,
+	message: MessageId,
+	emoji: ReactionEmoji,
+	kind: ReactionType,
+	buffer: std::vec::IntoIter<User>,
+	after: Option<UserId>,
+	cursor: Option<UserId>,
+	done: bool,
+}
+
+impl<'a> ReactionsIter<'a> {
+	/// Iterate over the users who added the given kind of reaction, rather
+	/// than normal reactions.
+	pub fn of_type(mut self, kind: ReactionType) -> Self {
+		self.kind = kind;
+		self
+	}
+
+	/// Begin iterating from after the given user.
+	pub fn after(mut self, user: UserId) -> Self {
+		self.after = Some(user);
+		self.cursor = Some(user);
+		self
+	}
+
+	/// Get the ID of the last user yielded, from which an interrupted
+	/// iteration can be resumed.
+	pub fn cursor(&self) -> Option<UserId> {
+		self.cursor
+	}
+}
+
+impl<'a> Iterator for ReactionsIter<'a> {
+	type Item = Result<User>;
+
+	fn next(&mut self) -> Option<Result<User>> {
+		if let Some(user) = self.buffer.next() {
+			self.cursor = Some(user.id);
+			return Some(Ok(user));
+		}
+		if self.done {
+			return None;
+		}
+		let page = match self.discord.get_reactions_of_type(
+			self.channel,
+			self.message,
+			self.emoji.clone(),
+			self.kind,
+			Some(100),
+			self.after,
+		) {
+			Ok(page) => page,
+			Err(err) => {
+				self.done = true;
+				return Some(Err(err));
+			}
+		};
+		if page.len() < 100 {
+			self.done = true;
+		}
+		match page.last() {
+			Some(last) => self.after = Some(last.id),
+			None => return None,
+		}
+		self.buffer = page.into_iter();
+		self.next()
+	}
+}
+
 /// Send a request with the correct `UserAgent`, retrying it a second time if the
 /// connection is aborted the first time.
 fn retry<'a, F: Fn() -> hyper::client::RequestBuilder<'a>>(
@@ -1618,7 +3379,7 @@ fn check_empty(mut response: hyper::client::Response) -> Result<()> {
 		}
 		let mut content = String::new();
 		response.read_to_string(&mut content)?;
-		debug!("Content: {}", content);
+		debug!("Content: {}", error::redact_output(&content));
 	}
 	Ok(())
 }
@@ -1635,6 +3396,146 @@ fn resolve_invite(invite: &str) -> &str {
 	}
 }
 
+/// The `X-Audit-Log-Reason` header, percent-encoded as Discord requires.
+#[derive(Clone, Debug)]
+struct AuditLogReason(String);
+
+impl AuditLogReason {
+	fn new(reason: &str) -> Option<AuditLogReason> {
+		if reason.is_empty() {
+			return None;
+		}
+		Some(AuditLogReason(percent_encode(reason)))
+	}
+}
+
+/// Percent-encode all but the unreserved characters of a string.
+fn percent_encode(input: &str) -> String {
+	use std::fmt::Write;
+	let mut encoded = String::with_capacity(input.len());
+	for &byte in input.as_bytes() {
+		match byte {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+				encoded.push(byte as char)
+			}
+			_ => {
+				let _ = write!(encoded, "%{:02X}", byte);
+			}
+		}
+	}
+	encoded
+}
+
+impl hyper::header::Header for AuditLogReason {
+	fn header_name() -> &'static str {
+		"X-Audit-Log-Reason"
+	}
+
+	fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<AuditLogReason> {
+		hyper::header::parsing::from_one_raw_str(raw).map(AuditLogReason)
+	}
+}
+
+impl hyper::header::HeaderFormat for AuditLogReason {
+	fn fmt_header(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+/// The maximum length of a text or news channel's topic.
+const TOPIC_LIMIT: usize = 1024;
+/// The maximum length of a forum channel's topic, or guidelines.
+const FORUM_TOPIC_LIMIT: usize = 4096;
+
+fn validate_channel_name(name: &str) -> Result<()> {
+	let len = name.chars().count();
+	if !(1..=100).contains(&len) {
+		return Err(Error::Validation("name", "must be between 1 and 100 characters"));
+	}
+	if name.chars().any(char::is_control) {
+		return Err(Error::Validation("name", "must not contain control characters"));
+	}
+	Ok(())
+}
+
+fn validate_channel_edit(map: &Object, topic_limit: usize) -> Result<()> {
+	if let Some(name) = map.get("name").and_then(|v| v.as_str()) {
+		validate_channel_name(name)?;
+	}
+	if let Some(delay) = map.get("rate_limit_per_user").and_then(|v| v.as_u64()) {
+		if delay > 21600 {
+			return Err(Error::OutOfRange("rate_limit_per_user", 0, 21600));
+		}
+	}
+	if let Some(topic) = map.get("topic").and_then(|v| v.as_str()) {
+		if topic.chars().count() > topic_limit {
+			return Err(Error::Validation(
+				"topic",
+				if topic_limit == FORUM_TOPIC_LIMIT {
+					"must be at most 4096 characters for forum channels"
+				} else {
+					"must be at most 1024 characters"
+				},
+			));
+		}
+	}
+	Ok(())
+}
+
+fn command_json<F: FnOnce(CreateCommand) -> CreateCommand>(
+	name: &str,
+	description: &str,
+	f: F,
+) -> Object {
+	let mut map = CreateCommand::__build(f);
+	map.insert("name".into(), json!(name));
+	map.insert("description".into(), json!(description));
+	map
+}
+
+fn check_member_limit(limit: u64) -> Result<()> {
+	if !(1..=1000).contains(&limit) {
+		return Err(Error::OutOfRange("limit", 1, 1000));
+	}
+	Ok(())
+}
+
+fn check_prune_days(days: u16) -> Result<()> {
+	if !(1..=30).contains(&days) {
+		return Err(Error::OutOfRange("days", 1, 30));
+	}
+	Ok(())
+}
+
+/// How many times a message send is retried after a transient failure.
+const MESSAGE_RETRIES: usize = 3;
+
+/// The JSON error code for a user who is not a member of the server.
+const UNKNOWN_MEMBER: u64 = 10007;
+
+/// Whether a status code indicates a failure in front of Discord's API
+/// servers which may succeed if retried.
+fn is_transient(status: hyper::status::StatusCode) -> bool {
+	use hyper::status::StatusCode::*;
+	match status {
+		BadGateway | ServiceUnavailable | GatewayTimeout => true,
+		_ => false,
+	}
+}
+
+/// Generate a nonce which is unique to this process for correlating sends.
+fn generate_nonce() -> String {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	static COUNTER: AtomicUsize = AtomicUsize::new(0);
+	let millis = time::SystemTime::now()
+		.duration_since(time::UNIX_EPOCH)
+		.map(|d| d.as_secs() * 1000 + u64::from(d.subsec_nanos()) / 1_000_000)
+		.unwrap_or(0);
+	let count = COUNTER.fetch_add(1, Ordering::Relaxed) as u64;
+	// Discord limits nonces to 25 characters
+	format!("{}{:05}", millis, count % 100_000)
+}
+
 fn sleep_ms(millis: u64) {
 	std::thread::sleep(time::Duration::from_millis(millis))
 }
@@ -1645,6 +3546,10 @@ struct Timer {
 	tick_len: time::Duration,
 }
 
+/// How many ticks a `Timer` may fall behind before it skips ahead, rather than
+/// firing in a burst to catch up.
+const MAX_TIMER_LAG: u32 = 5;
+
 #[cfg_attr(not(feature = "voice"), allow(dead_code))]
 impl Timer {
 	fn new(tick_len_ms: u64) -> Timer {
@@ -1665,8 +3570,9 @@ impl Timer {
 	}
 
 	fn check_tick(&mut self) -> bool {
-		if time::Instant::now() >= self.next_tick_at {
-			self.next_tick_at = self.next_tick_at + self.tick_len;
+		let now = time::Instant::now();
+		if now >= self.next_tick_at {
+			self.advance(now);
 			true
 		} else {
 			false
@@ -1674,14 +3580,45 @@ impl Timer {
 	}
 
 	fn sleep_until_tick(&mut self) {
-		let now = time::Instant::now();
+		let mut now = time::Instant::now();
 		if self.next_tick_at > now {
 			std::thread::sleep(self.next_tick_at - now);
+			now = self.next_tick_at;
+		}
+		self.advance(now);
+	}
+
+	// Schedule the next tick, skipping missed ticks if the thread was stalled
+	// for too long, such as by a suspended process.
+	fn advance(&mut self, now: time::Instant) {
+		self.next_tick_at += self.tick_len;
+		if now > self.next_tick_at {
+			let lag = now - self.next_tick_at;
+			if lag > self.tick_len * MAX_TIMER_LAG {
+				warn!("Timer fell {:?} behind, skipping ahead", lag);
+				self.next_tick_at = now + self.tick_len;
+			}
 		}
-		self.next_tick_at = self.next_tick_at + self.tick_len;
 	}
 }
 
+/// Set the read timeout of the socket underlying a websocket stream.
+fn set_read_timeout(
+	stream: &websocket::stream::WebSocketStream,
+	timeout: Option<time::Duration>,
+) -> std::io::Result<()> {
+	use websocket::stream::WebSocketStream;
+	match *stream {
+		WebSocketStream::Tcp(ref inner) => inner.set_read_timeout(timeout),
+		WebSocketStream::Ssl(ref inner) => inner.lock().unwrap().get_ref().set_read_timeout(timeout),
+	}
+}
+
+/// Whether an I/O error was caused by a read timeout or a non-blocking read.
+fn is_timeout(err: &std::io::Error) -> bool {
+	err.kind() == std::io::ErrorKind::WouldBlock || err.kind() == std::io::ErrorKind::TimedOut
+}
+
 trait ReceiverExt {
 	fn recv_json<F, T>(&mut self, decode: F) -> Result<T>
 	where
@@ -1690,6 +3627,9 @@ trait ReceiverExt {
 
 trait SenderExt {
 	fn send_json(&mut self, value: &serde_json::Value) -> Result<()>;
+
+	/// Send a payload in the encoding negotiated with the main gateway.
+	fn send_gateway(&mut self, value: &serde_json::Value) -> Result<()>;
 }
 
 impl ReceiverExt for websocket::client::Receiver<websocket::stream::WebSocketStream> {
@@ -1706,23 +3646,7 @@ impl ReceiverExt for websocket::client::Receiver<websocket::stream::WebSocketStr
 				String::from_utf8_lossy(&message.payload).into_owned(),
 			))
 		} else if message.opcode == Type::Binary || message.opcode == Type::Text {
-			let mut payload_vec;
-			let payload = if message.opcode == Type::Binary {
-				use std::io::Read;
-				payload_vec = Vec::new();
-				flate2::read::ZlibDecoder::new(&message.payload[..])
-					.read_to_end(&mut payload_vec)?;
-				&payload_vec[..]
-			} else {
-				&message.payload[..]
-			};
-			serde_json::from_reader(payload)
-				.map_err(From::from)
-				.and_then(decode)
-				.map_err(|e| {
-					warn!("Error decoding: {}", String::from_utf8_lossy(payload));
-					e
-				})
+			decode_payload(&message.payload, message.opcode == Type::Binary, decode)
 		} else {
 			Err(Error::Closed(
 				None,
@@ -1732,6 +3656,42 @@ impl ReceiverExt for websocket::client::Receiver<websocket::stream::WebSocketStr
 	}
 }
 
+/// Decode the JSON payload of a text frame or a zlib-compressed binary frame.
+///
+/// With the `etf` feature, binary frames may also hold External Term Format,
+/// compressed or not.
+fn decode_payload<F, T>(payload: &[u8], compressed: bool, decode: F) -> Result<T>
+where
+	F: FnOnce(serde_json::Value) -> Result<T>,
+{
+	#[cfg(feature = "etf")]
+	let compressed = compressed && payload.first() != Some(&etf::VERSION);
+	let mut payload_vec;
+	let payload = if compressed {
+		use std::io::Read;
+		payload_vec = Vec::new();
+		flate2::read::ZlibDecoder::new(payload).read_to_end(&mut payload_vec)?;
+		&payload_vec[..]
+	} else {
+		payload
+	};
+	#[cfg(feature = "etf")]
+	let value = if payload.first() == Some(&etf::VERSION) {
+		etf::decode(payload)
+	} else {
+		serde_json::from_reader(payload).map_err(From::from)
+	};
+	#[cfg(not(feature = "etf"))]
+	let value = serde_json::from_reader(payload).map_err(From::from);
+	value
+		.and_then(decode)
+		.map_err(|e| {
+			let payload = String::from_utf8_lossy(payload);
+			warn!("Error decoding: {}", error::redact_output(&payload));
+			e
+		})
+}
+
 impl SenderExt for websocket::client::Sender<websocket::stream::WebSocketStream> {
 	fn send_json(&mut self, value: &serde_json::Value) -> Result<()> {
 		use websocket::message::Message;
@@ -1741,6 +3701,19 @@ impl SenderExt for websocket::client::Sender<websocket::stream::WebSocketStream>
 			.map_err(Error::from)
 			.and_then(|m| self.send_message(&m).map_err(Error::from))
 	}
+
+	#[cfg(feature = "etf")]
+	fn send_gateway(&mut self, value: &serde_json::Value) -> Result<()> {
+		use websocket::message::Message;
+		use websocket::ws::sender::Sender;
+		self.send_message(&Message::binary(etf::encode(value)))
+			.map_err(Error::from)
+	}
+
+	#[cfg(not(feature = "etf"))]
+	fn send_gateway(&mut self, value: &serde_json::Value) -> Result<()> {
+		self.send_json(value)
+	}
 }
 
 mod internal {
@@ -1752,3 +3725,182 @@ mod internal {
 		Aborted,
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::io::{self, Cursor, Read, Write};
+	use std::net::SocketAddr;
+	use std::sync::{Arc, Mutex};
+
+	use hyper::net::{NetworkConnector, NetworkStream};
+
+	const NO_CONTENT: &[u8] = b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n";
+	const UNAVAILABLE: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n";
+	const FORBIDDEN: &[u8] = b"HTTP/1.1 403 Forbidden\r\nContent-Length: 2\r\n\r\n{}";
+
+	/// Records requests and answers each with the next queued response, or
+	/// an empty response once none are left.
+	#[derive(Clone, Default)]
+	struct MockConnector {
+		sent: Arc<Mutex<Vec<u8>>>,
+		responses: Arc<Mutex<Vec<&'static [u8]>>>,
+	}
+
+	impl MockConnector {
+		fn respond_with(responses: &[&'static [u8]]) -> MockConnector {
+			let connector = MockConnector::default();
+			connector.responses.lock().unwrap().extend(responses.iter().rev());
+			connector
+		}
+
+		fn sent(&self) -> String {
+			String::from_utf8(self.sent.lock().unwrap().clone()).unwrap()
+		}
+	}
+
+	struct MockStream {
+		sent: Arc<Mutex<Vec<u8>>>,
+		response: Cursor<&'static [u8]>,
+	}
+
+	impl NetworkConnector for MockConnector {
+		type Stream = MockStream;
+		fn connect(&self, _: &str, _: u16, _: &str) -> hyper::Result<MockStream> {
+			let response = self.responses.lock().unwrap().pop().unwrap_or(NO_CONTENT);
+			Ok(MockStream {
+				sent: self.sent.clone(),
+				response: Cursor::new(response),
+			})
+		}
+	}
+
+	fn mock_discord(connector: &MockConnector) -> Discord {
+		Discord {
+			rate_limits: RateLimits::default(),
+			client: hyper::Client::with_connector(connector.clone()),
+			token: "Bot token".to_owned(),
+			middleware: Vec::new(),
+		}
+	}
+
+	/// The JSON bodies of the requests sent so far.
+	fn sent_bodies(connector: &MockConnector) -> Vec<serde_json::Value> {
+		connector
+			.sent()
+			.split("\r\n\r\n")
+			.filter_map(|body| serde_json::Deserializer::from_str(body).into_iter().next())
+			.filter_map(|body| body.ok())
+			.collect()
+	}
+
+	impl Read for MockStream {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			self.response.read(buf)
+		}
+	}
+
+	impl Write for MockStream {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.sent.lock().unwrap().extend_from_slice(buf);
+			Ok(buf.len())
+		}
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	impl NetworkStream for MockStream {
+		fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+			Ok(([127, 0, 0, 1], 443).into())
+		}
+		fn set_read_timeout(&self, _: Option<time::Duration>) -> io::Result<()> {
+			Ok(())
+		}
+		fn set_write_timeout(&self, _: Option<time::Duration>) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	struct Tracing;
+
+	impl Middleware for Tracing {
+		fn before_request(&self, _: &str, headers: &mut hyper::header::Headers) {
+			headers.set_raw("X-Trace", vec![b"abc".to_vec()]);
+			headers.set(hyper::header::Authorization("stolen".to_owned()));
+		}
+	}
+
+	#[test]
+	fn test_request_headers() {
+		let connector = MockConnector::default();
+		let mut discord = mock_discord(&connector);
+		discord.add_middleware(Tracing);
+		let url = api_concat!("/channels/1/messages/2");
+		discord
+			.request_with_reason(url, "tidy up", || discord.client.delete(url))
+			.unwrap();
+
+		let sent = connector.sent();
+		let lines: Vec<&str> = sent.lines().collect();
+		assert_eq!(lines[0], "DELETE /api/v6/channels/1/messages/2 HTTP/1.1");
+		for header in &[
+			"Authorization: Bot token",
+			"Content-Type: application/json",
+			"X-Audit-Log-Reason: tidy%20up",
+			"X-Trace: abc",
+		] {
+			assert!(lines.contains(header), "{} missing from {:?}", header, lines);
+		}
+		assert!(lines.iter().any(|line| line.starts_with("User-Agent: ")));
+	}
+
+	#[test]
+	fn test_send_message_retry() {
+		let connector = MockConnector::respond_with(&[UNAVAILABLE, FORBIDDEN]);
+		let discord = mock_discord(&connector);
+		let result = discord.send_message_ex(ChannelId(1), |m| m.content("hi"));
+		assert!(result.is_err());
+
+		// the retry repeats the generated nonce, which Discord enforces
+		let bodies = sent_bodies(&connector);
+		assert_eq!(bodies.len(), 2);
+		assert_eq!(bodies[0], bodies[1]);
+		assert!(bodies[0]["nonce"].as_str().map_or(false, |n| !n.is_empty()));
+		assert_eq!(bodies[0]["enforce_nonce"], true);
+
+		let connector = MockConnector::respond_with(&[FORBIDDEN]);
+		let discord = mock_discord(&connector);
+		let result = discord.send_message_ex(ChannelId(1), |m| m.content("hi").nonce("mine"));
+		assert!(result.is_err());
+		let bodies = sent_bodies(&connector);
+		assert_eq!(bodies.len(), 1);
+		assert_eq!(bodies[0]["nonce"], "mine");
+		assert!(bodies[0].get("enforce_nonce").is_none());
+	}
+
+	#[test]
+	fn test_channel_limits() {
+		fn edit(key: &str, value: serde_json::Value, topic_limit: usize) -> Result<()> {
+			let mut map = Object::new();
+			map.insert(key.to_owned(), value);
+			validate_channel_edit(&map, topic_limit)
+		}
+		let text = |len| json!("x".repeat(len));
+
+		assert!(validate_channel_name("x").is_ok());
+		assert!(validate_channel_name(&"é".repeat(100)).is_ok());
+		assert!(validate_channel_name("").is_err());
+		assert!(validate_channel_name(&"é".repeat(101)).is_err());
+		assert!(validate_channel_name("new\nline").is_err());
+		assert!(edit("name", text(101), TOPIC_LIMIT).is_err());
+
+		assert!(edit("rate_limit_per_user", json!(21600), TOPIC_LIMIT).is_ok());
+		assert!(edit("rate_limit_per_user", json!(21601), TOPIC_LIMIT).is_err());
+
+		assert!(edit("topic", text(1024), TOPIC_LIMIT).is_ok());
+		assert!(edit("topic", text(1025), TOPIC_LIMIT).is_err());
+		assert!(edit("topic", text(4096), FORUM_TOPIC_LIMIT).is_ok());
+		assert!(edit("topic", text(4097), FORUM_TOPIC_LIMIT).is_err());
+	}
+}
@@ -0,0 +1,310 @@
+//! A stand-in voice server on the local machine, for testing audio pipelines.
+//!
+//! A `LoopbackServer` speaks just enough of the voice protocol to complete a
+//! `VoiceConnection`'s handshake without contacting Discord. Audio sent to it
+//! is decrypted and recorded, then encrypted again and echoed back as though
+//! spoken by another user, so that both the sending and receiving halves of
+//! the Opus, encryption, and packet pipeline are exercised.
+//!
+//! ```ignore
+//! let server = LoopbackServer::start()?;
+//! let mut voice = server.connect(ServerId(1), UserId(3))?;
+//! voice.set_receiver(Box::new(MyReceiver));
+//! voice.play(voice::create_pcm_source(false, File::open("tone.pcm")?));
+//! thread::sleep(Duration::from_secs(1));
+//! assert!(!server.packets().is_empty());
+//! ```
+
+use std::net::{Shutdown, SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use serde_json::Value;
+use sodiumoxide::crypto::secretbox as crypto;
+use websocket::server::Server;
+use websocket::stream::WebSocketStream;
+
+use model::*;
+use voice::VoiceConnection;
+use {ReceiverExt, Result, SenderExt};
+
+/// The SSRC a `LoopbackServer` assigns to the connection sending audio.
+pub const CLIENT_SSRC: u32 = 1;
+/// The SSRC of the audio a `LoopbackServer` echoes back.
+pub const ECHO_SSRC: u32 = 2;
+/// The user a `LoopbackServer` attributes echoed audio to.
+pub const ECHO_USER: UserId = UserId(2);
+
+const CHANNEL: ChannelId = ChannelId(1);
+const HEADER_LEN: usize = 12;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A voice packet received by a `LoopbackServer`.
+#[derive(Debug, Clone)]
+pub struct LoopbackPacket {
+	/// The packet's sequence number, increasing by one per packet
+	pub sequence: u16,
+	/// The packet's timestamp, increasing at 48000Hz
+	pub timestamp: u32,
+	/// The SSRC of the connection which sent the packet
+	pub ssrc: u32,
+	/// The decrypted Opus data
+	pub opus: Vec<u8>,
+}
+
+/// A fake voice server listening on the local machine.
+///
+/// Voice connections are served one at a time, until the server is dropped.
+/// Dropping the server ends the connection it is serving, if any, and waits
+/// for its threads to finish.
+pub struct LoopbackServer {
+	endpoint: String,
+	ws_addr: SocketAddr,
+	packets: Arc<Mutex<Vec<LoopbackPacket>>>,
+	closed: Arc<AtomicBool>,
+	live: Arc<Mutex<Option<WebSocketStream>>>,
+	threads: Vec<JoinHandle<()>>,
+}
+
+impl LoopbackServer {
+	/// Start a server on ports chosen by the system.
+	pub fn start() -> Result<LoopbackServer> {
+		let mut server = Server::bind("127.0.0.1:0")?;
+		let udp = UdpSocket::bind("127.0.0.1:0")?;
+		udp.set_read_timeout(Some(Duration::from_millis(100)))?;
+		let ws_addr = server.local_addr()?;
+		let endpoint = format!("ws://{}", ws_addr);
+		let udp_port = udp.local_addr()?.port();
+		let key = crypto::gen_key();
+		let key_bytes = key.0.to_vec();
+		let packets = Arc::new(Mutex::new(Vec::new()));
+		let closed = Arc::new(AtomicBool::new(false));
+		let live = Arc::new(Mutex::new(None));
+
+		let ws_closed = closed.clone();
+		let ws_live = live.clone();
+		let ws_thread = thread::Builder::new()
+			.name("discord voice loopback (WS)".to_owned())
+			.spawn(move || {
+				while !ws_closed.load(Ordering::SeqCst) {
+					let result =
+						serve_websocket(&mut server, udp_port, &key_bytes, &ws_live, &ws_closed);
+					if let Err(err) = result {
+						debug!("Loopback voice connection ended: {:?}", err);
+					}
+				}
+			})?;
+
+		let udp_packets = packets.clone();
+		let udp_closed = closed.clone();
+		let udp_thread = thread::Builder::new()
+			.name("discord voice loopback (UDP)".to_owned())
+			.spawn(move || serve_udp(&udp, &key, &udp_packets, &udp_closed))?;
+
+		Ok(LoopbackServer {
+			endpoint: endpoint,
+			ws_addr: ws_addr,
+			packets: packets,
+			closed: closed,
+			live: live,
+			threads: vec![ws_thread, udp_thread],
+		})
+	}
+
+	/// Get the endpoint to pass along with a voice server update to connect
+	/// to this server.
+	#[inline]
+	pub fn endpoint(&self) -> &str {
+		&self.endpoint
+	}
+
+	/// Create a `VoiceConnection` for the given user and server, and connect
+	/// it to this server, waiting for the handshake to finish.
+	///
+	/// The connection has no main gateway connection, so changes to its
+	/// mute and deaf status are not sent anywhere.
+	pub fn connect(&self, server_id: ServerId, user_id: UserId) -> Result<VoiceConnection> {
+		let (main_ws, _) = mpsc::channel();
+		let mut voice = VoiceConnection::__new(Some(server_id), user_id, main_ws);
+		voice.__begin_wait();
+		voice.connect(CHANNEL);
+		voice.__update_state(&VoiceState {
+			server_id: Some(server_id),
+			user_id: user_id,
+			channel_id: Some(CHANNEL),
+			session_id: "loopback".to_owned(),
+			token: None,
+			suppress: false,
+			self_mute: false,
+			self_deaf: false,
+			mute: false,
+			deaf: false,
+			self_stream: false,
+			self_video: false,
+			request_to_speak_timestamp: None,
+		});
+		voice.__update_server(&Some(self.endpoint.clone()), "loopback");
+		voice.__wait_result(CONNECT_TIMEOUT)?;
+		Ok(voice)
+	}
+
+	/// Get the voice packets received so far, in the order they arrived.
+	pub fn packets(&self) -> Vec<LoopbackPacket> {
+		lock(&self.packets).clone()
+	}
+}
+
+impl Drop for LoopbackServer {
+	fn drop(&mut self) {
+		self.closed.store(true, Ordering::SeqCst);
+		// the websocket thread is blocked either serving a connection or
+		// accepting one, so end the former and supply the latter
+		if let Some(mut stream) = lock(&self.live).take() {
+			let _ = stream.shutdown(Shutdown::Both);
+		}
+		let _ = TcpStream::connect(self.ws_addr);
+		for thread in self.threads.drain(..) {
+			let _ = thread.join();
+		}
+	}
+}
+
+/// Accept one voice websocket connection and serve it until it closes.
+fn serve_websocket(
+	server: &mut Server,
+	udp_port: u16,
+	key: &[u8],
+	live: &Mutex<Option<WebSocketStream>>,
+	closed: &AtomicBool,
+) -> Result<()> {
+	let request = server.accept()?.read_request()?;
+	request.validate()?;
+	let (mut sender, mut receiver) = request.accept().send()?.split();
+	// checked after storing the stream, so that a drop sees one or the other
+	*lock(live) = Some(sender.get_mut().try_clone()?);
+	if closed.load(Ordering::SeqCst) {
+		return Ok(());
+	}
+
+	// wait for the identify, then offer the UDP port
+	receiver.recv_json(Ok)?;
+	sender.send_json(&json! {{
+		"op": 8,
+		"d": { "heartbeat_interval": 41250 }
+	}})?;
+	sender.send_json(&json! {{
+		"op": 2,
+		"d": {
+			"ssrc": CLIENT_SSRC,
+			"ip": "127.0.0.1",
+			"port": udp_port,
+			"modes": ["xsalsa20_poly1305"],
+		}
+	}})?;
+
+	loop {
+		let message: Value = receiver.recv_json(Ok)?;
+		match message["op"].as_u64() {
+			// select protocol
+			Some(1) => sender.send_json(&json! {{
+				"op": 4,
+				"d": {
+					"mode": "xsalsa20_poly1305",
+					"secret_key": key,
+				}
+			}})?,
+			// the echo speaks whenever the client does
+			Some(5) => sender.send_json(&json! {{
+				"op": 5,
+				"d": {
+					"user_id": ECHO_USER.0.to_string(),
+					"ssrc": ECHO_SSRC,
+					"speaking": message["d"]["speaking"].as_bool().unwrap_or(false),
+				}
+			}})?,
+			// heartbeats need no reply
+			_ => {}
+		}
+	}
+}
+
+/// Answer IP discovery and echo voice packets until the server is closed.
+fn serve_udp(
+	udp: &UdpSocket,
+	key: &crypto::Key,
+	packets: &Mutex<Vec<LoopbackPacket>>,
+	closed: &AtomicBool,
+) {
+	let mut buffer = [0; 2048];
+	while !closed.load(Ordering::SeqCst) {
+		let (len, source) = match udp.recv_from(&mut buffer) {
+			Ok(received) => received,
+			Err(_) => continue,
+		};
+		let msg = &buffer[..len];
+		let reply = if len == 74 && msg[..2] == [0, 1] {
+			discovery_reply(msg, source)
+		} else if len > HEADER_LEN && msg[0] == 0x80 {
+			echo(msg, key).map(|(packet, reply)| {
+				lock(packets).push(packet);
+				reply
+			})
+		} else {
+			// keepalives need no reply
+			None
+		};
+		if let Some(reply) = reply {
+			let _ = udp.send_to(&reply, source);
+		}
+	}
+}
+
+/// Lock a mutex, ignoring poisoning by a panicked server thread.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+	mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Build the response to an IP discovery request, reporting the address it
+/// came from.
+fn discovery_reply(mut msg: &[u8], source: SocketAddr) -> Option<Vec<u8>> {
+	msg.read_u32::<BigEndian>().ok()?;
+	let ssrc = msg.read_u32::<BigEndian>().ok()?;
+	let mut reply = Vec::with_capacity(74);
+	reply.write_u16::<BigEndian>(0x2).ok()?;
+	reply.write_u16::<BigEndian>(70).ok()?;
+	reply.write_u32::<BigEndian>(ssrc).ok()?;
+	let address = source.ip().to_string();
+	reply.extend_from_slice(address.as_bytes());
+	reply.resize(8 + 64, 0);
+	reply.write_u16::<BigEndian>(source.port()).ok()?;
+	Some(reply)
+}
+
+/// Decrypt a voice packet, returning it along with a copy to send back from
+/// `ECHO_SSRC`.
+fn echo(packet: &[u8], key: &crypto::Key) -> Option<(LoopbackPacket, Vec<u8>)> {
+	let mut header = &packet[2..HEADER_LEN];
+	let sequence = header.read_u16::<BigEndian>().ok()?;
+	let timestamp = header.read_u32::<BigEndian>().ok()?;
+	let ssrc = header.read_u32::<BigEndian>().ok()?;
+	let mut nonce = crypto::Nonce([0; 24]);
+	nonce.0[..HEADER_LEN].clone_from_slice(&packet[..HEADER_LEN]);
+	let opus = crypto::open(&packet[HEADER_LEN..], &nonce, key).ok()?;
+
+	let mut reply = packet[..HEADER_LEN].to_vec();
+	(&mut reply[8..]).write_u32::<BigEndian>(ECHO_SSRC).ok()?;
+	nonce.0[..HEADER_LEN].clone_from_slice(&reply);
+	reply.extend(crypto::seal(&opus, &nonce, key));
+	Some((
+		LoopbackPacket {
+			sequence: sequence,
+			timestamp: timestamp,
+			ssrc: ssrc,
+			opus: opus,
+		},
+		reply,
+	))
+}
@@ -0,0 +1,41 @@
+//! Hooks into the requests made by the REST client.
+
+use std::time::Duration;
+
+use hyper::client::Response;
+use hyper::header::Headers;
+
+/// Observes, and may add headers to, every request made by a `Discord`.
+///
+/// Registered with `Discord::add_middleware`. Middleware runs in the order it
+/// was added, on whichever thread makes the request. Both methods do nothing
+/// by default.
+///
+/// ```ignore
+/// struct Tracing;
+///
+/// impl Middleware for Tracing {
+///     fn before_request(&self, _url: &str, headers: &mut Headers) {
+///         headers.set_raw("traceparent", vec![new_trace_id().into_bytes()]);
+///     }
+///
+///     fn after_response(&self, url: &str, response: &Response, elapsed: Duration) {
+///         println!("{} {} in {:?}", response.status, url, elapsed);
+///     }
+/// }
+///
+/// discord.add_middleware(Tracing);
+/// ```
+pub trait Middleware: Send + Sync {
+	/// Called before a request to `url` is sent, with headers which will be
+	/// added to it. The client's own `Content-Type`, `Authorization`, and
+	/// audit log reason headers take precedence over any set here.
+	///
+	/// A request retried after a rate limit or an aborted connection is seen
+	/// again.
+	fn before_request(&self, _url: &str, _headers: &mut Headers) {}
+
+	/// Called once a response to a request to `url` is received, before it is
+	/// checked for errors, with the time taken to receive it.
+	fn after_response(&self, _url: &str, _response: &Response, _elapsed: Duration) {}
+}
@@ -46,6 +46,38 @@ macro_rules! serial_decode {
 	};
 }
 
+/// Deserialize a hand-decoded type using its `decode`, so that it reads the
+/// same format as is received from Discord. For types whose `decode` returns
+/// an `Option`, `None` is an error.
+macro_rules! deserialize_using_decode {
+	($typ:ident) => {
+		impl<'d> ::serde::Deserialize<'d> for $typ {
+			fn deserialize<D: ::serde::Deserializer<'d>>(
+				d: D,
+			) -> ::std::result::Result<$typ, D::Error> {
+				let value = <Value as ::serde::Deserialize>::deserialize(d)?;
+				$typ::decode(value).map_err(::serde::de::Error::custom)
+			}
+		}
+	};
+	($typ:ident as optional) => {
+		impl<'d> ::serde::Deserialize<'d> for $typ {
+			fn deserialize<D: ::serde::Deserializer<'d>>(
+				d: D,
+			) -> ::std::result::Result<$typ, D::Error> {
+				let value = <Value as ::serde::Deserialize>::deserialize(d)?;
+				match $typ::decode(value) {
+					Ok(Some(decoded)) => Ok(decoded),
+					Ok(None) => {
+						Err(::serde::de::Error::custom(concat!("empty ", stringify!($typ))))
+					}
+					Err(err) => Err(::serde::de::Error::custom(err)),
+				}
+			}
+		}
+	};
+}
+
 macro_rules! string_decode_using_serial_name {
 	($typ:ident) => {
 		impl FromStr for $typ {
@@ -141,6 +173,22 @@ id! {
 	RoleId;
 	/// An identifier for an Emoji
 	EmojiId;
+	/// An identifier for a tag in a forum channel
+	ForumTagId;
+	/// An identifier for a Sticker
+	StickerId;
+	/// An identifier for a ScheduledEvent
+	ScheduledEventId;
+	/// An identifier for a Sku
+	SkuId;
+	/// An identifier for an Entitlement
+	EntitlementId;
+	/// An identifier for a Webhook
+	WebhookId;
+	/// An identifier for an ApplicationCommand
+	CommandId;
+	/// An identifier for an Interaction
+	InteractionId;
 }
 
 impl ServerId {
@@ -156,6 +204,17 @@ impl ServerId {
 	pub fn everyone(self) -> RoleId {
 		RoleId(self.0)
 	}
+
+	/// Returns the URL of this server's widget image in the given style.
+	///
+	/// The widget must be enabled in the server's settings.
+	pub fn widget_image_url(self, style: WidgetStyle) -> String {
+		format!(
+			"https://discord.com/api/guilds/{}/widget.png?style={}",
+			self.0,
+			style.name()
+		)
+	}
 }
 
 /// A mention targeted at a specific user, channel, or other entity.
@@ -182,6 +241,24 @@ impl fmt::Display for Mention {
 	}
 }
 
+/// A kind of mention which may be allowed to notify in an outgoing message
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum MentionType {
+	/// Mentions of individual users
+	Users,
+	/// Mentions of roles
+	Roles,
+	/// `@everyone` and `@here`
+	Everyone,
+}
+
+serial_use_mapping!(MentionType, named);
+serial_names! { MentionType;
+	Users, "users";
+	Roles, "roles";
+	Everyone, "everyone";
+}
+
 impl UserId {
 	/// Return a `Mention` which will ping this user.
 	#[inline(always)]
@@ -215,6 +292,44 @@ impl ChannelId {
 	}
 }
 
+/// The ID of a channel known to hold messages, from
+/// `PublicChannel::expect_text`
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub struct TextChannelId(ChannelId);
+
+/// The ID of a channel known to be a voice or stage channel, from
+/// `PublicChannel::expect_voice`
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub struct VoiceChannelId(ChannelId);
+
+impl From<TextChannelId> for ChannelId {
+	fn from(id: TextChannelId) -> ChannelId {
+		id.0
+	}
+}
+
+impl From<VoiceChannelId> for ChannelId {
+	fn from(id: VoiceChannelId) -> ChannelId {
+		id.0
+	}
+}
+
+/// A channel ID accepted where only a text channel makes sense.
+///
+/// Implemented for unchecked `ChannelId`s and for `TextChannelId`, so passing
+/// a `VoiceChannelId` is a compile error.
+pub trait IntoTextChannel: Into<ChannelId> {}
+impl IntoTextChannel for ChannelId {}
+impl IntoTextChannel for TextChannelId {}
+
+/// A channel ID accepted where only a voice channel makes sense.
+///
+/// Implemented for unchecked `ChannelId`s and for `VoiceChannelId`, so passing
+/// a `TextChannelId` is a compile error.
+pub trait IntoVoiceChannel: Into<ChannelId> {}
+impl IntoVoiceChannel for ChannelId {}
+impl IntoVoiceChannel for VoiceChannelId {}
+
 #[test]
 fn mention_test() {
 	assert_eq!(UserId(1234).mention().to_string(), "<@1234>");
@@ -222,6 +337,80 @@ fn mention_test() {
 	assert_eq!(ChannelId(1234).mention().to_string(), "<#1234>");
 }
 
+/// The file format in which to request an image from the CDN.
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum ImageFormat {
+	Jpeg,
+	Png,
+	WebP,
+	/// Only available for animated images, whose hashes begin with `a_`
+	Gif,
+}
+
+impl ImageFormat {
+	/// Get the file extension for this format.
+	pub fn extension(self) -> &'static str {
+		match self {
+			ImageFormat::Jpeg => "jpg",
+			ImageFormat::Png => "png",
+			ImageFormat::WebP => "webp",
+			ImageFormat::Gif => "gif",
+		}
+	}
+}
+
+#[test]
+fn image_url_test() {
+	assert_eq!(
+		cdn_image_url("https://cdn.discordapp.com/icons/1/abc".into(), ImageFormat::WebP, Some(256)),
+		"https://cdn.discordapp.com/icons/1/abc.webp?size=256"
+	);
+	assert_eq!(
+		ServerId(1234).widget_image_url(WidgetStyle::Banner2),
+		"https://discord.com/api/guilds/1234/widget.png?style=banner2"
+	);
+}
+
+/// Append the format and optional size to a CDN path.
+///
+/// Sizes must be a power of two between 16 and 4096.
+fn cdn_image_url(mut url: String, format: ImageFormat, size: Option<u32>) -> String {
+	url.push('.');
+	url.push_str(format.extension());
+	if let Some(size) = size {
+		url.push_str("?size=");
+		url.push_str(&size.to_string());
+	}
+	url
+}
+
+/// The style of a server widget image
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum WidgetStyle {
+	/// A small shield showing the online member count
+	Shield,
+	/// A large banner with the server icon, name, and online count
+	Banner1,
+	/// A smaller banner with the server icon, name, and online count
+	Banner2,
+	/// A large banner with a "Chat Now" label
+	Banner3,
+	/// A large banner with a "Join My Server" label
+	Banner4,
+}
+
+impl WidgetStyle {
+	fn name(self) -> &'static str {
+		match self {
+			WidgetStyle::Shield => "shield",
+			WidgetStyle::Banner1 => "banner1",
+			WidgetStyle::Banner2 => "banner2",
+			WidgetStyle::Banner3 => "banner3",
+			WidgetStyle::Banner4 => "banner4",
+		}
+	}
+}
+
 //=================
 // Rest model
 
@@ -243,7 +432,7 @@ pub enum ChannelType {
 	Voice,
 	/// A channel category in a server
 	Category,
-	///
+	/// A text channel whose messages can be published to following servers
 	News,
 	///
 	Store,
@@ -319,14 +508,33 @@ pub struct ServerInfo {
 }
 serial_decode!(ServerInfo);
 
+fn server_image(
+	id: ServerId,
+	kind: &str,
+	hash: &Option<String>,
+	format: ImageFormat,
+	size: Option<u32>,
+) -> Option<String> {
+	hash.as_ref().map(|hash| {
+		cdn_image_url(
+			format!(cdn_concat!("/{}/{}/{}"), kind, id, hash),
+			format,
+			size,
+		)
+	})
+}
+
 impl ServerInfo {
 	/// Returns the formatted URL of the server's icon.
 	///
 	/// Returns None if the server does not have an icon.
 	pub fn icon_url(&self) -> Option<String> {
-		self.icon
-			.as_ref()
-			.map(|icon| format!(cdn_concat!("/icons/{}/{}.jpg"), self.id, icon))
+		server_image(self.id, "icons", &self.icon, ImageFormat::Jpeg, None)
+	}
+
+	/// Returns the URL of the server's icon in the given format and size.
+	pub fn icon_url_with(&self, format: ImageFormat, size: u32) -> Option<String> {
+		server_image(self.id, "icons", &self.icon, format, Some(size))
 	}
 }
 
@@ -339,7 +547,9 @@ pub struct Server {
 	pub afk_channel_id: Option<ChannelId>,
 	pub icon: Option<String>,
 	pub roles: Vec<Role>,
-	pub region: String,
+	#[serde(default)]
+	pub region: Option<Region>,
+	#[serde(default)]
 	pub embed_enabled: bool,
 	pub embed_channel_id: Option<ChannelId>,
 	pub owner_id: UserId,
@@ -347,8 +557,22 @@ pub struct Server {
 	pub emojis: Vec<Emoji>,
 	pub features: Vec<String>,
 	pub splash: Option<String>,
-	pub default_message_notifications: u64,
+	pub banner: Option<String>,
+	pub default_message_notifications: NotificationLevel,
 	pub mfa_level: u64,
+	pub explicit_content_filter: ExplicitContentFilter,
+	/// Whether the boost progress bar is shown
+	#[serde(default)]
+	pub premium_progress_bar_enabled: bool,
+	/// Raid protection actions currently in effect
+	#[serde(default)]
+	pub incidents_data: Option<IncidentActions>,
+	/// The approximate number of members, if counts were requested
+	#[serde(default)]
+	pub approximate_member_count: Option<u64>,
+	/// The approximate number of online members, if counts were requested
+	#[serde(default)]
+	pub approximate_presence_count: Option<u64>,
 }
 serial_decode!(Server);
 
@@ -357,9 +581,36 @@ impl Server {
 	///
 	/// Returns None if the server does not have an icon.
 	pub fn icon_url(&self) -> Option<String> {
-		self.icon
-			.as_ref()
-			.map(|icon| format!(cdn_concat!("/icons/{}/{}.jpg"), self.id, icon))
+		server_image(self.id, "icons", &self.icon, ImageFormat::Jpeg, None)
+	}
+
+	/// Returns the URL of the server's icon in the given format and size.
+	pub fn icon_url_with(&self, format: ImageFormat, size: u32) -> Option<String> {
+		server_image(self.id, "icons", &self.icon, format, Some(size))
+	}
+
+	/// Returns the formatted URL of the server's invite splash.
+	///
+	/// Returns None if the server does not have a splash.
+	pub fn splash_url(&self) -> Option<String> {
+		server_image(self.id, "splashes", &self.splash, ImageFormat::Jpeg, None)
+	}
+
+	/// Returns the URL of the server's invite splash in the given format and size.
+	pub fn splash_url_with(&self, format: ImageFormat, size: u32) -> Option<String> {
+		server_image(self.id, "splashes", &self.splash, format, Some(size))
+	}
+
+	/// Returns the formatted URL of the server's banner.
+	///
+	/// Returns None if the server does not have a banner.
+	pub fn banner_url(&self) -> Option<String> {
+		server_image(self.id, "banners", &self.banner, ImageFormat::Jpeg, None)
+	}
+
+	/// Returns the URL of the server's banner in the given format and size.
+	pub fn banner_url_with(&self, format: ImageFormat, size: u32) -> Option<String> {
+		server_image(self.id, "banners", &self.banner, format, Some(size))
 	}
 }
 
@@ -371,13 +622,126 @@ pub struct ServerPrune {
 }
 serial_decode!(ServerPrune);
 
+/// The result of a prune dry run, along with the parameters it was computed
+/// with.
+#[derive(Debug, Clone)]
+pub struct ServerPrunePreview {
+	/// The number of members who would be pruned
+	pub pruned: u64,
+	/// The number of days of inactivity considered
+	pub days: u16,
+	/// Roles whose members were also considered for pruning
+	pub include_roles: Vec<RoleId>,
+}
+
+/// A color in `0xRRGGBB` form, as used by roles and embeds
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Color(pub u32);
+
+impl Color {
+	/// The default color, which leaves roles uncolored.
+	pub const DEFAULT: Color = Color(0);
+	pub const WHITE: Color = Color(0xFFFFFF);
+	pub const BLACK: Color = Color(0x000000);
+	pub const BLURPLE: Color = Color(0x5865F2);
+	pub const GREEN: Color = Color(0x57F287);
+	pub const YELLOW: Color = Color(0xFEE75C);
+	pub const FUCHSIA: Color = Color(0xEB459E);
+	pub const RED: Color = Color(0xED4245);
+	pub const BLUE: Color = Color(0x3498DB);
+	pub const ORANGE: Color = Color(0xE67E22);
+	pub const PURPLE: Color = Color(0x9B59B6);
+
+	/// Construct a color from its red, green, and blue components.
+	pub fn from_rgb(r: u8, g: u8, b: u8) -> Color {
+		Color((r as u32) << 16 | (g as u32) << 8 | b as u32)
+	}
+
+	/// Parse a color from six hex digits, optionally prefixed by `#` or `0x`.
+	pub fn from_hex_str(hex: &str) -> Option<Color> {
+		let digits = hex
+			.strip_prefix('#')
+			.or_else(|| hex.strip_prefix("0x"))
+			.or_else(|| hex.strip_prefix("0X"))
+			.unwrap_or(hex);
+		if digits.len() != 6 || !digits.bytes().all(|b| (b as char).is_digit(16)) {
+			return None;
+		}
+		u32::from_str_radix(digits, 16).ok().map(Color)
+	}
+
+	/// Get the red component of the color.
+	pub fn r(self) -> u8 {
+		(self.0 >> 16) as u8
+	}
+
+	/// Get the green component of the color.
+	pub fn g(self) -> u8 {
+		(self.0 >> 8) as u8
+	}
+
+	/// Get the blue component of the color.
+	pub fn b(self) -> u8 {
+		self.0 as u8
+	}
+
+	/// Get the red, green, and blue components of the color.
+	pub fn to_rgb(self) -> (u8, u8, u8) {
+		(self.r(), self.g(), self.b())
+	}
+}
+
+impl fmt::Debug for Color {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Color(#{:06X})", self.0)
+	}
+}
+
+/// Formats the color as `#RRGGBB`.
+impl fmt::Display for Color {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "#{:06X}", self.0)
+	}
+}
+
+impl FromStr for Color {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self> {
+		Color::from_hex_str(s).ok_or(Error::Other("Unable to parse string into Color"))
+	}
+}
+
+impl From<u64> for Color {
+	fn from(value: u64) -> Color {
+		Color(value as u32 & 0xFFFFFF)
+	}
+}
+
+impl From<(u8, u8, u8)> for Color {
+	fn from((r, g, b): (u8, u8, u8)) -> Color {
+		Color::from_rgb(r, g, b)
+	}
+}
+
+impl From<Color> for u32 {
+	fn from(color: Color) -> u32 {
+		color.0
+	}
+}
+
+impl From<Color> for u64 {
+	fn from(color: Color) -> u64 {
+		color.0 as u64
+	}
+}
+
 /// Information about a role
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Role {
 	pub id: RoleId,
 	pub name: String,
-	/// Color in `0xRRGGBB` form
-	pub color: u64,
+	/// The role's color, or `Color::DEFAULT` for none
+	pub color: Color,
 	pub hoist: bool,
 	pub managed: bool,
 	pub position: i64,
@@ -398,18 +762,57 @@ impl Role {
 /// A banning of a user
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Ban {
-	reason: Option<String>,
-	user: User,
+	pub reason: Option<String>,
+	pub user: User,
 }
 serial_decode!(Ban);
 
+/// The result of a bulk ban
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkBan {
+	/// Users who were banned
+	pub banned_users: Vec<UserId>,
+	/// Users who could not be banned
+	pub failed_users: Vec<UserId>,
+}
+serial_decode!(BulkBan);
+
+/// A news channel followed into another channel, as returned by
+/// `follow_news_channel`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FollowedChannel {
+	/// The news channel which was followed
+	pub channel_id: ChannelId,
+	/// The webhook created in the target channel to deliver published messages
+	pub webhook_id: WebhookId,
+}
+serial_decode!(FollowedChannel);
+
+/// Raid protection actions in effect for a server
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IncidentActions {
+	/// When invites to the server will be enabled again
+	#[serde(default)]
+	pub invites_disabled_until: Option<DateTime<FixedOffset>>,
+	/// When direct messages between members will be enabled again
+	#[serde(default)]
+	pub dms_disabled_until: Option<DateTime<FixedOffset>>,
+	/// When Discord last detected DM spam in the server
+	#[serde(default)]
+	pub dm_spam_detected_at: Option<DateTime<FixedOffset>>,
+	/// When Discord last detected a raid on the server
+	#[serde(default)]
+	pub raid_detected_at: Option<DateTime<FixedOffset>>,
+}
+serial_decode!(IncidentActions);
+
 /// Broadly-applicable user information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
 	pub id: UserId,
 	#[serde(rename = "username")]
 	pub name: String,
-	#[serde(deserialize_with = "::serial::deserialize_discrim")]
+	#[serde(default, deserialize_with = "::serial::deserialize_discrim")]
 	pub discriminator: u16,
 	pub avatar: Option<String>,
 	#[serde(default)]
@@ -432,6 +835,17 @@ impl User {
 			.as_ref()
 			.map(|av| format!(cdn_concat!("/avatars/{}/{}.jpg"), self.id, av))
 	}
+
+	/// Returns the URL of the user's avatar in the given format and size.
+	pub fn avatar_url_with(&self, format: ImageFormat, size: u32) -> Option<String> {
+		self.avatar.as_ref().map(|av| {
+			cdn_image_url(
+				format!(cdn_concat!("/avatars/{}/{}"), self.id, av),
+				format,
+				Some(size),
+			)
+		})
+	}
 }
 
 /// Information about a member of a server
@@ -441,11 +855,36 @@ pub struct Member {
 	pub roles: Vec<RoleId>,
 	pub nick: Option<String>,
 	pub joined_at: String,
+	#[serde(default)]
 	pub mute: bool,
+	#[serde(default)]
 	pub deaf: bool,
+	#[serde(default)]
+	pub flags: MemberFlags,
+	/// Whether the member has yet to pass membership screening
+	#[serde(default)]
+	pub pending: bool,
 }
 serial_decode!(Member);
 
+bitflags! {
+	/// Sets of flags that may be set on a server member.
+	///
+	/// See https://discord.com/developers/docs/resources/guild#guild-member-object-guild-member-flags
+	#[derive(Default)]
+	pub struct MemberFlags: u64 {
+		/// The member has left and rejoined the server
+		const DID_REJOIN = 1 << 0;
+		const COMPLETED_ONBOARDING = 1 << 1;
+		/// The member may talk without meeting the server's verification
+		/// requirements; may be set by bots
+		const BYPASSES_VERIFICATION = 1 << 2;
+		const STARTED_ONBOARDING = 1 << 3;
+	}
+}
+
+serial_single_field!(MemberFlags as bits: u64);
+
 impl Member {
 	/// Get this member's nickname if present or their username otherwise.
 	pub fn display_name(&self) -> &str {
@@ -457,6 +896,62 @@ impl Member {
 	}
 }
 
+/// A user's application to join a server with membership screening
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinRequest {
+	#[serde(deserialize_with = "::serial::deserialize_id")]
+	pub id: u64,
+	#[serde(rename = "guild_id")]
+	pub server_id: ServerId,
+	pub user_id: UserId,
+	pub user: Option<User>,
+	pub created_at: DateTime<FixedOffset>,
+	pub application_status: JoinRequestStatus,
+	/// The reason given by the moderator who rejected the request
+	pub rejection_reason: Option<String>,
+	/// The applicant's answers to the server's membership form
+	#[serde(default)]
+	pub form_responses: Vec<FormResponse>,
+	/// When a moderator approved or rejected the request
+	pub actioned_at: Option<DateTime<FixedOffset>>,
+}
+serial_decode!(JoinRequest);
+
+/// The progress of a request to join a server
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum JoinRequestStatus {
+	/// The user has begun filling in the membership form
+	Started,
+	/// The request awaits review by a moderator
+	Submitted,
+	Approved,
+	Rejected,
+}
+
+serial_use_mapping!(JoinRequestStatus, named);
+serial_names! { JoinRequestStatus;
+	Started, "STARTED";
+	Submitted, "SUBMITTED";
+	Approved, "APPROVED";
+	Rejected, "REJECTED";
+}
+
+/// An answer to one field of a server's membership form
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormResponse {
+	/// The kind of field, such as `TERMS`, `TEXT_INPUT`, `PARAGRAPH`, or
+	/// `MULTIPLE_CHOICE`
+	pub field_type: String,
+	pub label: String,
+	/// The choices for multiple choice fields, or the rules for terms fields
+	#[serde(default)]
+	pub values: Vec<String>,
+	/// The applicant's answer: a string for text fields, the index of the
+	/// chosen value for multiple choice fields, or whether the terms were
+	/// accepted
+	pub response: Option<Value>,
+}
+
 /// A private or public channel
 #[derive(Debug, Clone)]
 pub enum Channel {
@@ -464,14 +959,17 @@ pub enum Channel {
 	Group(Group),
 	/// Text channel to another user
 	Private(PrivateChannel),
-	/// Voice or text channel within a server
+	/// Voice, text, news, or forum channel within a server
 	Public(PublicChannel),
 	/// an organizational category that contains channels
 	Category(ChannelCategory),
 	/// a channel that users can follow and crosspost into their own server
+	#[deprecated(note = "News channels decode as `Channel::Public` with kind `ChannelType::News`")]
 	News,
 	/// a channel in which game developers can sell their game on Discord
 	Store,
+	/// A temporary sub-channel within a text or news channel
+	Thread(ThreadChannel),
 }
 
 impl Channel {
@@ -479,12 +977,12 @@ impl Channel {
 		let map = try!(into_map(value));
 		// https://discord.com/developers/docs/resources/channel#channel-object-channel-types
 		match req!(map.get("type").and_then(|x| x.as_u64())) {
-			0 | 2 => PublicChannel::decode(Value::Object(map)).map(Channel::Public),
+			0 | 2 | 5 | 15 => PublicChannel::decode(Value::Object(map)).map(Channel::Public),
 			1 => PrivateChannel::decode(Value::Object(map)).map(Channel::Private),
 			3 => Group::decode(Value::Object(map)).map(Channel::Group),
 			4 => ChannelCategory::decode(Value::Object(map)).map(Channel::Category),
-			5 => Ok(Channel::News),
 			6 => Ok(Channel::Store),
+			10 | 11 | 12 => ThreadChannel::decode(Value::Object(map)).map(Channel::Thread),
 			other => Err(Error::Decode(
 				"Expected value Channel type",
 				Value::from(other),
@@ -543,6 +1041,17 @@ impl Group {
 			)
 		})
 	}
+
+	/// Returns the URL of the group's icon in the given format and size.
+	pub fn icon_url_with(&self, format: ImageFormat, size: u32) -> Option<String> {
+		self.icon.as_ref().map(|icon| {
+			cdn_image_url(
+				format!(cdn_concat!("/channel-icons/{}/{}"), self.channel_id, icon),
+				format,
+				Some(size),
+			)
+		})
+	}
 }
 
 /// An active group or private call
@@ -550,7 +1059,7 @@ impl Group {
 pub struct Call {
 	pub channel_id: ChannelId,
 	pub message_id: MessageId,
-	pub region: String,
+	pub region: Region,
 	pub ringing: Vec<UserId>,
 	pub unavailable: bool,
 	pub voice_states: Vec<VoiceState>,
@@ -559,10 +1068,12 @@ serial_decode!(Call);
 
 /// Private text channel to another user
 /// https://discord.com/developers/docs/resources/channel#channel-object
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct PrivateChannel {
 	pub id: ChannelId,
+	#[serde(rename = "type")]
 	pub kind: ChannelType,
+	#[serde(rename = "recipients", serialize_with = "::serial::serialize_one")]
 	pub recipient: User,
 	pub last_message_id: Option<MessageId>,
 	pub owner_id: Option<UserId>,
@@ -598,13 +1109,16 @@ impl PrivateChannel {
 		)
 	}
 }
+deserialize_using_decode!(PrivateChannel);
 
 /// Public voice or text channel within a server
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct PublicChannel {
 	pub id: ChannelId,
 	pub name: String,
+	#[serde(rename = "guild_id")]
 	pub server_id: ServerId,
+	#[serde(rename = "type")]
 	pub kind: ChannelType,
 	pub permission_overwrites: Vec<PermissionOverwrite>,
 	pub topic: Option<String>,
@@ -615,6 +1129,16 @@ pub struct PublicChannel {
 	pub last_pin_timestamp: Option<DateTime<FixedOffset>>,
 	pub nsfw: bool,
 	pub parent_id: Option<ChannelId>,
+	/// Tags which can be applied to posts in a forum channel
+	pub available_tags: Vec<ForumTag>,
+	/// The emoji shown in the add reaction button on forum posts
+	pub default_reaction_emoji: Option<DefaultReaction>,
+	/// The default order of posts in a forum channel
+	pub default_sort_order: Option<SortOrder>,
+	/// The default layout of posts in a forum channel
+	pub default_forum_layout: Option<ForumLayout>,
+	/// The initial `rate_limit_per_user` of new threads in the channel
+	pub default_thread_rate_limit_per_user: Option<u64>,
 }
 
 impl PublicChannel {
@@ -633,13 +1157,13 @@ impl PublicChannel {
 				name: try!(remove(&mut value, "name").and_then(into_string)),
 				server_id: server_id,
 				topic: try!(opt(&mut value, "topic", into_string)),
-				position: req!(try!(remove(&mut value, "position")).as_i64()),
+				position: try!(opt(&mut value, "position", |v| Ok(req!(v.as_i64())))).unwrap_or(0),
 				kind: try!(remove(&mut value, "type").and_then(serde)),
 				last_message_id: try!(opt(&mut value, "last_message_id", MessageId::decode)),
-				permission_overwrites: try!(decode_array(
-					try!(remove(&mut value, "permission_overwrites")),
-					PermissionOverwrite::decode
-				)),
+				permission_overwrites: try!(opt(&mut value, "permission_overwrites", |v| {
+					decode_array(v, PermissionOverwrite::decode)
+				}))
+				.unwrap_or_default(),
 				bitrate: remove(&mut value, "bitrate").ok().and_then(|v| v.as_u64()),
 				user_limit: remove(&mut value, "user_limit")
 					.ok()
@@ -647,16 +1171,219 @@ impl PublicChannel {
 				last_pin_timestamp: try!(opt(&mut value, "last_pin_timestamp", into_timestamp)),
 				nsfw: try!(opt(&mut value, "nsfw", |v| Ok(req!(v.as_bool())))).unwrap_or(false),
 				parent_id: try!(opt(&mut value, "parent_id", ChannelId::decode)),
+				available_tags: try!(opt(&mut value, "available_tags", |v| {
+					decode_array(v, ForumTag::decode)
+				}))
+				.unwrap_or_default(),
+				default_reaction_emoji: try!(opt(
+					&mut value,
+					"default_reaction_emoji",
+					DefaultReaction::decode
+				)),
+				default_sort_order: try!(opt(&mut value, "default_sort_order", serde)),
+				default_forum_layout: try!(opt(&mut value, "default_forum_layout", serde)),
+				default_thread_rate_limit_per_user: try!(opt(
+					&mut value,
+					"default_thread_rate_limit_per_user",
+					|v| Ok(req!(v.as_u64()))
+				)),
 			}
 		)
 	}
 
+	/// Get this channel's ID as a `TextChannelId`, failing if it cannot hold
+	/// messages.
+	pub fn expect_text(&self) -> Result<TextChannelId> {
+		match self.kind {
+			ChannelType::Text
+			| ChannelType::News
+			| ChannelType::NewsThread
+			| ChannelType::PublicThread
+			| ChannelType::PrivateThread => Ok(TextChannelId(self.id)),
+			_ => Err(Error::Validation("channel", "is not a text channel")),
+		}
+	}
+
+	/// Get this channel's ID as a `VoiceChannelId`, failing if it is not a
+	/// voice or stage channel.
+	pub fn expect_voice(&self) -> Result<VoiceChannelId> {
+		match self.kind {
+			ChannelType::Voice | ChannelType::StageVoice => Ok(VoiceChannelId(self.id)),
+			_ => Err(Error::Validation("channel", "is not a voice channel")),
+		}
+	}
+
+	/// Find one of a forum channel's available tags by ID.
+	pub fn find_tag(&self, id: ForumTagId) -> Option<&ForumTag> {
+		self.available_tags.iter().find(|tag| tag.id == id)
+	}
+
 	/// Return a `Mention` which will link to this channel.
 	#[inline(always)]
 	pub fn mention(&self) -> Mention {
 		self.id.mention()
 	}
 }
+deserialize_using_decode!(PublicChannel);
+
+/// A thread within a server's text or news channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadChannel {
+	pub id: ChannelId,
+	#[serde(rename = "guild_id")]
+	pub server_id: ServerId,
+	/// The text or news channel this thread was created in
+	pub parent_id: ChannelId,
+	/// The user who started this thread
+	pub owner_id: Option<UserId>,
+	pub name: String,
+	#[serde(rename = "type")]
+	pub kind: ChannelType,
+	pub last_message_id: Option<MessageId>,
+	pub last_pin_timestamp: Option<DateTime<FixedOffset>>,
+	/// Seconds a user has to wait before sending another message
+	#[serde(default)]
+	pub rate_limit_per_user: u64,
+	/// Approximate count of messages, stops counting at 50
+	#[serde(default)]
+	pub message_count: u64,
+	/// Approximate count of users, stops counting at 50
+	#[serde(default)]
+	pub member_count: u64,
+	pub thread_metadata: ThreadMetadata,
+	/// The logged-in user's thread membership, if they have joined
+	pub member: Option<ThreadMember>,
+	/// The tags applied to a post in a forum channel
+	#[serde(default)]
+	pub applied_tags: Vec<ForumTagId>,
+}
+serial_decode!(ThreadChannel);
+
+impl ThreadChannel {
+	/// Decode a thread listed within a server, which may omit the server's ID.
+	pub fn decode_server(value: Value, server_id: ServerId) -> Result<ThreadChannel> {
+		let mut value = try!(into_map(value));
+		value.entry("guild_id").or_insert_with(|| json!(server_id));
+		ThreadChannel::decode(Value::Object(value))
+	}
+
+	/// Return a `Mention` which will link to this thread.
+	#[inline(always)]
+	pub fn mention(&self) -> Mention {
+		self.id.mention()
+	}
+}
+
+/// A tag which can be applied to posts in a forum channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForumTag {
+	pub id: ForumTagId,
+	pub name: String,
+	/// Whether only moderators can apply or remove this tag
+	#[serde(default)]
+	pub moderated: bool,
+	/// The ID of the tag's custom emoji
+	pub emoji_id: Option<EmojiId>,
+	/// The unicode character of the tag's emoji
+	pub emoji_name: Option<String>,
+}
+serial_decode!(ForumTag);
+
+/// The emoji shown in the add reaction button on forum posts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DefaultReaction {
+	/// The ID of a custom emoji
+	pub emoji_id: Option<EmojiId>,
+	/// The unicode character of a standard emoji
+	pub emoji_name: Option<String>,
+}
+serial_decode!(DefaultReaction);
+
+impl From<ReactionEmoji> for DefaultReaction {
+	fn from(emoji: ReactionEmoji) -> DefaultReaction {
+		match emoji {
+			ReactionEmoji::Unicode(name) => DefaultReaction {
+				emoji_id: None,
+				emoji_name: Some(name),
+			},
+			ReactionEmoji::Custom { id, .. } => DefaultReaction {
+				emoji_id: Some(id),
+				emoji_name: None,
+			},
+		}
+	}
+}
+
+/// The order in which posts in a forum channel are listed
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SortOrder {
+	/// Sort by the most recent activity in the post
+	LatestActivity,
+	/// Sort by when the post was created
+	CreationDate,
+}
+
+serial_use_mapping!(SortOrder, numeric);
+serial_numbers! { SortOrder;
+	LatestActivity, 0;
+	CreationDate, 1;
+}
+
+/// How posts in a forum channel are displayed
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ForumLayout {
+	/// No default has been set by a moderator
+	NotSet,
+	/// Display posts as a list
+	ListView,
+	/// Display posts as a collection of tiles
+	GalleryView,
+}
+
+serial_use_mapping!(ForumLayout, numeric);
+serial_numbers! { ForumLayout;
+	NotSet, 0;
+	ListView, 1;
+	GalleryView, 2;
+}
+
+/// Thread-specific fields not present on other channels
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadMetadata {
+	pub archived: bool,
+	/// Minutes of inactivity before the thread is automatically archived
+	pub auto_archive_duration: u64,
+	/// When the thread's archive status was last changed
+	pub archive_timestamp: DateTime<FixedOffset>,
+	#[serde(default)]
+	pub locked: bool,
+	/// Whether non-moderators can add other non-moderators (private threads only)
+	pub invitable: Option<bool>,
+}
+serial_decode!(ThreadMetadata);
+
+/// A user's membership in a thread
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadMember {
+	/// Omitted when sent as part of a `ThreadChannel`
+	pub id: Option<ChannelId>,
+	/// Omitted when sent as part of a `ThreadChannel`
+	pub user_id: Option<UserId>,
+	pub join_timestamp: DateTime<FixedOffset>,
+	pub flags: u64,
+}
+serial_decode!(ThreadMember);
+
+/// A list of threads and the logged-in user's memberships in them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadList {
+	pub threads: Vec<ThreadChannel>,
+	pub members: Vec<ThreadMember>,
+	/// Whether more archived threads are available
+	#[serde(default)]
+	pub has_more: bool,
+}
+serial_decode!(ThreadList);
 
 /// The type of edit being made to a Channel's permissions.
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
@@ -666,7 +1393,7 @@ pub enum PermissionOverwriteType {
 }
 
 /// A channel-specific permission overwrite for a role or member.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone)]
 pub struct PermissionOverwrite {
 	pub kind: PermissionOverwriteType,
 	pub allow: Permissions,
@@ -698,6 +1425,23 @@ impl PermissionOverwrite {
 		)
 	}
 }
+deserialize_using_decode!(PermissionOverwrite);
+
+impl ::serde::Serialize for PermissionOverwrite {
+	fn serialize<S: ::serde::Serializer>(&self, s: S) -> ::std::result::Result<S::Ok, S::Error> {
+		use serde::ser::SerializeStruct;
+		let (id, kind) = match self.kind {
+			PermissionOverwriteType::Member(id) => (id.0, "member"),
+			PermissionOverwriteType::Role(id) => (id.0, "role"),
+		};
+		let mut s = s.serialize_struct("PermissionOverwrite", 4)?;
+		s.serialize_field("id", &id)?;
+		s.serialize_field("type", kind)?;
+		s.serialize_field("allow", &self.allow)?;
+		s.serialize_field("deny", &self.deny)?;
+		s.end()
+	}
+}
 
 bitflags! {
 	/// Set of permissions assignable to a Role or PermissionOverwrite
@@ -815,6 +1559,17 @@ pub struct Attachment {
 	pub width: Option<u64>,
 	/// Height if the file is an image
 	pub height: Option<u64>,
+	/// The media type of the file, such as "image/png"
+	pub content_type: Option<String>,
+	/// Alt text describing the file
+	pub description: Option<String>,
+	/// Whether the attachment will be removed after a set period of time
+	#[serde(default)]
+	pub ephemeral: bool,
+	/// Length of the audio if the attachment is a voice message
+	pub duration_secs: Option<f64>,
+	/// Base64-encoded sampled waveform if the attachment is a voice message
+	pub waveform: Option<String>,
 }
 serial_decode!(Attachment);
 
@@ -827,18 +1582,172 @@ impl Attachment {
 			None
 		}
 	}
-}
 
-bitflags! {
-	/// Sets of flags that may be set on a message.
-	///
-	/// See https://discord.com/developers/docs/resources/channel#message-object-message-flags
-	pub struct MessageFlags: u64 {
+	/// Whether Discord reports the attachment to be an image.
+	pub fn is_image(&self) -> bool {
+		self.has_media_type("image/")
+	}
+
+	/// Whether Discord reports the attachment to be audio, including voice
+	/// messages.
+	pub fn is_audio(&self) -> bool {
+		self.has_media_type("audio/")
+	}
+
+	/// Whether Discord reports the attachment to be a video.
+	pub fn is_video(&self) -> bool {
+		self.has_media_type("video/")
+	}
+
+	fn has_media_type(&self, prefix: &str) -> bool {
+		match self.content_type {
+			Some(ref content_type) => content_type.starts_with(prefix),
+			None => false,
+		}
+	}
+}
+
+/// A place to upload a file before attaching it to a message, from
+/// `Discord::create_attachment_slots`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachmentSlot {
+	/// The index of the file in the request
+	#[serde(deserialize_with = "::serial::deserialize_id")]
+	pub id: u64,
+	/// Where to `PUT` the file's contents
+	pub upload_url: String,
+	/// The name to refer to the uploaded file by when sending the message
+	pub upload_filename: String,
+}
+serial_decode!(AttachmentSlot);
+
+/// The duration and waveform Discord displays for a voice message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoiceMessageMetadata {
+	/// Length of the audio in seconds
+	pub duration_secs: f64,
+	/// Loudness of the audio over time, with up to 256 points from 0 to 255
+	pub waveform: Vec<u8>,
+}
+
+impl VoiceMessageMetadata {
+	/// Compute the metadata from 48000Hz PCM samples. If `stereo` is true,
+	/// the channels should be interleaved.
+	pub fn from_pcm(samples: &[i16], stereo: bool) -> VoiceMessageMetadata {
+		const SAMPLE_RATE: usize = 48000;
+		const MAX_POINTS: usize = 256;
+		let channels = if stereo { 2 } else { 1 };
+		let frames = samples.len() / channels;
+		// roughly ten points per second, as the official clients produce
+		let points = ::std::cmp::min(MAX_POINTS, ::std::cmp::max(1, frames * 10 / SAMPLE_RATE));
+		let frames_per_point = ::std::cmp::max(1, (frames + points - 1) / points);
+		let waveform = samples
+			.chunks(frames_per_point * channels)
+			.map(|chunk| {
+				let peak = chunk.iter().map(|&s| (s as i32).abs()).max().unwrap_or(0);
+				(peak * 255 / 32768) as u8
+			})
+			.collect();
+		VoiceMessageMetadata {
+			duration_secs: frames as f64 / SAMPLE_RATE as f64,
+			waveform: waveform,
+		}
+	}
+}
+
+/// Rich content attached to a message
+///
+/// Embeds may be sent by bots or generated by Discord from links in the
+/// message's content, in which case most fields will be absent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Embed {
+	pub title: Option<String>,
+	/// The kind of embed, such as "rich", "image", "video", or "link"
+	#[serde(rename = "type")]
+	pub kind: Option<String>,
+	pub description: Option<String>,
+	pub url: Option<String>,
+	pub timestamp: Option<DateTime<FixedOffset>>,
+	pub color: Option<Color>,
+	pub footer: Option<EmbedFooter>,
+	pub image: Option<EmbedImage>,
+	pub thumbnail: Option<EmbedImage>,
+	pub video: Option<EmbedVideo>,
+	pub provider: Option<EmbedProvider>,
+	pub author: Option<EmbedAuthor>,
+	#[serde(default)]
+	pub fields: Vec<EmbedField>,
+}
+serial_decode!(Embed);
+
+/// Footer information for an embed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbedFooter {
+	pub text: String,
+	pub icon_url: Option<String>,
+	/// Proxied URL of the footer icon
+	pub proxy_icon_url: Option<String>,
+}
+
+/// An image or thumbnail in an embed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbedImage {
+	pub url: String,
+	/// Proxied URL of the image
+	pub proxy_url: Option<String>,
+	pub width: Option<u64>,
+	pub height: Option<u64>,
+}
+
+/// A video in an embed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbedVideo {
+	pub url: Option<String>,
+	/// Proxied URL of the video
+	pub proxy_url: Option<String>,
+	pub width: Option<u64>,
+	pub height: Option<u64>,
+}
+
+/// The site which provided an embed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbedProvider {
+	pub name: Option<String>,
+	pub url: Option<String>,
+}
+
+/// Author information for an embed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbedAuthor {
+	pub name: String,
+	pub url: Option<String>,
+	pub icon_url: Option<String>,
+	/// Proxied URL of the author icon
+	pub proxy_icon_url: Option<String>,
+}
+
+/// A name-value field in an embed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbedField {
+	pub name: String,
+	pub value: String,
+	#[serde(default)]
+	pub inline: bool,
+}
+
+bitflags! {
+	/// Sets of flags that may be set on a message.
+	///
+	/// See https://discord.com/developers/docs/resources/channel#message-object-message-flags
+	#[derive(Default)]
+	pub struct MessageFlags: u64 {
 		const CROSSPOSTED = 1 << 0;
 		const IS_CROSSPOST = 1 << 1;
 		const SUPPRESS_EMBEDS = 1 << 2;
 		const SOURCE_MESSAGE_DELETED = 1 << 3;
 		const URGENT = 1 << 4;
+		const SUPPRESS_NOTIFICATIONS = 1 << 12;
+		const IS_VOICE_MESSAGE = 1 << 13;
 	}
 }
 
@@ -869,14 +1778,125 @@ pub struct Message {
 	pub reactions: Vec<MessageReaction>,
 
 	pub attachments: Vec<Attachment>,
-	/// Follows OEmbed standard
-	pub embeds: Vec<Value>,
+	pub embeds: Vec<Embed>,
+	/// The message this message replies to, crossposts, or pins
+	pub message_reference: Option<MessageReference>,
+	/// The message being replied to, if it still exists
+	///
+	/// Only the first level of a reply chain is included; fetch the message
+	/// referenced by this one to continue following the chain.
 	pub referenced_message: Option<Box<Message>>,
+	/// Interactive buttons and menus, organized into action rows
+	#[serde(default)]
+	pub components: Vec<Component>,
+	/// Stickers sent with the message
+	#[serde(default)]
+	pub sticker_items: Vec<StickerItem>,
 
+	#[serde(default)]
 	pub flags: MessageFlags,
 }
 serial_decode!(Message);
 
+/// A reference from one message to another
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageReference {
+	pub message_id: Option<MessageId>,
+	pub channel_id: Option<ChannelId>,
+	#[serde(rename = "guild_id")]
+	pub server_id: Option<ServerId>,
+}
+serial_decode!(MessageReference);
+
+/// The type of a message component
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum ComponentType {
+	/// A container for other components
+	ActionRow,
+	Button,
+	/// A select menu of application-defined options
+	SelectMenu,
+	TextInput,
+	UserSelect,
+	RoleSelect,
+	MentionableSelect,
+	ChannelSelect,
+}
+
+serial_use_mapping!(ComponentType, numeric);
+serial_numbers! { ComponentType;
+	ActionRow, 1;
+	Button, 2;
+	SelectMenu, 3;
+	TextInput, 4;
+	UserSelect, 5;
+	RoleSelect, 6;
+	MentionableSelect, 7;
+	ChannelSelect, 8;
+}
+
+/// The appearance of a button component
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum ButtonStyle {
+	/// Blurple
+	Primary,
+	/// Grey
+	Secondary,
+	/// Green
+	Success,
+	/// Red
+	Danger,
+	/// Grey, navigates to a URL instead of sending an interaction
+	Link,
+}
+
+serial_use_mapping!(ButtonStyle, numeric);
+serial_numbers! { ButtonStyle;
+	Primary, 1;
+	Secondary, 2;
+	Success, 3;
+	Danger, 4;
+	Link, 5;
+}
+
+/// An interactive component attached to a message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Component {
+	#[serde(rename = "type")]
+	pub kind: ComponentType,
+	/// The children of an action row
+	#[serde(default)]
+	pub components: Vec<Component>,
+	/// Identifier sent along with interactions on this component
+	pub custom_id: Option<String>,
+	pub style: Option<ButtonStyle>,
+	pub label: Option<String>,
+	pub emoji: Option<ReactionEmoji>,
+	/// Target of a link button
+	pub url: Option<String>,
+	#[serde(default)]
+	pub disabled: bool,
+	/// The choices in a select menu
+	#[serde(default)]
+	pub options: Vec<SelectOption>,
+	pub placeholder: Option<String>,
+	pub min_values: Option<u64>,
+	pub max_values: Option<u64>,
+}
+serial_decode!(Component);
+
+/// A choice in a select menu component
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelectOption {
+	pub label: String,
+	pub value: String,
+	pub description: Option<String>,
+	pub emoji: Option<ReactionEmoji>,
+	#[serde(default)]
+	pub default: bool,
+}
+serial_decode!(SelectOption);
+
 /// The type of a message
 #[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
 pub enum MessageType {
@@ -953,6 +1973,12 @@ pub struct Invite {
 	pub channel_type: ChannelType,
 	pub channel_id: ChannelId,
 	pub channel_name: String,
+	/// The approximate number of members, if counts were requested
+	pub approximate_member_count: Option<u64>,
+	/// The approximate number of online members, if counts were requested
+	pub approximate_presence_count: Option<u64>,
+	/// When the invite expires, if it is not permanent
+	pub expires_at: Option<DateTime<FixedOffset>>,
 }
 
 impl Invite {
@@ -979,11 +2005,37 @@ impl Invite {
 				channel_type: channel_type,
 				channel_id: channel_id,
 				channel_name: channel_name,
+				approximate_member_count: try!(opt(
+					&mut value,
+					"approximate_member_count",
+					|v| Ok(req!(v.as_u64()))
+				)),
+				approximate_presence_count: try!(opt(
+					&mut value,
+					"approximate_presence_count",
+					|v| Ok(req!(v.as_u64()))
+				)),
+				expires_at: try!(opt(&mut value, "expires_at", serde)),
 			}
 		)
 	}
 }
 
+/// What a voice channel invite points its recipients at
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum InviteTargetType {
+	/// A user's stream in the channel
+	Stream,
+	/// An embedded application (activity) in the channel
+	EmbeddedApplication,
+}
+
+serial_use_mapping!(InviteTargetType, numeric);
+serial_numbers! { InviteTargetType;
+	Stream, 1;
+	EmbeddedApplication, 2;
+}
+
 /// Detailed information about an invite, available to server managers
 #[derive(Debug, Clone)]
 pub struct RichInvite {
@@ -1001,6 +2053,8 @@ pub struct RichInvite {
 	pub max_uses: u64,
 	pub temporary: bool,
 	pub uses: u64,
+	pub target_type: Option<InviteTargetType>,
+	pub target_user: Option<User>,
 }
 
 impl RichInvite {
@@ -1037,15 +2091,116 @@ impl RichInvite {
 				max_uses: req!(try!(remove(&mut value, "max_uses")).as_u64()),
 				temporary: req!(try!(remove(&mut value, "temporary")).as_bool()),
 				uses: req!(try!(remove(&mut value, "uses")).as_u64()),
+				target_type: try!(opt(&mut value, "target_type", serde)),
+				target_user: try!(opt(&mut value, "target_user", User::decode)),
 			}
 		)
 	}
 }
 
+/// A region in which voice servers are hosted
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum Region {
+	Brazil,
+	HongKong,
+	India,
+	Japan,
+	Rotterdam,
+	Russia,
+	Singapore,
+	SouthAfrica,
+	Sydney,
+	UsCentral,
+	UsEast,
+	UsSouth,
+	UsWest,
+	/// A region not known to this library, by its API name
+	Other(String),
+}
+
+impl Region {
+	/// Get the name of the region used by the API, such as "us-east".
+	pub fn name(&self) -> &str {
+		match *self {
+			Region::Brazil => "brazil",
+			Region::HongKong => "hongkong",
+			Region::India => "india",
+			Region::Japan => "japan",
+			Region::Rotterdam => "rotterdam",
+			Region::Russia => "russia",
+			Region::Singapore => "singapore",
+			Region::SouthAfrica => "southafrica",
+			Region::Sydney => "sydney",
+			Region::UsCentral => "us-central",
+			Region::UsEast => "us-east",
+			Region::UsSouth => "us-south",
+			Region::UsWest => "us-west",
+			Region::Other(ref name) => name,
+		}
+	}
+
+	/// Look up a region by the name used by the API, falling back to `Other`.
+	pub fn from_name(name: &str) -> Region {
+		match name {
+			"brazil" => Region::Brazil,
+			"hongkong" => Region::HongKong,
+			"india" => Region::India,
+			"japan" => Region::Japan,
+			"rotterdam" => Region::Rotterdam,
+			"russia" => Region::Russia,
+			"singapore" => Region::Singapore,
+			"southafrica" => Region::SouthAfrica,
+			"sydney" => Region::Sydney,
+			"us-central" => Region::UsCentral,
+			"us-east" => Region::UsEast,
+			"us-south" => Region::UsSouth,
+			"us-west" => Region::UsWest,
+			other => Region::Other(other.to_owned()),
+		}
+	}
+
+	#[doc(hidden)]
+	pub fn decode(value: Value) -> Result<Region> {
+		into_string(value).map(Region::from)
+	}
+}
+
+impl fmt::Display for Region {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+impl FromStr for Region {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self> {
+		Ok(Region::from_name(s))
+	}
+}
+
+impl From<String> for Region {
+	fn from(name: String) -> Region {
+		match Region::from_name(&name) {
+			Region::Other(_) => Region::Other(name),
+			known => known,
+		}
+	}
+}
+
+impl From<Region> for String {
+	fn from(region: Region) -> String {
+		match region {
+			Region::Other(name) => name,
+			known => known.name().to_owned(),
+		}
+	}
+}
+
 /// Information about an available voice region
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VoiceRegion {
-	pub id: String,
+	pub id: Region,
 	pub name: String,
 	pub sample_hostname: String,
 	pub sample_port: u16,
@@ -1077,6 +2232,7 @@ bitflags! {
 		const DIRECT_MESSAGES = 1 << 12;
 		const DIRECT_MESSAGE_REACTIONS = 1 << 13;
 		const DIRECT_MESSAGE_TYPING = 1 << 14;
+		const GUILD_SCHEDULED_EVENTS = 1 << 16;
 	}
 }
 
@@ -1119,8 +2275,9 @@ pub enum GameType {
 	Playing,
 	Streaming,
 	Listening,
-	Custom = 4,
-	Competing = 5,
+	Watching,
+	Custom,
+	Competing,
 }
 
 serial_use_mapping!(GameType, numeric);
@@ -1128,6 +2285,7 @@ serial_numbers! { GameType;
 	Playing, 0;
 	Streaming, 1;
 	Listening, 2;
+	Watching, 3;
 	Custom, 4;
 	Competing, 5;
 }
@@ -1135,27 +2293,59 @@ serial_numbers! { GameType;
 /// Information about a game being played
 /// https://discord.com/developers/docs/topics/gateway#activity-object
 /// (might merge it with `Activity` in the future)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Game {
 	pub name: String,
 	pub url: Option<String>,
+	#[serde(rename = "type")]
 	pub kind: GameType,
+	/// The custom status text, or the party status of a game
+	pub state: Option<String>,
+	/// The emoji shown beside a custom status
+	pub emoji: Option<ActivityEmoji>,
 }
 
 impl Game {
-	pub fn playing(name: String) -> Game {
+	fn new(kind: GameType, name: String) -> Game {
 		Game {
-			kind: GameType::Playing,
+			kind: kind,
 			name: name,
 			url: None,
+			state: None,
+			emoji: None,
 		}
 	}
 
+	pub fn playing(name: String) -> Game {
+		Game::new(GameType::Playing, name)
+	}
+
 	pub fn streaming(name: String, url: String) -> Game {
 		Game {
-			kind: GameType::Streaming,
-			name: name,
 			url: Some(url),
+			..Game::new(GameType::Streaming, name)
+		}
+	}
+
+	pub fn listening(name: String) -> Game {
+		Game::new(GameType::Listening, name)
+	}
+
+	pub fn watching(name: String) -> Game {
+		Game::new(GameType::Watching, name)
+	}
+
+	pub fn competing(name: String) -> Game {
+		Game::new(GameType::Competing, name)
+	}
+
+	/// A custom status, shown as its text and optional emoji rather than as
+	/// an activity.
+	pub fn custom(state: String, emoji: Option<ActivityEmoji>) -> Game {
+		Game {
+			state: Some(state),
+			emoji: emoji,
+			..Game::new(GameType::Custom, "Custom Status".into())
 		}
 	}
 
@@ -1178,9 +2368,12 @@ impl Game {
 			name: name,
 			kind: kind.and_then(GameType::from_num).unwrap_or(GameType::Playing),
 			url: try!(opt(&mut value, "url", into_string)),
+			state: try!(opt(&mut value, "state", into_string)),
+			emoji: try!(opt(&mut value, "emoji", ActivityEmoji::decode)),
 		}))
 	}
 }
+deserialize_using_decode!(Game as optional);
 
 /// A members's online status
 #[derive(Debug, Clone)]
@@ -1192,7 +2385,9 @@ pub struct Presence {
 	pub user: Option<User>,
 	pub nick: Option<String>,
 	/// user's current activities
-	pub activities: Option<Vec<Activity>>,
+	pub activities: Vec<Activity>,
+	/// the user's status on each kind of client
+	pub client_status: ClientStatus,
 }
 
 impl Presence {
@@ -1200,11 +2395,7 @@ impl Presence {
 		let mut value = try!(into_map(value));
 		let mut user_map = try!(remove(&mut value, "user").and_then(into_map));
 
-		let activities = match value.remove("activities") {
-			Some(v) => v,
-			None => Value::default(),
-		};
-		let activities: Option<Vec<Activity>> = serde_json::from_value(activities)?;
+		let activities = try!(opt(&mut value, "activities", |v| decode_array(v, Activity::decode)));
 
 		let (user_id, user) = if user_map.len() > 1 {
 			let user = try!(User::decode(Value::Object(user_map)));
@@ -1226,14 +2417,37 @@ impl Presence {
 			},
 			user: user,
 			nick: try!(opt(&mut value, "nick", into_string)),
-			activities,
+			activities: activities.unwrap_or_default(),
+			client_status: try!(opt(&mut value, "client_status", ClientStatus::decode))
+				.unwrap_or_default(),
 		})
 	}
 }
+deserialize_using_decode!(Presence);
+
+impl ::serde::Serialize for Presence {
+	fn serialize<S: ::serde::Serializer>(&self, s: S) -> ::std::result::Result<S::Ok, S::Error> {
+		use serde::ser::SerializeStruct;
+		let mut s = s.serialize_struct("Presence", 7)?;
+		match self.user {
+			Some(ref user) => s.serialize_field("user", user)?,
+			None => s.serialize_field("user", &json!({ "id": self.user_id }))?,
+		}
+		s.serialize_field("status", &self.status)?;
+		s.serialize_field("last_modified", &self.last_modified)?;
+		s.serialize_field("game", &self.game)?;
+		s.serialize_field("nick", &self.nick)?;
+		s.serialize_field("activities", &self.activities)?;
+		s.serialize_field("client_status", &self.client_status)?;
+		s.end()
+	}
+}
 
 /// A member's state within a voice channel
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VoiceState {
+	#[serde(rename = "guild_id")]
+	pub server_id: Option<ServerId>,
 	pub user_id: UserId,
 	pub channel_id: Option<ChannelId>,
 	pub session_id: String,
@@ -1243,6 +2457,14 @@ pub struct VoiceState {
 	pub self_deaf: bool,
 	pub mute: bool,
 	pub deaf: bool,
+	/// Whether the user is streaming with Go Live
+	#[serde(default)]
+	pub self_stream: bool,
+	/// Whether the user's camera is enabled
+	#[serde(default)]
+	pub self_video: bool,
+	/// When the user asked to speak in a stage channel, if they have
+	pub request_to_speak_timestamp: Option<DateTime<FixedOffset>>,
 }
 serial_decode!(VoiceState);
 
@@ -1270,18 +2492,58 @@ serial_numbers! { VerificationLevel;
 	Phone, 4;
 }
 
+/// Which members' messages are scanned for explicit content
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum ExplicitContentFilter {
+	/// Messages are not scanned
+	Disabled,
+	/// Messages from members without roles are scanned
+	MembersWithoutRoles,
+	/// Messages from all members are scanned
+	AllMembers,
+}
+
+serial_use_mapping!(ExplicitContentFilter, numeric);
+serial_numbers! { ExplicitContentFilter;
+	Disabled, 0;
+	MembersWithoutRoles, 1;
+	AllMembers, 2;
+}
+
 /// A parter custom emoji
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Emoji {
 	pub id: EmojiId,
 	pub name: String,
+	#[serde(default)]
 	pub managed: bool,
+	#[serde(default)]
 	pub require_colons: bool,
+	#[serde(default)]
 	pub animated: bool,
+	#[serde(default)]
 	pub roles: Vec<RoleId>,
 }
 serial_decode!(Emoji);
 
+impl Emoji {
+	/// Returns the URL of the emoji's image, as a GIF if animated or a PNG
+	/// otherwise.
+	pub fn url(&self) -> String {
+		let format = if self.animated {
+			ImageFormat::Gif
+		} else {
+			ImageFormat::Png
+		};
+		cdn_image_url(format!(cdn_concat!("/emojis/{}"), self.id), format, None)
+	}
+
+	/// Returns the URL of the emoji's image in the given format and size.
+	pub fn url_with(&self, format: ImageFormat, size: u32) -> String {
+		cdn_image_url(format!(cdn_concat!("/emojis/{}"), self.id), format, Some(size))
+	}
+}
+
 /// A full single reaction
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Reaction {
@@ -1289,18 +2551,51 @@ pub struct Reaction {
 	pub message_id: MessageId,
 	pub user_id: UserId,
 	pub emoji: ReactionEmoji,
+	/// Whether this is a super reaction
+	#[serde(default)]
+	pub burst: bool,
 }
 serial_decode!(Reaction);
 
 /// Information on a reaction as available at a glance on a message.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageReaction {
+	/// The total number of normal and super reactions
 	pub count: u64,
+	#[serde(default)]
+	pub count_details: ReactionCountDetails,
 	pub me: bool,
+	/// Whether the current user added a super reaction
+	#[serde(default)]
+	pub me_burst: bool,
+	/// The colors used for super reactions, as `#RRGGBB` strings
+	#[serde(default)]
+	pub burst_colors: Vec<String>,
 	pub emoji: ReactionEmoji,
 }
 serial_decode!(MessageReaction);
 
+/// A breakdown of a reaction's count by kind
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ReactionCountDetails {
+	pub normal: u64,
+	pub burst: u64,
+}
+
+/// The kind of a reaction
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ReactionType {
+	Normal,
+	/// A super reaction
+	Burst,
+}
+
+serial_use_mapping!(ReactionType, numeric);
+serial_numbers! { ReactionType;
+	Normal, 0;
+	Burst, 1;
+}
+
 /// Emoji information sent only from reaction events
 #[derive(Debug, Clone, PartialEq)]
 pub enum ReactionEmoji {
@@ -1310,6 +2605,184 @@ pub enum ReactionEmoji {
 serial_use_mapping!(ReactionEmoji, reaction_emoji);
 serial_decode!(ReactionEmoji);
 
+/// A sticker which can be sent in messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sticker {
+	pub id: StickerId,
+	pub name: String,
+	pub description: Option<String>,
+	/// Autocomplete and suggestion tags for the sticker
+	pub tags: String,
+	#[serde(rename = "type")]
+	pub kind: StickerType,
+	pub format_type: StickerFormat,
+	/// Whether a server sticker can currently be used, which may be false
+	/// after the server loses boosts
+	#[serde(default = "::serial::default_true")]
+	pub available: bool,
+	/// The server which owns a server sticker
+	#[serde(rename = "guild_id")]
+	pub server_id: Option<ServerId>,
+	/// The user who uploaded a server sticker, if visible
+	pub user: Option<User>,
+}
+serial_decode!(Sticker);
+
+impl Sticker {
+	/// Returns the URL of the sticker's image or Lottie animation.
+	pub fn url(&self) -> String {
+		sticker_url(self.id, self.format_type)
+	}
+}
+
+/// The smallest amount of data required to render a sticker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StickerItem {
+	pub id: StickerId,
+	pub name: String,
+	pub format_type: StickerFormat,
+}
+serial_decode!(StickerItem);
+
+impl StickerItem {
+	/// Returns the URL of the sticker's image or Lottie animation.
+	pub fn url(&self) -> String {
+		sticker_url(self.id, self.format_type)
+	}
+}
+
+fn sticker_url(id: StickerId, format: StickerFormat) -> String {
+	let extension = match format {
+		StickerFormat::Png | StickerFormat::Apng => "png",
+		StickerFormat::Lottie => "json",
+		StickerFormat::Gif => "gif",
+	};
+	format!(cdn_concat!("/stickers/{}.{}"), id, extension)
+}
+
+/// Where a sticker comes from
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum StickerType {
+	/// An official sticker in a pack
+	Standard,
+	/// A sticker uploaded to a server
+	Server,
+}
+
+serial_use_mapping!(StickerType, numeric);
+serial_numbers! { StickerType;
+	Standard, 1;
+	Server, 2;
+}
+
+/// The file format of a sticker
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum StickerFormat {
+	Png,
+	/// Animated PNG
+	Apng,
+	/// Lottie JSON animation
+	Lottie,
+	Gif,
+}
+
+serial_use_mapping!(StickerFormat, numeric);
+serial_numbers! { StickerFormat;
+	Png, 1;
+	Apng, 2;
+	Lottie, 3;
+	Gif, 4;
+}
+
+/// An event planned in a server's stage channel, voice channel, or elsewhere
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledEvent {
+	pub id: ScheduledEventId,
+	#[serde(rename = "guild_id")]
+	pub server_id: ServerId,
+	/// The channel the event takes place in, unless it is external
+	pub channel_id: Option<ChannelId>,
+	pub creator_id: Option<UserId>,
+	pub creator: Option<User>,
+	pub name: String,
+	pub description: Option<String>,
+	pub scheduled_start_time: DateTime<FixedOffset>,
+	/// When the event ends, required for external events
+	pub scheduled_end_time: Option<DateTime<FixedOffset>>,
+	pub privacy_level: ScheduledEventPrivacy,
+	pub status: ScheduledEventStatus,
+	pub entity_type: ScheduledEventType,
+	pub entity_metadata: Option<ScheduledEventMetadata>,
+	/// The number of users interested in the event, if requested
+	pub user_count: Option<u64>,
+	/// The cover image hash
+	pub image: Option<String>,
+}
+serial_decode!(ScheduledEvent);
+
+/// Additional information about an external scheduled event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledEventMetadata {
+	/// Where the event takes place
+	pub location: Option<String>,
+}
+
+/// Who may see a scheduled event
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ScheduledEventPrivacy {
+	/// Only members of the server
+	ServerOnly,
+}
+
+serial_use_mapping!(ScheduledEventPrivacy, numeric);
+serial_numbers! { ScheduledEventPrivacy;
+	ServerOnly, 2;
+}
+
+/// The progress of a scheduled event
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ScheduledEventStatus {
+	Scheduled,
+	Active,
+	Completed,
+	Canceled,
+}
+
+serial_use_mapping!(ScheduledEventStatus, numeric);
+serial_numbers! { ScheduledEventStatus;
+	Scheduled, 1;
+	Active, 2;
+	Completed, 3;
+	Canceled, 4;
+}
+
+/// Where a scheduled event takes place
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ScheduledEventType {
+	/// In a stage channel
+	StageInstance,
+	/// In a voice channel
+	Voice,
+	/// Somewhere outside Discord, given by the event's location
+	External,
+}
+
+serial_use_mapping!(ScheduledEventType, numeric);
+serial_numbers! { ScheduledEventType;
+	StageInstance, 1;
+	Voice, 2;
+	External, 3;
+}
+
+/// A user who has marked themselves interested in a scheduled event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledEventUser {
+	#[serde(rename = "guild_scheduled_event_id")]
+	pub event_id: ScheduledEventId,
+	pub user: User,
+}
+serial_decode!(ScheduledEventUser);
+
 /// Live server information
 #[derive(Debug, Clone)]
 pub struct LiveServer {
@@ -1319,7 +2792,7 @@ pub struct LiveServer {
 	pub application_id: Option<u64>,
 	pub voice_states: Vec<VoiceState>,
 	pub roles: Vec<Role>,
-	pub region: String,
+	pub region: Option<Region>,
 	pub presences: Vec<Presence>,
 	pub member_count: u64,
 	pub members: Vec<Member>,
@@ -1328,16 +2801,28 @@ pub struct LiveServer {
 	pub large: bool,
 	pub channels: Vec<PublicChannel>,
 	pub categories: Vec<ChannelCategory>,
+	/// The active threads the logged-in user can see. Emptied when the server
+	/// is added to a `State`, which tracks threads itself
+	pub threads: Vec<ThreadChannel>,
 	pub afk_timeout: u64,
 	pub afk_channel_id: Option<ChannelId>,
 	pub system_channel_id: Option<ChannelId>,
 	pub verification_level: VerificationLevel,
 	pub emojis: Vec<Emoji>,
+	pub stickers: Vec<Sticker>,
 	pub features: Vec<String>,
 	pub splash: Option<String>,
-	pub default_message_notifications: u64,
+	pub banner: Option<String>,
+	pub default_message_notifications: NotificationLevel,
 	pub mfa_level: u64,
-	pub explicit_content_filter: u64,
+	pub explicit_content_filter: ExplicitContentFilter,
+	/// Whether the boost progress bar is shown
+	pub premium_progress_bar_enabled: bool,
+	/// Raid protection actions currently in effect
+	pub incidents_data: Option<IncidentActions>,
+	/// The approximate number of online members, as of the last call to
+	/// `State::refresh_member_counts`
+	pub approximate_presence_count: Option<u64>,
 }
 
 impl LiveServer {
@@ -1359,7 +2844,7 @@ impl LiveServer {
 					try!(remove(&mut value, "roles")),
 					Role::decode
 				)),
-				region: try!(remove(&mut value, "region").and_then(into_string)),
+				region: try!(opt(&mut value, "region", Region::decode)),
 				// these presences don't contain a whole User, so discard that
 				presences: try!(decode_array(
 					try!(remove(&mut value, "presences")),
@@ -1383,23 +2868,40 @@ impl LiveServer {
 					try!(get(&mut value, "channels")),
 					ChannelCategory::decode
 				)),
+				threads: try!(opt(&mut value, "threads", |v| {
+					decode_array(v, |v| ThreadChannel::decode_server(v, id))
+				}))
+				.unwrap_or_default(),
 				verification_level: try!(remove(&mut value, "verification_level").and_then(serde)),
 				emojis: try!(
 					remove(&mut value, "emojis").and_then(|v| decode_array(v, Emoji::decode))
 				),
+				stickers: try!(opt(&mut value, "stickers", |v| decode_array(v, Sticker::decode)))
+					.unwrap_or_default(),
 				features: try!(
 					remove(&mut value, "features").and_then(|v| decode_array(v, into_string))
 				),
 				splash: try!(opt(&mut value, "splash", into_string)),
-				default_message_notifications: req!(try!(remove(
-					&mut value,
-					"default_message_notifications"
-				))
-				.as_u64()),
+				banner: try!(opt(&mut value, "banner", into_string)),
+				default_message_notifications: try!(
+					remove(&mut value, "default_message_notifications").and_then(serde)
+				),
 				mfa_level: req!(try!(remove(&mut value, "mfa_level")).as_u64()),
-				explicit_content_filter: req!(
-					try!(remove(&mut value, "explicit_content_filter")).as_u64()
+				explicit_content_filter: try!(
+					remove(&mut value, "explicit_content_filter").and_then(serde)
 				),
+				premium_progress_bar_enabled: try!(opt(
+					&mut value,
+					"premium_progress_bar_enabled",
+					|v| Ok(req!(v.as_bool()))
+				))
+				.unwrap_or(false),
+				incidents_data: try!(opt(&mut value, "incidents_data", serde)),
+				approximate_presence_count: try!(opt(
+					&mut value,
+					"approximate_presence_count",
+					|v| Ok(req!(v.as_u64()))
+				)),
 			}
 		)
 	}
@@ -1408,9 +2910,59 @@ impl LiveServer {
 	///
 	/// Returns None if the server does not have an icon.
 	pub fn icon_url(&self) -> Option<String> {
-		self.icon
-			.as_ref()
-			.map(|icon| format!(cdn_concat!("/icons/{}/{}.jpg"), self.id, icon))
+		server_image(self.id, "icons", &self.icon, ImageFormat::Jpeg, None)
+	}
+
+	/// Returns the URL of the server's icon in the given format and size.
+	pub fn icon_url_with(&self, format: ImageFormat, size: u32) -> Option<String> {
+		server_image(self.id, "icons", &self.icon, format, Some(size))
+	}
+
+	/// Returns the formatted URL of the server's invite splash.
+	///
+	/// Returns None if the server does not have a splash.
+	pub fn splash_url(&self) -> Option<String> {
+		server_image(self.id, "splashes", &self.splash, ImageFormat::Jpeg, None)
+	}
+
+	/// Returns the URL of the server's invite splash in the given format and size.
+	pub fn splash_url_with(&self, format: ImageFormat, size: u32) -> Option<String> {
+		server_image(self.id, "splashes", &self.splash, format, Some(size))
+	}
+
+	/// Returns the formatted URL of the server's banner.
+	///
+	/// Returns None if the server does not have a banner.
+	pub fn banner_url(&self) -> Option<String> {
+		server_image(self.id, "banners", &self.banner, ImageFormat::Jpeg, None)
+	}
+
+	/// Returns the URL of the server's banner in the given format and size.
+	pub fn banner_url_with(&self, format: ImageFormat, size: u32) -> Option<String> {
+		server_image(self.id, "banners", &self.banner, format, Some(size))
+	}
+
+	/// Calculate the server-wide permissions for a specific user, ignoring
+	/// channel permission overwrites.
+	pub fn server_permissions_for(&self, user: UserId) -> Permissions {
+		if user == self.owner_id {
+			return Permissions::all();
+		}
+		let mut permissions = match self.roles.iter().find(|r| r.id == self.id.everyone()) {
+			Some(r) => r.permissions,
+			None => return Permissions::empty(),
+		};
+		if let Some(member) = self.members.iter().find(|u| u.user.id == user) {
+			for &role in &member.roles {
+				if let Some(role) = self.roles.iter().find(|r| r.id == role) {
+					permissions |= role.permissions;
+				}
+			}
+		}
+		if permissions.contains(Permissions::ADMINISTRATOR) {
+			return Permissions::all();
+		}
+		permissions
 	}
 
 	/// Calculate the effective permissions for a specific user in a specific
@@ -1497,6 +3049,131 @@ impl LiveServer {
 		}
 		permissions
 	}
+
+	/// Get the server's channel categories, in the order Discord displays
+	/// them.
+	pub fn categories(&self) -> Vec<&PublicChannel> {
+		let mut categories: Vec<_> = self
+			.channels
+			.iter()
+			.filter(|c| c.kind == ChannelType::Category)
+			.collect();
+		categories.sort_by_key(|c| (c.position, c.id));
+		categories
+	}
+
+	/// Get the channels within a category, in the order Discord displays
+	/// them.
+	pub fn channels_in_category(&self, category: ChannelId) -> Vec<&PublicChannel> {
+		let mut channels: Vec<_> = self
+			.channels
+			.iter()
+			.filter(|c| c.parent_id == Some(category))
+			.collect();
+		channels.sort_by_key(|c| channel_sort_key(c));
+		channels
+	}
+
+	/// Get the server's channels in the order Discord displays them.
+	///
+	/// Channels outside any category come first, followed by each category
+	/// and then its channels. Within each, text channels come before voice
+	/// channels, and each kind is ordered by position. Channels whose category
+	/// is not known are treated as being outside any category.
+	pub fn sorted_channels(&self) -> Vec<&PublicChannel> {
+		let categories = self.categories();
+		let mut sorted: Vec<_> = self
+			.channels
+			.iter()
+			.filter(|c| {
+				c.kind != ChannelType::Category
+					&& c.parent_id.map_or(true, |p| !categories.iter().any(|cat| cat.id == p))
+			})
+			.collect();
+		sorted.sort_by_key(|c| channel_sort_key(c));
+		for category in categories {
+			sorted.push(category);
+			sorted.extend(self.channels_in_category(category.id));
+		}
+		sorted
+	}
+}
+
+deserialize_using_decode!(LiveServer);
+
+impl ::serde::Serialize for LiveServer {
+	fn serialize<S: ::serde::Serializer>(&self, s: S) -> ::std::result::Result<S::Ok, S::Error> {
+		use serde::ser::SerializeStruct;
+		let mut s = s.serialize_struct("LiveServer", 30)?;
+		s.serialize_field("id", &self.id)?;
+		s.serialize_field("name", &self.name)?;
+		s.serialize_field("owner_id", &self.owner_id)?;
+		s.serialize_field("application_id", &self.application_id)?;
+		s.serialize_field("voice_states", &self.voice_states)?;
+		s.serialize_field("roles", &self.roles)?;
+		s.serialize_field("region", &self.region)?;
+		s.serialize_field("presences", &self.presences)?;
+		s.serialize_field("member_count", &self.member_count)?;
+		s.serialize_field("members", &self.members)?;
+		s.serialize_field("joined_at", &self.joined_at)?;
+		s.serialize_field("icon", &self.icon)?;
+		s.serialize_field("large", &self.large)?;
+		s.serialize_field("channels", &ServerChannels(self))?;
+		s.serialize_field("threads", &self.threads)?;
+		s.serialize_field("afk_timeout", &self.afk_timeout)?;
+		s.serialize_field("afk_channel_id", &self.afk_channel_id)?;
+		s.serialize_field("system_channel_id", &self.system_channel_id)?;
+		s.serialize_field("verification_level", &self.verification_level)?;
+		s.serialize_field("emojis", &self.emojis)?;
+		s.serialize_field("stickers", &self.stickers)?;
+		s.serialize_field("features", &self.features)?;
+		s.serialize_field("splash", &self.splash)?;
+		s.serialize_field("banner", &self.banner)?;
+		s.serialize_field("default_message_notifications", &self.default_message_notifications)?;
+		s.serialize_field("mfa_level", &self.mfa_level)?;
+		s.serialize_field("explicit_content_filter", &self.explicit_content_filter)?;
+		s.serialize_field("premium_progress_bar_enabled", &self.premium_progress_bar_enabled)?;
+		s.serialize_field("incidents_data", &self.incidents_data)?;
+		s.serialize_field("approximate_presence_count", &self.approximate_presence_count)?;
+		s.end()
+	}
+}
+
+/// A server's channels as Discord lists them, with categories among the rest.
+struct ServerChannels<'a>(&'a LiveServer);
+
+impl<'a> ::serde::Serialize for ServerChannels<'a> {
+	fn serialize<S: ::serde::Serializer>(&self, s: S) -> ::std::result::Result<S::Ok, S::Error> {
+		use serde::ser::SerializeSeq;
+		let server = self.0;
+		let mut s = s.serialize_seq(None)?;
+		for channel in &server.channels {
+			s.serialize_element(channel)?;
+		}
+		// categories are usually listed with the channels already
+		for category in &server.categories {
+			if !server.channels.iter().any(|channel| channel.id == category.id) {
+				s.serialize_element(&json!({
+					"id": category.id,
+					"type": ChannelType::Category,
+					"name": category.name,
+					"parent_id": category.parent_id,
+					"nsfw": category.nsfw,
+					"position": category.position,
+				}))?;
+			}
+		}
+		s.end()
+	}
+}
+
+fn channel_sort_key(channel: &PublicChannel) -> (bool, i64, ChannelId) {
+	// voice channels are listed after all others within a category
+	let voice = match channel.kind {
+		ChannelType::Voice | ChannelType::StageVoice => true,
+		_ => false,
+	};
+	(voice, channel.position, channel.id)
 }
 
 /// A server which may be unavailable
@@ -1561,13 +3238,15 @@ impl PossibleServer<Server> {
 pub struct CurrentUser {
 	pub id: UserId,
 	pub username: String,
-	#[serde(deserialize_with = "::serial::deserialize_discrim")]
+	#[serde(default, deserialize_with = "::serial::deserialize_discrim")]
 	pub discriminator: u16,
 	pub avatar: Option<String>,
 	pub email: Option<String>,
+	#[serde(default)]
 	pub verified: bool,
 	#[serde(default)]
 	pub bot: bool,
+	#[serde(default)]
 	pub mfa_enabled: bool,
 }
 serial_decode!(CurrentUser);
@@ -1576,7 +3255,7 @@ serial_decode!(CurrentUser);
 pub struct CurrentUserPatch {
 	pub id: Option<UserId>,
 	pub username: Option<String>,
-	#[serde(deserialize_with = "::serial::deserialize_discrim_opt")]
+	#[serde(default, deserialize_with = "::serial::deserialize_discrim_opt")]
 	pub discriminator: Option<u16>,
 	pub avatar: Option<String>,
 	pub email: Option<String>,
@@ -1701,6 +3380,258 @@ mod test {
 		);
 		assert_field!(user, p3, email, avatar);
 	}
+
+	#[test]
+	fn test_reaction_remove_emoji() {
+		let event = Event::decode(
+			"MESSAGE_REACTION_REMOVE_EMOJI".into(),
+			json!({
+				"channel_id": "1234",
+				"guild_id": "5678",
+				"message_id": "9012",
+				"emoji": { "id": null, "name": "👌" },
+			}),
+		)
+		.unwrap();
+		match event {
+			Event::ReactionRemoveEmoji {
+				channel_id,
+				message_id,
+				emoji,
+			} => {
+				assert_eq!(channel_id, ChannelId(1234));
+				assert_eq!(message_id, MessageId(9012));
+				assert_eq!(emoji, ReactionEmoji::Unicode("👌".into()));
+			}
+			other => panic!("Unexpected event: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn test_color() {
+		assert_eq!(Color::from_rgb(0x34, 0x98, 0xdb), Color::BLUE);
+		assert_eq!(Color::from_hex_str("#3498db"), Some(Color::BLUE));
+		assert_eq!(Color::from_hex_str("0x3498DB"), Some(Color::BLUE));
+		assert_eq!(Color::from_hex_str("3498db"), Some(Color::BLUE));
+		assert_eq!(Color::from_hex_str("#3498d"), None);
+		assert_eq!(Color::from_hex_str("#+498db"), None);
+		assert_eq!(Color::from(0x3498db), Color::BLUE);
+		assert_eq!(Color::BLUE.to_rgb(), (0x34, 0x98, 0xdb));
+		assert_eq!(Color::BLUE.to_string(), "#3498DB");
+		assert_eq!(::serde_json::to_value(Color::BLUE).unwrap(), json!(0x3498db));
+	}
+
+	#[test]
+	fn test_decode_forum_channel() {
+		let channel = Channel::decode(json!({
+			"id": "41771983423143937",
+			"guild_id": "41771983423143936",
+			"name": "help",
+			"type": 15,
+			"position": 3,
+			"permission_overwrites": [],
+			"available_tags": [
+				{"id": "1", "name": "solved", "moderated": true, "emoji_id": null, "emoji_name": "✅"},
+			],
+			"default_reaction_emoji": {"emoji_id": null, "emoji_name": "👍"},
+			"default_sort_order": 1,
+			"default_forum_layout": 0,
+		}))
+		.unwrap();
+		let channel = match channel {
+			Channel::Public(channel) => channel,
+			other => panic!("expected public channel, got {:?}", other),
+		};
+		assert_eq!(channel.kind, ChannelType::Forum);
+		assert_eq!(channel.find_tag(ForumTagId(1)).unwrap().name, "solved");
+		assert_eq!(channel.default_sort_order, Some(SortOrder::CreationDate));
+		assert_eq!(channel.default_forum_layout, Some(ForumLayout::NotSet));
+		assert_eq!(
+			channel.default_reaction_emoji,
+			Some(ReactionEmoji::Unicode("👍".into()).into())
+		);
+	}
+
+	#[test]
+	fn test_decode_minimal_payloads() {
+		// fields Discord documents as optional may be absent entirely
+		let user = CurrentUser::decode(json!({
+			"id": "41771983423143936",
+			"username": "minimal",
+			"avatar": null,
+		}))
+		.unwrap();
+		assert_eq!(user.discriminator, 0);
+		assert!(!user.verified && !user.mfa_enabled && !user.bot);
+
+		let member = Member::decode(json!({
+			"user": {"id": "41771983423143936", "username": "minimal", "avatar": null},
+			"roles": [],
+			"joined_at": "2015-04-26T06:26:56.936000+00:00",
+		}))
+		.unwrap();
+		assert!(!member.mute && !member.deaf);
+
+		let emoji = Emoji::decode(json!({"id": "41771983423143938", "name": "blob"})).unwrap();
+		assert!(!emoji.animated && emoji.roles.is_empty());
+
+		let channel = Channel::decode(json!({
+			"id": "41771983423143937",
+			"guild_id": "41771983423143936",
+			"name": "announcements",
+			"type": 5,
+		}))
+		.unwrap();
+		match channel {
+			Channel::Public(channel) => {
+				assert_eq!(channel.kind, ChannelType::News);
+				assert_eq!(channel.position, 0);
+				assert!(channel.permission_overwrites.is_empty());
+				assert!(channel.last_pin_timestamp.is_none());
+			}
+			other => panic!("expected public channel, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn test_decode_embed() {
+		let embed = Embed::decode(json!({
+			"type": "rich",
+			"title": "Release notes",
+			"color": 0x3498db,
+			"timestamp": "2020-06-01T12:00:00.000000+00:00",
+			"footer": { "text": "discord-rs" },
+			"thumbnail": {
+				"url": "https://example.com/a.png",
+				"proxy_url": "https://media.discordapp.net/a.png",
+				"width": 64,
+				"height": 64,
+			},
+			"fields": [
+				{ "name": "Added", "value": "Embeds", "inline": true },
+				{ "name": "Removed", "value": "Nothing" },
+			],
+		}))
+		.unwrap();
+		assert_eq!(embed.title.as_ref().unwrap(), "Release notes");
+		assert_eq!(embed.color, Some(Color::BLUE));
+		assert_eq!(embed.footer.unwrap().text, "discord-rs");
+		assert_eq!(embed.thumbnail.unwrap().width, Some(64));
+		assert!(embed.image.is_none());
+		assert_eq!(embed.fields.len(), 2);
+		assert!(embed.fields[0].inline);
+		assert!(!embed.fields[1].inline);
+	}
+
+	#[test]
+	fn test_voice_message_metadata() {
+		// one second of silence followed by one second at full volume
+		let mut samples = vec![0i16; 48000];
+		samples.extend(vec![i16::MIN; 48000]);
+		let metadata = VoiceMessageMetadata::from_pcm(&samples, false);
+		assert_eq!(metadata.duration_secs, 2.0);
+		assert_eq!(metadata.waveform.len(), 20);
+		assert!(metadata.waveform[..10].iter().all(|&p| p == 0));
+		assert!(metadata.waveform[10..].iter().all(|&p| p == 255));
+
+		let stereo = VoiceMessageMetadata::from_pcm(&samples, true);
+		assert_eq!(stereo.duration_secs, 1.0);
+		assert_eq!(stereo.waveform.len(), 10);
+
+		let long = VoiceMessageMetadata::from_pcm(&vec![0; 48000 * 60], false);
+		assert_eq!(long.waveform.len(), 256);
+	}
+
+	#[test]
+	fn test_join_request() {
+		let event = Event::decode(
+			"GUILD_JOIN_REQUEST_UPDATE".into(),
+			json!({
+				"guild_id": "5678",
+				"status": "SUBMITTED",
+				"request": {
+					"id": "1111",
+					"guild_id": "5678",
+					"user_id": "1234",
+					"created_at": "2023-03-01T12:00:00.000000+00:00",
+					"application_status": "SUBMITTED",
+					"rejection_reason": null,
+					"actioned_at": null,
+					"form_responses": [{
+						"field_type": "TERMS",
+						"label": "Read and agree to the server rules",
+						"values": ["Be nice"],
+						"required": true,
+						"response": true,
+					}],
+				},
+			}),
+		)
+		.unwrap();
+		match event {
+			Event::ServerJoinRequestUpdate(request) => {
+				assert_eq!(request.id, 1111);
+				assert_eq!(request.server_id, ServerId(5678));
+				assert_eq!(request.application_status, JoinRequestStatus::Submitted);
+				assert_eq!(request.form_responses[0].response, Some(Value::Bool(true)));
+			}
+			other => panic!("Unexpected event: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn test_interaction_locale() {
+		let event = Event::decode(
+			"INTERACTION_CREATE".into(),
+			json!({
+				"id": "2222",
+				"application_id": "3333",
+				"type": 2,
+				"data": {"id": "4444", "name": "hello", "type": 1},
+				"guild_id": "5678",
+				"channel_id": "5679",
+				"member": {
+					"user": {
+						"id": "1234", "username": "Someone", "discriminator": "0", "avatar": null,
+					},
+					"roles": [],
+					"nick": null,
+					"joined_at": "2020-01-01T00:00:00.000000+00:00",
+				},
+				"token": "interaction-token",
+				"version": 1,
+				"locale": "pt-BR",
+				"guild_locale": "en-US",
+			}),
+		)
+		.unwrap();
+		match event {
+			Event::InteractionCreate(interaction) => {
+				assert_eq!(interaction.kind, InteractionType::ApplicationCommand);
+				assert_eq!(interaction.invoker().map(|user| user.id), Some(UserId(1234)));
+				assert_eq!(interaction.locale.as_ref().map(|s| &s[..]), Some("pt-BR"));
+				assert_eq!(interaction.guild_locale.as_ref().map(|s| &s[..]), Some("en-US"));
+			}
+			other => panic!("Unexpected event: {:?}", other),
+		}
+
+		let command = ApplicationCommand::decode(json!({
+			"id": "4444",
+			"application_id": "3333",
+			"name": "hello",
+			"name_localizations": {"fr": "bonjour"},
+			"description": "Say hello",
+			"description_localizations": null,
+			"options": [{"type": 6, "name": "target", "description": "Who to greet"}],
+			"version": "1",
+		}))
+		.unwrap();
+		assert_eq!(command.kind, CommandType::ChatInput);
+		let names = command.name_localizations.unwrap();
+		assert_eq!(names.get("fr").map(|s| &s[..]), Some("bonjour"));
+		assert_eq!(command.options[0].kind, CommandOptionType::User);
+		assert!(!command.options[0].required);
+	}
 }
 
 /// Information about the current application and the owner.
@@ -1710,6 +3641,7 @@ pub struct ApplicationInfo {
 	pub icon: Option<String>,
 	pub id: UserId,
 	pub name: String,
+	#[serde(default)]
 	pub rpc_origins: Vec<String>,
 	pub bot_public: bool,
 	pub bot_require_code_grant: bool,
@@ -1718,6 +3650,320 @@ pub struct ApplicationInfo {
 }
 serial_decode!(ApplicationInfo);
 
+/// Gateway connection information for a bot, from `Discord::get_bot_gateway`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BotGateway {
+	/// The URL to connect to
+	pub url: String,
+	/// The recommended number of shards
+	pub shards: u64,
+	pub session_start_limit: SessionStartLimit,
+}
+serial_decode!(BotGateway);
+
+/// How many new gateway sessions a bot may currently start.
+///
+/// Each identify counts against this limit, while resuming a session does
+/// not.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionStartLimit {
+	/// The total number of session starts allowed per reset period
+	pub total: u64,
+	/// The number of session starts remaining
+	pub remaining: u64,
+	/// Milliseconds until the limit resets
+	pub reset_after: u64,
+	/// How many shards may identify in each 5 second window
+	pub max_concurrency: u64,
+}
+
+/// A premium offering of an application, such as a subscription
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sku {
+	pub id: SkuId,
+	#[serde(rename = "type")]
+	pub kind: SkuType,
+	pub application_id: ApplicationId,
+	pub name: String,
+	/// A system-generated URL slug based on the name
+	pub slug: String,
+	#[serde(default)]
+	pub flags: u64,
+}
+serial_decode!(Sku);
+
+/// The kind of a SKU
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SkuType {
+	/// A permanent one-time purchase
+	Durable,
+	/// A one-time purchase which can be used up
+	Consumable,
+	/// A recurring subscription
+	Subscription,
+	/// A group of subscriptions, generated by Discord
+	SubscriptionGroup,
+}
+
+serial_use_mapping!(SkuType, numeric);
+serial_numbers! { SkuType;
+	Durable, 2;
+	Consumable, 3;
+	Subscription, 5;
+	SubscriptionGroup, 6;
+}
+
+/// Access to a SKU granted to a user or a server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entitlement {
+	pub id: EntitlementId,
+	pub sku_id: SkuId,
+	pub application_id: ApplicationId,
+	/// The user granted the entitlement, if granted to a user
+	pub user_id: Option<UserId>,
+	/// The server granted the entitlement, if granted to a server
+	#[serde(rename = "guild_id")]
+	pub server_id: Option<ServerId>,
+	#[serde(rename = "type")]
+	pub kind: EntitlementType,
+	#[serde(default)]
+	pub deleted: bool,
+	/// When the entitlement begins, absent for test entitlements
+	pub starts_at: Option<DateTime<FixedOffset>>,
+	/// When the entitlement ends, absent for test entitlements
+	pub ends_at: Option<DateTime<FixedOffset>>,
+	/// Whether a consumable entitlement has been used up
+	#[serde(default)]
+	pub consumed: bool,
+}
+serial_decode!(Entitlement);
+
+impl Entitlement {
+	/// Get the user or server granted the entitlement.
+	pub fn owner(&self) -> Option<EntitlementOwner> {
+		match (self.server_id, self.user_id) {
+			(Some(server), _) => Some(EntitlementOwner::Server(server)),
+			(None, Some(user)) => Some(EntitlementOwner::User(user)),
+			(None, None) => None,
+		}
+	}
+
+	/// Check whether the entitlement currently grants its SKU: it has not
+	/// been deleted or consumed, and has started and not yet ended.
+	pub fn is_active(&self) -> bool {
+		let now = ::chrono::Utc::now();
+		!self.deleted
+			&& !self.consumed
+			&& self.starts_at.map_or(true, |start| start <= now)
+			&& self.ends_at.map_or(true, |end| end > now)
+	}
+}
+
+/// The user or server an entitlement is granted to
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum EntitlementOwner {
+	User(UserId),
+	Server(ServerId),
+}
+
+impl From<UserId> for EntitlementOwner {
+	fn from(user: UserId) -> EntitlementOwner {
+		EntitlementOwner::User(user)
+	}
+}
+
+impl From<ServerId> for EntitlementOwner {
+	fn from(server: ServerId) -> EntitlementOwner {
+		EntitlementOwner::Server(server)
+	}
+}
+
+/// How an entitlement was granted
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum EntitlementType {
+	/// Purchased by a user
+	Purchase,
+	/// Granted by the user's Nitro subscription
+	PremiumSubscription,
+	/// Gifted by the application's developer
+	DeveloperGift,
+	/// Purchased by a developer in application test mode
+	TestModePurchase,
+	/// Granted when the SKU was free
+	FreePurchase,
+	/// Gifted by another user
+	UserGift,
+	/// Claimed by a user for free as a Nitro subscriber
+	PremiumPurchase,
+	/// Purchased as an application subscription
+	ApplicationSubscription,
+}
+
+serial_use_mapping!(EntitlementType, numeric);
+serial_numbers! { EntitlementType;
+	Purchase, 1;
+	PremiumSubscription, 2;
+	DeveloperGift, 3;
+	TestModePurchase, 4;
+	FreePurchase, 5;
+	UserGift, 6;
+	PremiumPurchase, 7;
+	ApplicationSubscription, 8;
+}
+
+/// A slash command or context menu entry registered by an application
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApplicationCommand {
+	pub id: CommandId,
+	#[serde(rename = "type", default)]
+	pub kind: CommandType,
+	pub application_id: ApplicationId,
+	/// The server the command is registered in, or `None` if it is global
+	#[serde(rename = "guild_id")]
+	pub server_id: Option<ServerId>,
+	pub name: String,
+	/// The name to show instead in each locale, when requested
+	pub name_localizations: Option<BTreeMap<String, String>>,
+	/// Empty for context menu entries
+	pub description: String,
+	/// The description to show instead in each locale, when requested
+	pub description_localizations: Option<BTreeMap<String, String>>,
+	#[serde(default)]
+	pub options: Vec<CommandOption>,
+	/// Changes whenever the command is updated
+	pub version: String,
+}
+serial_decode!(ApplicationCommand);
+
+/// How an application command is invoked
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CommandType {
+	/// A slash command, typed into the message box
+	ChatInput,
+	/// An entry in the menu shown when right-clicking a user
+	User,
+	/// An entry in the menu shown when right-clicking a message
+	Message,
+}
+
+serial_use_mapping!(CommandType, numeric);
+serial_numbers! { CommandType;
+	ChatInput, 1;
+	User, 2;
+	Message, 3;
+}
+
+impl Default for CommandType {
+	fn default() -> CommandType {
+		CommandType::ChatInput
+	}
+}
+
+/// A parameter of a slash command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandOption {
+	#[serde(rename = "type")]
+	pub kind: CommandOptionType,
+	pub name: String,
+	pub name_localizations: Option<BTreeMap<String, String>>,
+	pub description: String,
+	pub description_localizations: Option<BTreeMap<String, String>>,
+	#[serde(default)]
+	pub required: bool,
+	/// The options of a subcommand or subcommand group
+	#[serde(default)]
+	pub options: Vec<CommandOption>,
+}
+serial_decode!(CommandOption);
+
+/// The kind of value a slash command option accepts
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CommandOptionType {
+	SubCommand,
+	SubCommandGroup,
+	String,
+	Integer,
+	Boolean,
+	User,
+	Channel,
+	Role,
+	/// A user or a role
+	Mentionable,
+	/// A floating-point number
+	Number,
+	Attachment,
+}
+
+serial_use_mapping!(CommandOptionType, numeric);
+serial_numbers! { CommandOptionType;
+	SubCommand, 1;
+	SubCommandGroup, 2;
+	String, 3;
+	Integer, 4;
+	Boolean, 5;
+	User, 6;
+	Channel, 7;
+	Role, 8;
+	Mentionable, 9;
+	Number, 10;
+	Attachment, 11;
+}
+
+/// A user's use of an application command or message component
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+	pub id: InteractionId,
+	pub application_id: ApplicationId,
+	#[serde(rename = "type")]
+	pub kind: InteractionType,
+	/// The command invoked or component used, depending on the kind
+	pub data: Option<Value>,
+	#[serde(rename = "guild_id")]
+	pub server_id: Option<ServerId>,
+	pub channel_id: Option<ChannelId>,
+	/// The invoking member, if invoked in a server
+	pub member: Option<Member>,
+	/// The invoking user, if invoked in a private channel
+	pub user: Option<User>,
+	/// A token used to respond to the interaction, valid for 15 minutes
+	pub token: String,
+	pub version: u64,
+	/// The invoking user's chosen language, absent for pings
+	pub locale: Option<String>,
+	/// The server's preferred language, if invoked in a server
+	pub guild_locale: Option<String>,
+}
+serial_decode!(Interaction);
+
+impl Interaction {
+	/// Get the user who caused the interaction, whether a server member or
+	/// not.
+	pub fn invoker(&self) -> Option<&User> {
+		self.member.as_ref().map(|member| &member.user).or(self.user.as_ref())
+	}
+}
+
+/// The kind of an interaction
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum InteractionType {
+	/// Sent to check an interactions endpoint URL, never over the gateway
+	Ping,
+	ApplicationCommand,
+	MessageComponent,
+	/// A user is typing an option which offers autocompletion
+	ApplicationCommandAutocomplete,
+	ModalSubmit,
+}
+
+serial_use_mapping!(InteractionType, numeric);
+serial_numbers! { InteractionType;
+	Ping, 1;
+	ApplicationCommand, 2;
+	MessageComponent, 3;
+	ApplicationCommandAutocomplete, 4;
+	ModalSubmit, 5;
+}
+
 /// A type of relationship this user has with another.
 #[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
 pub enum RelationshipType {
@@ -1762,7 +4008,7 @@ pub struct FriendSourceFlags {
 serial_decode!(FriendSourceFlags);
 
 /// User settings usually used to influence client behavior
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct UserSettings {
 	pub detect_platform_accounts: bool,
 	pub developer_mode: bool,
@@ -1772,6 +4018,7 @@ pub struct UserSettings {
 	pub locale: String,
 	pub message_display_compact: bool,
 	pub render_embeds: bool,
+	#[serde(rename = "guild_positions")]
 	pub server_positions: Vec<ServerId>,
 	pub show_current_game: bool,
 	pub status: String,
@@ -1779,6 +4026,7 @@ pub struct UserSettings {
 	pub convert_emoticons: bool,
 	pub friend_source_flags: FriendSourceFlags,
 	/// Servers whose members cannot private message this user.
+	#[serde(rename = "restricted_guilds")]
 	pub restricted_servers: Vec<ServerId>,
 }
 
@@ -1825,6 +4073,7 @@ impl UserSettings {
 		.map(Some)
 	}
 }
+deserialize_using_decode!(UserSettings as optional);
 
 /// Notification level for a channel or server
 #[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
@@ -1933,6 +4182,8 @@ pub struct ReadyEvent {
 	pub version: u64,
 	pub user: CurrentUser,
 	pub session_id: String,
+	/// The gateway URL to use when resuming this session.
+	pub resume_gateway_url: Option<String>,
 	pub user_settings: Option<UserSettings>,
 	pub read_state: Option<Vec<ReadState>>,
 	pub private_channels: Vec<Channel>,
@@ -1946,7 +4197,7 @@ pub struct ReadyEvent {
 	pub notes: Option<BTreeMap<UserId, Option<String>>>,
 	/// The shard info for this session; the shard id used and the total number
 	/// of shards.
-	pub shard: Option<[u8; 2]>,
+	pub shard: Option<[u32; 2]>,
 }
 
 /// the emoji used for a custom status
@@ -1977,9 +4228,57 @@ pub struct Activity {
 	pub details: Option<String>,
 	/// stream url, is validated when type is 1
 	pub url: Option<String>,
+	/// the activity's name, such as the game being played
+	#[serde(default)]
+	pub name: String,
+	/// the application the activity belongs to, for rich presence
+	pub application_id: Option<ApplicationId>,
+	/// when the activity started and ends
+	pub timestamps: Option<ActivityTimestamps>,
+	/// images and hover text shown for the activity
+	pub assets: Option<ActivityAssets>,
+	/// the party the user is playing in
+	pub party: Option<ActivityParty>,
 }
 serial_decode!(Activity);
 
+/// The start and end of an activity, as Unix times in milliseconds
+/// https://discord.com/developers/docs/topics/gateway#activity-object-activity-timestamps
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActivityTimestamps {
+	pub start: Option<u64>,
+	pub end: Option<u64>,
+}
+
+/// Images shown for a rich presence activity
+/// https://discord.com/developers/docs/topics/gateway#activity-object-activity-assets
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActivityAssets {
+	pub large_image: Option<String>,
+	pub large_text: Option<String>,
+	pub small_image: Option<String>,
+	pub small_text: Option<String>,
+}
+
+/// The party a user is playing in
+/// https://discord.com/developers/docs/topics/gateway#activity-object-activity-party
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActivityParty {
+	pub id: Option<String>,
+	/// the party's current and maximum size
+	pub size: Option<(u64, u64)>,
+}
+
+/// A user's status on each kind of client, absent where they are offline
+/// https://discord.com/developers/docs/topics/gateway#client-status-object
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ClientStatus {
+	pub desktop: Option<OnlineStatus>,
+	pub mobile: Option<OnlineStatus>,
+	pub web: Option<OnlineStatus>,
+}
+serial_decode!(ClientStatus);
+
 /// Event received over a websocket connection
 #[derive(Debug, Clone)]
 pub enum Event {
@@ -2030,7 +4329,7 @@ pub enum Event {
 	CallUpdate {
 		channel_id: ChannelId,
 		message_id: MessageId,
-		region: String,
+		region: Region,
 		ringing: Vec<UserId>,
 	},
 	/// A group call has been deleted (the call ended)
@@ -2075,7 +4374,7 @@ pub enum Event {
 		mentions: Option<Vec<User>>,
 		mention_roles: Option<Vec<RoleId>>,
 		attachments: Option<Vec<Attachment>>,
-		embeds: Option<Vec<Value>>,
+		embeds: Option<Vec<Embed>>,
 	},
 	/// Another logged-in device acknowledged this message
 	MessageAck {
@@ -2105,7 +4404,22 @@ pub enum Event {
 		nick: Option<String>,
 	},
 	ServerMemberRemove(ServerId, User),
-	ServerMembersChunk(ServerId, Vec<Member>),
+	/// Members sent in response to a member request, possibly split across
+	/// several chunks
+	ServerMembersChunk {
+		server_id: ServerId,
+		members: Vec<Member>,
+		/// The index of this chunk, starting from zero
+		chunk_index: u64,
+		/// The number of chunks in the response
+		chunk_count: u64,
+		/// Requested user IDs which are not members of the server
+		not_found: Vec<UserId>,
+		/// The members' presences, if requested
+		presences: Vec<Presence>,
+		/// The nonce given with the request
+		nonce: Option<String>,
+	},
 	ServerSync {
 		server_id: ServerId,
 		large: bool,
@@ -2122,6 +4436,35 @@ pub enum Event {
 
 	ServerIntegrationsUpdate(ServerId),
 	ServerEmojisUpdate(ServerId, Vec<Emoji>),
+	ServerStickersUpdate(ServerId, Vec<Sticker>),
+
+	ServerScheduledEventCreate(ScheduledEvent),
+	ServerScheduledEventUpdate(ScheduledEvent),
+	ServerScheduledEventDelete(ScheduledEvent),
+	/// A user marked themselves interested in a scheduled event
+	ServerScheduledEventUserAdd {
+		server_id: ServerId,
+		event_id: ScheduledEventId,
+		user_id: UserId,
+	},
+	/// A user is no longer interested in a scheduled event
+	ServerScheduledEventUserRemove {
+		server_id: ServerId,
+		event_id: ScheduledEventId,
+		user_id: UserId,
+	},
+
+	/// A user began or submitted a request to join a server with membership
+	/// screening
+	ServerJoinRequestCreate(JoinRequest),
+	/// A join request was submitted, approved, or rejected
+	ServerJoinRequestUpdate(JoinRequest),
+	/// A join request was withdrawn or the user left the server
+	ServerJoinRequestDelete {
+		server_id: ServerId,
+		id: u64,
+		user_id: UserId,
+	},
 
 	ChannelCreate(Channel),
 	ChannelUpdate(Channel),
@@ -2137,6 +4480,53 @@ pub enum Event {
 
 	ReactionAdd(Reaction),
 	ReactionRemove(Reaction),
+	/// All reactions were removed from a message
+	ReactionRemoveAll {
+		channel_id: ChannelId,
+		message_id: MessageId,
+	},
+	/// All reactions using a single emoji were removed from a message
+	ReactionRemoveEmoji {
+		channel_id: ChannelId,
+		message_id: MessageId,
+		emoji: ReactionEmoji,
+	},
+
+	ThreadCreate(ThreadChannel),
+	ThreadUpdate(ThreadChannel),
+	ThreadDelete {
+		id: ChannelId,
+		server_id: ServerId,
+		parent_id: ChannelId,
+		kind: ChannelType,
+	},
+	/// Users were added to or removed from a thread
+	ThreadMembersUpdate {
+		id: ChannelId,
+		server_id: ServerId,
+		/// Approximate count of users, stops counting at 50
+		member_count: u64,
+		added_members: Vec<ThreadMember>,
+		removed_member_ids: Vec<UserId>,
+	},
+	/// The logged-in user gained access to a channel's threads
+	ThreadListSync {
+		server_id: ServerId,
+		/// The parent channels being synced, or `None` for the whole server
+		channel_ids: Option<Vec<ChannelId>>,
+		threads: Vec<ThreadChannel>,
+		members: Vec<ThreadMember>,
+	},
+
+	/// A user or server was granted a SKU
+	EntitlementCreate(Entitlement),
+	/// An entitlement was renewed or ended
+	EntitlementUpdate(Entitlement),
+	/// An entitlement was refunded or revoked
+	EntitlementDelete(Entitlement),
+
+	/// A user invoked an application command or used a message component
+	InteractionCreate(Interaction),
 
 	/// An event type not covered by the above
 	Unknown(String, Object),
@@ -2156,6 +4546,7 @@ impl Event {
 				version: req!(try!(remove(&mut value, "v")).as_u64()),
 				user: try!(remove(&mut value, "user").and_then(CurrentUser::decode)),
 				session_id: try!(remove(&mut value, "session_id").and_then(into_string)),
+				resume_gateway_url: try!(opt(&mut value, "resume_gateway_url", into_string)),
 				read_state: try!(opt(&mut value, "read_state", |v| decode_array(v, ReadState::decode))),
 				private_channels: try!(decode_array(try!(remove(&mut value, "private_channels")), Channel::decode)),
 				presences: try!(decode_array(try!(remove(&mut value, "presences")), Presence::decode)),
@@ -2261,7 +4652,7 @@ impl Event {
 				Event::CallUpdate {
 					channel_id: try!(remove(&mut value, "channel_id").and_then(ChannelId::decode)),
 					message_id: try!(remove(&mut value, "message_id").and_then(MessageId::decode)),
-					region: try!(remove(&mut value, "region").and_then(into_string)),
+					region: try!(remove(&mut value, "region").and_then(Region::decode)),
 					ringing: try!(decode_array(
 						try!(remove(&mut value, "ringing")),
 						UserId::decode
@@ -2312,6 +4703,23 @@ impl Event {
 			Reaction::decode(Value::Object(value)).map(Event::ReactionAdd)
 		} else if kind == "MESSAGE_REACTION_REMOVE" {
 			Reaction::decode(Value::Object(value)).map(Event::ReactionRemove)
+		} else if kind == "MESSAGE_REACTION_REMOVE_ALL" {
+			warn_json!(
+				value,
+				Event::ReactionRemoveAll {
+					channel_id: try!(remove(&mut value, "channel_id").and_then(ChannelId::decode)),
+					message_id: try!(remove(&mut value, "message_id").and_then(MessageId::decode)),
+				}
+			)
+		} else if kind == "MESSAGE_REACTION_REMOVE_EMOJI" {
+			warn_json!(
+				value,
+				Event::ReactionRemoveEmoji {
+					channel_id: try!(remove(&mut value, "channel_id").and_then(ChannelId::decode)),
+					message_id: try!(remove(&mut value, "message_id").and_then(MessageId::decode)),
+					emoji: try!(remove(&mut value, "emoji").and_then(ReactionEmoji::decode)),
+				}
+			)
 		} else if kind == "MESSAGE_CREATE" {
 			Message::decode(Value::Object(value)).map(Event::MessageCreate)
 		} else if kind == "MESSAGE_UPDATE" {
@@ -2343,7 +4751,7 @@ impl Event {
 						v,
 						Attachment::decode
 					))),
-					embeds: try!(opt(&mut value, "embeds", |v| decode_array(v, Ok))),
+					embeds: try!(opt(&mut value, "embeds", |v| decode_array(v, Embed::decode))),
 				}
 			)
 		} else if kind == "MESSAGE_ACK" {
@@ -2408,12 +4816,25 @@ impl Event {
 		} else if kind == "GUILD_MEMBERS_CHUNK" {
 			warn_json!(
 				value,
-				Event::ServerMembersChunk(
-					try!(remove(&mut value, "guild_id").and_then(ServerId::decode)),
-					try!(
+				Event::ServerMembersChunk {
+					server_id: try!(remove(&mut value, "guild_id").and_then(ServerId::decode)),
+					members: try!(
 						remove(&mut value, "members").and_then(|v| decode_array(v, Member::decode))
 					),
-				)
+					chunk_index: try!(opt(&mut value, "chunk_index", |v| Ok(req!(v.as_u64()))))
+						.unwrap_or(0),
+					chunk_count: try!(opt(&mut value, "chunk_count", |v| Ok(req!(v.as_u64()))))
+						.unwrap_or(1),
+					not_found: try!(opt(&mut value, "not_found", |v| {
+						decode_array(v, UserId::decode)
+					}))
+					.unwrap_or_default(),
+					presences: try!(opt(&mut value, "presences", |v| {
+						decode_array(v, Presence::decode)
+					}))
+					.unwrap_or_default(),
+					nonce: try!(opt(&mut value, "nonce", into_string)),
+				}
 			)
 		} else if kind == "GUILD_SYNC" {
 			warn_json!(
@@ -2477,6 +4898,59 @@ impl Event {
 					remove(&mut value, "guild_id").and_then(ServerId::decode)
 				),)
 			)
+		} else if kind == "GUILD_SCHEDULED_EVENT_CREATE" {
+			ScheduledEvent::decode(Value::Object(value)).map(Event::ServerScheduledEventCreate)
+		} else if kind == "GUILD_SCHEDULED_EVENT_UPDATE" {
+			ScheduledEvent::decode(Value::Object(value)).map(Event::ServerScheduledEventUpdate)
+		} else if kind == "GUILD_SCHEDULED_EVENT_DELETE" {
+			ScheduledEvent::decode(Value::Object(value)).map(Event::ServerScheduledEventDelete)
+		} else if kind == "GUILD_SCHEDULED_EVENT_USER_ADD" {
+			warn_json!(
+				value,
+				Event::ServerScheduledEventUserAdd {
+					server_id: try!(remove(&mut value, "guild_id").and_then(ServerId::decode)),
+					event_id: try!(
+						remove(&mut value, "guild_scheduled_event_id").and_then(ScheduledEventId::decode)
+					),
+					user_id: try!(remove(&mut value, "user_id").and_then(UserId::decode)),
+				}
+			)
+		} else if kind == "GUILD_SCHEDULED_EVENT_USER_REMOVE" {
+			warn_json!(
+				value,
+				Event::ServerScheduledEventUserRemove {
+					server_id: try!(remove(&mut value, "guild_id").and_then(ServerId::decode)),
+					event_id: try!(
+						remove(&mut value, "guild_scheduled_event_id").and_then(ScheduledEventId::decode)
+					),
+					user_id: try!(remove(&mut value, "user_id").and_then(UserId::decode)),
+				}
+			)
+		} else if kind == "GUILD_JOIN_REQUEST_CREATE" || kind == "GUILD_JOIN_REQUEST_UPDATE" {
+			// the request's status is repeated alongside it
+			let request = try!(remove(&mut value, "request").and_then(JoinRequest::decode));
+			Ok(if kind == "GUILD_JOIN_REQUEST_CREATE" {
+				Event::ServerJoinRequestCreate(request)
+			} else {
+				Event::ServerJoinRequestUpdate(request)
+			})
+		} else if kind == "GUILD_JOIN_REQUEST_DELETE" {
+			warn_json!(
+				value,
+				Event::ServerJoinRequestDelete {
+					server_id: try!(remove(&mut value, "guild_id").and_then(ServerId::decode)),
+					id: try!(remove(&mut value, "id").and_then(decode_id)),
+					user_id: try!(remove(&mut value, "user_id").and_then(UserId::decode)),
+				}
+			)
+		} else if kind == "GUILD_STICKERS_UPDATE" {
+			warn_json!(
+				value,
+				Event::ServerStickersUpdate(
+					try!(remove(&mut value, "guild_id").and_then(ServerId::decode)),
+					try!(remove(&mut value, "stickers").and_then(|v| decode_array(v, Sticker::decode))),
+				)
+			)
 		} else if kind == "GUILD_EMOJIS_UPDATE" {
 			warn_json!(
 				value,
@@ -2507,12 +4981,151 @@ impl Event {
 					last_pin_timestamp: try!(opt(&mut value, "last_pin_timestamp", into_timestamp)),
 				}
 			)
+		} else if kind == "THREAD_CREATE" {
+			ThreadChannel::decode(Value::Object(value)).map(Event::ThreadCreate)
+		} else if kind == "THREAD_UPDATE" {
+			ThreadChannel::decode(Value::Object(value)).map(Event::ThreadUpdate)
+		} else if kind == "THREAD_DELETE" {
+			warn_json!(
+				value,
+				Event::ThreadDelete {
+					id: try!(remove(&mut value, "id").and_then(ChannelId::decode)),
+					server_id: try!(remove(&mut value, "guild_id").and_then(ServerId::decode)),
+					parent_id: try!(remove(&mut value, "parent_id").and_then(ChannelId::decode)),
+					kind: try!(remove(&mut value, "type").and_then(serde)),
+				}
+			)
+		} else if kind == "THREAD_MEMBERS_UPDATE" {
+			warn_json!(
+				value,
+				Event::ThreadMembersUpdate {
+					id: try!(remove(&mut value, "id").and_then(ChannelId::decode)),
+					server_id: try!(remove(&mut value, "guild_id").and_then(ServerId::decode)),
+					member_count: req!(try!(remove(&mut value, "member_count")).as_u64()),
+					added_members: try!(opt(&mut value, "added_members", |v| decode_array(
+						v,
+						ThreadMember::decode
+					)))
+					.unwrap_or_default(),
+					removed_member_ids: try!(opt(&mut value, "removed_member_ids", |v| {
+						decode_array(v, UserId::decode)
+					}))
+					.unwrap_or_default(),
+				}
+			)
+		} else if kind == "THREAD_LIST_SYNC" {
+			warn_json!(
+				value,
+				Event::ThreadListSync {
+					server_id: try!(remove(&mut value, "guild_id").and_then(ServerId::decode)),
+					channel_ids: try!(opt(&mut value, "channel_ids", |v| decode_array(
+						v,
+						ChannelId::decode
+					))),
+					threads: try!(decode_array(
+						try!(remove(&mut value, "threads")),
+						ThreadChannel::decode
+					)),
+					members: try!(decode_array(
+						try!(remove(&mut value, "members")),
+						ThreadMember::decode
+					)),
+				}
+			)
+		} else if kind == "ENTITLEMENT_CREATE" {
+			Entitlement::decode(Value::Object(value)).map(Event::EntitlementCreate)
+		} else if kind == "ENTITLEMENT_UPDATE" {
+			Entitlement::decode(Value::Object(value)).map(Event::EntitlementUpdate)
+		} else if kind == "ENTITLEMENT_DELETE" {
+			Entitlement::decode(Value::Object(value)).map(Event::EntitlementDelete)
+		} else if kind == "INTERACTION_CREATE" {
+			Interaction::decode(Value::Object(value)).map(Event::InteractionCreate)
 		} else {
 			Ok(Event::Unknown(kind, value))
 		}
 	}
 }
 
+/// A reason given by the gateway for closing the connection.
+///
+/// See https://discord.com/developers/docs/topics/opcodes-and-status-codes#gateway-gateway-close-event-codes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CloseCode {
+	/// 4000: An unknown error occurred
+	UnknownError,
+	/// 4001: An invalid opcode or payload was sent
+	UnknownOpcode,
+	/// 4002: A payload could not be decoded
+	DecodeError,
+	/// 4003: A payload was sent before identifying
+	NotAuthenticated,
+	/// 4004: The token is invalid
+	AuthenticationFailed,
+	/// 4005: More than one identify was sent
+	AlreadyAuthenticated,
+	/// 4006: The session is no longer valid
+	SessionNoLongerValid,
+	/// 4007: An invalid sequence number was sent when resuming
+	InvalidSeq,
+	/// 4008: Payloads were sent too quickly
+	RateLimited,
+	/// 4009: The session timed out
+	SessionTimedOut,
+	/// 4010: An invalid shard was sent when identifying
+	InvalidShard,
+	/// 4011: The bot is in too many servers to connect without sharding
+	ShardingRequired,
+	/// 4012: An invalid gateway version was requested
+	InvalidApiVersion,
+	/// 4013: An invalid intent was sent when identifying
+	InvalidIntents,
+	/// 4014: A privileged intent was sent which the bot is not approved for
+	DisallowedIntents,
+}
+
+serial_numbers! { CloseCode;
+	UnknownError, 4000;
+	UnknownOpcode, 4001;
+	DecodeError, 4002;
+	NotAuthenticated, 4003;
+	AuthenticationFailed, 4004;
+	AlreadyAuthenticated, 4005;
+	SessionNoLongerValid, 4006;
+	InvalidSeq, 4007;
+	RateLimited, 4008;
+	SessionTimedOut, 4009;
+	InvalidShard, 4010;
+	ShardingRequired, 4011;
+	InvalidApiVersion, 4012;
+	InvalidIntents, 4013;
+	DisallowedIntents, 4014;
+}
+
+impl CloseCode {
+	/// Whether the session may be resumed after the connection closes.
+	pub fn can_resume(self) -> bool {
+		match self {
+			CloseCode::SessionNoLongerValid | CloseCode::InvalidSeq | CloseCode::SessionTimedOut => false,
+			other => other.can_reconnect(),
+		}
+	}
+
+	/// Whether a new connection may be established after this one closes.
+	/// If not, reconnecting would fail the same way until the bot's token,
+	/// sharding, or intents are fixed.
+	pub fn can_reconnect(self) -> bool {
+		match self {
+			CloseCode::AuthenticationFailed
+			| CloseCode::InvalidShard
+			| CloseCode::ShardingRequired
+			| CloseCode::InvalidApiVersion
+			| CloseCode::InvalidIntents
+			| CloseCode::DisallowedIntents => false,
+			_ => true,
+		}
+	}
+}
+
 #[doc(hidden)]
 #[derive(Debug, Clone)]
 pub enum GatewayEvent {
@@ -2684,14 +5297,14 @@ fn decode_notes(value: Value) -> Result<BTreeMap<UserId, Option<String>>> {
 		.collect()
 }
 
-fn decode_shards(value: Value) -> Result<[u8; 2]> {
+fn decode_shards(value: Value) -> Result<[u32; 2]> {
 	let array = try!(into_array(value));
 	Ok([
-		req!(try!(array.get(0).ok_or(Error::Other("Expected shard number"))).as_u64()) as u8,
+		req!(try!(array.get(0).ok_or(Error::Other("Expected shard number"))).as_u64()) as u32,
 		req!(try!(array
 			.get(1)
 			.ok_or(Error::Other("Expected total shard number")))
-		.as_u64()) as u8,
+		.as_u64()) as u32,
 	])
 }
 
@@ -2733,6 +5346,10 @@ pub fn decode_array<T, F: Fn(Value) -> Result<T>>(value: Value, f: F) -> Result<
 
 fn warn_field(name: &str, map: Object) {
 	if !map.is_empty() {
-		trace!("Unhandled keys: {} has {:?}", name, Value::Object(map))
+		trace!(
+			"Unhandled keys: {} has {}",
+			name,
+			::error::redact_output(&format!("{:?}", Value::Object(map)))
+		)
 	}
 }
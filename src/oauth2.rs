@@ -0,0 +1,208 @@
+//! OAuth2 helpers for inviting bots and acting on behalf of users.
+//!
+//! Use an `AuthorizationUrl` to send users to Discord's authorization page.
+//! Once they return to the redirect URI with a code, exchange it for an
+//! `AccessToken` with `exchange_code`, and pass the token's `access_token` to
+//! `Discord::from_bearer_token`.
+
+use std::fmt::Write;
+
+use hyper::header::ContentType;
+
+use model::{ApplicationId, ApplicationInfo, Permissions, ServerId};
+use Result;
+
+/// Scope for adding a bot to a server.
+pub const BOT: &'static str = "bot";
+/// Scope for reading the user's profile, without their email address.
+pub const IDENTIFY: &'static str = "identify";
+/// Scope for reading the user's email address, along with `IDENTIFY`.
+pub const EMAIL: &'static str = "email";
+/// Scope for listing the servers the user is in.
+pub const GUILDS: &'static str = "guilds";
+/// Scope for adding the user to servers the bot is in.
+pub const GUILDS_JOIN: &'static str = "guilds.join";
+/// Scope for registering slash commands in a server.
+pub const APPLICATIONS_COMMANDS: &'static str = "applications.commands";
+
+/// A builder for the URL of Discord's OAuth2 authorization page.
+///
+/// ```ignore
+/// let info = discord.get_application_info()?;
+/// let url = oauth2::AuthorizationUrl::from_application(&info)
+///     .with_bot(permissions::SEND_MESSAGES | permissions::READ_MESSAGES)
+///     .build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct AuthorizationUrl {
+	client_id: u64,
+	scopes: Vec<String>,
+	permissions: Option<Permissions>,
+	redirect_uri: Option<String>,
+	state: Option<String>,
+	server: Option<ServerId>,
+	disable_server_select: bool,
+}
+
+impl AuthorizationUrl {
+	/// Start building an authorization URL for the given application.
+	pub fn new(client_id: ApplicationId) -> Self {
+		AuthorizationUrl {
+			client_id: client_id.0,
+			scopes: Vec::new(),
+			permissions: None,
+			redirect_uri: None,
+			state: None,
+			server: None,
+			disable_server_select: false,
+		}
+	}
+
+	/// Start building an authorization URL for the application described by
+	/// `get_application_info`.
+	pub fn from_application(info: &ApplicationInfo) -> Self {
+		AuthorizationUrl::new(ApplicationId(info.id.0))
+	}
+
+	/// Request a scope, such as `IDENTIFY` or `GUILDS`.
+	pub fn with_scope(&mut self, scope: &str) -> &mut Self {
+		if !self.scopes.iter().any(|s| s == scope) {
+			self.scopes.push(scope.to_owned());
+		}
+		self
+	}
+
+	/// Request the `bot` scope, creating a role for the bot with the given
+	/// permissions in the server it is added to.
+	pub fn with_bot(&mut self, permissions: Permissions) -> &mut Self {
+		self.permissions = Some(permissions);
+		self.with_scope(BOT)
+	}
+
+	/// Set where Discord sends the user after authorization. Must match one
+	/// of the application's registered redirect URIs.
+	pub fn with_redirect_uri(&mut self, redirect_uri: &str) -> &mut Self {
+		self.redirect_uri = Some(redirect_uri.to_owned());
+		self
+	}
+
+	/// Set a value which Discord passes back to the redirect URI, to protect
+	/// against cross-site request forgery.
+	pub fn with_state(&mut self, state: &str) -> &mut Self {
+		self.state = Some(state.to_owned());
+		self
+	}
+
+	/// Preselect the server the bot is added to. If `locked` is true, the user
+	/// may not choose a different server.
+	pub fn with_server(&mut self, server: ServerId, locked: bool) -> &mut Self {
+		self.server = Some(server);
+		self.disable_server_select = locked;
+		self
+	}
+
+	/// Build the URL.
+	pub fn build(&self) -> String {
+		let mut url = format!(
+			"https://discord.com/oauth2/authorize?client_id={}",
+			self.client_id
+		);
+		if !self.scopes.is_empty() {
+			let _ = write!(url, "&scope={}", ::percent_encode(&self.scopes.join(" ")));
+		}
+		if let Some(permissions) = self.permissions {
+			let _ = write!(url, "&permissions={}", permissions.bits());
+		}
+		if let Some(ref redirect_uri) = self.redirect_uri {
+			let _ = write!(
+				url,
+				"&response_type=code&redirect_uri={}",
+				::percent_encode(redirect_uri)
+			);
+		}
+		if let Some(ref state) = self.state {
+			let _ = write!(url, "&state={}", ::percent_encode(state));
+		}
+		if let Some(server) = self.server {
+			let _ = write!(url, "&guild_id={}", server);
+			if self.disable_server_select {
+				url.push_str("&disable_guild_select=true");
+			}
+		}
+		url
+	}
+}
+
+/// An access token granted to an application by a user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessToken {
+	/// The token to pass to `Discord::from_bearer_token`
+	pub access_token: String,
+	/// The kind of token, usually "Bearer"
+	pub token_type: String,
+	/// How many seconds the token is valid for
+	pub expires_in: u64,
+	/// The token to pass to `refresh_token` once this one expires
+	pub refresh_token: String,
+	/// The granted scopes, separated by spaces
+	pub scope: String,
+}
+
+/// Exchange the code passed to the redirect URI for an access token.
+///
+/// The `redirect_uri` must be the same one used in the `AuthorizationUrl`.
+pub fn exchange_code(
+	client_id: ApplicationId,
+	client_secret: &str,
+	code: &str,
+	redirect_uri: &str,
+) -> Result<AccessToken> {
+	token_request(
+		client_id,
+		client_secret,
+		&format!(
+			"grant_type=authorization_code&code={}&redirect_uri={}",
+			::percent_encode(code),
+			::percent_encode(redirect_uri)
+		),
+	)
+}
+
+/// Obtain a new access token using the refresh token of an earlier one.
+pub fn refresh_token(
+	client_id: ApplicationId,
+	client_secret: &str,
+	refresh_token: &str,
+) -> Result<AccessToken> {
+	token_request(
+		client_id,
+		client_secret,
+		&format!(
+			"grant_type=refresh_token&refresh_token={}",
+			::percent_encode(refresh_token)
+		),
+	)
+}
+
+fn token_request(client_id: ApplicationId, client_secret: &str, grant: &str) -> Result<AccessToken> {
+	use hyper::mime::{Mime, SubLevel, TopLevel};
+
+	let body = format!(
+		"client_id={}&client_secret={}&{}",
+		client_id,
+		::percent_encode(client_secret),
+		grant
+	);
+	let client = ::tls_client();
+	let response = ::check_status(::retry(|| {
+		client
+			.post(api_concat!("/oauth2/token"))
+			.header(ContentType(Mime(
+				TopLevel::Application,
+				SubLevel::WwwFormUrlEncoded,
+				vec![],
+			)))
+			.body(&body)
+	}))?;
+	::from_reader(response)
+}
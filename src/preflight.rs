@@ -0,0 +1,149 @@
+//! Local permission checks ahead of REST requests.
+
+use model::*;
+use {Discord, Error, Result, State};
+
+/// A `Discord` client which checks the bot's permissions, as tracked by a
+/// `State`, before making requests which need them.
+///
+/// Obtained from `Discord::preflight`. A request the bot lacks permission for
+/// fails with `Error::MissingPermission` without being sent, rather than
+/// spending a rate-limited request on a 403 response. If the server or channel
+/// is not known to the `State`, the request is sent unchecked.
+///
+/// ```ignore
+/// if let Err(Error::MissingPermission(missing)) =
+///     discord.preflight(&state).kick_member(server, user)
+/// {
+///     println!("Cannot kick without {:?}", missing);
+/// }
+/// ```
+pub struct Preflight<'a> {
+	discord: &'a Discord,
+	state: &'a State,
+}
+
+impl<'a> Preflight<'a> {
+	pub(crate) fn new(discord: &'a Discord, state: &'a State) -> Preflight<'a> {
+		Preflight {
+			discord: discord,
+			state: state,
+		}
+	}
+
+	/// Check that the bot has the given permissions in a channel.
+	pub fn check_channel(&self, channel: ChannelId, required: Permissions) -> Result<()> {
+		let server = match self
+			.state
+			.find_channel_server(channel)
+			.and_then(|id| self.state.find_server(id))
+		{
+			Some(server) => server,
+			None => return Ok(()),
+		};
+		missing(required, server.permissions_for(channel, self.state.user().id))
+	}
+
+	/// Check that the bot has the given server-wide permissions.
+	pub fn check_server(&self, server: ServerId, required: Permissions) -> Result<()> {
+		match self.state.find_server(server) {
+			Some(server) => missing(required, server.server_permissions_for(self.state.user().id)),
+			None => Ok(()),
+		}
+	}
+
+	/// Send a message to a given channel, if the bot may.
+	///
+	/// See `Discord::send_message`.
+	pub fn send_message(
+		&self,
+		channel: ChannelId,
+		text: &str,
+		nonce: &str,
+		tts: bool,
+	) -> Result<Message> {
+		let mut required = Permissions::SEND_MESSAGES;
+		if tts {
+			required |= Permissions::SEND_TTS_MESSAGES;
+		}
+		self.check_channel(channel, required)?;
+		self.discord.send_message(channel, text, nonce, tts)
+	}
+
+	/// Delete a message, if it was posted by the bot or the bot may manage
+	/// messages in its channel.
+	///
+	/// See `Discord::delete_message`.
+	pub fn delete_message(&self, message: &Message) -> Result<()> {
+		if message.author.id != self.state.user().id {
+			self.check_channel(message.channel_id, Permissions::MANAGE_MESSAGES)?;
+		}
+		self.discord.delete_message(message.channel_id, message.id)
+	}
+
+	/// Bulk delete messages from a channel, if the bot may manage messages
+	/// there.
+	///
+	/// See `Discord::delete_messages`.
+	pub fn delete_messages(&self, channel: ChannelId, messages: &[MessageId]) -> Result<()> {
+		self.check_channel(channel, Permissions::MANAGE_MESSAGES)?;
+		self.discord.delete_messages(channel, messages)
+	}
+
+	/// Pin a message, if the bot may manage messages in its channel.
+	///
+	/// See `Discord::pin_message`.
+	pub fn pin_message(&self, channel: ChannelId, message: MessageId) -> Result<()> {
+		self.check_channel(channel, Permissions::MANAGE_MESSAGES)?;
+		self.discord.pin_message(channel, message)
+	}
+
+	/// Unpin a message, if the bot may manage messages in its channel.
+	///
+	/// See `Discord::unpin_message`.
+	pub fn unpin_message(&self, channel: ChannelId, message: MessageId) -> Result<()> {
+		self.check_channel(channel, Permissions::MANAGE_MESSAGES)?;
+		self.discord.unpin_message(channel, message)
+	}
+
+	/// Kick a member from a server, if the bot may.
+	///
+	/// See `Discord::kick_member`.
+	pub fn kick_member(&self, server: ServerId, user: UserId) -> Result<()> {
+		self.check_server(server, Permissions::KICK_MEMBERS)?;
+		self.discord.kick_member(server, user)
+	}
+
+	/// Ban a user from a server, if the bot may.
+	///
+	/// See `Discord::add_ban`.
+	pub fn add_ban(&self, server: ServerId, user: UserId, delete_message_days: u32) -> Result<()> {
+		self.check_server(server, Permissions::BAN_MEMBERS)?;
+		self.discord.add_ban(server, user, delete_message_days)
+	}
+
+	/// Unban a user from a server, if the bot may.
+	///
+	/// See `Discord::remove_ban`.
+	pub fn remove_ban(&self, server: ServerId, user: UserId) -> Result<()> {
+		self.check_server(server, Permissions::BAN_MEMBERS)?;
+		self.discord.remove_ban(server, user)
+	}
+
+	/// Replace a member's roles, if the bot may manage roles.
+	///
+	/// See `Discord::edit_member_roles`.
+	pub fn edit_member_roles(&self, server: ServerId, user: UserId, roles: &[RoleId]) -> Result<()> {
+		self.check_server(server, Permissions::MANAGE_ROLES)?;
+		self.discord.edit_member_roles(server, user, roles)
+	}
+}
+
+fn missing(required: Permissions, actual: Permissions) -> Result<()> {
+	let missing = required - actual;
+	if missing.is_empty() {
+		Ok(())
+	} else {
+		Err(Error::MissingPermission(missing))
+	}
+}
@@ -1,50 +1,167 @@
+//! Tracking of the REST API's rate limits.
+//!
+//! `Discord` uses a `RateLimits` to delay requests which would exceed a rate
+//! limit and to retry requests which were rejected with a 429 response. The
+//! time source is a `Clock`, so that rate limit handling can be exercised with
+//! a `MockClock` and simulated responses rather than real waiting:
+//!
+//! ```ignore
+//! use discord::ratelimit::{MockClock, RateLimits};
+//! use hyper::header::Headers;
+//! use hyper::status::StatusCode;
+//!
+//! let limits = RateLimits::with_clock(MockClock::new(1_500_000_000_000));
+//! let mut headers = Headers::new();
+//! headers.set_raw("Retry-After", vec![b"2000".to_vec()]);
+//! assert!(limits.update("/channels/1/messages", StatusCode::TooManyRequests, &headers));
+//! assert_eq!(limits.clock().sleeps(), vec![2100]);
+//! ```
+
 use std;
 use std::collections::BTreeMap;
 use std::sync::Mutex;
 
 use chrono::prelude::*;
 use hyper;
+use hyper::header::Headers;
+use hyper::status::StatusCode;
 
 use {Error, Result};
 
+/// A source of the current time which can also wait.
+pub trait Clock: Send + Sync {
+	/// The current Unix time in seconds.
+	fn now(&self) -> i64;
+	/// The current Unix time in milliseconds.
+	fn now_ms(&self) -> u64 {
+		self.now() as u64 * 1000
+	}
+	/// Block for the given number of milliseconds.
+	fn sleep_ms(&self, millis: u64);
+}
+
+/// The real clock, used by default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> i64 {
+		Utc::now().timestamp()
+	}
+
+	fn now_ms(&self) -> u64 {
+		Utc::now().timestamp_millis() as u64
+	}
+
+	fn sleep_ms(&self, millis: u64) {
+		::sleep_ms(millis)
+	}
+}
+
+/// A simulated clock which only advances when slept on, recording each sleep.
+#[derive(Debug, Default)]
+pub struct MockClock {
+	now_ms: Mutex<u64>,
+	sleeps: Mutex<Vec<u64>>,
+}
+
+impl MockClock {
+	/// Create a clock starting at the given Unix time in milliseconds.
+	pub fn new(now_ms: u64) -> MockClock {
+		MockClock {
+			now_ms: Mutex::new(now_ms),
+			sleeps: Mutex::new(Vec::new()),
+		}
+	}
+
+	/// Move the clock forward without recording a sleep.
+	pub fn advance(&self, millis: u64) {
+		*self.now_ms.lock().expect("Clock poisoned") += millis;
+	}
+
+	/// Get the durations of all sleeps so far, in milliseconds.
+	pub fn sleeps(&self) -> Vec<u64> {
+		self.sleeps.lock().expect("Clock poisoned").clone()
+	}
+}
+
+impl Clock for MockClock {
+	fn now(&self) -> i64 {
+		(self.now_ms() / 1000) as i64
+	}
+
+	fn now_ms(&self) -> u64 {
+		*self.now_ms.lock().expect("Clock poisoned")
+	}
+
+	fn sleep_ms(&self, millis: u64) {
+		self.sleeps.lock().expect("Clock poisoned").push(millis);
+		self.advance(millis);
+	}
+}
+
+/// The global and per-endpoint rate limits of the REST API.
 #[derive(Default)]
-pub struct RateLimits {
+pub struct RateLimits<C = SystemClock> {
+	clock: C,
 	global: Mutex<RateLimit>,
 	endpoints: Mutex<BTreeMap<String, RateLimit>>,
 }
 
-impl RateLimits {
-	/// Check before issuing a request for the given URL.
+impl<C: Clock> RateLimits<C> {
+	/// Track rate limits using the given clock.
+	pub fn with_clock(clock: C) -> RateLimits<C> {
+		RateLimits {
+			clock: clock,
+			global: Mutex::default(),
+			endpoints: Mutex::default(),
+		}
+	}
+
+	/// Get the clock used to track rate limits.
+	pub fn clock(&self) -> &C {
+		&self.clock
+	}
+
+	/// Check before issuing a request for the given URL, sleeping if no
+	/// requests remain.
 	pub fn pre_check(&self, url: &str) {
 		self.global
 			.lock()
 			.expect("Rate limits poisoned")
-			.pre_check();
+			.pre_check(&self.clock);
 		if let Some(rl) = self
 			.endpoints
 			.lock()
 			.expect("Rate limits poisoned")
 			.get_mut(url)
 		{
-			rl.pre_check();
+			rl.pre_check(&self.clock);
 		}
 	}
 
 	/// Update based on rate limit headers in the response for given URL.
 	/// Returns `true` if the request was rate limited and should be retried.
 	pub fn post_update(&self, url: &str, response: &hyper::client::Response) -> bool {
-		if response.headers.get_raw("X-RateLimit-Global").is_some() {
+		self.update(url, response.status, &response.headers)
+	}
+
+	/// Update based on the status and rate limit headers of a response for
+	/// the given URL, sleeping if it was rate limited. Returns `true` if the
+	/// request should be retried.
+	pub fn update(&self, url: &str, status: StatusCode, headers: &Headers) -> bool {
+		if headers.get_raw("X-RateLimit-Global").is_some() {
 			self.global
 				.lock()
 				.expect("Rate limits poisoned")
-				.post_update(response)
+				.post_update(&self.clock, status, headers)
 		} else {
 			self.endpoints
 				.lock()
 				.expect("Rate limits poisoned")
 				.entry(url.to_owned())
 				.or_insert_with(RateLimit::default)
-				.post_update(response)
+				.post_update(&self.clock, status, headers)
 		}
 	}
 }
@@ -57,13 +174,13 @@ struct RateLimit {
 }
 
 impl RateLimit {
-	fn pre_check(&mut self) {
+	fn pre_check<C: Clock>(&mut self, clock: &C) {
 		// break out if uninitialized
 		if self.limit == 0 {
 			return;
 		}
 
-		let difference = self.reset - Utc::now().timestamp();
+		let difference = self.reset - clock.now();
 		if difference < 0 {
 			// If reset is apparently in the past, optimistically assume that
 			// the reset has occurred and we're good for the next three seconds
@@ -78,7 +195,7 @@ impl RateLimit {
 			// 900ms in case "difference" is off by 1
 			let delay = difference as u64 * 1000 + 900;
 			warn!("pre-ratelimit: sleeping for {}ms", delay);
-			::sleep_ms(delay);
+			clock.sleep_ms(delay);
 			return;
 		}
 
@@ -88,8 +205,8 @@ impl RateLimit {
 		self.remaining -= 1;
 	}
 
-	fn post_update(&mut self, response: &hyper::client::Response) -> bool {
-		match self.try_post_update(response) {
+	fn post_update<C: Clock>(&mut self, clock: &C, status: StatusCode, headers: &Headers) -> bool {
+		match self.try_post_update(clock, status, headers) {
 			Err(e) => {
 				error!("rate limit checking error: {}", e);
 				false
@@ -98,21 +215,26 @@ impl RateLimit {
 		}
 	}
 
-	fn try_post_update(&mut self, response: &hyper::client::Response) -> Result<bool> {
-		if let Some(reset) = read_header(&response.headers, "X-RateLimit-Reset")? {
+	fn try_post_update<C: Clock>(
+		&mut self,
+		clock: &C,
+		status: StatusCode,
+		headers: &Headers,
+	) -> Result<bool> {
+		if let Some(reset) = read_header(headers, "X-RateLimit-Reset")? {
 			self.reset = reset;
 		}
-		if let Some(limit) = read_header(&response.headers, "X-RateLimit-Limit")? {
+		if let Some(limit) = read_header(headers, "X-RateLimit-Limit")? {
 			self.limit = limit;
 		}
-		if let Some(remaining) = read_header(&response.headers, "X-RateLimit-Remaining")? {
+		if let Some(remaining) = read_header(headers, "X-RateLimit-Remaining")? {
 			self.remaining = remaining;
 		}
-		if response.status == hyper::status::StatusCode::TooManyRequests {
-			if let Some(delay) = read_header(&response.headers, "Retry-After")? {
+		if status == StatusCode::TooManyRequests {
+			if let Some(delay) = read_header(headers, "Retry-After")? {
 				let delay = delay as u64 + 100; // 100ms of leeway
 				warn!("429: sleeping for {}ms", delay);
-				::sleep_ms(delay);
+				clock.sleep_ms(delay);
 				return Ok(true); // retry the request
 			}
 		}
@@ -120,7 +242,7 @@ impl RateLimit {
 	}
 }
 
-fn read_header(headers: &hyper::header::Headers, name: &str) -> Result<Option<i64>> {
+fn read_header(headers: &Headers, name: &str) -> Result<Option<i64>> {
 	match headers.get_raw(name) {
 		Some(hdr) => {
 			if hdr.len() == 1 {
@@ -141,3 +263,46 @@ fn read_header(headers: &hyper::header::Headers, name: &str) -> Result<Option<i6
 		None => Ok(None),
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn headers(values: &[(&'static str, &str)]) -> Headers {
+		let mut headers = Headers::new();
+		for &(name, value) in values {
+			headers.set_raw(name, vec![value.as_bytes().to_vec()]);
+		}
+		headers
+	}
+
+	#[test]
+	fn test_rate_limit_sequence() {
+		let limits = RateLimits::with_clock(MockClock::new(1_000_000));
+		let url = "/channels/1/messages";
+
+		// two requests allowed until the reset five seconds from now
+		let ok = headers(&[
+			("X-RateLimit-Limit", "2"),
+			("X-RateLimit-Remaining", "1"),
+			("X-RateLimit-Reset", "1005"),
+		]);
+		limits.pre_check(url);
+		assert!(!limits.update(url, StatusCode::Ok, &ok));
+		limits.pre_check(url);
+		assert!(limits.clock().sleeps().is_empty());
+
+		// the next request waits for the reset
+		limits.pre_check(url);
+		assert_eq!(limits.clock().sleeps(), vec![5900]);
+
+		// a 429 sleeps for the requested time and asks for a retry
+		let limited = headers(&[("Retry-After", "1500")]);
+		assert!(limits.update(url, StatusCode::TooManyRequests, &limited));
+		assert_eq!(limits.clock().sleeps(), vec![5900, 1600]);
+
+		// other endpoints are unaffected
+		limits.pre_check("/users/@me");
+		assert_eq!(limits.clock().sleeps().len(), 2);
+	}
+}
@@ -0,0 +1,285 @@
+//! Replay of recorded gateway sessions.
+//!
+//! A `Recording` is the sequence of websocket frames received from the
+//! gateway over the life of a `Connection`, including compressed frames and
+//! the closures that cause it to resume or reconnect. A `Replay` feeds those
+//! frames through the same decoding as a live connection and follows the same
+//! session bookkeeping, which allows event handlers and `State` tracking to be
+//! regression-tested against realistic data without a network connection.
+//!
+//! Recordings are stored as a JSON array with one object per frame:
+//!
+//! ```json
+//! [
+//!     {"text": "{\"op\":10,\"d\":{\"heartbeat_interval\":41250}}"},
+//!     {"binary": "789c..."},
+//!     {"close": 1001, "reason": "going away"}
+//! ]
+//! ```
+//!
+//! Text frames hold the raw payload, binary frames hold the hex-encoded
+//! zlib-compressed payload, and close frames hold the optional status code and
+//! reason sent by the server.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde_json::{self, Value};
+
+use connection::{recovery_after_close, Recovery};
+use model::*;
+use {decode_payload, Error, Result, State};
+
+/// A single websocket frame received from the gateway.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+	/// An uncompressed text frame.
+	Text(Vec<u8>),
+	/// A zlib-compressed binary frame.
+	Binary(Vec<u8>),
+	/// A close frame with its status code and reason.
+	Close(Option<u16>, String),
+}
+
+impl Frame {
+	/// Construct a text frame containing the given payload.
+	pub fn text(payload: &Value) -> Frame {
+		Frame::Text(payload.to_string().into_bytes())
+	}
+
+	/// Construct a binary frame containing the given payload, compressed.
+	pub fn compressed(payload: &Value) -> Frame {
+		let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+		encoder
+			.write_all(payload.to_string().as_bytes())
+			.and_then(|_| encoder.finish())
+			.map(Frame::Binary)
+			.expect("Compressing to memory cannot fail")
+	}
+
+	fn decode(value: Value) -> Result<Frame> {
+		if let Some(text) = value.get("text").and_then(|v| v.as_str()) {
+			return Ok(Frame::Text(text.as_bytes().to_owned()));
+		}
+		if let Some(binary) = value.get("binary").and_then(|v| v.as_str()) {
+			return decode_hex(binary).map(Frame::Binary);
+		}
+		if let Some(code) = value.get("close") {
+			let reason = value.get("reason").and_then(|v| v.as_str()).unwrap_or("");
+			return Ok(Frame::Close(code.as_u64().map(|c| c as u16), reason.to_owned()));
+		}
+		Err(Error::Decode("Unknown frame kind", value))
+	}
+
+	fn encode(&self) -> Value {
+		match *self {
+			Frame::Text(ref payload) => json! {{
+				"text": String::from_utf8_lossy(payload),
+			}},
+			Frame::Binary(ref payload) => json! {{
+				"binary": encode_hex(payload),
+			}},
+			Frame::Close(code, ref reason) => json! {{
+				"close": code,
+				"reason": reason,
+			}},
+		}
+	}
+}
+
+/// A recorded sequence of gateway frames.
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+	frames: Vec<Frame>,
+}
+
+impl Recording {
+	/// Create an empty recording.
+	pub fn new() -> Recording {
+		Recording::default()
+	}
+
+	/// Load a recording from a fixture file.
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<Recording> {
+		Recording::from_reader(File::open(path)?)
+	}
+
+	/// Load a recording from JSON in the fixture format.
+	pub fn from_reader<R: Read>(reader: R) -> Result<Recording> {
+		let frames: Vec<Value> = serde_json::from_reader(reader)?;
+		Ok(Recording {
+			frames: frames.into_iter().map(Frame::decode).collect::<Result<_>>()?,
+		})
+	}
+
+	/// Write the recording as JSON in the fixture format.
+	pub fn to_writer<W: Write>(&self, writer: W) -> Result<()> {
+		let frames: Vec<Value> = self.frames.iter().map(Frame::encode).collect();
+		serde_json::to_writer_pretty(writer, &frames).map_err(From::from)
+	}
+
+	/// Append a frame to the recording.
+	pub fn push(&mut self, frame: Frame) {
+		self.frames.push(frame);
+	}
+
+	/// Get the frames in the recording.
+	pub fn frames(&self) -> &[Frame] {
+		&self.frames
+	}
+}
+
+/// A reaction a live `Connection` would have taken to a replayed frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+	/// Answer a heartbeat request with the given sequence number.
+	Heartbeat(u64),
+	/// Identify again after the session was invalidated.
+	Identify,
+	/// Resume the given session ID from the given sequence number.
+	Resume(String, u64),
+	/// Open a fresh connection and identify.
+	Reconnect,
+}
+
+/// A `Connection` stand-in which replays a `Recording`.
+pub struct Replay {
+	frames: ::std::vec::IntoIter<Frame>,
+	session_id: Option<String>,
+	last_sequence: u64,
+	actions: Vec<Action>,
+}
+
+impl Replay {
+	/// Begin replaying a recording.
+	///
+	/// Like `Connection::new`, returns the `ReadyEvent` which must be the first
+	/// event in the recording.
+	pub fn start(recording: Recording) -> Result<(Replay, ReadyEvent)> {
+		let mut replay = Replay {
+			frames: recording.frames.into_iter(),
+			session_id: None,
+			last_sequence: 0,
+			actions: Vec::new(),
+		};
+		match replay.recv_event()? {
+			Event::Ready(ready) => Ok((replay, ready)),
+			other => {
+				debug!("Unexpected event: {:?}", other);
+				Err(Error::Protocol("Expected Ready during replay"))
+			}
+		}
+	}
+
+	/// Replay a recording from start to finish, keeping a `State` updated and
+	/// calling the handler after each event has been applied to it.
+	///
+	/// Returns the final `State`.
+	pub fn run<F: FnMut(&State, &Event)>(recording: Recording, mut handler: F) -> Result<State> {
+		let (mut replay, ready) = Replay::start(recording)?;
+		let mut state = State::new(ready.clone());
+		handler(&state, &Event::Ready(ready));
+		loop {
+			let event = match replay.recv_event() {
+				Ok(event) => event,
+				Err(Error::Closed(None, _)) => return Ok(state),
+				Err(err) => return Err(err),
+			};
+			state.update(&event);
+			handler(&state, &event);
+		}
+	}
+
+	/// Receive the next event from the recording.
+	///
+	/// Closures and reconnect requests are handled as `Connection::recv_event`
+	/// would, with the decision recorded in `actions()`. A closure with a close
+	/// code that forbids reconnecting is returned as `Error::GatewayClosed`.
+	/// Once the recording is exhausted, returns `Error::Closed` with no status
+	/// code.
+	pub fn recv_event(&mut self) -> Result<Event> {
+		loop {
+			let gateway = match self.frames.next() {
+				None => return Err(Error::Closed(None, "End of recording".into())),
+				Some(Frame::Close(code, reason)) => {
+					let action = match recovery_after_close(code, reason, &self.session_id)? {
+						Recovery::Resume(session_id) => {
+							Action::Resume(session_id, self.last_sequence)
+						}
+						Recovery::Reconnect => {
+							self.session_id = None;
+							Action::Reconnect
+						}
+					};
+					self.actions.push(action);
+					continue;
+				}
+				Some(Frame::Text(payload)) => {
+					decode_payload(&payload, false, GatewayEvent::decode)?
+				}
+				Some(Frame::Binary(payload)) => {
+					decode_payload(&payload, true, GatewayEvent::decode)?
+				}
+			};
+			match gateway {
+				GatewayEvent::Dispatch(sequence, event) => {
+					self.last_sequence = sequence;
+					if let Event::Ready(ReadyEvent { ref session_id, .. }) = event {
+						self.session_id = Some(session_id.clone());
+					}
+					return Ok(event);
+				}
+				GatewayEvent::Heartbeat(sequence) => {
+					self.actions.push(Action::Heartbeat(sequence));
+				}
+				GatewayEvent::Reconnect => {
+					self.actions.push(Action::Reconnect);
+				}
+				GatewayEvent::InvalidateSession => {
+					self.session_id = None;
+					self.actions.push(Action::Identify);
+				}
+				GatewayEvent::Hello(_) | GatewayEvent::HeartbeatAck => {}
+			}
+		}
+	}
+
+	/// Get the actions a live connection would have taken so far.
+	pub fn actions(&self) -> &[Action] {
+		&self.actions
+	}
+
+	/// Get the current session ID, if any.
+	pub fn session_id(&self) -> Option<&str> {
+		self.session_id.as_ref().map(|s| &s[..])
+	}
+
+	/// Get the sequence number of the last dispatched event.
+	pub fn last_sequence(&self) -> u64 {
+		self.last_sequence
+	}
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+	let mut result = String::with_capacity(bytes.len() * 2);
+	for byte in bytes {
+		result.push_str(&format!("{:02x}", byte));
+	}
+	result
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+	if hex.len() % 2 != 0 || !hex.is_ascii() {
+		return Err(Error::Other("Invalid hex string in recording"));
+	}
+	(0..hex.len())
+		.step_by(2)
+		.map(|i| {
+			u8::from_str_radix(&hex[i..i + 2], 16)
+				.map_err(|_| Error::Other("Invalid hex string in recording"))
+		})
+		.collect()
+}
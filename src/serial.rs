@@ -24,6 +24,16 @@ pub fn ignore_errors<'d, T: Deserialize<'d> + Default, D: Deserializer<'d>>(
 	Ok(T::deserialize(v).ok().unwrap_or_default())
 }
 
+/// Serialize a single value as a one-element list.
+pub fn serialize_one<T: Serialize, S: Serializer>(v: &T, s: S) -> Result<S::Ok, S::Error> {
+	::std::slice::from_ref(v).serialize(s)
+}
+
+/// Default a missing boolean field to true.
+pub fn default_true() -> bool {
+	true
+}
+
 /// Deserialize a maybe-string ID into a u64.
 pub fn deserialize_id<'d, D: Deserializer<'d>>(d: D) -> Result<u64, D::Error> {
 	struct IdVisitor;
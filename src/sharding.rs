@@ -0,0 +1,266 @@
+//! Supervision of several sharded gateway connections at once.
+
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+use connection::{is_fatal, ConnectionBuilder};
+use model::Event;
+use ratelimit::{Clock, SystemClock};
+use {Discord, Error, Result};
+
+/// How long each identify bucket must wait between session starts.
+const IDENTIFY_INTERVAL: u64 = 5000;
+/// How long to wait before restarting a shard whose connection has failed.
+const RESTART_DELAY: u64 = 5000;
+
+/// Runs one `Connection` per shard on background threads, passing their
+/// events to a single receiver.
+///
+/// Shards identify in the order permitted by the bot's `max_concurrency`, so
+/// that large bots do not exceed Discord's session start limits. A shard
+/// whose connection fails outright is restarted after a short delay, and its
+/// new `ReadyEvent` is delivered like any other event. A shard which fails in
+/// a way restarting cannot fix, such as an `Error::GatewayClosed` for an
+/// invalid token or intents, is stopped instead, and that error is the last
+/// thing it reports.
+///
+/// Since each `Connection` is owned by its thread, per-connection methods such
+/// as `set_presence` and `voice` are not available through the manager.
+///
+/// ```ignore
+/// let manager = ShardManager::start(&discord, None, |builder| {
+///     builder.with_intents(intents::GUILDS | intents::GUILD_MESSAGES);
+/// })?;
+/// loop {
+///     match manager.recv_event() {
+///         (shard, Ok(event)) => state.update(&event),
+///         (shard, Err(err)) => println!("[Shard {}] {:?}", shard, err),
+///     }
+/// }
+/// ```
+pub struct ShardManager {
+	events: mpsc::Receiver<(u32, Result<Event>)>,
+	// keeps `events` open even once every shard has stopped
+	_sender: mpsc::Sender<(u32, Result<Event>)>,
+	total_shards: u32,
+}
+
+impl ShardManager {
+	/// Start a connection for every shard.
+	///
+	/// If `total_shards` is `None`, the number of shards Discord recommends is
+	/// used. The `configure` function is applied to each shard's
+	/// `ConnectionBuilder` before every connection attempt.
+	pub fn start<F>(discord: &Discord, total_shards: Option<u32>, configure: F) -> Result<ShardManager>
+	where
+		F: Fn(&mut ConnectionBuilder) + Send + Sync + 'static,
+	{
+		let gateway = discord.get_bot_gateway()?;
+		let total_shards = shard_count(total_shards, gateway.shards)?;
+
+		let queue = Arc::new(IdentifyQueue::new(
+			SystemClock,
+			gateway.session_start_limit.max_concurrency,
+		));
+		let configure = Arc::new(configure);
+		let (tx, rx) = mpsc::channel();
+		for shard_id in 0..total_shards {
+			let shard = Shard {
+				shard: [shard_id, total_shards],
+				url: gateway.url.clone(),
+				token: discord.token.clone(),
+				queue: queue.clone(),
+				configure: configure.clone(),
+				events: tx.clone(),
+			};
+			::std::thread::Builder::new()
+				.name(format!("Discord Shard {}", shard_id))
+				.spawn(move || shard.run())?;
+		}
+		Ok(ShardManager {
+			events: rx,
+			_sender: tx,
+			total_shards: total_shards,
+		})
+	}
+
+	/// Receive an event from any shard, tagged with the shard's id, blocking
+	/// until one is available.
+	///
+	/// Errors are reported with the shard they occurred on. Shards recover
+	/// from most errors on their own; those they cannot recover from stop the
+	/// shard, after which it reports nothing more.
+	pub fn recv_event(&self) -> (u32, Result<Event>) {
+		self.events
+			.recv()
+			.expect("The manager holds a sender, so events never disconnect")
+	}
+
+	/// Receive an event from any shard if one is available, without blocking.
+	pub fn try_recv_event(&self) -> Option<(u32, Result<Event>)> {
+		self.events.try_recv().ok()
+	}
+
+	/// Get the number of shards being run.
+	#[inline]
+	pub fn total_shards(&self) -> u32 {
+		self.total_shards
+	}
+}
+
+struct Shard<F> {
+	shard: [u32; 2],
+	url: String,
+	token: String,
+	queue: Arc<IdentifyQueue>,
+	configure: Arc<F>,
+	events: mpsc::Sender<(u32, Result<Event>)>,
+}
+
+impl<F: Fn(&mut ConnectionBuilder)> Shard<F> {
+	fn run(self) {
+		let shard_id = self.shard[0];
+		// Each pass of this loop is one connection; stop once the manager is
+		// dropped and the events have nowhere to go.
+		loop {
+			let mut builder = ConnectionBuilder::new(self.url.clone(), &self.token);
+			(self.configure)(&mut builder);
+			builder.with_shard(self.shard[0], self.shard[1]);
+			let mut connection = match self.queue.identify(shard_id, || builder.connect()) {
+				Ok((connection, ready)) => {
+					if self.events.send((shard_id, Ok(Event::Ready(ready)))).is_err() {
+						let _ = connection.shutdown();
+						return;
+					}
+					connection
+				}
+				Err(err) => {
+					warn!("[Shard {}] Failed to connect: {:?}", shard_id, err);
+					let restart = can_restart(&err);
+					if self.events.send((shard_id, Err(err))).is_err() || !restart {
+						return;
+					}
+					::sleep_ms(RESTART_DELAY);
+					continue;
+				}
+			};
+			loop {
+				match connection.recv_event() {
+					Ok(event) => {
+						if self.events.send((shard_id, Ok(event))).is_err() {
+							let _ = connection.shutdown();
+							return;
+						}
+					}
+					Err(err) => {
+						let dead = is_fatal(&err);
+						let restart = can_restart(&err);
+						if self.events.send((shard_id, Err(err))).is_err() {
+							return;
+						}
+						if dead && !restart {
+							warn!("[Shard {}] Connection lost for good, stopping", shard_id);
+							return;
+						} else if dead {
+							warn!("[Shard {}] Connection lost, restarting", shard_id);
+							break;
+						}
+					}
+				}
+			}
+			::sleep_ms(RESTART_DELAY);
+		}
+	}
+}
+
+/// Choose the number of shards to run, falling back to Discord's
+/// recommendation.
+fn shard_count(requested: Option<u32>, recommended: u64) -> Result<u32> {
+	let total = requested.unwrap_or(recommended as u32);
+	if total == 0 {
+		return Err(Error::OutOfRange("total_shards", 1, u32::MAX as u64));
+	}
+	Ok(total)
+}
+
+/// Whether a new connection may succeed where one failed with the given
+/// error, rather than failing again in the same way.
+fn can_restart(err: &Error) -> bool {
+	match *err {
+		Error::Hyper(_) | Error::WebSocket(_) | Error::Io(_) => true,
+		Error::Closed(..) | Error::Protocol(_) | Error::RateLimited(_) => true,
+		Error::Status(status, _) => status.is_server_error(),
+		_ => false,
+	}
+}
+
+/// Spaces out identifies so that each of the `max_concurrency` buckets starts
+/// at most one session per interval.
+struct IdentifyQueue<C = SystemClock> {
+	clock: C,
+	buckets: Vec<Mutex<Option<u64>>>,
+}
+
+impl<C: Clock> IdentifyQueue<C> {
+	fn new(clock: C, max_concurrency: u64) -> IdentifyQueue<C> {
+		IdentifyQueue {
+			clock: clock,
+			buckets: (0..::std::cmp::max(max_concurrency, 1))
+				.map(|_| Mutex::new(None))
+				.collect(),
+		}
+	}
+
+	/// Wait until the given shard may identify, then run `connect`.
+	///
+	/// The shard's bucket is held until `connect` returns, and the interval
+	/// is counted from then, since the session only starts once the
+	/// identify has been sent.
+	fn identify<T, F: FnOnce() -> T>(&self, shard_id: u32, connect: F) -> T {
+		let bucket = &self.buckets[shard_id as usize % self.buckets.len()];
+		let mut last = bucket.lock().expect("Identify queue poisoned");
+		if let Some(last) = *last {
+			let elapsed = self.clock.now_ms().saturating_sub(last);
+			if elapsed < IDENTIFY_INTERVAL {
+				self.clock.sleep_ms(IDENTIFY_INTERVAL - elapsed);
+			}
+		}
+		let result = connect();
+		*last = Some(self.clock.now_ms());
+		result
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use ratelimit::MockClock;
+
+	#[test]
+	fn test_shard_count() {
+		assert_eq!(shard_count(None, 3).unwrap(), 3);
+		assert_eq!(shard_count(Some(2), 3).unwrap(), 2);
+		assert!(shard_count(Some(0), 3).is_err());
+		assert!(shard_count(None, 0).is_err());
+	}
+
+	#[test]
+	fn test_identify_spacing() {
+		let queue = IdentifyQueue::new(MockClock::new(1_000_000), 2);
+
+		// the first identify in each bucket goes straight through
+		queue.identify(0, || queue.clock.advance(1000));
+		queue.identify(1, || ());
+		assert!(queue.clock.sleeps().is_empty());
+
+		// the interval counts from when the first connection returned
+		queue.clock.advance(1500);
+		queue.identify(2, || ());
+		assert_eq!(queue.clock.sleeps(), vec![3500]);
+
+		// a bucket which has waited long enough is not delayed
+		queue.clock.advance(5000);
+		queue.identify(3, || ());
+		assert_eq!(queue.clock.sleeps(), vec![3500]);
+	}
+}
@@ -1,26 +1,143 @@
 use super::model::*;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{Read, Write};
+use std::ops::Deref;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+/// How long archived threads are kept by default.
+const DEFAULT_THREAD_RETENTION: u64 = 60 * 60;
+// how long a typing indicator lasts unless renewed
+const TYPING_DURATION: i64 = 10;
+
+// what everyone in a private channel or group may do
+const PRIVATE_PERMISSIONS: Permissions = Permissions::from_bits_truncate(
+	Permissions::READ_MESSAGES.bits()
+		| Permissions::SEND_MESSAGES.bits()
+		| Permissions::SEND_TTS_MESSAGES.bits()
+		| Permissions::EMBED_LINKS.bits()
+		| Permissions::ATTACH_FILES.bits()
+		| Permissions::READ_HISTORY.bits()
+		| Permissions::EXTERNAL_EMOJIS.bits()
+		| Permissions::ADD_REACTIONS.bits()
+		| Permissions::VOICE_CONNECT.bits()
+		| Permissions::VOICE_SPEAK.bits()
+		| Permissions::VOICE_USE_VAD.bits(),
+);
+
+/// Look up a server to modify, or record a warning and abandon the event.
+macro_rules! server {
+	($self:ident, $warnings:ident, $id:expr) => {
+		// indexing the fields directly leaves the other fields free to borrow
+		match $self.server_index.get(&$id) {
+			Some(&pos) => &mut Arc::make_mut(&mut $self.servers)[pos],
+			None => {
+				$warnings.push(StateWarning::UnknownServer($id));
+				return;
+			}
+		}
+	};
+}
+
+/// What a `State` keeps track of, to limit the memory used by large bots.
+///
+/// Everything is kept by default. Skipped information is dropped as it
+/// arrives, except that the logged-in user's own member and voice state are
+/// always kept.
+///
+/// ```ignore
+/// let state = State::with_options(ready, CacheOptions {
+///     presences: false,
+///     max_members: Some(1000),
+///     ..CacheOptions::default()
+/// });
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheOptions {
+	/// Keep the online status and activities of server members and friends
+	pub presences: bool,
+	/// Keep server member lists
+	pub members: bool,
+	/// The most members to keep for each server, if limited
+	pub max_members: Option<usize>,
+	/// Keep who is in each server's voice channels
+	pub voice_states: bool,
+}
+
+impl Default for CacheOptions {
+	fn default() -> CacheOptions {
+		CacheOptions {
+			presences: true,
+			members: true,
+			max_members: None,
+			voice_states: true,
+		}
+	}
+}
+
+impl CacheOptions {
+	/// Whether a new member may be added to a server which already has `len`
+	/// members kept.
+	fn admits_member(&self, len: usize) -> bool {
+		self.members && self.max_members.map_or(true, |max| len < max)
+	}
+}
 
 /// Known state composed from received events.
-#[derive(Debug, Clone)]
+///
+/// Can be saved and loaded with `save` and `load` to keep it across restarts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct State {
 	user: CurrentUser,
-	servers: Vec<LiveServer>,
+	// the larger collections are shared with snapshots until next modified
+	servers: Arc<Vec<LiveServer>>,
+	// indexes into `servers`, kept in sync by `add_server` and `remove_server`
+	// and rebuilt by `reindex` after loading
+	#[serde(skip)]
+	server_index: BTreeMap<ServerId, usize>,
+	#[serde(skip)]
+	channel_index: Arc<BTreeMap<ChannelId, ServerId>>,
+	// positions of each server's members and presences, kept in sync with
+	// every change to those lists
+	#[serde(skip)]
+	user_index: BTreeMap<ServerId, Arc<UserIndex>>,
+	#[serde(skip)]
+	presence_index: VecIndex,
 	unavailable_servers: Vec<ServerId>,
 	private_channels: Vec<PrivateChannel>,
 	groups: BTreeMap<ChannelId, Group>,
 	calls: BTreeMap<ChannelId, Call>,
-	presences: Vec<Presence>,
+	presences: Arc<Vec<Presence>>,
 	relationships: Vec<Relationship>,
+	threads: Arc<BTreeMap<ChannelId, ThreadChannel>>,
+	thread_retention: Duration,
+	#[serde(default)]
+	cache: CacheOptions,
+	#[serde(default)]
+	entitlements: BTreeMap<EntitlementId, Entitlement>,
+	// when each user was last seen typing in each channel
+	#[serde(skip)]
+	typing: BTreeMap<ChannelId, Vec<(UserId, ::chrono::DateTime<::chrono::Utc>)>>,
 	// user accounts only
 	settings: Option<UserSettings>,
 	server_settings: Option<Vec<UserServerSettings>>,
 	notes: Option<BTreeMap<UserId, Option<String>>>,
+	shard: Option<[u32; 2]>,
 }
 
 impl State {
 	/// Create a new state from an initial `ReadyEvent`.
 	pub fn new(ready: ReadyEvent) -> State {
+		State::with_options(ready, CacheOptions::default())
+	}
+
+	/// Create a new state from an initial `ReadyEvent`, keeping only what the
+	/// given options allow.
+	pub fn with_options(mut ready: ReadyEvent, cache: CacheOptions) -> State {
+		if !cache.presences {
+			ready.presences.clear();
+		}
 		let mut servers = Vec::new();
 		let mut unavailable = Vec::new();
 		for server in ready.servers {
@@ -40,28 +157,180 @@ impl State {
 				_ => {}
 			}
 		}
-		State {
+		let mut state = State {
 			user: ready.user,
-			servers: servers,
+			servers: Arc::new(Vec::new()),
+			server_index: BTreeMap::new(),
+			channel_index: Arc::new(BTreeMap::new()),
+			user_index: BTreeMap::new(),
+			presence_index: VecIndex::build(&ready.presences),
 			unavailable_servers: unavailable,
 			private_channels: private_channels,
 			groups: groups,
 			calls: BTreeMap::new(),
-			presences: ready.presences,
+			presences: Arc::new(ready.presences),
 			relationships: ready.relationships,
+			threads: Arc::new(BTreeMap::new()),
+			thread_retention: Duration::from_secs(DEFAULT_THREAD_RETENTION),
+			cache: cache,
+			entitlements: BTreeMap::new(),
+			typing: BTreeMap::new(),
 			settings: ready.user_settings,
 			server_settings: ready.user_server_settings,
 			notes: ready.notes,
+			shard: ready.shard,
+		};
+		for server in servers {
+			state.add_server(server);
 		}
+		state
+	}
+
+	/// Write the state out as JSON, to be restored by `load`.
+	pub fn save<W: Write>(&self, writer: W) -> ::Result<()> {
+		::serde_json::to_writer(writer, self).map_err(From::from)
+	}
+
+	/// Read a state written by `save`.
+	///
+	/// Events which arrived while the state was not being updated are
+	/// missing from it, so this is best paired with resuming the session the
+	/// state was saved from. A new session's `Ready` event replaces the state
+	/// entirely.
+	pub fn load<R: Read>(reader: R) -> ::Result<State> {
+		let mut state: State = ::serde_json::from_reader(reader)?;
+		state.reindex();
+		Ok(state)
+	}
+
+	/// Rebuild every index from the lists it covers.
+	fn reindex(&mut self) {
+		self.server_index.clear();
+		self.user_index.clear();
+		let channel_index = Arc::make_mut(&mut self.channel_index);
+		channel_index.clear();
+		for (pos, server) in self.servers.iter().enumerate() {
+			self.server_index.insert(server.id, pos);
+			for channel in &server.channels {
+				channel_index.insert(channel.id, server.id);
+			}
+			self.user_index.insert(server.id, Arc::new(UserIndex::build(server)));
+		}
+		self.presence_index = VecIndex::build(&self.presences);
+	}
+
+	/// Get the options limiting what this state keeps track of.
+	#[inline]
+	pub fn cache_options(&self) -> CacheOptions {
+		self.cache
+	}
+
+	/// Add a server, replacing any existing server with the same ID.
+	fn add_server(&mut self, mut server: LiveServer) {
+		trim_server(&mut server, self.cache, self.user.id);
+		let channel_index = Arc::make_mut(&mut self.channel_index);
+		for channel in &server.channels {
+			channel_index.insert(channel.id, server.id);
+		}
+		// the server's thread list is complete, so replaces any threads known
+		let threads = Arc::make_mut(&mut self.threads);
+		threads.retain(|_, thread| thread.server_id != server.id);
+		for thread in server.threads.drain(..) {
+			threads.insert(thread.id, thread);
+		}
+		self.evict_archived_threads();
+		self.user_index.insert(server.id, Arc::new(UserIndex::build(&server)));
+		let servers = Arc::make_mut(&mut self.servers);
+		if let Some(&pos) = self.server_index.get(&server.id) {
+			servers[pos] = server;
+		} else {
+			self.server_index.insert(server.id, servers.len());
+			servers.push(server);
+		}
+	}
+
+	/// Remove a server, if present.
+	fn remove_server(&mut self, id: ServerId) {
+		if let Some(pos) = self.server_index.remove(&id) {
+			Arc::make_mut(&mut self.servers).remove(pos);
+			for index in self.server_index.values_mut() {
+				if *index > pos {
+					*index -= 1;
+				}
+			}
+			Arc::make_mut(&mut self.channel_index).retain(|_, &mut server| server != id);
+			self.user_index.remove(&id);
+			Arc::make_mut(&mut self.threads).retain(|_, thread| thread.server_id != id);
+		}
+	}
+
+	/// Set how long threads are kept after being archived, evicting any which
+	/// have been archived for longer. Defaults to one hour.
+	///
+	/// Archived threads are otherwise kept until deleted, so a short retention
+	/// bounds the memory used by servers with many threads.
+	pub fn set_thread_retention(&mut self, retention: Duration) {
+		self.thread_retention = retention;
+		self.evict_archived_threads();
+	}
+
+	/// Evict threads which have been archived for longer than the retention
+	/// period.
+	fn evict_archived_threads(&mut self) {
+		let cutoff = match ::chrono::Duration::from_std(self.thread_retention) {
+			Ok(retention) => ::chrono::Utc::now() - retention,
+			Err(_) => return,
+		};
+		let expired = |thread: &ThreadChannel| {
+			let metadata = &thread.thread_metadata;
+			metadata.archived && metadata.archive_timestamp.with_timezone(&::chrono::Utc) <= cutoff
+		};
+		// avoid copying threads shared with a snapshot when none are evicted
+		if self.threads.values().any(&expired) {
+			Arc::make_mut(&mut self.threads).retain(|_, thread| !expired(thread));
+		}
+	}
+
+	fn update_thread(&mut self, thread: &ThreadChannel) {
+		Arc::make_mut(&mut self.threads).insert(thread.id, thread.clone());
+		self.evict_archived_threads();
+	}
+
+	/// Take an immutable copy of the state which can be shared between
+	/// threads.
+	///
+	/// Servers and other large collections are shared with the snapshot
+	/// rather than copied, and are only copied by this state once it next
+	/// modifies them. Clones of the snapshot share it entirely, so handing one
+	/// to each worker thread is cheap. Later updates to this state are not
+	/// reflected in the snapshot.
+	pub fn snapshot(&self) -> Snapshot {
+		Snapshot(Arc::new(self.clone()))
+	}
+
+	/// Get the shard this state was built from, as the shard id and the total
+	/// number of shards, or `None` if the connection was not sharded.
+	#[inline]
+	pub fn shard(&self) -> Option<[u32; 2]> {
+		self.shard
 	}
 
 	/// Count the total number of server members not yet downloaded.
+	///
+	/// Members beyond the cache options' `max_members` are not counted.
 	pub fn unknown_members(&self) -> u64 {
+		if !self.cache.members {
+			return 0;
+		}
 		let mut total = 0;
-		for srv in &self.servers {
+		for srv in self.servers.iter() {
 			let members = srv.members.len() as u64;
-			if srv.member_count > members {
-				total += srv.member_count - members;
+			let wanted = match self.cache.max_members {
+				Some(max) => ::std::cmp::min(srv.member_count, max as u64),
+				None => srv.member_count,
+			};
+			if wanted > members {
+				total += wanted - members;
 			} else if srv.member_count < members {
 				warn!(
 					"Inconsistent member count for {:?}: {} < {}",
@@ -92,20 +361,135 @@ impl State {
 
 	#[doc(hidden)]
 	pub fn __download_members(&mut self) -> Vec<ServerId> {
-		self.servers
+		let user_index = &mut self.user_index;
+		Arc::make_mut(&mut self.servers)
 			.iter_mut()
 			.filter(|s| s.large)
 			.map(|s| {
 				s.members.clear();
+				if let Some(index) = user_index.get_mut(&s.id) {
+					Arc::make_mut(index).members = VecIndex::default();
+				}
 				s.id
 			})
 			.collect::<Vec<_>>()
 	}
 
 	/// Update the state according to the changes described in the given event.
-	pub fn update(&mut self, event: &Event) {
+	///
+	/// Returns a warning for each part of the event which could not be applied
+	/// because it referred to something not present in the state. Warnings
+	/// usually indicate that the state has fallen out of sync with Discord.
+	pub fn update(&mut self, event: &Event) -> Vec<StateWarning> {
+		let mut warnings = Vec::new();
+		self.apply(event, &mut warnings);
+		for warning in &warnings {
+			match self.shard {
+				Some([id, total]) => debug!(
+					"Could not apply event to state (shard {}/{}): {}",
+					id, total, warning
+				),
+				None => debug!("Could not apply event to state: {}", warning),
+			}
+		}
+		warnings
+	}
+
+	/// Update the state as `update` does, also returning the previous values
+	/// of anything the event changed or removed.
+	///
+	/// ```ignore
+	/// let (changes, _) = state.update_with_changes(&event);
+	/// for change in changes {
+	///     if let StateChange::MemberUpdate(_, ref old) = change {
+	///         println!("{} was {:?}", old.user.name, old.nick);
+	///     }
+	/// }
+	/// ```
+	pub fn update_with_changes(&mut self, event: &Event) -> (Vec<StateChange>, Vec<StateWarning>) {
+		let changes = self.changes(event);
+		(changes, self.update(event))
+	}
+
+	/// Find the entities an event is about to change.
+	fn changes(&self, event: &Event) -> Vec<StateChange> {
+		let server = |id| self.find_server(id);
+		let change = match *event {
+			Event::UserUpdate(_) => Some(StateChange::UserUpdate(self.user.clone())),
+			Event::ServerUpdate(ref srv) => {
+				server(srv.id).map(|old| StateChange::ServerUpdate(old.clone()))
+			}
+			Event::ServerMemberUpdate {
+				server_id,
+				ref user,
+				..
+			} => self
+				.find_member(server_id, user.id)
+				.map(|old| StateChange::MemberUpdate(server_id, old.clone())),
+			Event::ServerMemberRemove(server_id, ref user) => self
+				.find_member(server_id, user.id)
+				.map(|old| StateChange::MemberRemove(server_id, old.clone())),
+			Event::PresenceUpdate {
+				server_id,
+				ref presence,
+				..
+			} => {
+				let (presences, index) = match server_id {
+					Some(id) => match (server(id), self.user_index.get(&id)) {
+						(Some(srv), Some(index)) => (&srv.presences[..], &index.presences),
+						_ => return Vec::new(),
+					},
+					None => (&self.presences[..], &self.presence_index),
+				};
+				index
+					.get(presences, presence.user_id)
+					.map(|old| StateChange::PresenceUpdate(server_id, old.clone()))
+			}
+			Event::VoiceStateUpdate(Some(server_id), ref state) => server(server_id)
+				.and_then(|srv| srv.voice_states.iter().find(|v| v.user_id == state.user_id))
+				.map(|old| StateChange::VoiceStateUpdate(server_id, old.clone())),
+			Event::ChannelUpdate(Channel::Public(ref channel)) => self
+				.public_channel(channel.server_id, channel.id)
+				.map(|old| StateChange::ChannelUpdate(old.clone())),
+			Event::ChannelDelete(Channel::Public(ref channel)) => self
+				.public_channel(channel.server_id, channel.id)
+				.map(|old| StateChange::ChannelDelete(old.clone())),
+			Event::ServerRoleUpdate(server_id, ref role) => server(server_id)
+				.and_then(|srv| srv.roles.iter().find(|r| r.id == role.id))
+				.map(|old| StateChange::RoleUpdate(server_id, old.clone())),
+			Event::ServerRoleDelete(server_id, role_id) => server(server_id)
+				.and_then(|srv| srv.roles.iter().find(|r| r.id == role_id))
+				.map(|old| StateChange::RoleDelete(server_id, old.clone())),
+			Event::ThreadUpdate(ref thread)
+			| Event::ChannelUpdate(Channel::Thread(ref thread)) => self
+				.threads
+				.get(&thread.id)
+				.map(|old| StateChange::ThreadUpdate(old.clone())),
+			Event::ThreadDelete { id, .. } => {
+				self.threads.get(&id).map(|old| StateChange::ThreadDelete(old.clone()))
+			}
+			Event::ChannelDelete(Channel::Thread(ref thread)) => self
+				.threads
+				.get(&thread.id)
+				.map(|old| StateChange::ThreadDelete(old.clone())),
+			_ => None,
+		};
+		change.into_iter().collect()
+	}
+
+	fn public_channel(&self, server: ServerId, channel: ChannelId) -> Option<&PublicChannel> {
+		self.find_server(server)?.channels.iter().find(|c| c.id == channel)
+	}
+
+	fn apply(&mut self, event: &Event, warnings: &mut Vec<StateWarning>) {
 		match *event {
-			Event::Ready(ref ready) => *self = State::new(ready.clone()),
+			Event::Ready(ref ready) => {
+				let thread_retention = self.thread_retention;
+				let entitlements = ::std::mem::replace(&mut self.entitlements, BTreeMap::new());
+				*self = State::with_options(ready.clone(), self.cache);
+				self.thread_retention = thread_retention;
+				self.entitlements = entitlements;
+			}
 			Event::UserUpdate(ref user) => {
 				self.user.update_from(user);
 			}
@@ -192,26 +576,25 @@ impl State {
 				}
 			}
 			Event::VoiceStateUpdate(Some(server_id), ref state) => {
-				self.servers
-					.iter_mut()
-					.find(|s| s.id == server_id)
-					.map(|srv| {
-						if !state.channel_id.is_some() {
-							// Remove the user from the voice state list
-							srv.voice_states.retain(|v| v.user_id != state.user_id);
-						} else {
-							// Update or add to the voice state list
-							if let Some(srv_state) = srv
-								.voice_states
-								.iter_mut()
-								.find(|u| u.user_id == state.user_id)
-							{
-								srv_state.clone_from(state);
-								return;
-							}
-							srv.voice_states.push(state.clone());
-						}
-					});
+				if !self.cache.voice_states && state.user_id != self.user.id {
+					return;
+				}
+				let srv = server!(self, warnings, server_id);
+				if !state.channel_id.is_some() {
+					// Remove the user from the voice state list
+					srv.voice_states.retain(|v| v.user_id != state.user_id);
+				} else {
+					// Update or add to the voice state list
+					if let Some(srv_state) = srv
+						.voice_states
+						.iter_mut()
+						.find(|u| u.user_id == state.user_id)
+					{
+						srv_state.clone_from(state);
+						return;
+					}
+					srv.voice_states.push(state.clone());
+				}
 			}
 			Event::CallCreate(ref call) => {
 				use std::collections::btree_map::Entry;
@@ -241,11 +624,15 @@ impl State {
 			Event::ChannelRecipientAdd(channel_id, ref user) => {
 				if let Some(group) = self.groups.get_mut(&channel_id) {
 					group.recipients.push(user.clone());
+				} else {
+					warnings.push(StateWarning::UnknownChannel(channel_id));
 				}
 			}
 			Event::ChannelRecipientRemove(channel_id, ref user) => {
 				if let Some(group) = self.groups.get_mut(&channel_id) {
 					group.recipients.retain(|u| u.id != user.id);
+				} else {
+					warnings.push(StateWarning::UnknownChannel(channel_id));
 				}
 			}
 			Event::PresenceUpdate {
@@ -254,26 +641,27 @@ impl State {
 				..
 			} => {
 				if let Some(server_id) = server_id {
-					self.servers
-						.iter_mut()
-						.find(|s| s.id == server_id)
-						.map(|srv| {
-							// If the user was modified, update the member list
-							if let Some(user) = presence.user.as_ref() {
-								srv.members.iter_mut().find(|u| u.user.id == user.id).map(
-									|member| {
-										member.user.clone_from(user);
-									},
-								);
-							}
-							update_presence(&mut srv.presences, presence);
-						});
-				} else {
-					update_presence(&mut self.presences, presence);
+					let srv = server!(self, warnings, server_id);
+					let index = user_index(&mut self.user_index, server_id);
+					// If the user was modified, update the member list
+					if let Some(user) = presence.user.as_ref() {
+						if let Some(member) = index.members.get_mut(&mut srv.members, user.id) {
+							member.user.clone_from(user);
+						}
+					}
+					if self.cache.presences {
+						update_presence(&mut srv.presences, &mut index.presences, presence);
+					}
+				} else if self.cache.presences {
+					let presences = Arc::make_mut(&mut self.presences);
+					update_presence(presences, &mut self.presence_index, presence);
 				}
 			}
 			Event::PresencesReplace(ref presences) => {
-				self.presences.clone_from(presences);
+				if self.cache.presences {
+					self.presences = Arc::new(presences.clone());
+					self.presence_index = VecIndex::build(presences);
+				}
 			}
 			Event::RelationshipAdd(ref relationship) => {
 				if let Some(rel) = self
@@ -291,79 +679,92 @@ impl State {
 			}
 			Event::ServerCreate(PossibleServer::Offline(server_id))
 			| Event::ServerDelete(PossibleServer::Offline(server_id)) => {
-				self.servers.retain(|s| s.id != server_id);
+				self.remove_server(server_id);
 				if !self.unavailable_servers.contains(&server_id) {
 					self.unavailable_servers.push(server_id);
 				}
 			}
 			Event::ServerCreate(PossibleServer::Online(ref server)) => {
 				self.unavailable_servers.retain(|&id| id != server.id);
-				self.servers.push(server.clone())
+				self.add_server(server.clone())
 			}
 			Event::ServerDelete(PossibleServer::Online(ref server)) => {
-				self.servers.retain(|s| s.id != server.id);
+				self.remove_server(server.id);
 			}
 			Event::ServerUpdate(ref server) => {
-				self.servers
-					.iter_mut()
-					.find(|s| s.id == server.id)
-					.map(|srv| {
-						srv.name.clone_from(&server.name);
-						srv.afk_timeout = server.afk_timeout;
-						srv.afk_channel_id.clone_from(&server.afk_channel_id);
-						srv.icon.clone_from(&server.icon);
-						srv.roles.clone_from(&server.roles);
-						srv.region.clone_from(&server.region);
-						// embed_enabled and embed_channel_id skipped
-						srv.owner_id.clone_from(&server.owner_id);
-						srv.verification_level = server.verification_level;
-					});
-			}
-			Event::ServerMemberAdd(ref server_id, ref member) => {
-				self.servers
-					.iter_mut()
-					.find(|s| s.id == *server_id)
-					.map(|srv| {
-						srv.member_count += 1;
-						srv.members.push(member.clone());
-					});
+				let srv = server!(self, warnings, server.id);
+				srv.name.clone_from(&server.name);
+				srv.afk_timeout = server.afk_timeout;
+				srv.afk_channel_id.clone_from(&server.afk_channel_id);
+				srv.icon.clone_from(&server.icon);
+				srv.splash.clone_from(&server.splash);
+				srv.banner.clone_from(&server.banner);
+				srv.roles.clone_from(&server.roles);
+				srv.region.clone_from(&server.region);
+				// embed_enabled and embed_channel_id skipped
+				srv.owner_id.clone_from(&server.owner_id);
+				srv.verification_level = server.verification_level;
+				srv.default_message_notifications = server.default_message_notifications;
+				srv.mfa_level = server.mfa_level;
+				srv.explicit_content_filter = server.explicit_content_filter;
+				srv.premium_progress_bar_enabled = server.premium_progress_bar_enabled;
+				srv.incidents_data = server.incidents_data.clone();
+			}
+			Event::ServerMemberAdd(server_id, ref member) => {
+				let srv = server!(self, warnings, server_id);
+				srv.member_count += 1;
+				if member.user.id == self.user.id || self.cache.admits_member(srv.members.len()) {
+					user_index(&mut self.user_index, server_id)
+						.members
+						.insert(&mut srv.members, member.clone());
+				}
 			}
 			Event::ServerMemberUpdate {
-				ref server_id,
+				server_id,
 				ref roles,
 				ref user,
 				ref nick,
 			} => {
-				self.servers
-					.iter_mut()
-					.find(|s| s.id == *server_id)
-					.map(|srv| {
-						srv.members
-							.iter_mut()
-							.find(|m| m.user.id == user.id)
-							.map(|member| {
-								member.user.clone_from(user);
-								member.roles.clone_from(roles);
-								member.nick.clone_from(nick);
-							})
-					});
-			}
-			Event::ServerMemberRemove(ref server_id, ref user) => {
-				self.servers
-					.iter_mut()
-					.find(|s| s.id == *server_id)
-					.map(|srv| {
-						srv.member_count -= 1;
-						srv.members.retain(|m| m.user.id != user.id);
-					});
-			}
-			Event::ServerMembersChunk(server_id, ref members) => {
-				self.servers
-					.iter_mut()
-					.find(|s| s.id == server_id)
-					.map(|srv| {
-						srv.members.extend_from_slice(members);
-					});
+				let srv = server!(self, warnings, server_id);
+				let index = user_index(&mut self.user_index, server_id);
+				if let Some(member) = index.members.get_mut(&mut srv.members, user.id) {
+					member.user.clone_from(user);
+					member.roles.clone_from(roles);
+					member.nick.clone_from(nick);
+				} else if !srv.large {
+					// members of large servers are only known once downloaded
+					warnings.push(StateWarning::UnknownMember(server_id, user.id));
+				}
+			}
+			Event::ServerMemberRemove(server_id, ref user) => {
+				let srv = server!(self, warnings, server_id);
+				srv.member_count -= 1;
+				user_index(&mut self.user_index, server_id)
+					.members
+					.remove(&mut srv.members, user.id);
+			}
+			Event::ServerMembersChunk {
+				server_id,
+				ref members,
+				ref presences,
+				..
+			} => {
+				let srv = server!(self, warnings, server_id);
+				let index = user_index(&mut self.user_index, server_id);
+				// targeted requests may return members which are already known
+				for member in members {
+					if member.user.id == self.user.id
+						|| index.members.contains(member.user.id)
+						|| self.cache.admits_member(srv.members.len())
+					{
+						index.members.insert(&mut srv.members, member.clone());
+					}
+				}
+				if self.cache.presences {
+					for presence in presences {
+						index.presences.insert(&mut srv.presences, presence.clone());
+					}
+				}
 			}
 			Event::ServerSync {
 				server_id,
@@ -371,43 +772,36 @@ impl State {
 				ref members,
 				ref presences,
 			} => {
-				self.servers
-					.iter_mut()
-					.find(|s| s.id == server_id)
-					.map(|srv| {
-						srv.large = large;
-						srv.members.clone_from(members);
-						srv.presences.clone_from(presences);
-					});
-			}
-			Event::ServerRoleCreate(ref server_id, ref role) => {
-				self.servers
-					.iter_mut()
-					.find(|s| s.id == *server_id)
-					.map(|srv| {
-						srv.roles.push(role.clone());
-					});
+				let srv = server!(self, warnings, server_id);
+				srv.large = large;
+				srv.members.clone_from(members);
+				srv.presences.clone_from(presences);
+				trim_server(srv, self.cache, self.user.id);
+				self.user_index.insert(server_id, Arc::new(UserIndex::build(srv)));
 			}
-			Event::ServerRoleUpdate(ref server_id, ref role) => {
-				self.servers
-					.iter_mut()
-					.find(|s| s.id == *server_id)
-					.map(|srv| {
-						srv.roles
-							.iter_mut()
-							.find(|r| r.id == role.id)
-							.map(|srv_role| {
-								srv_role.clone_from(role);
-							});
-					});
-			}
-			Event::ServerRoleDelete(ref server_id, ref role_id) => {
-				self.servers
-					.iter_mut()
-					.find(|s| s.id == *server_id)
-					.map(|srv| {
-						srv.roles.retain(|r| r.id != *role_id);
-					});
+			Event::ServerRoleCreate(server_id, ref role) => {
+				let srv = server!(self, warnings, server_id);
+				srv.roles.push(role.clone());
+			}
+			Event::ServerRoleUpdate(server_id, ref role) => {
+				let srv = server!(self, warnings, server_id);
+				if let Some(srv_role) = srv.roles.iter_mut().find(|r| r.id == role.id) {
+					srv_role.clone_from(role);
+				} else {
+					warnings.push(StateWarning::UnknownRole(server_id, role.id));
+				}
+			}
+			Event::ServerRoleDelete(server_id, role_id) => {
+				let srv = server!(self, warnings, server_id);
+				let before = srv.roles.len();
+				srv.roles.retain(|r| r.id != role_id);
+				if srv.roles.len() == before {
+					warnings.push(StateWarning::UnknownRole(server_id, role_id));
+				}
+			}
+			Event::ServerStickersUpdate(server_id, ref stickers) => {
+				let srv = server!(self, warnings, server_id);
+				srv.stickers.clone_from(stickers);
 			}
 			Event::ChannelCreate(ref channel) => match *channel {
 				Channel::Group(ref group) => {
@@ -417,25 +811,16 @@ impl State {
 					self.private_channels.push(channel.clone());
 				}
 				Channel::Public(ref channel) => {
-					self.servers
-						.iter_mut()
-						.find(|s| s.id == channel.server_id)
-						.map(|srv| {
-							srv.channels.push(channel.clone());
-						});
+					server!(self, warnings, channel.server_id).channels.push(channel.clone());
+					Arc::make_mut(&mut self.channel_index).insert(channel.id, channel.server_id);
 				}
 				Channel::Category(ref channel) => {
 					if let Some(server_id) = channel.server_id {
-						self.servers
-							.iter_mut()
-							.find(|s| s.id == server_id)
-							.map(|srv| {
-								srv.categories.push(channel.clone());
-							});
+						server!(self, warnings, server_id).categories.push(channel.clone());
 					}
 				}
-				Channel::News => {}
-				Channel::Store => {}
+				Channel::News | Channel::Store => {}
+				Channel::Thread(ref thread) => self.update_thread(thread),
 			},
 			Event::ChannelUpdate(ref channel) => match *channel {
 				Channel::Group(ref group) => {
@@ -459,96 +844,145 @@ impl State {
 					}
 				}
 				Channel::Private(ref channel) => {
-					self.private_channels
-						.iter_mut()
-						.find(|c| c.id == channel.id)
-						.map(|chan| {
-							chan.clone_from(channel);
-						});
+					if let Some(chan) = self.private_channels.iter_mut().find(|c| c.id == channel.id) {
+						chan.clone_from(channel);
+					} else {
+						warnings.push(StateWarning::UnknownChannel(channel.id));
+					}
 				}
 				Channel::Public(ref channel) => {
-					self.servers
-						.iter_mut()
-						.find(|s| s.id == channel.server_id)
-						.map(|srv| {
-							srv.channels
-								.iter_mut()
-								.find(|c| c.id == channel.id)
-								.map(|chan| {
-									chan.clone_from(channel);
-								})
-						});
+					let srv = server!(self, warnings, channel.server_id);
+					if let Some(chan) = srv.channels.iter_mut().find(|c| c.id == channel.id) {
+						chan.clone_from(channel);
+					} else {
+						warnings.push(StateWarning::UnknownChannel(channel.id));
+					}
 				}
 				Channel::Category(ref channel) => {
 					if let Some(server_id) = channel.server_id {
-						self.servers
-							.iter_mut()
-							.find(|s| s.id == server_id)
-							.map(|srv| {
-								srv.categories
-									.iter_mut()
-									.find(|c| c.id == channel.id)
-									.map(|chan| {
-										chan.clone_from(channel);
-									})
-							});
+						let srv = server!(self, warnings, server_id);
+						if let Some(chan) = srv.categories.iter_mut().find(|c| c.id == channel.id) {
+							chan.clone_from(channel);
+						} else {
+							warnings.push(StateWarning::UnknownChannel(channel.id));
+						}
 					}
 				}
-				Channel::News => {}
-				Channel::Store => {}
+				Channel::News | Channel::Store => {}
+				Channel::Thread(ref thread) => self.update_thread(thread),
 			},
 			Event::ChannelDelete(ref channel) => match *channel {
 				Channel::Group(ref group) => {
 					self.groups.remove(&group.channel_id);
+					self.calls.remove(&group.channel_id);
 				}
 				Channel::Private(ref channel) => {
 					self.private_channels.retain(|c| c.id != channel.id);
+					self.calls.remove(&channel.id);
 				}
 				Channel::Public(ref channel) => {
-					self.servers
-						.iter_mut()
-						.find(|s| s.id == channel.server_id)
-						.map(|srv| {
-							srv.channels.retain(|c| c.id != channel.id);
-						});
+					Arc::make_mut(&mut self.channel_index).remove(&channel.id);
+					Arc::make_mut(&mut self.threads)
+						.retain(|_, thread| thread.parent_id != channel.id);
+					let srv = server!(self, warnings, channel.server_id);
+					srv.channels.retain(|c| c.id != channel.id);
 				}
 				Channel::Category(ref channel) => {
 					if let Some(server_id) = channel.server_id {
-						self.servers
-							.iter_mut()
-							.find(|s| s.id == server_id)
-							.map(|srv| {
-								srv.categories.retain(|c| c.id != channel.id);
-							});
+						let srv = server!(self, warnings, server_id);
+						srv.categories.retain(|c| c.id != channel.id);
 					}
 				}
-				Channel::News => {}
-				Channel::Store => {}
+				Channel::News | Channel::Store => {}
+				Channel::Thread(ref thread) => {
+					Arc::make_mut(&mut self.threads).remove(&thread.id);
+				}
 			},
+			Event::ThreadCreate(ref thread) | Event::ThreadUpdate(ref thread) => {
+				self.update_thread(thread);
+			}
+			Event::ThreadDelete { id, .. } => {
+				Arc::make_mut(&mut self.threads).remove(&id);
+			}
+			Event::ThreadMembersUpdate { id, member_count, .. } => {
+				if let Some(thread) = Arc::make_mut(&mut self.threads).get_mut(&id) {
+					thread.member_count = member_count;
+				}
+			}
+			Event::ThreadListSync {
+				server_id,
+				ref channel_ids,
+				ref threads,
+				..
+			} => {
+				// the synced channels' threads are replaced entirely
+				let known = Arc::make_mut(&mut self.threads);
+				known.retain(|_, thread| {
+					thread.server_id != server_id
+						|| channel_ids
+							.as_ref()
+							.map_or(false, |ids| !ids.contains(&thread.parent_id))
+				});
+				for thread in threads {
+					known.insert(thread.id, thread.clone());
+				}
+				self.evict_archived_threads();
+			}
+			Event::EntitlementCreate(ref entitlement)
+			| Event::EntitlementUpdate(ref entitlement) => {
+				self.entitlements.insert(entitlement.id, entitlement.clone());
+			}
+			Event::EntitlementDelete(ref entitlement) => {
+				self.entitlements.remove(&entitlement.id);
+			}
+			Event::TypingStart {
+				channel_id,
+				user_id,
+				..
+			} => {
+				let now = ::chrono::Utc::now();
+				let cutoff = now - ::chrono::Duration::seconds(TYPING_DURATION);
+				for users in self.typing.values_mut() {
+					users.retain(|&(_, since)| since > cutoff);
+				}
+				self.typing.retain(|_, users| !users.is_empty());
+				let users = self.typing.entry(channel_id).or_insert_with(Vec::new);
+				match users.iter_mut().find(|&&mut (user, _)| user == user_id) {
+					Some(entry) => entry.1 = now,
+					None => users.push((user_id, now)),
+				}
+			}
+			Event::MessageCreate(ref message) => {
+				// sending a message ends the sender's typing indicator
+				if let Some(users) = self.typing.get_mut(&message.channel_id) {
+					users.retain(|&(user, _)| user != message.author.id);
+				}
+			}
 			Event::ChannelPinsUpdate {
-				ref channel_id,
+				channel_id,
 				ref last_pin_timestamp,
 			} => {
-				for server in &mut self.servers {
-					for channel in &mut server.channels {
-						if channel.id == *channel_id {
-							channel.last_pin_timestamp = *last_pin_timestamp;
-							return;
-						}
+				if let Some(&server_id) = self.channel_index.get(&channel_id) {
+					let srv = server!(self, warnings, server_id);
+					if let Some(channel) = srv.channels.iter_mut().find(|c| c.id == channel_id) {
+						channel.last_pin_timestamp = *last_pin_timestamp;
 					}
+					return;
 				}
 
 				for channel in &mut self.private_channels {
-					if channel.id == *channel_id {
+					if channel.id == channel_id {
 						channel.last_pin_timestamp = *last_pin_timestamp;
 						return;
 					}
 				}
 
-				if let Some(group) = self.groups.get_mut(channel_id) {
+				if let Some(group) = self.groups.get_mut(&channel_id) {
 					group.last_pin_timestamp = *last_pin_timestamp;
 					return;
 				}
+
+				warnings.push(StateWarning::UnknownChannel(channel_id));
 			}
 			_ => {}
 		}
@@ -566,6 +1000,16 @@ impl State {
 		&self.servers
 	}
 
+	/// Get the servers this user has access to, as a list which can be kept
+	/// without copying it.
+	///
+	/// The list is shared until this state next changes a server, when this
+	/// state makes its own copy, so it is not updated with the state.
+	#[inline]
+	pub fn shared_servers(&self) -> Arc<Vec<LiveServer>> {
+		self.servers.clone()
+	}
+
 	/// Get the currently unavailable servers.
 	pub fn unavailable_servers(&self) -> &[ServerId] {
 		&self.unavailable_servers
@@ -632,23 +1076,170 @@ impl State {
 		self.notes.as_ref()
 	}
 
+	/// Get the known threads within a text, news, or forum channel.
+	///
+	/// Includes active threads and recently archived ones; see
+	/// `set_thread_retention`.
+	pub fn threads_in(&self, channel: ChannelId) -> Vec<&ThreadChannel> {
+		self.threads
+			.values()
+			.filter(|thread| thread.parent_id == channel)
+			.collect()
+	}
+
+	/// Get the users currently typing in a channel, in the order they began.
+	///
+	/// A user is considered to be typing for ten seconds after each typing
+	/// event is received, or until they send a message.
+	pub fn users_typing(&self, channel: ChannelId) -> Vec<UserId> {
+		let cutoff = ::chrono::Utc::now() - ::chrono::Duration::seconds(TYPING_DURATION);
+		self.typing.get(&channel).map_or_else(Vec::new, |users| {
+			users
+				.iter()
+				.filter(|&&(_, since)| since > cutoff)
+				.map(|&(user, _)| user)
+				.collect()
+		})
+	}
+
+	/// Look up a thread by its ID.
+	pub fn find_thread(&self, id: ChannelId) -> Option<&ThreadChannel> {
+		self.threads.get(&id)
+	}
+
+	/// Record entitlements fetched with `Discord::get_entitlements`.
+	///
+	/// Entitlements are not sent on connecting, only as they change, so
+	/// fetching them once at startup lets `has_entitlement` answer for
+	/// purchases made earlier. They are kept when a new session begins.
+	pub fn add_entitlements<I: IntoIterator<Item = Entitlement>>(&mut self, entitlements: I) {
+		for entitlement in entitlements {
+			self.entitlements.insert(entitlement.id, entitlement);
+		}
+	}
+
+	/// Get the known entitlements granted to a user or server, including
+	/// inactive ones.
+	pub fn entitlements_of<O: Into<EntitlementOwner>>(&self, owner: O) -> Vec<&Entitlement> {
+		let owner = Some(owner.into());
+		self.entitlements
+			.values()
+			.filter(|entitlement| entitlement.owner() == owner)
+			.collect()
+	}
+
+	/// Check whether a user or server has an active entitlement to a SKU.
+	///
+	/// Only entitlements seen in events or added with `add_entitlements`
+	/// are known.
+	pub fn has_entitlement<O: Into<EntitlementOwner>>(&self, sku: SkuId, owner: O) -> bool {
+		let owner = Some(owner.into());
+		self.entitlements.values().any(|entitlement| {
+			entitlement.sku_id == sku && entitlement.owner() == owner && entitlement.is_active()
+		})
+	}
+
 	/// Look up a server by its ID.
 	pub fn find_server(&self, id: ServerId) -> Option<&LiveServer> {
-		for server in &self.servers {
-			if server.id == id {
-				return Some(server);
+		self.server_index.get(&id).map(|&pos| &self.servers[pos])
+	}
+
+	/// Look up a member of a server by their user ID.
+	pub fn find_member(&self, server: ServerId, user: UserId) -> Option<&Member> {
+		let srv = self.find_server(server)?;
+		self.user_index.get(&server)?.members.get(&srv.members, user)
+	}
+
+	/// Look up a member of a server by their nickname or username, ignoring
+	/// case.
+	///
+	/// A username may include its discriminator, as in `name#1234`. Members
+	/// whose nickname matches are preferred over those whose username does.
+	pub fn find_member_by_name(&self, server: ServerId, name: &str) -> Option<&Member> {
+		let members = &self.find_server(server)?.members;
+		let name = name.to_lowercase();
+		let (username, discriminator) = match name.rfind('#') {
+			Some(pos) => match name[pos + 1..].parse::<u16>() {
+				Ok(discrim) => (&name[..pos], Some(discrim)),
+				Err(_) => (&name[..], None),
+			},
+			None => (&name[..], None),
+		};
+		members
+			.iter()
+			.find(|m| m.nick.as_ref().map_or(false, |nick| nick.to_lowercase() == name))
+			.or_else(|| {
+				members.iter().find(|m| {
+					m.user.name.to_lowercase() == username
+						&& discriminator.map_or(true, |d| d == m.user.discriminator)
+				})
+			})
+	}
+
+	/// Get the known members of a server who have a role.
+	///
+	/// The server's `@everyone` role, whose ID is the server's, matches every
+	/// member.
+	pub fn members_with_role(&self, server: ServerId, role: RoleId) -> Vec<&Member> {
+		let srv = match self.find_server(server) {
+			Some(srv) => srv,
+			None => return Vec::new(),
+		};
+		srv.members
+			.iter()
+			.filter(|m| role.0 == server.0 || m.roles.contains(&role))
+			.collect()
+	}
+
+	/// Look up a user by their ID among server members, private channel and
+	/// group recipients, and relationships.
+	///
+	/// The logged-in user is found only if they appear in one of these.
+	pub fn find_user(&self, id: UserId) -> Option<&User> {
+		for srv in self.servers.iter() {
+			if let Some(member) = self.find_member(srv.id, id) {
+				return Some(&member.user);
 			}
 		}
-		None
+		for channel in &self.private_channels {
+			if channel.recipient.id == id {
+				return Some(&channel.recipient);
+			}
+		}
+		for group in self.groups.values() {
+			if let Some(user) = group.recipients.iter().find(|u| u.id == id) {
+				return Some(user);
+			}
+		}
+		self.relationships.iter().find(|r| r.user.id == id).map(|r| &r.user)
+	}
+
+	/// Correct each server's `member_count`, which drifts while events are
+	/// missed, and record its `approximate_presence_count`.
+	///
+	/// Makes one request per server, so this is best done occasionally, such
+	/// as after a reconnect.
+	pub fn refresh_member_counts(&mut self, discord: &::Discord) -> ::Result<()> {
+		for server in Arc::make_mut(&mut self.servers) {
+			let counts = discord.get_server_with_counts(server.id)?;
+			if let Some(count) = counts.approximate_member_count {
+				server.member_count = count;
+			}
+			server.approximate_presence_count = counts.approximate_presence_count;
+		}
+		Ok(())
+	}
+
+	/// Look up which server a public channel belongs to.
+	pub fn find_channel_server(&self, id: ChannelId) -> Option<ServerId> {
+		self.channel_index.get(&id).cloned()
 	}
 
 	/// Look up a private or public channel by its ID.
 	pub fn find_channel(&self, id: ChannelId) -> Option<ChannelRef> {
-		for server in &self.servers {
-			for channel in &server.channels {
-				if channel.id == id {
-					return Some(ChannelRef::Public(server, channel));
-				}
+		if let Some(server) = self.find_channel_server(id).and_then(|s| self.find_server(s)) {
+			if let Some(channel) = server.channels.iter().find(|c| c.id == id) {
+				return Some(ChannelRef::Public(server, channel));
 			}
 		}
 		for channel in &self.private_channels {
@@ -662,11 +1253,31 @@ impl State {
 		None
 	}
 
+	/// Calculate a user's effective permissions in a channel.
+	///
+	/// Threads take the permissions of their parent channel. In private
+	/// channels and groups, participants may send messages and files, react,
+	/// and join calls, while other users have no permissions. Returns `None`
+	/// if the channel is not known.
+	pub fn permissions_in(&self, channel: ChannelId, user: UserId) -> Option<Permissions> {
+		let channel = self.find_thread(channel).map_or(channel, |thread| thread.parent_id);
+		let participant = match self.find_channel(channel)? {
+			ChannelRef::Public(server, _) => return Some(server.permissions_for(channel, user)),
+			ChannelRef::Private(private) => user == private.recipient.id,
+			ChannelRef::Group(group) => group.recipients.iter().any(|u| u.id == user),
+		};
+		if participant || user == self.user.id {
+			Some(PRIVATE_PERMISSIONS)
+		} else {
+			Some(Permissions::empty())
+		}
+	}
+
 	/// Look up the voice channel or group call a user is in, if any.
 	///
 	/// For bot users which may be in multiple voice channels, the first found is returned.
 	pub fn find_voice_user(&self, user_id: UserId) -> Option<(Option<ServerId>, ChannelId)> {
-		for server in &self.servers {
+		for server in self.servers.iter() {
 			for vstate in &server.voice_states {
 				if vstate.user_id == user_id {
 					if let Some(channel_id) = vstate.channel_id {
@@ -686,13 +1297,13 @@ impl State {
 	}
 }
 
-fn update_presence(vec: &mut Vec<Presence>, presence: &Presence) {
+fn update_presence(vec: &mut Vec<Presence>, index: &mut VecIndex, presence: &Presence) {
 	if presence.status == OnlineStatus::Offline {
 		// Remove the user from the presence list
-		vec.retain(|u| u.user_id != presence.user_id);
+		index.remove(vec, presence.user_id);
 	} else {
 		// Update or add to the presence list
-		if let Some(srv_presence) = vec.iter_mut().find(|u| u.user_id == presence.user_id) {
+		if let Some(srv_presence) = index.get_mut(vec, presence.user_id) {
 			if presence.user.is_none() {
 				let user = srv_presence.user.clone();
 				srv_presence.clone_from(presence);
@@ -702,7 +1313,284 @@ fn update_presence(vec: &mut Vec<Presence>, presence: &Presence) {
 			}
 			return;
 		}
-		vec.push(presence.clone());
+		index.insert(vec, presence.clone());
+	}
+}
+
+/// Drop whatever the cache options skip from a server.
+fn trim_server(server: &mut LiveServer, cache: CacheOptions, me: UserId) {
+	if !cache.presences {
+		server.presences.clear();
+	}
+	if !cache.voice_states {
+		server.voice_states.retain(|v| v.user_id == me);
+	}
+	let mut kept = 0;
+	server.members.retain(|m| {
+		let keep = m.user.id == me || cache.admits_member(kept);
+		if keep {
+			kept += 1;
+		}
+		keep
+	});
+}
+
+fn user_index(map: &mut BTreeMap<ServerId, Arc<UserIndex>>, server: ServerId) -> &mut UserIndex {
+	Arc::make_mut(map.entry(server).or_insert_with(Default::default))
+}
+
+/// Positions of a server's members and presences, by user.
+#[derive(Debug, Clone, Default)]
+struct UserIndex {
+	members: VecIndex,
+	presences: VecIndex,
+}
+
+impl UserIndex {
+	fn build(server: &LiveServer) -> UserIndex {
+		UserIndex {
+			members: VecIndex::build(&server.members),
+			presences: VecIndex::build(&server.presences),
+		}
+	}
+}
+
+/// Something listed once per user.
+trait PerUser {
+	fn user_id(&self) -> UserId;
+}
+
+impl PerUser for Member {
+	fn user_id(&self) -> UserId {
+		self.user.id
+	}
+}
+
+impl PerUser for Presence {
+	fn user_id(&self) -> UserId {
+		self.user_id
+	}
+}
+
+/// The position of each user's entry in a list, so that it can be found
+/// without a linear scan.
+///
+/// Entries are removed by swapping the last entry into their place, so lists
+/// do not keep their order.
+#[derive(Debug, Clone, Default)]
+struct VecIndex(HashMap<UserId, usize>);
+
+impl VecIndex {
+	fn build<T: PerUser>(vec: &[T]) -> VecIndex {
+		VecIndex(vec.iter().enumerate().map(|(i, item)| (item.user_id(), i)).collect())
+	}
+
+	fn contains(&self, user: UserId) -> bool {
+		self.0.contains_key(&user)
+	}
+
+	fn get<'a, T>(&self, vec: &'a [T], user: UserId) -> Option<&'a T> {
+		self.0.get(&user).and_then(|&pos| vec.get(pos))
+	}
+
+	fn get_mut<'a, T>(&self, vec: &'a mut [T], user: UserId) -> Option<&'a mut T> {
+		match self.0.get(&user) {
+			Some(&pos) => vec.get_mut(pos),
+			None => None,
+		}
+	}
+
+	/// Add an entry, replacing any existing entry for the same user.
+	fn insert<T: PerUser>(&mut self, vec: &mut Vec<T>, item: T) {
+		match self.0.get(&item.user_id()) {
+			Some(&pos) => vec[pos] = item,
+			None => {
+				self.0.insert(item.user_id(), vec.len());
+				vec.push(item);
+			}
+		}
+	}
+
+	fn remove<T: PerUser>(&mut self, vec: &mut Vec<T>, user: UserId) -> Option<T> {
+		let pos = self.0.remove(&user)?;
+		let item = vec.swap_remove(pos);
+		if let Some(moved) = vec.get(pos) {
+			self.0.insert(moved.user_id(), pos);
+		}
+		Some(item)
+	}
+}
+
+/// An immutable, cheaply cloneable copy of a `State`, from `State::snapshot`.
+///
+/// Dereferences to the `State` it was taken from.
+#[derive(Debug, Clone)]
+pub struct Snapshot(Arc<State>);
+
+impl Deref for Snapshot {
+	type Target = State;
+
+	fn deref(&self) -> &State {
+		&self.0
+	}
+}
+
+/// A `State` which can be read and updated from several threads.
+///
+/// Clones share the same state. Typically one thread applies events with
+/// `update` while others read it. A thread which panics while holding the lock
+/// may leave an update half-applied, but does not make the state unusable: its
+/// indexes are rebuilt whenever it is locked from then on, at some cost.
+///
+/// ```ignore
+/// let state = SharedState::new(State::new(ready));
+/// let reader = state.clone();
+/// thread::spawn(move || loop {
+///     println!("in {} servers", reader.read().servers().len());
+///     thread::sleep(Duration::from_secs(60));
+/// });
+/// loop {
+///     let event = connection.recv_event()?;
+///     state.update(&event);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SharedState(Arc<RwLock<State>>);
+
+impl SharedState {
+	/// Share a state between threads.
+	pub fn new(state: State) -> SharedState {
+		SharedState(Arc::new(RwLock::new(state)))
+	}
+
+	/// Update the state according to an event. See `State::update`.
+	pub fn update(&self, event: &Event) -> Vec<StateWarning> {
+		self.write().update(event)
+	}
+
+	/// Lock the state for reading, blocking until any update finishes.
+	///
+	/// Updates wait until the guard is dropped, so avoid holding it for long.
+	pub fn read(&self) -> RwLockReadGuard<'_, State> {
+		if let Ok(guard) = self.0.read() {
+			return guard;
+		}
+		// repair the indexes, which only a writer can do
+		drop(self.write());
+		self.0.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+
+	/// Lock the state for writing, blocking until all readers finish.
+	pub fn write(&self) -> RwLockWriteGuard<'_, State> {
+		self.0.write().unwrap_or_else(|poisoned| {
+			let mut state = poisoned.into_inner();
+			state.reindex();
+			// the state is whole again, so later locks need not repair it
+			self.0.clear_poison();
+			state
+		})
+	}
+
+	/// Take an immutable copy of the state. See `State::snapshot`.
+	pub fn snapshot(&self) -> Snapshot {
+		self.read().snapshot()
+	}
+
+	/// Get a copy of the logged-in user's information.
+	pub fn user(&self) -> CurrentUser {
+		self.read().user().clone()
+	}
+
+	/// Build a list of all known server IDs. See `State::all_servers`.
+	pub fn all_servers(&self) -> Vec<ServerId> {
+		self.read().all_servers()
+	}
+
+	/// Get a copy of a server by its ID.
+	pub fn find_server(&self, id: ServerId) -> Option<LiveServer> {
+		self.read().find_server(id).cloned()
+	}
+
+	/// Look up which server a public channel belongs to.
+	pub fn find_channel_server(&self, id: ChannelId) -> Option<ServerId> {
+		self.read().find_channel_server(id)
+	}
+
+	/// Get a copy of a member of a server by their user ID.
+	pub fn find_member(&self, server: ServerId, user: UserId) -> Option<Member> {
+		self.read().find_member(server, user).cloned()
+	}
+
+	/// Look up the voice channel or group call a user is in, if any. See
+	/// `State::find_voice_user`.
+	pub fn find_voice_user(&self, user: UserId) -> Option<(Option<ServerId>, ChannelId)> {
+		self.read().find_voice_user(user)
+	}
+}
+
+impl From<State> for SharedState {
+	fn from(state: State) -> SharedState {
+		SharedState::new(state)
+	}
+}
+
+/// The previous value of something changed by an event, from
+/// `State::update_with_changes`.
+#[derive(Debug, Clone)]
+pub enum StateChange {
+	/// The logged-in user's information changed.
+	UserUpdate(CurrentUser),
+	/// A server's settings changed.
+	ServerUpdate(LiveServer),
+	/// A member's roles, nickname, or user information changed.
+	MemberUpdate(ServerId, Member),
+	/// A member left or was removed from a server.
+	MemberRemove(ServerId, Member),
+	/// A user's presence changed, in a server or among friends.
+	PresenceUpdate(Option<ServerId>, Presence),
+	/// A user moved between, joined, or left a server's voice channels.
+	VoiceStateUpdate(ServerId, VoiceState),
+	/// A server channel's settings changed.
+	ChannelUpdate(PublicChannel),
+	/// A server channel was deleted.
+	ChannelDelete(PublicChannel),
+	/// A role's name, color, or permissions changed.
+	RoleUpdate(ServerId, Role),
+	/// A role was deleted.
+	RoleDelete(ServerId, Role),
+	/// A thread's name, settings, or archive status changed.
+	ThreadUpdate(ThreadChannel),
+	/// A thread was deleted.
+	ThreadDelete(ThreadChannel),
+}
+
+/// A part of an event which could not be applied to the `State`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateWarning {
+	/// The event referred to a server which is not known.
+	UnknownServer(ServerId),
+	/// The event referred to a channel which is not known.
+	UnknownChannel(ChannelId),
+	/// The event referred to a member of a server who is not known.
+	///
+	/// Only reported for servers whose members have all been downloaded.
+	UnknownMember(ServerId, UserId),
+	/// The event referred to a role which is not known in a server.
+	UnknownRole(ServerId, RoleId),
+}
+
+impl fmt::Display for StateWarning {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			StateWarning::UnknownServer(id) => write!(f, "unknown server {}", id),
+			StateWarning::UnknownChannel(id) => write!(f, "unknown channel {}", id),
+			StateWarning::UnknownMember(server, user) => {
+				write!(f, "unknown member {} of server {}", user, server)
+			}
+			StateWarning::UnknownRole(server, role) => {
+				write!(f, "unknown role {} of server {}", role, server)
+			}
+		}
 	}
 }
 
@@ -723,3 +1611,255 @@ fn opt_modify<T: Clone>(dest: &mut T, src: &Option<T>) {
 		dest.clone_from(val);
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn dispatch(kind: &str, data: ::serde_json::Value) -> Event {
+		let payload = json!({"op": 0, "s": 1, "t": kind, "d": data});
+		match GatewayEvent::decode(payload).unwrap() {
+			GatewayEvent::Dispatch(_, event) => event,
+			_ => panic!("{} was not a dispatch", kind),
+		}
+	}
+
+	fn member(id: &str, nick: &str) -> ::serde_json::Value {
+		json!({
+			"user": {"id": id, "username": "Someone", "discriminator": "1234", "avatar": null},
+			"roles": [],
+			"nick": nick,
+			"joined_at": "2020-01-01T00:00:00.000000+00:00",
+			"mute": false,
+			"deaf": false,
+		})
+	}
+
+	fn ready() -> ReadyEvent {
+		match dispatch(
+			"READY",
+			json!({
+				"v": 6,
+				"user": {"id": "10", "username": "Bot", "discriminator": "0001", "avatar": null,
+					"email": null, "verified": true, "bot": true, "mfa_enabled": false},
+				"session_id": "abc",
+				"private_channels": [],
+				"presences": [],
+				"relationships": [],
+				"guilds": [{"id": "20", "unavailable": true}],
+				"_trace": [],
+			}),
+		) {
+			Event::Ready(ready) => ready,
+			other => panic!("not a ready: {:?}", other),
+		}
+	}
+
+	// a state with one server, whose one member is nicknamed "before"
+	fn state_with_server() -> State {
+		let mut state = State::new(ready());
+		let warnings = state.update(&create_server("20", &[]));
+		assert!(warnings.is_empty(), "{:?}", warnings);
+		state
+	}
+
+	// a server whose one member is nicknamed "before", with the named text channels
+	fn create_server(id: &str, channels: &[&str]) -> Event {
+		let channels: Vec<_> = channels
+			.iter()
+			.map(|channel| {
+				json!({"id": channel, "name": "general", "type": 0, "position": 0,
+					"permission_overwrites": []})
+			})
+			.collect();
+		dispatch(
+			"GUILD_CREATE",
+			json!({
+				"id": id, "name": "Server", "owner_id": "30", "voice_states": [],
+				"roles": [], "presences": [], "member_count": 1,
+				"members": [member("30", "before")],
+				"joined_at": "2020-01-01T00:00:00.000000+00:00", "icon": null, "large": false,
+				"afk_timeout": 300, "afk_channel_id": null, "channels": channels,
+				"verification_level": 0, "emojis": [], "features": [], "splash": null,
+				"default_message_notifications": 0, "mfa_level": 0, "explicit_content_filter": 0,
+			}),
+		)
+	}
+
+	fn rename_member(nick: &str) -> Event {
+		let mut update = member("30", nick);
+		update["guild_id"] = json!("20");
+		dispatch("GUILD_MEMBER_UPDATE", update)
+	}
+
+	#[test]
+	fn test_member_update_changes() {
+		let mut state = state_with_server();
+		let (changes, warnings) = state.update_with_changes(&rename_member("after"));
+		assert!(warnings.is_empty(), "{:?}", warnings);
+		assert_eq!(changes.len(), 1);
+		match changes[0] {
+			StateChange::MemberUpdate(ServerId(20), ref old) => {
+				assert_eq!(old.nick.as_ref().map(|s| &s[..]), Some("before"));
+			}
+			ref other => panic!("unexpected change: {:?}", other),
+		}
+		let member = state.find_member(ServerId(20), UserId(30)).unwrap();
+		assert_eq!(member.nick.as_ref().map(|s| &s[..]), Some("after"));
+	}
+
+	fn archived_thread(id: &str, archived_at: ::chrono::DateTime<::chrono::Utc>) -> Event {
+		dispatch(
+			"THREAD_CREATE",
+			json!({
+				"id": id, "guild_id": "20", "parent_id": "21", "name": "thread", "type": 11,
+				"owner_id": "30", "last_message_id": null, "rate_limit_per_user": 0,
+				"message_count": 0, "member_count": 1,
+				"thread_metadata": {
+					"archived": true,
+					"archive_timestamp": archived_at.to_rfc3339(),
+					"auto_archive_duration": 60,
+					"locked": false,
+				},
+			}),
+		)
+	}
+
+	#[test]
+	fn test_thread_eviction() {
+		let mut state = state_with_server();
+		state.set_thread_retention(Duration::from_secs(60 * 60));
+		let now = ::chrono::Utc::now();
+		state.update(&archived_thread("40", now - ::chrono::Duration::hours(2)));
+		state.update(&archived_thread("41", now - ::chrono::Duration::minutes(5)));
+		assert!(state.find_thread(ChannelId(40)).is_none());
+		assert!(state.find_thread(ChannelId(41)).is_some());
+
+		state.set_thread_retention(Duration::from_secs(60));
+		assert!(state.find_thread(ChannelId(41)).is_none());
+	}
+
+	#[test]
+	fn test_shared_state_poison() {
+		let shared = SharedState::new(state_with_server());
+		let writer = shared.clone();
+		let result = ::std::thread::spawn(move || {
+			let _guard = writer.write();
+			panic!("interrupted update");
+		})
+		.join();
+		assert!(result.is_err());
+		assert!(shared.0.is_poisoned());
+
+		assert!(shared.read().find_member(ServerId(20), UserId(30)).is_some());
+		assert!(!shared.0.is_poisoned());
+	}
+
+	#[test]
+	fn test_update_warnings() {
+		let mut state = state_with_server();
+		let mut update = member("31", "stranger");
+		update["guild_id"] = json!("20");
+		let warnings = state.update(&dispatch("GUILD_MEMBER_UPDATE", update.clone()));
+		assert_eq!(warnings, [StateWarning::UnknownMember(ServerId(20), UserId(31))]);
+
+		update["guild_id"] = json!("99");
+		let warnings = state.update(&dispatch("GUILD_MEMBER_UPDATE", update));
+		assert_eq!(warnings, [StateWarning::UnknownServer(ServerId(99))]);
+
+		let pins = json!({"channel_id": "98", "last_pin_timestamp": null});
+		let warnings = state.update(&dispatch("CHANNEL_PINS_UPDATE", pins));
+		assert_eq!(warnings, [StateWarning::UnknownChannel(ChannelId(98))]);
+	}
+
+	#[test]
+	fn test_snapshot_sharing() {
+		let mut state = state_with_server();
+		let snapshot = state.snapshot();
+		assert!(Arc::ptr_eq(&state.shared_servers(), &snapshot.shared_servers()));
+
+		state.update(&rename_member("after"));
+		assert!(!Arc::ptr_eq(&state.shared_servers(), &snapshot.shared_servers()));
+		let member = snapshot.find_member(ServerId(20), UserId(30)).unwrap();
+		assert_eq!(member.nick.as_ref().map(|s| &s[..]), Some("before"));
+	}
+
+	#[test]
+	fn test_remove_middle_server() {
+		let mut state = state_with_server();
+		state.update(&create_server("21", &["50"]));
+		state.update(&create_server("22", &["51", "52"]));
+		state.update(&dispatch("GUILD_DELETE", json!({"id": "21", "unavailable": true})));
+
+		assert!(state.find_server(ServerId(21)).is_none());
+		assert!(state.find_channel(ChannelId(50)).is_none());
+		assert_eq!(state.find_server(ServerId(22)).map(|srv| srv.id), Some(ServerId(22)));
+		for &id in &[51, 52] {
+			match state.find_channel(ChannelId(id)) {
+				Some(ChannelRef::Public(srv, channel)) => {
+					assert_eq!(srv.id, ServerId(22));
+					assert_eq!(channel.id, ChannelId(id));
+				}
+				other => panic!("channel {} not found: {:?}", id, other),
+			}
+		}
+		assert!(state.find_member(ServerId(22), UserId(30)).is_some());
+	}
+
+	#[test]
+	fn test_disabled_caches() {
+		let mut state = State::with_options(
+			ready(),
+			CacheOptions {
+				presences: false,
+				members: false,
+				max_members: None,
+				voice_states: false,
+			},
+		);
+		state.update(&create_server("20", &["21"]));
+		let mut joined = member("31", "joined");
+		joined["guild_id"] = json!("20");
+		state.update(&dispatch("GUILD_MEMBER_ADD", joined));
+		state.update(&dispatch(
+			"PRESENCE_UPDATE",
+			json!({"user": {"id": "31"}, "guild_id": "20", "status": "online"}),
+		));
+		state.update(&dispatch(
+			"VOICE_STATE_UPDATE",
+			json!({
+				"guild_id": "20", "channel_id": "21", "user_id": "31", "session_id": "abc",
+				"deaf": false, "mute": false, "self_deaf": false, "self_mute": false,
+				"suppress": false,
+			}),
+		));
+
+		let srv = state.find_server(ServerId(20)).unwrap();
+		assert!(srv.members.is_empty());
+		assert!(srv.presences.is_empty());
+		assert!(srv.voice_states.is_empty());
+		assert!(state.find_member(ServerId(20), UserId(31)).is_none());
+	}
+
+	#[test]
+	fn test_typing_expiry() {
+		let mut state = state_with_server();
+		let typing = |user: &str| {
+			dispatch(
+				"TYPING_START",
+				json!({"channel_id": "21", "user_id": user, "timestamp": 1_600_000_000}),
+			)
+		};
+		state.update(&typing("30"));
+		assert_eq!(state.users_typing(ChannelId(21)), [UserId(30)]);
+
+		// typing is timed from when the event arrived, so backdate it
+		state.typing.get_mut(&ChannelId(21)).unwrap()[0].1 -=
+			::chrono::Duration::seconds(TYPING_DURATION + 1);
+		assert!(state.users_typing(ChannelId(21)).is_empty());
+
+		state.update(&typing("31"));
+		assert_eq!(state.users_typing(ChannelId(21)), [UserId(31)]);
+		assert_eq!(state.typing[&ChannelId(21)].len(), 1);
+	}
+}
@@ -0,0 +1,59 @@
+//! An asynchronous `Stream` of a connection's events.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_channel::mpsc;
+use futures_core::Stream;
+
+use connection::{is_fatal, Connection};
+use model::Event;
+use Result;
+
+/// A `Stream` of the events received by a `Connection` running on its own
+/// thread, from `Connection::into_stream`.
+///
+/// The stream ends after yielding an error the connection cannot recover
+/// from. Dropping the stream shuts the connection down once the next event
+/// arrives.
+///
+/// ```ignore
+/// let mut events = connection.into_stream()?;
+/// while let Some(event) = events.next().await {
+///     match event? {
+///         Event::MessageCreate(message) => { /* ... */ }
+///         _ => {}
+///     }
+/// }
+/// ```
+pub struct EventStream {
+	events: mpsc::UnboundedReceiver<Result<Event>>,
+}
+
+impl EventStream {
+	pub(crate) fn start(mut connection: Connection) -> Result<EventStream> {
+		let (tx, rx) = mpsc::unbounded();
+		::std::thread::Builder::new()
+			.name("Discord Event Stream".into())
+			.spawn(move || loop {
+				let event = connection.recv_event();
+				let fatal = event.as_ref().err().map_or(false, is_fatal);
+				if tx.unbounded_send(event).is_err() {
+					let _ = connection.shutdown();
+					return;
+				}
+				if fatal {
+					return;
+				}
+			})?;
+		Ok(EventStream { events: rx })
+	}
+}
+
+impl Stream for EventStream {
+	type Item = Result<Event>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Event>>> {
+		Pin::new(&mut self.events).poll_next(cx)
+	}
+}
@@ -3,10 +3,13 @@
 //! A `VoiceConnection` for a server is obtained from a `Connection`. It can then be used to
 //! join a channel, change mute/deaf status, and play and receive audio.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::error::Error as StdError;
+use std::fmt;
 use std::io::{self, Read, Write};
-use std::net::UdpSocket;
+use std::net::{SocketAddr, UdpSocket};
 use std::sync::mpsc;
+use std::time::Duration;
 
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
 use opus;
@@ -16,7 +19,7 @@ use websocket::client::{Client, Sender};
 use websocket::stream::WebSocketStream;
 
 use model::*;
-use {Error, ReceiverExt, Result, SenderExt};
+use {is_timeout, set_read_timeout, Error, ReceiverExt, Result, SenderExt};
 
 /// An active or inactive voice connection, obtained from `Connection::voice`.
 pub struct VoiceConnection {
@@ -27,6 +30,9 @@ pub struct VoiceConnection {
 	channel_id: Option<ChannelId>,
 	mute: bool,
 	deaf: bool,
+	channel_bitrate: Option<u32>,
+	bitrate_override: Option<u32>,
+	address_preference: AddressPreference,
 
 	// main WS receive control
 	session_id: Option<String>,
@@ -34,6 +40,112 @@ pub struct VoiceConnection {
 
 	// voice thread (voice WS + UDP) control
 	sender: mpsc::Sender<Status>,
+	results: mpsc::Receiver<Result<()>>,
+	connecting: bool,
+}
+
+/// A failure to establish or maintain a voice connection.
+///
+/// Returned as `Error::Voice` from `Connection::connect_voice_and_wait` and
+/// passed to `AudioReceiver::voice_error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VoiceError {
+	/// The voice server did not respond in time during setup
+	HandshakeTimeout,
+	/// The voice server offered none of the supported encryption modes, with
+	/// the modes it did offer
+	UnsupportedMode(Vec<String>),
+	/// The voice server's response to IP discovery was missing or malformed
+	IpDiscoveryFailed,
+	/// The voice server did not provide a usable encryption key
+	EncryptionSetupFailed,
+	/// The voice websocket was closed
+	Disconnected {
+		/// The close code, if one was sent
+		code: Option<u16>,
+	},
+}
+
+impl fmt::Display for VoiceError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			VoiceError::UnsupportedMode(ref modes) => {
+				write!(f, "No supported voice mode among {:?}", modes)
+			}
+			VoiceError::Disconnected { code: Some(code) } => {
+				write!(f, "Voice connection closed with code {}", code)
+			}
+			_ => f.write_str(self.description()),
+		}
+	}
+}
+
+impl StdError for VoiceError {
+	fn description(&self) -> &str {
+		match *self {
+			VoiceError::HandshakeTimeout => "Voice handshake timed out",
+			VoiceError::UnsupportedMode(_) => "No supported voice mode",
+			VoiceError::IpDiscoveryFailed => "Voice IP discovery failed",
+			VoiceError::EncryptionSetupFailed => "Voice encryption setup failed",
+			VoiceError::Disconnected { .. } => "Voice connection closed",
+		}
+	}
+}
+
+/// The duration of audio encoded in each outgoing voice packet.
+///
+/// Shorter frames reduce latency, while longer frames reduce CPU and bandwidth
+/// overhead. The default is 20ms.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FrameSize {
+	/// 10ms, or 480 samples per channel
+	Ms10,
+	/// 20ms, or 960 samples per channel
+	Ms20,
+	/// 40ms, or 1920 samples per channel
+	Ms40,
+	/// 60ms, or 2880 samples per channel
+	Ms60,
+}
+
+impl FrameSize {
+	/// The frame duration in milliseconds.
+	pub fn millis(self) -> u64 {
+		match self {
+			FrameSize::Ms10 => 10,
+			FrameSize::Ms20 => 20,
+			FrameSize::Ms40 => 40,
+			FrameSize::Ms60 => 60,
+		}
+	}
+
+	/// The number of samples per channel in each frame at 48000Hz.
+	pub fn samples(self) -> usize {
+		self.millis() as usize * SAMPLE_RATE as usize / 1000
+	}
+}
+
+impl Default for FrameSize {
+	fn default() -> FrameSize {
+		FrameSize::Ms20
+	}
+}
+
+/// Which IP versions are tried when reaching a voice server over UDP.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AddressPreference {
+	/// Try each of the server's IPv4 addresses, then each of its IPv6 addresses
+	Auto,
+	/// Try only the server's IPv4 addresses
+	V4,
+	/// Try only the server's IPv6 addresses
+	V6,
+}
+
+impl Default for AddressPreference {
+	fn default() -> AddressPreference {
+		AddressPreference::Auto
+	}
 }
 
 /// A readable audio source.
@@ -45,7 +157,8 @@ pub trait AudioSource: Send {
 
 	/// Called each frame when more audio is required.
 	///
-	/// Samples should be supplied at 48000Hz, and if `is_stereo` returned true, the channels
+	/// The buffer holds one frame of the size set by `VoiceConnection::set_frame_size`,
+	/// 960 samples per channel by default. Samples should be supplied at 48000Hz, and if `is_stereo` returned true, the channels
 	/// should be interleaved, left first.
 	///
 	/// The result should normally be `Some(N)`, where `N` is the number of samples written to the
@@ -82,6 +195,12 @@ pub trait AudioReceiver: Send {
 		stereo: bool,
 		data: &[i16],
 	);
+
+	/// Called when the voice connection fails to be established or is lost.
+	///
+	/// Errors specific to voice are reported as `Error::Voice`. The connection will not be
+	/// retried until the next call to `VoiceConnection::connect`.
+	fn voice_error(&mut self, _error: &Error) {}
 }
 
 impl VoiceConnection {
@@ -92,7 +211,8 @@ impl VoiceConnection {
 		main_ws: mpsc::Sender<::internal::Status>,
 	) -> Self {
 		let (tx, rx) = mpsc::channel();
-		start_voice_thread(server_id, rx);
+		let (results_tx, results) = mpsc::channel();
+		start_voice_thread(server_id, rx, results_tx);
 		VoiceConnection {
 			server_id: server_id,
 			user_id: user_id,
@@ -100,9 +220,14 @@ impl VoiceConnection {
 			channel_id: None,
 			mute: false,
 			deaf: false,
+			channel_bitrate: None,
+			bitrate_override: None,
+			address_preference: AddressPreference::default(),
 			session_id: None,
 			endpoint_token: None,
 			sender: tx,
+			results: results,
+			connecting: false,
 		}
 	}
 
@@ -111,6 +236,21 @@ impl VoiceConnection {
 	#[inline]
 	pub fn connect(&mut self, channel_id: ChannelId) {
 		self.channel_id = Some(channel_id);
+		self.set_channel_bitrate(None);
+		self.send_connect();
+	}
+
+	/// Connect to the specified voice channel, encoding audio at the channel's bitrate as
+	/// known to the given `State`. Any previous channel on this server will be disconnected from.
+	///
+	/// If the channel's bitrate is not known, the encoder's default is used, as with `connect`.
+	pub fn connect_with_state(&mut self, channel_id: ChannelId, state: &::State) {
+		let bitrate = match state.find_channel(channel_id) {
+			Some(::ChannelRef::Public(_, channel)) => channel.bitrate.map(|b| b as u32),
+			_ => None,
+		};
+		self.channel_id = Some(channel_id);
+		self.set_channel_bitrate(bitrate);
 		self.send_connect();
 	}
 
@@ -121,6 +261,28 @@ impl VoiceConnection {
 		self.send_connect();
 	}
 
+	#[doc(hidden)]
+	pub fn __begin_wait(&mut self) {
+		while self.results.try_recv().is_ok() {}
+		self.connecting = false;
+	}
+
+	#[doc(hidden)]
+	pub fn __is_connecting(&self) -> bool {
+		self.connecting
+	}
+
+	#[doc(hidden)]
+	pub fn __wait_result(&self, timeout: Duration) -> Result<()> {
+		match self.results.recv_timeout(timeout) {
+			Ok(result) => result,
+			Err(mpsc::RecvTimeoutError::Timeout) => Err(VoiceError::HandshakeTimeout.into()),
+			Err(mpsc::RecvTimeoutError::Disconnected) => {
+				Err(Error::Other("Voice thread exited"))
+			}
+		}
+	}
+
 	/// Set the mute status of the voice connection.
 	///
 	/// Note that enabling mute client-side is cosmetic and does not prevent the sending of audio;
@@ -211,22 +373,88 @@ impl VoiceConnection {
 		self.thread_send(Status::SetReceiver(Some(receiver)));
 	}
 
+	/// Set the bitrate, in bits per second, at which to encode audio, overriding the bitrate
+	/// of the channel. `None` returns to the channel's bitrate.
+	///
+	/// The bitrate is limited to between 8000 and 512000.
+	#[inline]
+	pub fn set_bitrate(&mut self, bitrate: Option<u32>) {
+		self.bitrate_override = bitrate;
+		self.send_bitrate();
+	}
+
+	fn set_channel_bitrate(&mut self, bitrate: Option<u32>) {
+		if self.channel_bitrate != bitrate {
+			self.channel_bitrate = bitrate;
+			self.send_bitrate();
+		}
+	}
+
+	fn send_bitrate(&mut self) {
+		let bitrate = self.bitrate_override.or(self.channel_bitrate);
+		self.thread_send(Status::SetBitrate(
+			bitrate.map(|b| ::std::cmp::min(::std::cmp::max(b, MIN_BITRATE), MAX_BITRATE)),
+		));
+	}
+
+	/// Set the duration of audio sent in each packet, adjusting how often
+	/// `AudioSource::read_frame` is called and how much it is asked for.
+	#[inline]
+	pub fn set_frame_size(&mut self, frame_size: FrameSize) {
+		self.thread_send(Status::SetFrameSize(frame_size));
+	}
+
+	/// Set which IP versions are tried when reaching the voice server,
+	/// taking effect on the next connection. Defaults to `Auto`.
+	///
+	/// The voice server's hostname may resolve to several addresses, each of
+	/// which is tried in turn until one answers.
+	#[inline]
+	pub fn set_address_preference(&mut self, preference: AddressPreference) {
+		self.address_preference = preference;
+	}
+
 	/// Clear the voice receiver, discarding incoming voice.
 	#[inline]
 	pub fn clear_receiver(&mut self) {
 		self.thread_send(Status::SetReceiver(None));
 	}
 
+	/// Scale a user's incoming audio by the given factor before it is passed to the receiver.
+	///
+	/// A volume of `1.0` leaves the audio unchanged. Louder samples are clipped. The user is
+	/// recognized once they have been seen speaking on this connection.
+	#[inline]
+	pub fn set_user_volume(&mut self, user_id: UserId, volume: f32) {
+		self.thread_send(Status::SetUserVolume(user_id, volume));
+	}
+
+	/// Stop passing a user's incoming audio to the receiver.
+	///
+	/// Speaking updates for the user are still delivered.
+	#[inline]
+	pub fn mute_user(&mut self, user_id: UserId) {
+		self.thread_send(Status::SetUserMute(user_id, true));
+	}
+
+	/// Resume passing a user's incoming audio to the receiver.
+	#[inline]
+	pub fn unmute_user(&mut self, user_id: UserId) {
+		self.thread_send(Status::SetUserMute(user_id, false));
+	}
+
 	fn thread_send(&mut self, status: Status) {
 		match self.sender.send(status) {
 			Ok(()) => {}
 			Err(mpsc::SendError(status)) => {
 				// voice thread has crashed... start it over again
 				let (tx, rx) = mpsc::channel();
+				let (results_tx, results) = mpsc::channel();
 				self.sender = tx;
+				self.results = results;
 				self.sender.send(status).unwrap(); // should be infallible
 				debug!("Restarting crashed voice thread...");
-				start_voice_thread(self.server_id, rx);
+				start_voice_thread(self.server_id, rx, results_tx);
 				self.send_connect();
 			}
 		}
@@ -234,6 +462,7 @@ impl VoiceConnection {
 
 	#[inline]
 	fn internal_disconnect(&mut self) {
+		self.connecting = false;
 		self.thread_send(Status::Disconnect);
 	}
 
@@ -247,12 +476,14 @@ impl VoiceConnection {
 				return;
 			}
 		};
+		self.connecting = true;
 		self.thread_send(Status::Connect(ConnStartInfo {
 			server_id: server_id,
 			user_id: user_id,
 			session_id: session_id,
 			endpoint: endpoint,
 			token: token,
+			address_preference: self.address_preference,
 		}));
 	}
 }
@@ -406,26 +637,60 @@ pub fn open_ytdl_stream(url: &str) -> Result<Box<dyn AudioSource>> {
 enum Status {
 	SetSource(Option<Box<dyn AudioSource>>),
 	SetReceiver(Option<Box<dyn AudioReceiver>>),
+	SetUserVolume(UserId, f32),
+	SetUserMute(UserId, bool),
+	SetFrameSize(FrameSize),
+	SetBitrate(Option<u32>),
 	Connect(ConnStartInfo),
 	Disconnect,
 }
 
-fn start_voice_thread(server_id: Option<ServerId>, rx: mpsc::Receiver<Status>) {
+/// Per-user adjustments applied to received audio.
+#[derive(Default)]
+struct ReceiveMix {
+	volumes: HashMap<UserId, f32>,
+	muted: HashSet<UserId>,
+}
+
+impl ReceiveMix {
+	/// Apply the user's adjustments to the samples, returning false if they are muted.
+	fn apply(&self, user_id: UserId, data: &mut [i16]) -> bool {
+		if self.muted.contains(&user_id) {
+			return false;
+		}
+		if let Some(&volume) = self.volumes.get(&user_id) {
+			for sample in data.iter_mut() {
+				let scaled = (*sample as f32 * volume).round();
+				*sample = scaled.max(i16::MIN as f32).min(i16::MAX as f32) as i16;
+			}
+		}
+		true
+	}
+}
+
+fn start_voice_thread(
+	server_id: Option<ServerId>,
+	rx: mpsc::Receiver<Status>,
+	results: mpsc::Sender<Result<()>>,
+) {
 	let name = match server_id {
 		Some(ServerId(id)) => format!("discord voice (server {})", id),
 		None => "discord voice (private/groups)".to_owned(),
 	};
 	::std::thread::Builder::new()
 		.name(name)
-		.spawn(move || voice_thread(rx))
+		.spawn(move || voice_thread(rx, results))
 		.expect("Failed to start voice thread");
 }
 
-fn voice_thread(channel: mpsc::Receiver<Status>) {
+fn voice_thread(channel: mpsc::Receiver<Status>, results: mpsc::Sender<Result<()>>) {
 	let mut audio_source = None;
 	let mut receiver = None;
+	let mut mix = ReceiveMix::default();
 	let mut connection = None;
-	let mut audio_timer = ::Timer::new(20);
+	let mut frame_size = FrameSize::default();
+	let mut bitrate = None;
+	let mut audio_timer = ::Timer::new(frame_size.millis());
 
 	// start the main loop
 	'outer: loop {
@@ -434,10 +699,37 @@ fn voice_thread(channel: mpsc::Receiver<Status>) {
 			match channel.try_recv() {
 				Ok(Status::SetSource(s)) => audio_source = s,
 				Ok(Status::SetReceiver(r)) => receiver = r,
+				Ok(Status::SetUserVolume(user_id, volume)) => {
+					mix.volumes.insert(user_id, volume);
+				}
+				Ok(Status::SetUserMute(user_id, true)) => {
+					mix.muted.insert(user_id);
+				}
+				Ok(Status::SetUserMute(user_id, false)) => {
+					mix.muted.remove(&user_id);
+				}
+				Ok(Status::SetFrameSize(size)) => {
+					if size != frame_size {
+						frame_size = size;
+						audio_timer = ::Timer::new(size.millis());
+					}
+				}
+				Ok(Status::SetBitrate(b)) => bitrate = b,
 				Ok(Status::Connect(info)) => {
-					connection = InternalConnection::new(info)
-						.map_err(|e| error!("Error connecting to voice: {:?}", e))
-						.ok();
+					connection = match InternalConnection::new(info) {
+						Ok(conn) => {
+							let _ = results.send(Ok(()));
+							Some(conn)
+						}
+						Err(e) => {
+							error!("Error connecting to voice: {:?}", e);
+							if let Some(receiver) = receiver.as_mut() {
+								receiver.voice_error(&e);
+							}
+							let _ = results.send(Err(e));
+							None
+						}
+					};
 				}
 				Ok(Status::Disconnect) => connection = None,
 				Err(mpsc::TryRecvError::Empty) => break,
@@ -449,8 +741,18 @@ fn voice_thread(channel: mpsc::Receiver<Status>) {
 		let mut error = false;
 		if let Some(connection) = connection.as_mut() {
 			// update() will sleep using audio_timer as needed
-			if let Err(e) = connection.update(&mut audio_source, &mut receiver, &mut audio_timer) {
+			if let Err(e) = connection.update(
+				&mut audio_source,
+				&mut receiver,
+				&mix,
+				frame_size,
+				bitrate,
+				&mut audio_timer,
+			) {
 				error!("Error in voice connection: {:?}", e);
+				if let Some(receiver) = receiver.as_mut() {
+					receiver.voice_error(&e);
+				}
 				error = true;
 			}
 		} else {
@@ -470,6 +772,7 @@ struct ConnStartInfo {
 	endpoint: String,
 	session_id: String,
 	token: String,
+	address_preference: AddressPreference,
 }
 
 struct InternalConnection {
@@ -486,8 +789,10 @@ struct InternalConnection {
 	speaking: bool,
 	silence_frames: u8,
 	decoder_map: HashMap<(u32, opus::Channels), opus::Decoder>,
+	ssrc_map: HashMap<u32, UserId>,
 	encoder: opus::Encoder,
 	encoder_stereo: bool,
+	encoder_bitrate: Option<u32>,
 	keepalive_timer: ::Timer,
 	audio_keepalive_timer: ::Timer,
 	ws_thread: Option<::std::thread::JoinHandle<()>>,
@@ -496,6 +801,30 @@ struct InternalConnection {
 
 const SAMPLE_RATE: u32 = 48000;
 const HEADER_LEN: usize = 12;
+/// Samples per channel in the longest frame Opus can decode, 120ms.
+const MAX_FRAME_SAMPLES: usize = 5760;
+/// Room for the packet header and encoded audio of a 20ms frame.
+const PACKET_LEN: usize = 512;
+/// The lowest and highest bitrates the encoder may be set to.
+const MIN_BITRATE: u32 = 8000;
+const MAX_BITRATE: u32 = 512000;
+/// Room for the packet header and encoded audio of a 60ms frame at the highest bitrate.
+const MAX_PACKET_LEN: usize = HEADER_LEN + 16 + MAX_BITRATE as usize * 60 / 6400;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for an answer to IP discovery from each address tried.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Translate errors reading from the voice websocket during setup.
+fn handshake_error(err: Error) -> Error {
+	match err {
+		Error::WebSocket(::websocket::result::WebSocketError::IoError(ref io)) if is_timeout(io) => {
+			VoiceError::HandshakeTimeout.into()
+		}
+		Error::Io(ref io) if is_timeout(io) => VoiceError::HandshakeTimeout.into(),
+		Error::Closed(code, _) => VoiceError::Disconnected { code: code }.into(),
+		other => other,
+	}
+}
 
 impl InternalConnection {
 	fn new(info: ConnStartInfo) -> Result<InternalConnection> {
@@ -505,22 +834,30 @@ impl InternalConnection {
 			mut endpoint,
 			session_id,
 			token,
+			address_preference,
 		} = info;
 
-		// prepare the URL: drop the :80 and prepend wss://
+		// prepare the URL: drop the :80 and prepend wss://, unless a scheme
+		// was given, as for a loopback server
 		if endpoint.ends_with(":80") {
 			let len = endpoint.len();
 			endpoint.truncate(len - 3);
 		}
+		let url = if endpoint.contains("://") {
+			format!("{}?v=4", endpoint)
+		} else {
+			format!("wss://{}?v=4", endpoint)
+		};
 		// establish the websocket connection
 		// v=4 as described at https://discord.com/developers/docs/topics/voice-connections#voice-gateway-versioning-gateway-versions
-		let url = match ::websocket::client::request::Url::parse(&format!("wss://{}?v=4", endpoint)) {
+		let url = match ::websocket::client::request::Url::parse(&url) {
 			Ok(url) => url,
 			Err(_) => return Err(Error::Other("Invalid endpoint URL")),
 		};
 		let response = Client::connect(url)?.send()?;
 		response.validate()?;
 		let (mut sender, mut receiver) = response.begin().split();
+		set_read_timeout(receiver.get_mut().get_mut(), Some(HANDSHAKE_TIMEOUT))?;
 
 		// send the handshake
 		let map = json! {{
@@ -536,7 +873,7 @@ impl InternalConnection {
 
 		let mut interval = 10_000; // crappy guess in case we fail to receive one
 		let (port, ssrc, modes, ip) = loop {
-			match receiver.recv_json(VoiceEvent::decode)? {
+			match receiver.recv_json(VoiceEvent::decode).map_err(handshake_error)? {
 				VoiceEvent::Hello { heartbeat_interval } => {
 					interval = heartbeat_interval;
 				}
@@ -555,75 +892,53 @@ impl InternalConnection {
 			}
 		};
 		if !modes.iter().any(|s| s == "xsalsa20_poly1305") {
-			return Err(Error::Protocol(
-				"Voice mode \"xsalsa20_poly1305\" unavailable",
-			));
+			return Err(VoiceError::UnsupportedMode(modes).into());
 		}
 
-		// bind a UDP socket and send the ssrc value in a packet as identification
-		let destination = {
+		// try each of the server's addresses until one answers IP discovery
+		let candidates = {
 			use std::net::ToSocketAddrs;
-			(ip.as_ref().map(|ip| &ip[..]).unwrap_or(&endpoint[..]), port)
-				.to_socket_addrs()?
-				.next()
-				.ok_or(Error::Other("Failed to resolve voice hostname"))?
+			let host = ip.as_ref().map(|ip| &ip[..]).unwrap_or(&endpoint[..]);
+			voice_addresses((host, port).to_socket_addrs()?, address_preference)
 		};
-		let udp = UdpSocket::bind("0.0.0.0:0")?;
-		debug!("local addr = {:?}", udp.local_addr());
-		{
-			// https://discord.com/developers/docs/topics/voice-connections#ip-discovery
-			let mut bytes = [0; 2 + 2 + 4 + 64 + 2];
-			let mut msg = &mut bytes[..];
-			msg.write_u16::<BigEndian>(0x1)?;
-			msg.write_u16::<BigEndian>(70)?;
-			msg.write_u32::<BigEndian>(ssrc)?;
-			debug!("sending {:x?} to {:?}", bytes, destination);
-			udp.send_to(&bytes, destination)?;
+		let mut result = Err(Error::Other("Failed to resolve voice hostname"));
+		for destination in candidates {
+			result = discover_ip(destination, ssrc).map(|found| (destination, found));
+			match result {
+				Ok(_) => break,
+				Err(ref err) => debug!("IP discovery via {} failed: {:?}", destination, err),
+			}
 		}
+		let (destination, (udp, addr, port_number)) = result?;
 
-		{
-			// receive the response to the identification to get port and address info
-			let mut bytes = [0; 256];
-			let (len, _) = udp.recv_from(&mut bytes)?;
-			let mut msg = &bytes[..len];
-			assert_eq!(0x2, msg.read_u16::<BigEndian>()?);
-			assert_eq!(70, msg.read_u16::<BigEndian>()?);
-			assert_eq!(ssrc, msg.read_u32::<BigEndian>()?);
-			let (addr, mut msg) = msg.split_at(64);
-			let addr = &addr[..addr.iter().position(|&x| x == 0).unwrap()];
-			let port_number = msg.read_u16::<BigEndian>()?;
-
-			// send the acknowledgement websocket message
-			let map = json! {{
-				"op": 1,
-				"d": {
-					"protocol": "udp",
-					"data": {
-						"address": addr,
-						"port": port_number,
-						"mode": "xsalsa20_poly1305",
-					}
+		// send the acknowledgement websocket message
+		let map = json! {{
+			"op": 1,
+			"d": {
+				"protocol": "udp",
+				"data": {
+					"address": addr,
+					"port": port_number,
+					"mode": "xsalsa20_poly1305",
 				}
-			}};
-			sender.send_json(&map)?;
-		}
+			}
+		}};
+		sender.send_json(&map)?;
 
 		// discard websocket messages until we get the Ready
 		let encryption_key;
 		loop {
-			match receiver.recv_json(VoiceEvent::decode)? {
+			match receiver.recv_json(VoiceEvent::decode).map_err(handshake_error)? {
 				VoiceEvent::Hello { heartbeat_interval } => {
 					// Not hit in usual operation; just for coverage.
 					interval = heartbeat_interval;
 				},
 				VoiceEvent::SessionDescription { mode, secret_key } => {
-					encryption_key =
-						crypto::Key::from_slice(&secret_key).expect("failed to create key");
 					if mode != "xsalsa20_poly1305" {
-						return Err(Error::Protocol(
-							"Voice mode in Ready was not \"xsalsa20_poly1305\"",
-						));
+						return Err(VoiceError::UnsupportedMode(vec![mode]).into());
 					}
+					encryption_key = crypto::Key::from_slice(&secret_key)
+						.ok_or(VoiceError::EncryptionSetupFailed)?;
 					break;
 				}
 				VoiceEvent::Unknown(op, value) => {
@@ -633,6 +948,8 @@ impl InternalConnection {
 			}
 		}
 
+		set_read_timeout(receiver.get_mut().get_mut(), None)?;
+
 		// start two child threads: one for the voice websocket and another for UDP voice packets
 		let thread = ::std::thread::current();
 		let thread_name = thread.name().unwrap_or("discord voice");
@@ -658,10 +975,17 @@ impl InternalConnection {
 						};
 					}
 					loop {
-						while let Ok(msg) = receiver.recv_json(VoiceEvent::decode) {
-							match tx1.send(RecvStatus::Websocket(msg)) {
-								Ok(()) => {}
-								Err(_) => return,
+						loop {
+							match receiver.recv_json(VoiceEvent::decode) {
+								Ok(msg) => match tx1.send(RecvStatus::Websocket(msg)) {
+									Ok(()) => {}
+									Err(_) => return,
+								},
+								Err(Error::Closed(code, _)) => {
+									let _ = tx1.send(RecvStatus::Closed(code));
+									return;
+								}
+								Err(_) => break,
 							}
 						}
 						if let Ok(_) = ws_reader_close.try_recv() {
@@ -709,12 +1033,14 @@ impl InternalConnection {
 			silence_frames: 0,
 
 			decoder_map: HashMap::new(),
+			ssrc_map: HashMap::new(),
 			encoder: opus::Encoder::new(
 				SAMPLE_RATE,
 				opus::Channels::Mono,
 				opus::Application::Audio,
 			)?,
 			encoder_stereo: false,
+			encoder_bitrate: None,
 			keepalive_timer: ::Timer::new(interval),
 			// after 5 minutes of us sending nothing, Discord will stop sending voice data to us
 			audio_keepalive_timer: ::Timer::new(4 * 60 * 1000),
@@ -728,10 +1054,21 @@ impl InternalConnection {
 		&mut self,
 		source: &mut Option<Box<dyn AudioSource>>,
 		receiver: &mut Option<Box<dyn AudioReceiver>>,
+		mix: &ReceiveMix,
+		frame_size: FrameSize,
+		bitrate: Option<u32>,
 		audio_timer: &mut ::Timer,
 	) -> Result<()> {
-		let mut audio_buffer = [0i16; 960 * 2]; // 20 ms, stereo
-		let mut packet = [0u8; 512]; // 256 forces opus to reduce bitrate for some packets
+		let mut audio_buffer = [0i16; MAX_FRAME_SAMPLES * 2]; // stereo
+		// 256 per 20ms forces opus to reduce bitrate for some packets
+		let mut packet = [0u8; MAX_PACKET_LEN];
+		let mut packet_len = PACKET_LEN * ::std::cmp::max(1, frame_size.millis() as usize / 20);
+		if let Some(bitrate) = bitrate {
+			// leave a quarter again of the average for variable bitrate
+			let needed = HEADER_LEN + 16 + bitrate as usize * frame_size.millis() as usize / 6400;
+			packet_len = ::std::cmp::min(::std::cmp::max(packet_len, needed), MAX_PACKET_LEN);
+		}
+		let samples = frame_size.samples();
 		let mut nonce = crypto::Nonce([0; 24]);
 
 		// Check for received voice data
@@ -743,9 +1080,13 @@ impl InternalConnection {
 						ssrc,
 						speaking,
 					}) => {
+						self.ssrc_map.insert(ssrc, user_id);
 						receiver.speaking_update(ssrc, user_id, speaking);
 					}
 					RecvStatus::Websocket(_) => {}
+					RecvStatus::Closed(code) => {
+						return Err(VoiceError::Disconnected { code: code }.into());
+					}
 					RecvStatus::Udp(packet) => {
 						let mut handle = &packet[2..];
 						let sequence = handle.read_u16::<BigEndian>()?;
@@ -764,20 +1105,25 @@ impl InternalConnection {
 								})
 								.decode(&decrypted, &mut audio_buffer, false)?;
 							let stereo = channels == opus::Channels::Stereo;
-							receiver.voice_packet(
-								ssrc,
-								sequence,
-								timestamp,
-								stereo,
-								&audio_buffer[..if stereo { len * 2 } else { len }],
-							);
+							let data = &mut audio_buffer[..if stereo { len * 2 } else { len }];
+							let audible = match self.ssrc_map.get(&ssrc) {
+								Some(&user_id) => mix.apply(user_id, data),
+								None => true,
+							};
+							if audible {
+								receiver.voice_packet(ssrc, sequence, timestamp, stereo, data);
+							}
 						}
 					}
 				}
 			}
 		} else {
 			// if there's no receiver, discard incoming events
-			while let Ok(_) = self.receive_chan.try_recv() {}
+			while let Ok(status) = self.receive_chan.try_recv() {
+				if let RecvStatus::Closed(code) = status {
+					return Err(VoiceError::Disconnected { code: code }.into());
+				}
+			}
 		}
 
 		// Send the voice websocket keepalive if needed
@@ -800,16 +1146,20 @@ impl InternalConnection {
 		let mut clear_source = false;
 		let len = if let Some(source) = source.as_mut() {
 			let stereo = source.is_stereo();
-			if stereo != self.encoder_stereo {
+			if stereo != self.encoder_stereo || bitrate != self.encoder_bitrate {
 				let channels = if stereo {
 					opus::Channels::Stereo
 				} else {
 					opus::Channels::Mono
 				};
 				self.encoder = opus::Encoder::new(SAMPLE_RATE, channels, opus::Application::Audio)?;
+				if let Some(bitrate) = bitrate {
+					self.encoder.set_bitrate(opus::Bitrate::Bits(bitrate as i32))?;
+				}
 				self.encoder_stereo = stereo;
+				self.encoder_bitrate = bitrate;
 			}
-			let buffer_len = if stereo { 960 * 2 } else { 960 };
+			let buffer_len = if stereo { samples * 2 } else { samples };
 			match source.read_frame(&mut audio_buffer[..buffer_len]) {
 				Some(len) => len,
 				None => {
@@ -829,7 +1179,7 @@ impl InternalConnection {
 			if self.silence_frames > 0 {
 				// send a few frames of silence; could be optimized to be pre-encoded
 				self.silence_frames -= 1;
-				for value in &mut audio_buffer[..] {
+				for value in &mut audio_buffer[..samples * 2] {
 					*value = 0;
 				}
 			} else {
@@ -839,7 +1189,7 @@ impl InternalConnection {
 		} else {
 			self.silence_frames = 5;
 			// zero-fill the rest of the buffer
-			for value in &mut audio_buffer[len..] {
+			for value in &mut audio_buffer[len..samples * 2] {
 				*value = 0;
 			}
 		}
@@ -857,8 +1207,8 @@ impl InternalConnection {
 		nonce.0[..HEADER_LEN].clone_from_slice(&packet[..HEADER_LEN]);
 
 		// encode the audio data
-		let extent = packet.len() - 16; // leave 16 bytes for encryption overhead
-		let buffer_len = if self.encoder_stereo { 960 * 2 } else { 960 };
+		let extent = packet_len - 16; // leave 16 bytes for encryption overhead
+		let buffer_len = if self.encoder_stereo { samples * 2 } else { samples };
 		let len = self
 			.encoder
 			.encode(&audio_buffer[..buffer_len], &mut packet[HEADER_LEN..extent])?;
@@ -870,7 +1220,7 @@ impl InternalConnection {
 		packet[HEADER_LEN..HEADER_LEN + crypted.len()].clone_from_slice(&crypted);
 
 		self.sequence = self.sequence.wrapping_add(1);
-		self.timestamp = self.timestamp.wrapping_add(960);
+		self.timestamp = self.timestamp.wrapping_add(samples as u32);
 
 		// wait until the right time, then transmit the packet
 		audio_timer.sleep_until_tick();
@@ -910,4 +1260,99 @@ impl Drop for InternalConnection {
 enum RecvStatus {
 	Websocket(VoiceEvent),
 	Udp(Vec<u8>),
+	Closed(Option<u16>),
+}
+
+/// Order a voice server's addresses to be tried, keeping only those of the
+/// preferred IP versions.
+fn voice_addresses<I>(addrs: I, preference: AddressPreference) -> Vec<SocketAddr>
+where
+	I: Iterator<Item = SocketAddr>,
+{
+	let mut addrs: Vec<SocketAddr> = addrs
+		.filter(|addr| match preference {
+			AddressPreference::Auto => true,
+			AddressPreference::V4 => addr.is_ipv4(),
+			AddressPreference::V6 => addr.is_ipv6(),
+		})
+		.collect();
+	// the sort is stable, so the resolver's order is otherwise kept
+	addrs.sort_by_key(|addr| addr.is_ipv6());
+	addrs
+}
+
+/// Bind a UDP socket of the destination's IP version and send the ssrc value
+/// through it as identification, returning the socket along with the
+/// external address and port reported in response.
+fn discover_ip(destination: SocketAddr, ssrc: u32) -> Result<(UdpSocket, String, u16)> {
+	let local = if destination.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+	let udp = UdpSocket::bind(local)?;
+	udp.set_read_timeout(Some(DISCOVERY_TIMEOUT))?;
+	debug!("local addr = {:?}", udp.local_addr());
+	{
+		// https://discord.com/developers/docs/topics/voice-connections#ip-discovery
+		let mut bytes = [0; 2 + 2 + 4 + 64 + 2];
+		let mut msg = &mut bytes[..];
+		msg.write_u16::<BigEndian>(0x1)?;
+		msg.write_u16::<BigEndian>(70)?;
+		msg.write_u32::<BigEndian>(ssrc)?;
+		debug!("sending {:x?} to {:?}", bytes, destination);
+		udp.send_to(&bytes, destination)?;
+	}
+
+	// receive the response to the identification to get port and address info
+	let mut bytes = [0; 256];
+	let len = match udp.recv_from(&mut bytes) {
+		Ok((len, _)) => len,
+		Err(ref err) if is_timeout(err) => return Err(VoiceError::IpDiscoveryFailed.into()),
+		Err(err) => return Err(err.into()),
+	};
+	let (addr, port) =
+		parse_ip_discovery(&bytes[..len], ssrc).ok_or(VoiceError::IpDiscoveryFailed)?;
+	Ok((udp, String::from_utf8_lossy(addr).into_owned(), port))
+}
+
+/// Parse an IP discovery response, returning the external address and port.
+fn parse_ip_discovery(mut msg: &[u8], ssrc: u32) -> Option<(&[u8], u16)> {
+	if msg.len() < 2 + 2 + 4 + 64 + 2
+		|| msg.read_u16::<BigEndian>().ok()? != 0x2
+		|| msg.read_u16::<BigEndian>().ok()? != 70
+		|| msg.read_u32::<BigEndian>().ok()? != ssrc
+	{
+		return None;
+	}
+	let (addr, mut msg) = msg.split_at(64);
+	let addr = &addr[..addr.iter().position(|&x| x == 0)?];
+	let port = msg.read_u16::<BigEndian>().ok()?;
+	Some((addr, port))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_receive_mix() {
+		let mut mix = ReceiveMix::default();
+		mix.volumes.insert(UserId(1), 0.5);
+		mix.volumes.insert(UserId(2), 2.0);
+		mix.muted.insert(UserId(3));
+
+		// users without adjustments are passed through untouched
+		let mut data = [100, -100];
+		assert!(mix.apply(UserId(4), &mut data));
+		assert_eq!(data, [100, -100]);
+
+		let mut data = [101, -101, 0];
+		assert!(mix.apply(UserId(1), &mut data));
+		assert_eq!(data, [51, -51, 0]);
+
+		// amplified samples are clipped rather than wrapping around
+		let mut data = [20000, -20000, 100];
+		assert!(mix.apply(UserId(2), &mut data));
+		assert_eq!(data, [i16::MAX, i16::MIN, 200]);
+
+		let mut data = [100];
+		assert!(!mix.apply(UserId(3), &mut data));
+	}
 }
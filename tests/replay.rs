@@ -0,0 +1,112 @@
+extern crate discord;
+
+use discord::model::{ChannelId, Event, OnlineStatus, UserId};
+use discord::replay::{Action, Recording, Replay};
+use discord::{ChannelRef, State};
+
+fn recording() -> Recording {
+	Recording::open(concat!(
+		env!("CARGO_MANIFEST_DIR"),
+		"/tests/fixtures/gateway_session.json"
+	))
+	.expect("failed to load recording")
+}
+
+#[test]
+fn replay_session_bookkeeping() {
+	let (mut replay, ready) = Replay::start(recording()).unwrap();
+	assert_eq!(ready.session_id, "5e8a0d2b7c1f4e3a9b6d");
+
+	let mut kinds = Vec::new();
+	loop {
+		match replay.recv_event() {
+			Ok(Event::Ready(ready)) => kinds.push(format!("Ready({})", ready.session_id)),
+			Ok(Event::ServerCreate(_)) => kinds.push("ServerCreate".to_owned()),
+			Ok(Event::MessageCreate(message)) => kinds.push(message.content),
+			Ok(Event::Resumed { .. }) => kinds.push("Resumed".to_owned()),
+			Ok(Event::ChannelUpdate(_)) => kinds.push("ChannelUpdate".to_owned()),
+			Ok(other) => panic!("unexpected event: {:?}", other),
+			Err(discord::Error::Closed(None, _)) => break,
+			Err(err) => panic!("replay failed: {:?}", err),
+		}
+	}
+	assert_eq!(
+		kinds,
+		[
+			"ServerCreate",
+			"hello from the recording",
+			"Resumed",
+			"ChannelUpdate",
+			"Ready(9c41e7f02ab34d6e8f15)",
+			"ServerCreate",
+			"Ready(d3b2a1f0e9c84b7a6d5c)",
+		]
+	);
+	assert_eq!(
+		replay.actions(),
+		&[
+			Action::Heartbeat(3),
+			Action::Resume("5e8a0d2b7c1f4e3a9b6d".to_owned(), 3),
+			Action::Reconnect,
+			Action::Reconnect,
+		][..]
+	);
+	assert_eq!(replay.session_id(), Some("d3b2a1f0e9c84b7a6d5c"));
+	assert_eq!(replay.last_sequence(), 1);
+}
+
+#[test]
+fn replay_into_state() {
+	let channel = ChannelId(41771983423143937);
+	let mut names = Vec::new();
+	let mut threads = Vec::new();
+	let state = Replay::run(recording(), |state, event| match *event {
+		Event::ChannelUpdate(_) => {
+			if let Some(ChannelRef::Public(_, public)) = state.find_channel(channel) {
+				names.push(public.name.clone());
+			}
+		}
+		Event::ServerCreate(_) => threads.push(state.threads_in(channel).len()),
+		_ => {}
+	})
+	.unwrap();
+	assert_eq!(names, ["renamed"]);
+	// the second session's server has no threads, replacing the first's
+	assert_eq!(threads, [1, 0]);
+	// the final session saw only the unavailable server
+	assert!(state.servers().is_empty());
+	assert_eq!(state.unavailable_servers().len(), 1);
+}
+
+#[test]
+fn state_save_and_load() {
+	let channel = ChannelId(41771983423143937);
+	let mut saved = Vec::new();
+	Replay::run(recording(), |state, event| {
+		if let Event::ChannelUpdate(_) = *event {
+			state.save(&mut saved).unwrap();
+		}
+	})
+	.unwrap();
+
+	let state = State::load(&saved[..]).unwrap();
+	assert_eq!(state.servers().len(), 1);
+	match state.find_channel(channel) {
+		Some(ChannelRef::Public(server, public)) => {
+			assert_eq!(public.name, "renamed");
+			assert_eq!(state.find_server(server.id).map(|s| s.id), Some(server.id));
+		}
+		other => panic!("channel not restored: {:?}", other),
+	}
+
+	let server = &state.servers()[0];
+	let member = state.find_member(server.id, UserId(80351110224678912));
+	assert_eq!(member.and_then(|m| m.nick.as_ref()).map(|n| &n[..]), Some("Replayer"));
+	assert_eq!(server.presences.len(), 1);
+	assert_eq!(server.presences[0].user_id, UserId(53908099506183680));
+	assert_eq!(server.presences[0].status, OnlineStatus::Idle);
+	assert_eq!(server.presences[0].activities[0].name, "Replaying");
+	let thread = state.find_thread(ChannelId(41771983423143940));
+	assert_eq!(thread.map(|t| &t.name[..]), Some("first thread"));
+	assert_eq!(state.threads_in(channel).len(), 1);
+}
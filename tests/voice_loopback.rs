@@ -0,0 +1,72 @@
+//! Sends audio through a voice connection to a loopback server and checks
+//! that it arrives and is echoed back.
+
+#![cfg(feature = "voice")]
+
+extern crate discord;
+
+use std::io::Cursor;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use discord::loopback::{LoopbackServer, CLIENT_SSRC, ECHO_SSRC, ECHO_USER};
+use discord::model::{ServerId, UserId};
+use discord::voice::{self, AudioReceiver};
+
+struct EchoCounter {
+	speaking: Arc<AtomicUsize>,
+	packets: Arc<AtomicUsize>,
+}
+
+impl AudioReceiver for EchoCounter {
+	fn speaking_update(&mut self, ssrc: u32, user_id: UserId, speaking: bool) {
+		if ssrc == ECHO_SSRC && user_id == ECHO_USER && speaking {
+			self.speaking.fetch_add(1, Ordering::SeqCst);
+		}
+	}
+
+	fn voice_packet(&mut self, ssrc: u32, _: u16, _: u32, stereo: bool, data: &[i16]) {
+		if ssrc == ECHO_SSRC && !stereo && !data.is_empty() {
+			self.packets.fetch_add(1, Ordering::SeqCst);
+		}
+	}
+}
+
+#[test]
+fn voice_loopback() {
+	let server = LoopbackServer::start().unwrap();
+	let mut voice = server.connect(ServerId(1), UserId(3)).unwrap();
+
+	let speaking = Arc::new(AtomicUsize::new(0));
+	let echoed = Arc::new(AtomicUsize::new(0));
+	voice.set_receiver(Box::new(EchoCounter {
+		speaking: speaking.clone(),
+		packets: echoed.clone(),
+	}));
+
+	// half a second of a 500Hz square wave, as 16-bit little-endian mono
+	let mut pcm = Vec::new();
+	for i in 0..24000 {
+		let sample: i16 = if i / 48 % 2 == 0 { 4000 } else { -4000 };
+		pcm.push(sample as u8);
+		pcm.push((sample >> 8) as u8);
+	}
+	voice.play(voice::create_pcm_source(false, Cursor::new(pcm)));
+
+	let started = Instant::now();
+	while echoed.load(Ordering::SeqCst) < 10 && started.elapsed() < Duration::from_secs(5) {
+		thread::sleep(Duration::from_millis(20));
+	}
+
+	let packets = server.packets();
+	assert!(packets.len() >= 10, "only {} packets arrived", packets.len());
+	for pair in packets.windows(2) {
+		assert_eq!(pair[0].ssrc, CLIENT_SSRC);
+		assert_eq!(pair[1].sequence, pair[0].sequence.wrapping_add(1));
+		assert_eq!(pair[1].timestamp, pair[0].timestamp.wrapping_add(960));
+	}
+	assert!(speaking.load(Ordering::SeqCst) > 0);
+	assert!(echoed.load(Ordering::SeqCst) >= 10);
+}